@@ -0,0 +1,93 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// NOTE: days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn main() {
+    // NOTE: SOURCE_DATE_EPOCH keeps reproducible builds reproducible. Watching src reruns this
+    // on every source change, so the timestamp stays current
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64)
+        });
+
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+
+    println!(
+        "cargo:rustc-env=RUSTOS_BUILD_TIMESTAMP={:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+
+    // NOTE: the initrd linked into the kernel, see src/initrd.rs. Taken again when
+    // RUSTOS_INITRD names another archive or the archive changes
+    println!("cargo:rerun-if-env-changed=RUSTOS_INITRD");
+
+    let archive = match std::env::var("RUSTOS_INITRD") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+
+            std::fs::read(&path)
+                .unwrap_or_else(|error| panic!("can't read the initrd {}: {}", path, error))
+        }
+        Err(_) => Vec::new(),
+    };
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR not set by cargo");
+
+    std::fs::write(std::path::Path::new(&out_dir).join("initrd.tar"), archive)
+        .expect("can't write the initrd");
+
+    // NOTE: the FAT32 test volumes are mostly zeroes, tests/fat32.rs includes only the rest: the
+    // number of 512 byte sectors, then each sector that isn't all zeroes after its index
+    for image in ["fat32.img", "fat32-driver.img"] {
+        let path = std::path::Path::new("tests/data").join(image);
+
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let bytes = std::fs::read(&path)
+            .unwrap_or_else(|error| panic!("can't read {}: {}", path.display(), error));
+        let mut sectors = (bytes.len() as u64 / 512).to_le_bytes().to_vec();
+
+        for (index, sector) in bytes.chunks(512).enumerate() {
+            if sector.iter().any(|&byte| byte != 0) {
+                sectors.extend_from_slice(&(index as u64).to_le_bytes());
+                sectors.extend_from_slice(sector);
+            }
+        }
+
+        std::fs::write(
+            std::path::Path::new(&out_dir).join(format!("{}.sectors", image)),
+            sectors,
+        )
+        .expect("can't write a test volume");
+    }
+}
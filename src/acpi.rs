@@ -0,0 +1,235 @@
+use crate::memory;
+use x86_64::PhysAddr;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// NOTE: the real mode segment of the EBDA is stored in the BIOS data area
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+const HEADER_LENGTH: usize = 36;
+const MADT_ENTRIES_OFFSET: usize = 44;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+// NOTE: all of physical memory is mapped by the bootloader, tables are never unmapped
+fn physical_bytes(address: u64, length: usize) -> &'static [u8] {
+    let virt = memory::phys_to_virt(PhysAddr::new(address));
+
+    unsafe { core::slice::from_raw_parts(virt.as_ptr(), length) }
+}
+
+// NOTE: the RSDP sits on a 16 byte boundary in the first KiB of the EBDA or in the BIOS area
+fn find_rsdp() -> Option<&'static [u8]> {
+    let ebda = (read_u16(physical_bytes(EBDA_SEGMENT_POINTER, 2), 0) as u64) << 4;
+    let ebda_range = match ebda {
+        0 => 0..0,
+        _ => ebda..ebda + 1024,
+    };
+
+    let address = ebda_range
+        .step_by(16)
+        .chain((BIOS_AREA_START..BIOS_AREA_END).step_by(16))
+        .find(|address| {
+            let rsdp = physical_bytes(*address, 20);
+
+            &rsdp[..8] == RSDP_SIGNATURE && checksum(rsdp)
+        })?;
+
+    // NOTE: ACPI 2.0+ extends the RSDP to 36 bytes with the XSDT address
+    match physical_bytes(address, 20)[15] {
+        0 => Some(physical_bytes(address, 20)),
+        _ => Some(physical_bytes(address, 36)),
+    }
+}
+
+fn table_at(address: u64) -> Option<&'static [u8]> {
+    let length = read_u32(physical_bytes(address, HEADER_LENGTH), 4) as usize;
+    let table = physical_bytes(address, length.max(HEADER_LENGTH));
+
+    checksum(table).then_some(table)
+}
+
+// NOTE: looks the table up through the XSDT when there is one, the RSDT otherwise
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp = find_rsdp()?;
+    let (root, entry_size) = match rsdp.len() {
+        36 => (table_at(read_u64(rsdp, 24))?, 8),
+        _ => (table_at(read_u32(rsdp, 16) as u64)?, 4),
+    };
+
+    root[HEADER_LENGTH..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        })
+        .filter_map(table_at)
+        .find(|table| &table[..4] == signature)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    // NOTE: `flags` are MPS INTI flags, polarity in bits 0-1 and trigger mode in bits 2-3
+    InterruptOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Madt<'a> {
+    table: &'a [u8],
+}
+
+impl Madt<'static> {
+    pub fn find() -> Option<Madt<'static>> {
+        find_table(b"APIC").and_then(Madt::parse)
+    }
+}
+
+impl<'a> Madt<'a> {
+    pub fn parse(table: &'a [u8]) -> Option<Madt<'a>> {
+        (table.len() >= MADT_ENTRIES_OFFSET && &table[..4] == b"APIC").then_some(Madt { table })
+    }
+
+    pub fn local_apic_address(&self) -> u32 {
+        read_u32(self.table, 36)
+    }
+
+    // NOTE: bit 0 of the flags means the legacy 8259 PICs are also installed
+    pub fn has_legacy_pics(&self) -> bool {
+        read_u32(self.table, 40) & 1 != 0
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + 'a {
+        let table = self.table;
+        let mut offset = MADT_ENTRIES_OFFSET;
+
+        core::iter::from_fn(move || {
+            let header = table.get(offset..offset + 2)?;
+            let entry = table.get(offset..offset + (header[1] as usize).max(2))?;
+
+            offset += entry.len();
+
+            Some(match (entry[0], entry.len()) {
+                (0, 8..) => MadtEntry::LocalApic {
+                    processor_id: entry[2],
+                    apic_id: entry[3],
+                    flags: read_u32(entry, 4),
+                },
+                (1, 12..) => MadtEntry::IoApic {
+                    id: entry[2],
+                    address: read_u32(entry, 4),
+                    gsi_base: read_u32(entry, 8),
+                },
+                (2, 10..) => MadtEntry::InterruptOverride {
+                    bus: entry[2],
+                    source: entry[3],
+                    gsi: read_u32(entry, 4),
+                    flags: read_u16(entry, 8),
+                },
+                (4, 6..) => MadtEntry::LocalApicNmi {
+                    processor_id: entry[2],
+                    flags: read_u16(entry, 3),
+                    lint: entry[5],
+                },
+                (kind, _) => MadtEntry::Other(kind),
+            })
+        })
+    }
+}
+
+// NOTE: only the fields of the HPET description table the driver needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HpetTable {
+    pub address: u64,
+    pub minimum_tick: u16,
+}
+
+impl HpetTable {
+    pub fn find() -> Option<HpetTable> {
+        find_table(b"HPET").and_then(HpetTable::parse)
+    }
+
+    // NOTE: the base address is a generic address structure, space 0 means memory mapped
+    pub fn parse(table: &[u8]) -> Option<HpetTable> {
+        (table.len() >= 56 && &table[..4] == b"HPET" && table[40] == 0).then(|| HpetTable {
+            address: read_u64(table, 44),
+            minimum_tick: read_u16(table, 53),
+        })
+    }
+}
+
+#[test_case]
+fn test_madt_entries() {
+    let mut table = [0u8; 70];
+    let entries = [
+        1, 12, 2, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0, // IOAPIC 2 at 0xfec00000
+        2, 10, 0, 0, 2, 0, 0, 0, 0x0f, 0x00, // IRQ 0 on GSI 2, active low, level
+        9, 4, 0, 0, // unknown entry type
+    ];
+
+    table[..4].copy_from_slice(b"APIC");
+    table[4] = 70;
+    table[36..40].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+    table[40] = 1;
+    table[MADT_ENTRIES_OFFSET..].copy_from_slice(&entries);
+
+    let madt = Madt::parse(&table).unwrap();
+    let mut entries = madt.entries();
+
+    assert_eq!(madt.local_apic_address(), 0xfee0_0000);
+    assert!(madt.has_legacy_pics());
+    assert_eq!(
+        entries.next(),
+        Some(MadtEntry::IoApic {
+            id: 2,
+            address: 0xfec0_0000,
+            gsi_base: 0
+        })
+    );
+    assert_eq!(
+        entries.next(),
+        Some(MadtEntry::InterruptOverride {
+            bus: 0,
+            source: 0,
+            gsi: 2,
+            flags: 0x0f
+        })
+    );
+    assert_eq!(entries.next(), Some(MadtEntry::Other(9)));
+    assert_eq!(entries.next(), None);
+}
@@ -0,0 +1,167 @@
+pub mod debug;
+mod fixed_size_block;
+mod linked_list;
+pub mod slab;
+
+use crate::memory::paging::{self, MapError};
+use alloc::boxed::Box;
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use fixed_size_block::FixedSizeBlockAllocator;
+use spin::{Mutex, MutexGuard};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+// NOTE: an otherwise unused slot of the lower half, far away from the kernel image; 2 MiB
+// aligned and sized so the whole heap fits in a single huge page. The heap starts at a random
+// 2 MiB multiple above this unless KASLR is off
+pub const HEAP_BASE: usize = 0x4444_4440_0000;
+pub const HEAP_SIZE: usize = 2 * 1024 * 1024;
+
+pub fn heap_start() -> usize {
+    HEAP_BASE + crate::memory::kaslr::heap_slide() as usize
+}
+
+#[cfg(not(feature = "debug-heap"))]
+#[global_allocator]
+static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+
+#[cfg(feature = "debug-heap")]
+#[global_allocator]
+static ALLOCATOR: debug::DebugAllocator<Locked<FixedSizeBlockAllocator>> =
+    debug::DebugAllocator::new(Locked::new(FixedSizeBlockAllocator::new()));
+
+fn heap() -> &'static Locked<FixedSizeBlockAllocator> {
+    #[cfg(feature = "debug-heap")]
+    return ALLOCATOR.inner();
+
+    #[cfg(not(feature = "debug-heap"))]
+    return &ALLOCATOR;
+}
+
+// NOTE: bytes as requested by the layouts, not counting block rounding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub peak: usize,
+    pub allocations: u64,
+    pub deallocations: u64,
+}
+
+impl HeapStats {
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+}
+
+static USED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+fn record_alloc(size: usize) {
+    let used = USED.fetch_add(size, Ordering::Relaxed) + size;
+
+    PEAK.fetch_max(used, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn record_dealloc(size: usize) {
+    USED.fetch_sub(size, Ordering::Relaxed);
+    DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+// NOTE: the global allocator's counters, the test allocators below it aren't counted
+pub fn stats() -> HeapStats {
+    HeapStats {
+        size: HEAP_SIZE,
+        used: USED.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfMemory(pub Layout);
+
+// NOTE: like alloc::alloc::alloc but failure comes back as an error instead of ending up in
+// handle_alloc_error, for allocations the caller can survive without
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, OutOfMemory> {
+    if layout.size() == 0 {
+        return Err(OutOfMemory(layout));
+    }
+
+    NonNull::new(unsafe { alloc::alloc::alloc(layout) }).ok_or(OutOfMemory(layout))
+}
+
+/// # Safety
+///
+/// `ptr` must come from `try_alloc` with the same `layout`.
+pub unsafe fn dealloc(ptr: NonNull<u8>, layout: Layout) {
+    alloc::alloc::dealloc(ptr.as_ptr(), layout);
+}
+
+// NOTE: Box::new that fails instead of panicking, the value is handed back on failure
+pub fn try_box<T>(value: T) -> Result<Box<T>, T> {
+    let layout = Layout::new::<T>();
+
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+
+    match try_alloc(layout) {
+        Ok(ptr) => unsafe {
+            let ptr = ptr.as_ptr() as *mut T;
+
+            ptr.write(value);
+
+            Ok(Box::from_raw(ptr))
+        },
+        Err(_) => Err(value),
+    }
+}
+
+// NOTE: wrapper to implement GlobalAlloc, which only gets &self, on allocators behind a lock
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Locked<A> {
+        Locked {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+// NOTE: `align` must be a power of two
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+// NOTE: maps the heap region up front and hands it to the allocator
+pub fn init_heap() -> Result<(), MapError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    paging::map_range(VirtAddr::new(heap_start() as u64), HEAP_SIZE as u64, flags)?;
+
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        heap().lock().init(heap_start(), HEAP_SIZE)
+    });
+
+    Ok(())
+}
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+    assert_eq!(align_up(0x2000, 0x1000), 0x2000);
+    assert_eq!(align_up(3, 8), 8);
+}
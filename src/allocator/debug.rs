@@ -0,0 +1,237 @@
+use super::slab::POISON;
+use crate::memory::stack;
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::asm;
+use core::fmt;
+use core::ptr;
+use x86_64::VirtAddr;
+
+// NOTE: bytes around each allocation and the one new allocations are filled with, as in Linux
+pub const REDZONE: u8 = 0xcc;
+pub const UNINITIALISED: u8 = 0x5a;
+const REDZONE_SIZE: usize = 16;
+
+// NOTE: return addresses recorded per allocation, innermost first
+const CALLERS: usize = 4;
+
+const LIVE: u64 = 0x6c69_7665_6865_6170;
+const FREED: u64 = 0x6672_6565_6865_6170;
+
+// NOTE: at the start of the block, the leading redzone fills the rest of the prefix
+#[repr(C)]
+struct Header {
+    magic: u64,
+    size: usize,
+    callers: [u64; CALLERS],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionKind {
+    LeadingRedzone,
+    TrailingRedzone,
+    // NOTE: the header is gone, e.g. a double free or a pointer the allocator never returned
+    Header,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corruption {
+    pub kind: CorruptionKind,
+    pub size: usize,
+    pub callers: [u64; CALLERS],
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap corruption ({:?}) in a {} byte allocation from",
+            self.kind, self.size
+        )?;
+
+        for caller in self.callers.iter().take_while(|caller| **caller != 0) {
+            write!(f, " {:#x}", caller)?;
+        }
+
+        Ok(())
+    }
+}
+
+// NOTE: follows the frame pointer chain, which only dev builds force. Stops at anything that
+// doesn't look like a caller's frame and at the ends of the current stack, IST stacks and
+// others stack::bounds doesn't know get no call sites
+#[inline(always)]
+fn callers() -> [u64; CALLERS] {
+    let mut callers = [0; CALLERS];
+    let mut frame: u64;
+    let stack_pointer: u64;
+
+    if !cfg!(debug_assertions) {
+        return callers;
+    }
+
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack));
+        asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack));
+    }
+
+    let Some((_, top)) = stack::bounds(VirtAddr::new_truncate(stack_pointer)) else {
+        return callers;
+    };
+
+    for caller in callers.iter_mut() {
+        if frame < stack_pointer || frame + 16 > top.as_u64() || !frame.is_multiple_of(8) {
+            break;
+        }
+
+        let (next, address) = unsafe { (*(frame as *const u64), *((frame + 8) as *const u64)) };
+
+        *caller = address;
+
+        if next <= frame {
+            break;
+        }
+
+        frame = next;
+    }
+
+    callers
+}
+
+// NOTE: wraps another allocator, every block gets a header and redzones on both sides; new
+// blocks are filled with UNINITIALISED and freed ones with POISON. Dealloc panics when a
+// redzone was written to or the block isn't live
+pub struct DebugAllocator<A> {
+    inner: A,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: A) -> DebugAllocator<A> {
+        DebugAllocator { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    // NOTE: header and leading redzone, padded so the user pointer keeps the alignment
+    fn prefix(layout: Layout) -> usize {
+        super::align_up(
+            core::mem::size_of::<Header>() + REDZONE_SIZE,
+            layout.align().max(8),
+        )
+    }
+
+    fn outer(layout: Layout) -> Option<Layout> {
+        let size = Self::prefix(layout)
+            .checked_add(layout.size())?
+            .checked_add(REDZONE_SIZE)?;
+
+        Layout::from_size_align(size, layout.align().max(8)).ok()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from this allocator's `alloc` with `layout`.
+    pub unsafe fn check(&self, ptr: *mut u8, layout: Layout) -> Result<(), Corruption> {
+        let header = ptr.sub(Self::prefix(layout)) as *const Header;
+        let corruption = |kind| Corruption {
+            kind,
+            size: (*header).size,
+            callers: (*header).callers,
+        };
+
+        if (*header).magic != LIVE || (*header).size != layout.size() {
+            return Err(corruption(CorruptionKind::Header));
+        }
+
+        let intact = |start: *const u8| (0..REDZONE_SIZE).all(|byte| *start.add(byte) == REDZONE);
+
+        if !intact(ptr.sub(REDZONE_SIZE)) {
+            return Err(corruption(CorruptionKind::LeadingRedzone));
+        }
+
+        if !intact(ptr.add(layout.size())) {
+            return Err(corruption(CorruptionKind::TrailingRedzone));
+        }
+
+        Ok(())
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(outer) = Self::outer(layout) else {
+            return ptr::null_mut();
+        };
+        let base = self.inner.alloc(outer);
+
+        if base.is_null() {
+            return base;
+        }
+
+        let ptr = base.add(Self::prefix(layout));
+
+        ptr::write_bytes(base, REDZONE, outer.size());
+        ptr::write_bytes(ptr, UNINITIALISED, layout.size());
+        (base as *mut Header).write(Header {
+            magic: LIVE,
+            size: layout.size(),
+            callers: callers(),
+        });
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(corruption) = self.check(ptr, layout) {
+            panic!("{}", corruption);
+        }
+
+        let base = ptr.sub(Self::prefix(layout));
+        let outer = Self::outer(layout).expect("layout was allocated");
+
+        (*(base as *mut Header)).magic = FREED;
+        ptr::write_bytes(ptr, POISON, layout.size());
+        self.inner.dealloc(base, outer);
+    }
+}
+
+#[test_case]
+fn test_redzones() {
+    use super::linked_list::LinkedListAllocator;
+    use super::Locked;
+
+    static mut ARENA: [u64; 512] = [0; 512];
+
+    let allocator = DebugAllocator::new(Locked::new(LinkedListAllocator::new()));
+    let layout = Layout::from_size_align(24, 32).unwrap();
+
+    unsafe {
+        allocator
+            .inner()
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA) as usize, 4096);
+
+        let ptr = allocator.alloc(layout);
+
+        assert_eq!(ptr as usize % 32, 0);
+        assert_eq!(*ptr, UNINITIALISED);
+        assert_eq!(allocator.check(ptr, layout), Ok(()));
+
+        *ptr.add(24) = 0;
+
+        let corruption = allocator.check(ptr, layout).unwrap_err();
+
+        assert_eq!(corruption.kind, CorruptionKind::TrailingRedzone);
+        assert_eq!(corruption.size, 24);
+
+        *ptr.add(24) = REDZONE;
+        allocator.dealloc(ptr, layout);
+
+        assert_eq!(*ptr, POISON);
+        assert_eq!(
+            allocator.check(ptr, layout).unwrap_err().kind,
+            CorruptionKind::Header
+        );
+    }
+}
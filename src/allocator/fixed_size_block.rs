@@ -0,0 +1,195 @@
+use super::linked_list::LinkedListAllocator;
+use super::{record_alloc, record_dealloc, Locked};
+use crate::memory::oom::{self, OomRequest};
+use core::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+use x86_64::instructions::interrupts;
+
+// NOTE: each size is also the block's alignment, so they have to be powers of two
+const BLOCK_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct BlockNode {
+    next: *mut BlockNode,
+}
+
+// NOTE: freed blocks go on a per size list and are handed out again in O(1); the list
+// allocator provides new blocks and everything bigger than the largest size
+pub struct FixedSizeBlockAllocator {
+    heads: [*mut BlockNode; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+unsafe impl Send for FixedSizeBlockAllocator {}
+
+fn block_index(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+
+    BLOCK_SIZES.iter().position(|&size| size >= required)
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> FixedSizeBlockAllocator {
+        FixedSizeBlockAllocator {
+            heads: [ptr::null_mut(); BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The memory range must be mapped, unused, and this must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback.init(heap_start, heap_size);
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `GlobalAlloc::alloc`.
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Some(index) = block_index(&layout) else {
+            return self.fallback.alloc(layout);
+        };
+
+        match self.heads[index] {
+            head if head.is_null() => {
+                let size = BLOCK_SIZES[index];
+
+                self.fallback
+                    .alloc(Layout::from_size_align_unchecked(size, size))
+            }
+            head => {
+                self.heads[index] = (*head).next;
+
+                head as *mut u8
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `GlobalAlloc::dealloc`.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let Some(index) = block_index(&layout) else {
+            return self.fallback.dealloc(ptr, layout);
+        };
+
+        // NOTE: blocks are at least 16 bytes, enough for the node
+        debug_assert!(mem::size_of::<BlockNode>() <= BLOCK_SIZES[index]);
+
+        let node = ptr as *mut BlockNode;
+
+        node.write(BlockNode {
+            next: self.heads[index],
+        });
+        self.heads[index] = node;
+    }
+}
+
+// NOTE: the lock is taken with interrupts off so handlers can allocate too
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocate = || interrupts::without_interrupts(|| self.lock().alloc(layout));
+        let mut ptr = allocate();
+
+        if ptr.is_null() && oom::report(OomRequest::Heap(layout)) {
+            ptr = allocate();
+        }
+
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.lock().dealloc(ptr, layout));
+        record_dealloc(layout.size());
+    }
+}
+
+#[test_case]
+fn test_block_reuse() {
+    static mut ARENA: [u64; 1024] = [0; 1024];
+
+    let mut allocator = FixedSizeBlockAllocator::new();
+    let layout = Layout::from_size_align(24, 8).unwrap();
+
+    unsafe {
+        allocator.init(ptr::addr_of_mut!(ARENA) as usize, 8192);
+
+        let first = allocator.alloc(layout);
+
+        assert_eq!(first as usize % 32, 0);
+
+        allocator.dealloc(first, layout);
+
+        assert_eq!(allocator.alloc(layout), first);
+        assert!(!allocator
+            .alloc(Layout::from_size_align(4096, 8).unwrap())
+            .is_null());
+    }
+}
+
+// NOTE: not a pass/fail test, prints the cycles per alloc/dealloc pair of both allocators
+#[test_case]
+fn test_benchmark_against_linked_list() {
+    use crate::{serial_print, tsc};
+
+    const ROUNDS: usize = 1000;
+    const BATCH: usize = 32;
+    static mut LIST_ARENA: [u64; 8192] = [0; 8192];
+    static mut BLOCK_ARENA: [u64; 8192] = [0; 8192];
+
+    let layouts = [16, 48, 200, 1000].map(|size| Layout::from_size_align(size, 8).unwrap());
+    let mut list = LinkedListAllocator::new();
+    let mut blocks = FixedSizeBlockAllocator::new();
+    let mut pointers = [ptr::null_mut(); BATCH];
+
+    unsafe {
+        list.init(ptr::addr_of_mut!(LIST_ARENA) as usize, 65536);
+        blocks.init(ptr::addr_of_mut!(BLOCK_ARENA) as usize, 65536);
+    }
+
+    let start = tsc::read();
+
+    for round in 0..ROUNDS {
+        let layout = layouts[round % layouts.len()];
+
+        unsafe {
+            pointers
+                .iter_mut()
+                .for_each(|pointer| *pointer = list.alloc(layout));
+            pointers
+                .iter()
+                .for_each(|pointer| list.dealloc(*pointer, layout));
+        }
+    }
+
+    let list_cycles = tsc::read() - start;
+    let start = tsc::read();
+
+    for round in 0..ROUNDS {
+        let layout = layouts[round % layouts.len()];
+
+        unsafe {
+            pointers
+                .iter_mut()
+                .for_each(|pointer| *pointer = blocks.alloc(layout));
+            pointers
+                .iter()
+                .for_each(|pointer| blocks.dealloc(*pointer, layout));
+        }
+    }
+
+    let block_cycles = tsc::read() - start;
+    let pairs = (ROUNDS * BATCH) as u64;
+
+    assert!(pointers.iter().all(|pointer| !pointer.is_null()));
+
+    serial_print!(
+        "list {} vs fixed size block {} cycles per pair\t",
+        list_cycles / pairs,
+        block_cycles / pairs
+    );
+}
@@ -0,0 +1,232 @@
+use super::{align_up, Locked};
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr;
+use x86_64::instructions::interrupts;
+
+// NOTE: header written at the start of each free region
+struct ListNode {
+    size: usize,
+    next: *mut ListNode,
+}
+
+impl ListNode {
+    fn start(&self) -> usize {
+        self as *const ListNode as usize
+    }
+
+    fn end(&self) -> usize {
+        self.start() + self.size
+    }
+}
+
+// NOTE: free regions are kept sorted by address so a freed block can be merged with its
+// neighbours, which keeps the heap from fragmenting into pieces too small to use
+pub struct LinkedListAllocator {
+    head: ListNode,
+}
+
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> LinkedListAllocator {
+        LinkedListAllocator {
+            head: ListNode {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The memory range must be mapped, unused, and this must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    // NOTE: the region has to be large and aligned enough to hold a ListNode
+    unsafe fn add_free_region(&mut self, address: usize, size: usize) {
+        assert_eq!(align_up(address, mem::align_of::<ListNode>()), address);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let head: *mut ListNode = &mut self.head;
+        let mut previous = head;
+
+        while !(*previous).next.is_null() && ((*previous).next as usize) < address {
+            previous = (*previous).next;
+        }
+
+        let node = address as *mut ListNode;
+
+        node.write(ListNode {
+            size,
+            next: (*previous).next,
+        });
+
+        let next = (*node).next;
+
+        if !next.is_null() && (*node).end() == next as usize {
+            (*node).size += (*next).size;
+            (*node).next = (*next).next;
+        }
+
+        if previous != head && (*previous).end() == address {
+            (*previous).size += (*node).size;
+            (*previous).next = (*node).next;
+        } else {
+            (*previous).next = node;
+        }
+    }
+
+    // NOTE: start of the allocation inside `region` and the region's leading and trailing
+    // leftovers, each either empty or big enough to go back on the list
+    fn fit(region: &ListNode, size: usize, align: usize) -> Option<(usize, usize, usize)> {
+        let node_size = mem::size_of::<ListNode>();
+        let mut start = align_up(region.start(), align);
+
+        if start != region.start() && start - region.start() < node_size {
+            start = align_up(region.start() + node_size, align);
+        }
+
+        let end = start.checked_add(size)?;
+
+        if end > region.end() {
+            return None;
+        }
+
+        let trailing = region.end() - end;
+
+        if trailing > 0 && trailing < node_size {
+            return None;
+        }
+
+        Some((start, start - region.start(), trailing))
+    }
+
+    // NOTE: first fit, the region is unlinked and its leftovers are added back
+    unsafe fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut previous: *mut ListNode = &mut self.head;
+
+        while !(*previous).next.is_null() {
+            let region = (*previous).next;
+
+            if let Some((start, leading, trailing)) = Self::fit(&*region, size, align) {
+                let (region_start, end) = ((*region).start(), start + size);
+
+                (*previous).next = (*region).next;
+
+                if leading > 0 {
+                    self.add_free_region(region_start, leading);
+                }
+
+                if trailing > 0 {
+                    self.add_free_region(end, trailing);
+                }
+
+                return start as *mut u8;
+            }
+
+            previous = region;
+        }
+
+        ptr::null_mut()
+    }
+
+    // NOTE: every block has to be able to hold a ListNode once it is freed
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+
+        (
+            layout.size().max(mem::size_of::<ListNode>()),
+            layout.align(),
+        )
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `GlobalAlloc::alloc`.
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        self.allocate(size, align)
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `GlobalAlloc::dealloc`.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+    }
+
+    pub fn free(&self) -> usize {
+        let mut free = 0;
+        let mut node = self.head.next;
+
+        while !node.is_null() {
+            unsafe {
+                free += (*node).size;
+                node = (*node).next;
+            }
+        }
+
+        free
+    }
+
+    #[cfg(test)]
+    fn regions(&self) -> usize {
+        let mut count = 0;
+        let mut node = self.head.next;
+
+        while !node.is_null() {
+            count += 1;
+            node = unsafe { (*node).next };
+        }
+
+        count
+    }
+}
+
+// NOTE: the lock is taken with interrupts off so handlers can allocate too
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.lock().alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.lock().dealloc(ptr, layout));
+    }
+}
+
+#[test_case]
+fn test_freed_blocks_merge() {
+    static mut ARENA: [u64; 512] = [0; 512];
+
+    let mut allocator = LinkedListAllocator::new();
+    let start = ptr::addr_of_mut!(ARENA) as usize;
+
+    unsafe {
+        allocator.init(start, 4096);
+
+        let a = allocator.allocate(64, 8);
+        let b = allocator.allocate(128, 64);
+        let c = allocator.allocate(256, 8);
+
+        assert_eq!(a as usize, start);
+        assert_eq!(b as usize % 64, 0);
+        assert!(!c.is_null());
+        assert!(allocator.allocate(8192, 8).is_null());
+
+        allocator.add_free_region(a as usize, 64);
+        allocator.add_free_region(c as usize, 256);
+        allocator.add_free_region(b as usize, 128);
+    }
+
+    assert_eq!(allocator.regions(), 1);
+    assert_eq!(allocator.free(), 4096);
+}
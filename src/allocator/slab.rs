@@ -0,0 +1,209 @@
+use super::align_up;
+use crate::memory::{frame_allocator, phys_to_virt};
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+const FRAME_SIZE: usize = 4096;
+// NOTE: slabs grow until they hold at least this many objects
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+// NOTE: byte freed objects are filled with when poisoning, as in Linux
+pub const POISON: u8 = 0x6b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub allocations: u64,
+    pub frees: u64,
+    pub active: usize,
+    pub slabs: usize,
+    // NOTE: freed objects found modified when they were handed out again
+    pub poison_violations: u64,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+struct Slabs {
+    free: *mut FreeObject,
+    stats: CacheStats,
+}
+
+unsafe impl Send for Slabs {}
+
+// NOTE: objects of one size carved out of slabs of contiguous frames from the frame allocator,
+// reached through the physical memory mapping; slabs are never given back
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    align: usize,
+    poison: bool,
+    slabs: Mutex<Slabs>,
+}
+
+impl SlabCache {
+    // NOTE: `align` must be a power of two no bigger than a frame
+    pub const fn new(name: &'static str, size: usize, align: usize, poison: bool) -> SlabCache {
+        let align = if align < 8 { 8 } else { align };
+        let size = if size < 8 { 8 } else { size };
+
+        SlabCache {
+            name,
+            object_size: (size + align - 1) & !(align - 1),
+            align,
+            poison,
+            slabs: Mutex::new(Slabs {
+                free: ptr::null_mut(),
+                stats: CacheStats {
+                    allocations: 0,
+                    frees: 0,
+                    active: 0,
+                    slabs: 0,
+                    poison_violations: 0,
+                },
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    fn slab_order(&self) -> usize {
+        let bytes = self.object_size * MIN_OBJECTS_PER_SLAB;
+
+        bytes
+            .div_ceil(FRAME_SIZE)
+            .next_power_of_two()
+            .trailing_zeros() as usize
+    }
+
+    fn grow(&self, slabs: &mut Slabs) -> bool {
+        let order = self.slab_order();
+        let Some(frame) = frame_allocator::allocate_frames(order) else {
+            return false;
+        };
+        let start = phys_to_virt(frame.start_address()).as_u64() as usize;
+        let end = start + (FRAME_SIZE << order);
+        let mut object = align_up(start, self.align);
+
+        while object + self.object_size <= end {
+            self.release(slabs, object as *mut u8);
+            object += self.object_size;
+        }
+
+        slabs.stats.slabs += 1;
+
+        true
+    }
+
+    fn release(&self, slabs: &mut Slabs, object: *mut u8) {
+        unsafe {
+            if self.poison {
+                ptr::write_bytes(object, POISON, self.object_size);
+            }
+
+            (object as *mut FreeObject).write(FreeObject { next: slabs.free });
+        }
+
+        slabs.free = object as *mut FreeObject;
+    }
+
+    // NOTE: everything past the free list link must still be poison
+    fn poison_intact(&self, object: *mut u8) -> bool {
+        let offset = core::mem::size_of::<FreeObject>();
+
+        (offset..self.object_size).all(|byte| unsafe { *object.add(byte) } == POISON)
+    }
+
+    // NOTE: the object is uninitialised, or poisoned when the cache poisons
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        interrupts::without_interrupts(|| {
+            let mut slabs = self.slabs.lock();
+
+            if slabs.free.is_null() && !self.grow(&mut slabs) {
+                return None;
+            }
+
+            let object = slabs.free;
+
+            slabs.free = unsafe { (*object).next };
+
+            if self.poison && !self.poison_intact(object as *mut u8) {
+                slabs.stats.poison_violations += 1;
+                log::error!(
+                    "slab {}: object {:p} was modified after being freed",
+                    self.name,
+                    object
+                );
+            }
+
+            slabs.stats.allocations += 1;
+            slabs.stats.active += 1;
+
+            NonNull::new(object as *mut u8)
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `object` must come from `alloc` on this cache and no longer be used.
+    pub unsafe fn free(&self, object: NonNull<u8>) {
+        interrupts::without_interrupts(|| {
+            let mut slabs = self.slabs.lock();
+
+            self.release(&mut slabs, object.as_ptr());
+            slabs.stats.frees += 1;
+            slabs.stats.active -= 1;
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        interrupts::without_interrupts(|| self.slabs.lock().stats)
+    }
+}
+
+#[test_case]
+fn test_cache_reuse() {
+    static CACHE: SlabCache = SlabCache::new("test", 40, 16, false);
+
+    let first = CACHE.alloc().unwrap();
+    let second = CACHE.alloc().unwrap();
+
+    assert_ne!(first, second);
+    assert_eq!(first.as_ptr() as usize % 16, 0);
+    assert_eq!(CACHE.object_size(), 48);
+
+    unsafe { CACHE.free(first) };
+
+    assert_eq!(CACHE.alloc(), Some(first));
+    assert_eq!(
+        CACHE.stats(),
+        CacheStats {
+            allocations: 3,
+            frees: 1,
+            active: 2,
+            slabs: 1,
+            poison_violations: 0,
+        }
+    );
+}
+
+#[test_case]
+fn test_use_after_free_detected() {
+    static CACHE: SlabCache = SlabCache::new("poisoned", 64, 8, true);
+
+    let object = CACHE.alloc().unwrap();
+
+    unsafe {
+        CACHE.free(object);
+        object.as_ptr().add(32).write(0);
+    }
+
+    assert_eq!(CACHE.alloc(), Some(object));
+    assert_eq!(CACHE.stats().poison_violations, 1);
+}
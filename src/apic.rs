@@ -0,0 +1,187 @@
+use crate::memory::{self, MmioRegion, VolatileCell};
+use crate::pit;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// NOTE: CPUID leaf 1, EDX bit 9
+const CPUID_APIC: u32 = 1 << 9;
+
+// NOTE: register offsets inside the 4 KiB MMIO page
+const ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const END_OF_INTERRUPT: usize = 0xb0;
+const SPURIOUS_INTERRUPT: usize = 0xf0;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3e0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const DELIVERY_EXTINT: u32 = 0b111 << 8;
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DESTINATION_ALL_BUT_SELF: u32 = 0b11 << 18;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// NOTE: lowest 4 bits must be set on older APICs, 0xff is the conventional choice
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const CALIBRATION_MS: u32 = 10;
+
+// NOTE: the register page, set once the APIC is enabled
+static REGISTERS: Once<MmioRegion> = Once::new();
+// NOTE: set by start_timer, for switching between periodic and one-shot mode
+static TIMER_VECTOR: AtomicU8 = AtomicU8::new(0);
+static TIMER_PERIOD: AtomicU32 = AtomicU32::new(0);
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_supported() -> bool {
+    __cpuid(1).edx & CPUID_APIC != 0
+}
+
+pub fn is_enabled() -> bool {
+    REGISTERS.r#try().is_some()
+}
+
+fn register(offset: usize) -> &'static VolatileCell<u32> {
+    REGISTERS
+        .r#try()
+        .expect("local APIC used before apic::init")
+        .register(offset)
+}
+
+fn read(offset: usize) -> u32 {
+    register(offset).read()
+}
+
+fn write(offset: usize, value: u32) {
+    register(offset).write(value);
+}
+
+// NOTE: LINT0 is left in virtual wire mode so the 8259 keeps delivering ISA IRQs until they
+// are routed elsewhere, returns false when there is no local APIC
+pub fn init() -> bool {
+    enable(DELIVERY_EXTINT, DELIVERY_NMI)
+}
+
+// NOTE: for application processors once the boot CPU's APIC is up, every CPU sees its own
+// APIC behind the same page. LINT0 and LINT1 stay masked, the legacy PIC and NMIs only go to
+// the boot CPU
+pub fn init_ap() -> bool {
+    is_enabled() && enable(LVT_MASKED, LVT_MASKED)
+}
+
+fn enable(lint0: u32, lint1: u32) -> bool {
+    if !is_supported() {
+        return false;
+    }
+
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let value = unsafe { apic_base.read() };
+    let physical = PhysAddr::new(value & APIC_BASE_ADDRESS_MASK);
+
+    if REGISTERS.r#try().is_none() {
+        let Ok(registers) = memory::map_mmio(physical, 4096) else {
+            return false;
+        };
+
+        REGISTERS.call_once(|| registers);
+    }
+
+    unsafe { apic_base.write(value | APIC_BASE_ENABLE) };
+
+    write(TASK_PRIORITY, 0);
+    write(LVT_LINT0, lint0);
+    write(LVT_LINT1, lint1);
+    write(LVT_ERROR, LVT_MASKED);
+    write(LVT_TIMER, LVT_MASKED);
+    write(SPURIOUS_INTERRUPT, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+
+    true
+}
+
+pub fn id() -> u8 {
+    (read(ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(END_OF_INTERRUPT, 0);
+}
+
+// NOTE: the destination goes in first, writing the low half sends the IPI
+fn send_ipi(apic_id: u8, command: u32) {
+    write(INTERRUPT_COMMAND_HIGH, (apic_id as u32) << 24);
+    write(INTERRUPT_COMMAND_LOW, command);
+
+    while read(INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+// NOTE: a fixed interrupt at `vector` on every other CPU
+pub fn send_all_but_self(vector: u8) {
+    send_ipi(0, DESTINATION_ALL_BUT_SELF | LEVEL_ASSERT | vector as u32);
+}
+
+// NOTE: resets the CPU into its wait for a startup IPI
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+// NOTE: the CPU starts in real mode at `page` * 4 KiB
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_ipi(apic_id, DELIVERY_STARTUP | LEVEL_ASSERT | page as u32);
+}
+
+// NOTE: the timer runs off the bus clock, its rate is measured against the PIT first
+pub fn start_timer(vector: u8, hz: u32) {
+    write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(TIMER_INITIAL_COUNT, u32::MAX);
+    pit::wait_polled_ms(CALIBRATION_MS);
+
+    let elapsed = u32::MAX - read(TIMER_CURRENT_COUNT);
+    let ticks_per_second = elapsed as u64 * 1000 / CALIBRATION_MS as u64;
+    let period = (ticks_per_second / hz.max(1) as u64).clamp(1, u32::MAX as u64) as u32;
+
+    TIMER_VECTOR.store(vector, Ordering::Relaxed);
+    TIMER_PERIOD.store(period, Ordering::Relaxed);
+    TIMER_FREQUENCY.store(ticks_per_second, Ordering::Relaxed);
+
+    write(TIMER_INITIAL_COUNT, 0);
+    resume_timer();
+}
+
+// NOTE: replaces the periodic interrupt with a single one after `us`, until resume_timer
+pub fn oneshot_timer(us: u64) {
+    let count = TIMER_FREQUENCY.load(Ordering::Relaxed).saturating_mul(us) / 1_000_000;
+
+    write(TIMER_INITIAL_COUNT, 0);
+    write(LVT_TIMER, TIMER_VECTOR.load(Ordering::Relaxed) as u32);
+    write(TIMER_INITIAL_COUNT, count.clamp(1, u32::MAX as u64) as u32);
+}
+
+// NOTE: back to the rate start_timer was given, the next period starts now
+pub fn resume_timer() {
+    write(
+        LVT_TIMER,
+        LVT_TIMER_PERIODIC | TIMER_VECTOR.load(Ordering::Relaxed) as u32,
+    );
+    write(TIMER_INITIAL_COUNT, TIMER_PERIOD.load(Ordering::Relaxed));
+}
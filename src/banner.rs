@@ -0,0 +1,87 @@
+use crate::vga_buffer::Color;
+use crate::{println, println_colored};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::arch::x86_64::__cpuid;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const BUILD_TIMESTAMP: &str = env!("RUSTOS_BUILD_TIMESTAMP");
+
+pub const DEFAULT_ART: &[&str] = &[
+    r"                 _",
+    r"  _ __ _   _ ___| |_ ___  ___",
+    r" | '__| | | / __| __/ _ \/ __|",
+    r" | |  | |_| \__ \ || (_) \__ \",
+    r" |_|   \__,_|___/\__\___/|___/",
+];
+
+pub struct Banner<'a> {
+    pub art: &'a [&'a str],
+    pub foreground: Color,
+    pub background: Color,
+    // NOTE: usable memory in bytes, the line is skipped when unknown
+    pub memory: Option<u64>,
+}
+
+impl Default for Banner<'_> {
+    fn default() -> Self {
+        Banner {
+            art: DEFAULT_ART,
+            foreground: Color::LIGHTCYAN,
+            background: Color::BLACK,
+            memory: None,
+        }
+    }
+}
+
+impl Banner<'_> {
+    pub fn with_memory_map(mut self, memory_map: &MemoryMap) -> Self {
+        self.memory = Some(usable_memory(memory_map));
+        self
+    }
+
+    pub fn print(&self) {
+        for line in self.art {
+            println_colored!(self.foreground, self.background, "{}", line);
+        }
+
+        println!();
+        println!("rustos {} (built {})", VERSION, BUILD_TIMESTAMP);
+
+        if let Some(memory) = self.memory {
+            println!("memory: {} KiB usable", memory / 1024);
+        }
+
+        let vendor = cpu_vendor();
+
+        println!(
+            "cpu: {}",
+            core::str::from_utf8(&vendor).unwrap_or("unknown")
+        );
+        println!();
+    }
+}
+
+pub fn usable_memory(memory_map: &MemoryMap) -> u64 {
+    memory_map
+        .iter()
+        .filter(|region| region.region_type == MemoryRegionType::Usable)
+        .map(|region| region.range.end_addr() - region.range.start_addr())
+        .sum()
+}
+
+// NOTE: the 12 byte vendor id from cpuid leaf 0, e.g. "GenuineIntel"
+pub fn cpu_vendor() -> [u8; 12] {
+    let result = __cpuid(0);
+    let mut vendor = [0; 12];
+
+    vendor[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&result.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&result.ecx.to_le_bytes());
+
+    vendor
+}
+
+#[test_case]
+fn test_cpu_vendor() {
+    assert!(cpu_vendor().iter().all(|byte| byte.is_ascii_graphic()));
+}
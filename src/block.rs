@@ -0,0 +1,245 @@
+mod ramdisk;
+
+pub use ramdisk::RamDisk;
+
+use crate::sync::IrqMutex;
+use crate::thread;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::future::{self, Future};
+use core::pin::Pin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    // NOTE: blocks past the end of the device
+    OutOfRange,
+    // NOTE: a buffer that isn't a whole number of blocks
+    Unaligned,
+    ReadOnly,
+    // NOTE: the device reported an error or stopped answering
+    Io,
+    // NOTE: registering a name that is taken
+    Exists,
+}
+
+// NOTE: a disk, partition or anything else addressed in fixed size blocks. Buffers are a whole
+// number of blocks starting at `start`; writes may be cached until flush
+pub trait BlockDevice: Send + Sync {
+    // NOTE: in bytes, a power of two
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError>;
+
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+pub type BlockFuture<'a> = Pin<Box<dyn Future<Output = Result<(), BlockError>> + Send + 'a>>;
+
+// NOTE: the same for drivers whose requests complete by interrupt, the futures resolve when the
+// device is done with the buffer
+pub trait AsyncBlockDevice: Send + Sync {
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    fn read_blocks<'a>(&'a self, start: u64, buffer: &'a mut [u8]) -> BlockFuture<'a>;
+
+    fn write_blocks<'a>(&'a self, start: u64, data: &'a [u8]) -> BlockFuture<'a>;
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+// NOTE: how many blocks `len` bytes at `start` are, for drivers to check requests with
+pub fn check_request(
+    block_size: usize,
+    block_count: u64,
+    start: u64,
+    len: usize,
+) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::Unaligned);
+    }
+
+    let count = (len / block_size) as u64;
+
+    match start.checked_add(count) {
+        Some(end) if end <= block_count => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+// NOTE: an async driver used synchronously, the calling thread sleeps while its request is
+// in flight
+pub struct Blocking<D>(pub D);
+
+impl<D: AsyncBlockDevice> BlockDevice for Blocking<D> {
+    fn block_size(&self) -> usize {
+        self.0.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.0.block_count()
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        thread::block_on(self.0.read_blocks(start, buffer))
+    }
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError> {
+        thread::block_on(self.0.write_blocks(start, data))
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        thread::block_on(self.0.flush())
+    }
+}
+
+// NOTE: a synchronous device used from async code. The work happens when the future is made,
+// so it is ready the first time it is polled, and the executor waits meanwhile
+pub struct Immediate<D>(pub D);
+
+impl<D: BlockDevice> AsyncBlockDevice for Immediate<D> {
+    fn block_size(&self) -> usize {
+        self.0.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.0.block_count()
+    }
+
+    fn read_blocks<'a>(&'a self, start: u64, buffer: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(future::ready(self.0.read_blocks(start, buffer)))
+    }
+
+    fn write_blocks<'a>(&'a self, start: u64, data: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(future::ready(self.0.write_blocks(start, data)))
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(future::ready(self.0.flush()))
+    }
+}
+
+// NOTE: `buffer.len()` bytes from byte `offset`, for filesystems whose structures don't line up
+// with blocks; the blocks at either end are read whole
+pub fn read_bytes(
+    device: &dyn BlockDevice,
+    offset: u64,
+    buffer: &mut [u8],
+) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let first = offset / block_size;
+    let skip = (offset % block_size) as usize;
+    let len = (skip + buffer.len()).div_ceil(block_size as usize) * block_size as usize;
+
+    if skip == 0 && len == buffer.len() {
+        return device.read_blocks(first, buffer);
+    }
+
+    let mut blocks = vec![0; len];
+
+    device.read_blocks(first, &mut blocks)?;
+    buffer.copy_from_slice(&blocks[skip..skip + buffer.len()]);
+
+    Ok(())
+}
+
+// NOTE: the same for writing, the rest of the blocks at either end is read and written back
+pub fn write_bytes(device: &dyn BlockDevice, offset: u64, data: &[u8]) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let first = offset / block_size;
+    let skip = (offset % block_size) as usize;
+    let len = (skip + data.len()).div_ceil(block_size as usize) * block_size as usize;
+
+    if skip == 0 && len == data.len() {
+        return device.write_blocks(first, data);
+    }
+
+    let mut blocks = vec![0; len];
+
+    device.read_blocks(first, &mut blocks)?;
+    blocks[skip..skip + data.len()].copy_from_slice(data);
+    device.write_blocks(first, &blocks)
+}
+
+// NOTE: drivers register what they find under a name, e.g. ata0 or nvme0n1, and filesystems
+// are mounted on what they look up here
+static DEVICES: IrqMutex<BTreeMap<String, Arc<dyn BlockDevice>>> = IrqMutex::new(BTreeMap::new());
+
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) -> Result<(), BlockError> {
+    let mut devices = DEVICES.lock();
+
+    if devices.contains_key(name) {
+        return Err(BlockError::Exists);
+    }
+
+    devices.insert(String::from(name), device);
+
+    Ok(())
+}
+
+// NOTE: users that looked the device up keep it
+pub fn unregister(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().remove(name)
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(name).cloned()
+}
+
+// NOTE: sorted by name
+pub fn devices() -> Vec<(String, Arc<dyn BlockDevice>)> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|(name, device)| (name.clone(), device.clone()))
+        .collect()
+}
+
+#[test_case]
+fn test_registry() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(512, 8));
+
+    assert_eq!(register("test-ram0", disk.clone()), Ok(()));
+    assert_eq!(register("test-ram0", disk.clone()), Err(BlockError::Exists));
+    assert!(devices().iter().any(|(name, _)| name == "test-ram0"));
+    assert_eq!(get("test-ram0").unwrap().block_count(), 8);
+    assert!(unregister("test-ram0").is_some());
+    assert!(get("test-ram0").is_none());
+}
+
+#[test_case]
+fn test_bytes_and_adapters() {
+    let disk = Blocking(Immediate(RamDisk::new(512, 4)));
+
+    assert_eq!(write_bytes(&disk, 500, &[7; 20]), Ok(()));
+
+    let mut blocks = [0; 1024];
+
+    assert_eq!(disk.read_blocks(0, &mut blocks), Ok(()));
+    assert!(blocks[..500].iter().all(|&byte| byte == 0));
+    assert!(blocks[500..520].iter().all(|&byte| byte == 7));
+
+    let mut bytes = [0; 4];
+
+    assert_eq!(read_bytes(&disk, 518, &mut bytes), Ok(()));
+    assert_eq!(bytes, [7, 7, 0, 0]);
+    assert_eq!(
+        disk.read_blocks(3, &mut blocks),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(disk.write_blocks(0, &[0; 100]), Err(BlockError::Unaligned));
+    assert_eq!(disk.flush(), Ok(()));
+}
@@ -0,0 +1,72 @@
+use super::{check_request, BlockDevice, BlockError};
+use crate::sync::Mutex;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// NOTE: a block device on the kernel heap, for tests and for disk images loaded into memory
+pub struct RamDisk {
+    block_size: usize,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    // NOTE: zeroed
+    pub fn new(block_size: usize, block_count: u64) -> RamDisk {
+        RamDisk::from_image(block_size, vec![0; block_size * block_count as usize])
+    }
+
+    // NOTE: a last partial block is padded with zeros
+    pub fn from_image(block_size: usize, mut image: Vec<u8>) -> RamDisk {
+        assert!(
+            block_size.is_power_of_two(),
+            "block size not a power of two"
+        );
+
+        image.resize(image.len().div_ceil(block_size) * block_size, 0);
+
+        RamDisk {
+            block_size,
+            data: Mutex::new(image),
+        }
+    }
+
+    pub fn into_image(self) -> Vec<u8> {
+        self.data.into_inner()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let data = self.data.lock();
+        let count = (data.len() / self.block_size) as u64;
+
+        check_request(self.block_size, count, start, buffer.len())?;
+
+        let offset = start as usize * self.block_size;
+
+        buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, bytes: &[u8]) -> Result<(), BlockError> {
+        let mut data = self.data.lock();
+        let count = (data.len() / self.block_size) as u64;
+
+        check_request(self.block_size, count, start, bytes.len())?;
+
+        let offset = start as usize * self.block_size;
+
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use crate::klog;
+use crate::serial::ComPort;
+use crate::sync::IrqMutex;
+use crate::vga_buffer::{Color, ScreenStorage, Writer, BLANK_SCREEN, WRITER};
+use core::fmt;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+
+// NOTE: output device behind print!/println!, selected at boot through set_backend
+pub trait Console: Send {
+    fn write_str(&mut self, s: &str);
+
+    fn clear(&mut self);
+
+    fn set_color(&mut self, foreground: Color, background: Color);
+
+    // NOTE: (foreground, background) to put back after colored output, None while the
+    // backend's defaults are in use
+    fn color(&self) -> Option<(Color, Color)>;
+
+    fn reset_color(&mut self);
+
+    // NOTE: (rows, columns) of text the backend can show
+    fn dimensions(&self) -> (usize, usize);
+}
+
+// NOTE: lets core::fmt machinery write into any backend
+pub struct ConsoleAdapter<'a>(pub &'a mut dyn Console);
+
+impl fmt::Write for ConsoleAdapter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+
+        Ok(())
+    }
+}
+
+// NOTE: None means the VGA text console (WRITER)
+static BACKEND: IrqMutex<Option<&'static IrqMutex<dyn Console>>> = IrqMutex::new(None);
+
+// NOTE: every lock taken by the printing paths is an IrqMutex, so an interrupt handler
+// printing on the same CPU can never spin on a lock its own code holds
+pub fn set_backend(backend: &'static IrqMutex<dyn Console>) {
+    *BACKEND.lock() = Some(backend);
+}
+
+pub fn reset_backend() {
+    *BACKEND.lock() = None;
+}
+
+pub fn backend() -> &'static IrqMutex<dyn Console> {
+    match *BACKEND.lock() {
+        Some(backend) => backend,
+        None => &*WRITER,
+    }
+}
+
+// NOTE: base of the port print!/println! output is copied to, 0 when not mirrored
+static SERIAL_MIRROR: AtomicU16 = AtomicU16::new(if cfg!(feature = "serial-mirror") {
+    ComPort::Com1 as u16
+} else {
+    0
+});
+
+// NOTE: also on by default with the serial-mirror feature, handy with QEMU's -serial stdio
+pub fn set_serial_mirror(port: Option<ComPort>) {
+    SERIAL_MIRROR.store(port.map_or(0, |port| port as u16), Ordering::Relaxed);
+}
+
+pub fn serial_mirror() -> Option<ComPort> {
+    ComPort::from_base(SERIAL_MIRROR.load(Ordering::Relaxed))
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! print_colored {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::console::_print_colored($fg, $bg, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! println_colored {
+    ($fg:expr, $bg:expr) => ($crate::print_colored!($fg, $bg, "\n"));
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::print_colored!($fg, $bg, "{}\n", format_args!($($arg)*))
+    );
+}
+
+// NOTE: output goes through the lock-free klog ring and is drained right away when possible
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    klog::push(None, args);
+    klog::drain();
+}
+
+#[doc(hidden)]
+pub fn _print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+    klog::push(Some((foreground, background)), args);
+    klog::drain();
+}
+
+// NOTE: console 0 is the kernel log behind WRITER/println!
+pub const CONSOLE_COUNT: usize = 4;
+
+static mut SCREENS: [ScreenStorage; CONSOLE_COUNT - 1] = [BLANK_SCREEN; CONSOLE_COUNT - 1];
+
+lazy_static! {
+    // NOTE: every Writer takes exclusive ownership of one of the SCREENS slots
+    static ref CONSOLES: [IrqMutex<Writer>; CONSOLE_COUNT - 1] = unsafe {
+        [
+            IrqMutex::new(Writer::offscreen(&mut *addr_of_mut!(SCREENS[0]))),
+            IrqMutex::new(Writer::offscreen(&mut *addr_of_mut!(SCREENS[1]))),
+            IrqMutex::new(Writer::offscreen(&mut *addr_of_mut!(SCREENS[2]))),
+        ]
+    };
+}
+
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn console(index: usize) -> &'static IrqMutex<Writer> {
+    match index {
+        0 => &WRITER,
+        index => &CONSOLES[index - 1],
+    }
+}
+
+pub fn active_console() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn switch_console(index: usize) {
+    let current = active_console();
+
+    if index == current || index >= CONSOLE_COUNT {
+        return;
+    }
+
+    // NOTE: locks are always taken in index order to avoid deadlocks
+    let (low, high) = (current.min(index), current.max(index));
+
+    let mut low_writer = console(low).lock();
+    let mut high_writer = console(high).lock();
+
+    low_writer.swap_screen(&mut high_writer);
+    ACTIVE.store(index, Ordering::Relaxed);
+}
+
+#[macro_export]
+macro_rules! console_print {
+    ($index:expr, $($arg:tt)*) => ($crate::console::_print_to($index, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! console_println {
+    ($index:expr) => ($crate::console_print!($index, "\n"));
+    ($index:expr, $($arg:tt)*) => ($crate::console_print!($index, "{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print_to(index: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    console(index).lock().write_fmt(args).unwrap();
+}
+
+#[test_case]
+fn test_switch_console() {
+    console_print!(1, "second console");
+
+    assert!(!console(1).lock().is_visible());
+
+    switch_console(1);
+
+    assert_eq!(active_console(), 1);
+    assert!(console(1).lock().is_visible());
+    assert!(!WRITER.lock().is_visible());
+
+    for (i, c) in "second console".bytes().enumerate() {
+        assert_eq!(console(1).lock().char_at(0, i), c);
+    }
+
+    switch_console(0);
+
+    assert!(WRITER.lock().is_visible());
+    assert!(!console(1).lock().is_visible());
+}
+
+#[cfg(test)]
+struct CountingConsole {
+    bytes: usize,
+}
+
+#[cfg(test)]
+impl Console for CountingConsole {
+    fn write_str(&mut self, s: &str) {
+        self.bytes += s.len();
+    }
+
+    fn clear(&mut self) {
+        self.bytes = 0;
+    }
+
+    fn set_color(&mut self, _foreground: Color, _background: Color) {}
+
+    fn color(&self) -> Option<(Color, Color)> {
+        None
+    }
+
+    fn reset_color(&mut self) {}
+
+    fn dimensions(&self) -> (usize, usize) {
+        (0, 0)
+    }
+}
+
+#[cfg(test)]
+static COUNTING_CONSOLE: IrqMutex<CountingConsole> = IrqMutex::new(CountingConsole { bytes: 0 });
+
+#[test_case]
+fn test_println_uses_registered_backend() {
+    set_backend(&COUNTING_CONSOLE);
+    println!("1234");
+    reset_backend();
+
+    assert_eq!(COUNTING_CONSOLE.lock().bytes, 5);
+}
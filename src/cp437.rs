@@ -0,0 +1,107 @@
+// NOTE: code page 437 is the character set of the VGA ROM font and the embedded PSF font
+const LOW_GLYPHS: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const HIGH_GLYPHS: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+const HOUSE: u8 = 0x7f;
+
+// NOTE: glyph index for characters CP437 has a direct equivalent for
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() && !c.is_ascii_control() {
+        return Some(c as u8);
+    }
+
+    if c == '⌂' {
+        return Some(HOUSE);
+    }
+
+    // NOTE: index 0 of LOW_GLYPHS is NUL and never matches a real glyph
+    if let Some(index) = LOW_GLYPHS.iter().skip(1).position(|glyph| *glyph == c) {
+        return Some(index as u8 + 1);
+    }
+
+    HIGH_GLYPHS
+        .iter()
+        .position(|glyph| *glyph == c)
+        .map(|index| index as u8 + 0x80)
+}
+
+// NOTE: ASCII spelling for common characters CP437 lacks
+fn transliterate(c: char) -> Option<&'static str> {
+    let replacement = match c {
+        '…' => "...",
+        '‘' | '’' | '‚' | '′' => "'",
+        '“' | '”' | '„' | '″' => "\"",
+        '–' | '—' | '‐' | '−' => "-",
+        '×' => "x",
+        '•' => "*",
+        '⇒' => "=>",
+        '€' => "EUR",
+        '©' => "(c)",
+        '®' => "(r)",
+        '™' => "TM",
+        'À' | 'Á' | 'Â' | 'Ã' => "A",
+        'È' | 'Ê' | 'Ë' => "E",
+        'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' => "O",
+        'Ù' | 'Ú' | 'Û' => "U",
+        'ã' => "a",
+        'õ' => "o",
+        'ý' => "y",
+        'Ý' => "Y",
+        'ø' => "o",
+        'Ø' => "O",
+        _ => return None,
+    };
+
+    Some(replacement)
+}
+
+// NOTE: the other way round, e.g. for FAT short names whose OEM code page is CP437 by default
+pub fn to_char(glyph: u8) -> char {
+    match glyph {
+        0..=0x1f => LOW_GLYPHS[glyph as usize],
+        HOUSE => '⌂',
+        0x80.. => HIGH_GLYPHS[glyph as usize - 0x80],
+        _ => glyph as char,
+    }
+}
+
+// NOTE: calls `f` with the glyphs for `c`, unknown characters become '?'
+pub fn encode<F>(c: char, mut f: F)
+where
+    F: FnMut(u8),
+{
+    if let Some(glyph) = from_char(c) {
+        return f(glyph);
+    }
+
+    match transliterate(c) {
+        Some(replacement) => replacement.bytes().for_each(f),
+        None => f(b'?'),
+    }
+}
+
+#[test_case]
+fn test_cp437_mapping() {
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('═'), Some(0xcd));
+    assert_eq!(from_char('→'), Some(0x1a));
+    assert_eq!(from_char('\u{a0}'), Some(0xff));
+    assert_eq!(from_char('\u{1}'), None);
+    assert_eq!(to_char(0x82), 'é');
+    assert_eq!(to_char(b'A'), 'A');
+    assert_eq!(to_char(0x1a), '→');
+}
@@ -0,0 +1,76 @@
+use crate::console::Console;
+use crate::sync::IrqMutex;
+use crate::vga_buffer::Color;
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+// NOTE: QEMU's debug console, every byte written to the port ends up in the `-debugcon` chardev
+// (e.g. `-debugcon stdio`), real hardware ignores it
+const DEBUGCON_PORT: u16 = 0xe9;
+
+// NOTE: needs no initialization, locks nor statics, usable from the very first instruction
+pub fn write_byte(byte: u8) {
+    unsafe { Port::<u8>::new(DEBUGCON_PORT).write(byte) };
+}
+
+pub fn write_str(s: &str) {
+    for byte in s.bytes() {
+        write_byte(byte);
+    }
+}
+
+// NOTE: QEMU reads the port back as 0xe9 when the debug console is present
+pub fn is_present() -> bool {
+    unsafe { Port::<u8>::new(DEBUGCON_PORT).read() == DEBUGCON_PORT as u8 }
+}
+
+pub struct DebugCon;
+
+impl fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_str(s);
+
+        Ok(())
+    }
+}
+
+impl Console for DebugCon {
+    fn write_str(&mut self, s: &str) {
+        write_str(s);
+    }
+
+    fn clear(&mut self) {}
+
+    fn set_color(&mut self, _foreground: Color, _background: Color) {}
+
+    fn color(&self) -> Option<(Color, Color)> {
+        None
+    }
+
+    fn reset_color(&mut self) {}
+
+    fn dimensions(&self) -> (usize, usize) {
+        (24, 80)
+    }
+}
+
+// NOTE: for console::set_backend
+pub static DEBUGCON: IrqMutex<DebugCon> = IrqMutex::new(DebugCon);
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let _ = DebugCon.write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! debugcon_print {
+    ($($arg:tt)*) => ($crate::debugcon::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! debugcon_println {
+    () => ($crate::debugcon_print!("\n"));
+    ($($arg:tt)*) => ($crate::debugcon_print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,312 @@
+use crate::memory::address_space::AddressSpace;
+use crate::memory::paging::MapError;
+use crate::process::Arguments;
+use crate::usermode::{self, UserImage};
+use alloc::collections::BTreeMap;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    // NOTE: only static little endian x86_64 executables are loaded
+    Unsupported,
+    // NOTE: a segment whose sizes or addresses don't make sense, or that isn't in user memory
+    BadSegment,
+    // NOTE: an entry point outside the executable segments
+    BadEntry,
+    Map(MapError),
+}
+
+impl From<MapError> for ElfError {
+    fn from(error: MapError) -> ElfError {
+        ElfError::Map(error)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+}
+
+impl ProgramHeader {
+    // NOTE: readable is implied, x86_64 has no write-only or execute-only pages
+    fn page_flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        if self.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+
+        if self.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+
+        flags
+    }
+}
+
+// NOTE: a validated ELF64 executable, borrowed from the bytes it was parsed from
+#[derive(Debug, Clone, Copy)]
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_headers: usize,
+    program_header_count: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+
+        if &data[..4] != b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+
+        if data[4] != CLASS_64
+            || data[5] != LITTLE_ENDIAN
+            || read_u16(data, 16) != TYPE_EXECUTABLE
+            || read_u16(data, 18) != MACHINE_X86_64
+            || read_u16(data, 54) as usize != PROGRAM_HEADER_SIZE
+        {
+            return Err(ElfError::Unsupported);
+        }
+
+        let program_headers = read_u64(data, 32) as usize;
+        let program_header_count = read_u16(data, 56) as usize;
+        let end = program_header_count
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| size.checked_add(program_headers));
+
+        if end.is_none_or(|end| end > data.len()) {
+            return Err(ElfError::Truncated);
+        }
+
+        let file = ElfFile {
+            data,
+            entry: read_u64(data, 24),
+            program_headers,
+            program_header_count,
+        };
+
+        for header in file.program_headers() {
+            file.check(&header)?;
+        }
+
+        // NOTE: segments are in user memory, so this also keeps entry() from panicking on a
+        // non-canonical address
+        let executable = |segment: &ProgramHeader| {
+            segment.flags & PF_X != 0
+                && (segment.address..segment.address + segment.memory_size).contains(&file.entry)
+        };
+
+        if !file.segments().any(|segment| executable(&segment)) {
+            return Err(ElfError::BadEntry);
+        }
+
+        Ok(file)
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let start = self.program_headers;
+
+        (0..self.program_header_count).map(move |index| {
+            let offset = start + index * PROGRAM_HEADER_SIZE;
+
+            ProgramHeader {
+                kind: read_u32(data, offset),
+                flags: read_u32(data, offset + 4),
+                offset: read_u64(data, offset + 8),
+                address: read_u64(data, offset + 16),
+                file_size: read_u64(data, offset + 32),
+                memory_size: read_u64(data, offset + 40),
+            }
+        })
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers()
+            .filter(|header| header.kind == PT_LOAD)
+    }
+
+    fn check(&self, header: &ProgramHeader) -> Result<(), ElfError> {
+        if header.kind != PT_LOAD {
+            return Ok(());
+        }
+
+        let file_end = header.offset.checked_add(header.file_size);
+        let memory_end = header.address.checked_add(header.memory_size);
+
+        match (file_end, memory_end) {
+            (Some(file_end), Some(memory_end))
+                if header.file_size <= header.memory_size
+                    && file_end <= self.data.len() as u64
+                    && header.address >= usermode::USER_CODE_BASE
+                    && memory_end <= usermode::USER_DATA_END =>
+            {
+                Ok(())
+            }
+            _ => Err(ElfError::BadSegment),
+        }
+    }
+
+    // NOTE: the file bytes of a segment, the rest of it up to memory_size is zero
+    fn contents(&self, header: &ProgramHeader) -> &'a [u8] {
+        &self.data[header.offset as usize..(header.offset + header.file_size) as usize]
+    }
+}
+
+// NOTE: maps the PT_LOAD segments into `space` with their permissions plus a user stack with
+// `arguments` on it. A page shared by two segments gets the union of their permissions
+pub fn load(
+    space: &mut AddressSpace,
+    file: &ElfFile,
+    arguments: &Arguments,
+) -> Result<UserImage, ElfError> {
+    let mut pages: BTreeMap<Page, PageTableFlags> = BTreeMap::new();
+
+    for segment in file.segments().filter(|segment| segment.memory_size > 0) {
+        let first = Page::containing_address(VirtAddr::new(segment.address));
+        let last =
+            Page::containing_address(VirtAddr::new(segment.address + segment.memory_size - 1));
+
+        for page in Page::range_inclusive(first, last) {
+            let flags = pages.entry(page).or_insert(segment.page_flags());
+            let no_execute = flags.contains(PageTableFlags::NO_EXECUTE)
+                && segment.page_flags().contains(PageTableFlags::NO_EXECUTE);
+
+            *flags |= segment.page_flags();
+            flags.set(PageTableFlags::NO_EXECUTE, no_execute);
+        }
+    }
+
+    for (page, flags) in &pages {
+        space.map(*page, *flags)?;
+    }
+
+    // NOTE: fresh frames are zeroed so the bss needs nothing
+    for segment in file.segments() {
+        space.write(VirtAddr::new(segment.address), file.contents(&segment))?;
+    }
+
+    let end = pages
+        .keys()
+        .last()
+        .map_or(usermode::USER_CODE_BASE, |page| {
+            page.start_address().as_u64() + 4096
+        });
+
+    let stack_top = usermode::map_stack(space)?;
+
+    Ok(UserImage {
+        entry: file.entry(),
+        stack_top: arguments.push(space, stack_top, file.entry())?,
+        heap_start: VirtAddr::new(end),
+    })
+}
+
+// NOTE: a static executable with a single segment: the headers, then `code` right after them
+#[cfg(test)]
+pub(crate) fn build_executable(code: &[u8], flags: u32, bss: u64) -> alloc::vec::Vec<u8> {
+    let base = usermode::USER_CODE_BASE;
+    let code_offset = (HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64;
+    let file_size = code_offset + code.len() as u64;
+    let mut data = alloc::vec![0u8; file_size as usize];
+
+    data[..4].copy_from_slice(b"\x7fELF");
+    data[4] = CLASS_64;
+    data[5] = LITTLE_ENDIAN;
+    data[6] = 1;
+    data[16..18].copy_from_slice(&TYPE_EXECUTABLE.to_le_bytes());
+    data[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    data[24..32].copy_from_slice(&(base + code_offset).to_le_bytes());
+    data[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    data[52..54].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    data[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    data[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+    let header = &mut data[HEADER_SIZE..HEADER_SIZE + PROGRAM_HEADER_SIZE];
+
+    header[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    header[4..8].copy_from_slice(&flags.to_le_bytes());
+    header[16..24].copy_from_slice(&base.to_le_bytes());
+    header[32..40].copy_from_slice(&file_size.to_le_bytes());
+    header[40..48].copy_from_slice(&(file_size + bss).to_le_bytes());
+
+    data[code_offset as usize..].copy_from_slice(code);
+
+    data
+}
+
+#[test_case]
+fn test_parse_rejects_bad_files() {
+    let data = build_executable(&[0x0f, 0x0b], PF_R | PF_X, 0);
+
+    assert_eq!(ElfFile::parse(&data[..32]).err(), Some(ElfError::Truncated));
+
+    let mut bad = data.clone();
+
+    bad[0] = 0;
+    assert_eq!(ElfFile::parse(&bad).err(), Some(ElfError::BadMagic));
+
+    let mut kernel = data.clone();
+
+    kernel[HEADER_SIZE + 16..HEADER_SIZE + 24].copy_from_slice(&0x4444_4440_0000u64.to_le_bytes());
+    assert_eq!(ElfFile::parse(&kernel).err(), Some(ElfError::BadSegment));
+
+    let mut non_canonical = data.clone();
+
+    non_canonical[24..32].copy_from_slice(&0x8000_0000_0000u64.to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&non_canonical).err(),
+        Some(ElfError::BadEntry)
+    );
+    assert_eq!(
+        ElfFile::parse(&build_executable(&[0x0f, 0x0b], PF_R | PF_W, 0)).err(),
+        Some(ElfError::BadEntry)
+    );
+
+    let file = ElfFile::parse(&data).unwrap();
+
+    assert_eq!(file.segments().count(), 1);
+    assert_eq!(file.entry().as_u64(), usermode::USER_CODE_BASE + 120);
+}
@@ -0,0 +1,268 @@
+mod font;
+mod writer;
+
+pub use font::{default_font, font_8x8, Font};
+pub use writer::{disable_console, enable_console, FramebufferWriter, FRAMEBUFFER_WRITER};
+
+use crate::vga_buffer::Color;
+use crate::vga_registers::{self, ModeRegisters};
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    Bgr,
+    // NOTE: one byte palette index per pixel, see RGB332_PALETTE
+    Indexed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    pub width: usize,
+    pub height: usize,
+    // NOTE: pixels per scanline, may be larger than width
+    pub stride: usize,
+    pub bytes_per_pixel: usize,
+    pub format: PixelFormat,
+}
+
+impl FrameBufferInfo {
+    pub fn size(&self) -> usize {
+        self.stride * self.height * self.bytes_per_pixel
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb { red, green, blue }
+    }
+
+    // NOTE: 3 bits red, 3 bits green, 2 bits blue
+    fn to_rgb332(self) -> u8 {
+        (self.red & 0xe0) | ((self.green & 0xe0) >> 3) | (self.blue >> 6)
+    }
+}
+
+// NOTE: the standard VGA text palette, indexed by Color
+const VGA_PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+impl From<Color> for Rgb {
+    fn from(color: Color) -> Rgb {
+        VGA_PALETTE[color as usize]
+    }
+}
+
+pub struct FrameBuffer {
+    info: FrameBufferInfo,
+    buffer: &'static mut [u8],
+}
+
+impl FrameBuffer {
+    /// # Safety
+    ///
+    /// `address` must point to `info.size()` bytes of mapped memory that nothing else uses.
+    pub unsafe fn new(address: usize, info: FrameBufferInfo) -> FrameBuffer {
+        FrameBuffer {
+            info,
+            buffer: core::slice::from_raw_parts_mut(address as *mut u8, info.size()),
+        }
+    }
+
+    pub fn info(&self) -> FrameBufferInfo {
+        self.info
+    }
+
+    pub fn width(&self) -> usize {
+        self.info.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.info.height
+    }
+
+    // NOTE: pixels outside of the screen are silently dropped
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+
+        let offset = (y * self.info.stride + x) * self.info.bytes_per_pixel;
+        let pixel = &mut self.buffer[offset..offset + self.info.bytes_per_pixel];
+
+        // NOTE: volatile so writes to video memory are never elided
+        let mut write = |index: usize, value: u8| unsafe {
+            core::ptr::write_volatile(&mut pixel[index], value);
+        };
+
+        match self.info.format {
+            PixelFormat::Rgb => {
+                write(0, color.red);
+                write(1, color.green);
+                write(2, color.blue);
+            }
+            PixelFormat::Bgr => {
+                write(0, color.blue);
+                write(1, color.green);
+                write(2, color.red);
+            }
+            PixelFormat::Indexed => write(0, color.to_rgb332()),
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let x_end = (x + width).min(self.info.width);
+        let y_end = (y + height).min(self.info.height);
+
+        for py in y..y_end {
+            for px in x..x_end {
+                self.put_pixel(px, py, color);
+            }
+        }
+    }
+
+    // NOTE: moves the whole image up by `lines` scanlines and fills the freed rows
+    pub fn scroll_up(&mut self, lines: usize, fill: Rgb) {
+        let lines = lines.min(self.info.height);
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel;
+
+        self.buffer.copy_within(lines * row_bytes.., 0);
+        self.fill_rect(0, self.info.height - lines, self.info.width, lines, fill);
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        self.fill_rect(0, 0, self.info.width, self.info.height, color);
+    }
+
+    // NOTE: `pixels` is a row-major image `width` pixels wide, clipped to the screen
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[Rgb]) {
+        if width == 0 {
+            return;
+        }
+
+        for (row, line) in pixels.chunks(width).enumerate() {
+            for (col, color) in line.iter().enumerate() {
+                self.put_pixel(x + col, y + row, *color);
+            }
+        }
+    }
+}
+
+pub static FRAMEBUFFER: Mutex<Option<FrameBuffer>> = Mutex::new(None);
+
+const VGA_GRAPHICS_ADDRESS: usize = 0xa0000;
+
+// NOTE: standard VGA mode 13h register dump (320x200, 256 colors, linear)
+const MODE_320X200X256: ModeRegisters = ModeRegisters {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0f, 0x00, 0x0e],
+    crtc: [
+        0x5f, 0x4f, 0x50, 0x82, 0x54, 0x80, 0xbf, 0x1f, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x9c, 0x0e, 0x8f, 0x28, 0x40, 0x96, 0xb9, 0xa3, 0xff,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0f, 0xff],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f, 0x41, 0x00, 0x0f, 0x00, 0x00,
+    ],
+};
+
+// NOTE: palette matching Rgb::to_rgb332, scaled to the 6 bit DAC
+fn load_rgb332_palette() {
+    for index in 0..=255u8 {
+        let red = (index >> 5) * 63 / 7;
+        let green = ((index >> 2) & 0x07) * 63 / 7;
+        let blue = (index & 0x03) * 63 / 3;
+
+        vga_registers::dac_write(index, red, green, blue);
+    }
+}
+
+// NOTE: leaves VGA text mode, text written through vga_buffer is no longer displayed
+pub fn init_vga_320x200() {
+    vga_registers::write_mode(&MODE_320X200X256);
+    load_rgb332_palette();
+
+    let info = FrameBufferInfo {
+        width: 320,
+        height: 200,
+        stride: 320,
+        bytes_per_pixel: 1,
+        format: PixelFormat::Indexed,
+    };
+
+    let mut framebuffer = unsafe { FrameBuffer::new(VGA_GRAPHICS_ADDRESS, info) };
+
+    framebuffer.clear(Rgb::BLACK);
+    *FRAMEBUFFER.lock() = Some(framebuffer);
+}
+
+// NOTE: for a framebuffer handed over by the bootloader or set up through VESA
+pub fn init(framebuffer: FrameBuffer) {
+    *FRAMEBUFFER.lock() = Some(framebuffer);
+}
+
+#[cfg(test)]
+const TEST_INFO: FrameBufferInfo = FrameBufferInfo {
+    width: 8,
+    height: 4,
+    stride: 10,
+    bytes_per_pixel: 3,
+    format: PixelFormat::Bgr,
+};
+
+#[cfg(test)]
+static mut TEST_MEMORY: [u8; 10 * 4 * 3] = [0; 10 * 4 * 3];
+
+#[test_case]
+fn test_fill_rect_clips_to_screen() {
+    let address = core::ptr::addr_of_mut!(TEST_MEMORY) as usize;
+    let mut framebuffer = unsafe { FrameBuffer::new(address, TEST_INFO) };
+    let color = Rgb::new(1, 2, 3);
+
+    framebuffer.clear(Rgb::BLACK);
+    framebuffer.fill_rect(6, 2, 10, 10, color);
+
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * TEST_INFO.stride + x) * TEST_INFO.bytes_per_pixel;
+
+        unsafe {
+            (
+                TEST_MEMORY[offset],
+                TEST_MEMORY[offset + 1],
+                TEST_MEMORY[offset + 2],
+            )
+        }
+    };
+
+    assert_eq!(pixel(7, 3), (3, 2, 1));
+    assert_eq!(pixel(5, 3), (0, 0, 0));
+    // NOTE: padding between width and stride is never touched
+    assert_eq!(pixel(8, 3), (0, 0, 0));
+}
@@ -0,0 +1,124 @@
+// NOTE: PC Screen Font (PSF1 and PSF2) bitmap fonts, one bit per pixel, rows padded to bytes
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_HEADER_SIZE: usize = 4;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    pub width: usize,
+    pub height: usize,
+    glyph_count: usize,
+    glyph_size: usize,
+    glyphs: &'static [u8],
+}
+
+fn read_u32(data: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]) as usize
+}
+
+impl Font {
+    pub fn parse(data: &'static [u8]) -> Option<Font> {
+        if data.len() >= PSF1_HEADER_SIZE && data[..2] == PSF1_MAGIC {
+            let glyph_count = if data[2] & PSF1_MODE_512 != 0 {
+                512
+            } else {
+                256
+            };
+            let height = data[3] as usize;
+
+            return Font::new(8, height, glyph_count, height, &data[PSF1_HEADER_SIZE..]);
+        }
+
+        if data.len() >= PSF2_HEADER_SIZE && data[..4] == PSF2_MAGIC {
+            let header_size = read_u32(data, 8);
+            let glyph_count = read_u32(data, 16);
+            let glyph_size = read_u32(data, 20);
+            let height = read_u32(data, 24);
+            let width = read_u32(data, 28);
+
+            return Font::new(
+                width,
+                height,
+                glyph_count,
+                glyph_size,
+                data.get(header_size..)?,
+            );
+        }
+
+        None
+    }
+
+    fn new(
+        width: usize,
+        height: usize,
+        glyph_count: usize,
+        glyph_size: usize,
+        glyphs: &'static [u8],
+    ) -> Option<Font> {
+        if glyphs.len() < glyph_count * glyph_size || glyph_size < height * width.div_ceil(8) {
+            return None;
+        }
+
+        Some(Font {
+            width,
+            height,
+            glyph_count,
+            glyph_size,
+            glyphs,
+        })
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    // NOTE: glyphs are indexed by code page 437 value, unknown ones fall back to '?'
+    pub fn glyph(&self, index: u8) -> &'static [u8] {
+        let index = if (index as usize) < self.glyph_count {
+            index as usize
+        } else {
+            b'?' as usize
+        };
+        let start = index * self.glyph_size;
+
+        &self.glyphs[start..start + self.glyph_size]
+    }
+
+    pub fn is_set(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let byte = glyph[y * self.bytes_per_row() + x / 8];
+
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+static VGA_8X16: &[u8] = include_bytes!("vga8x16.psf");
+
+pub fn default_font() -> Font {
+    Font::parse(VGA_8X16).expect("embedded PSF font is malformed")
+}
+
+static VGA_8X8: &[u8] = include_bytes!("vga8x8.psf");
+
+// NOTE: used by the 80x50 text mode
+pub fn font_8x8() -> Font {
+    Font::parse(VGA_8X8).expect("embedded PSF font is malformed")
+}
+
+#[test_case]
+fn test_default_font_glyph() {
+    let font = default_font();
+    let glyph = font.glyph(b'A');
+
+    assert_eq!((font.width, font.height), (8, 16));
+    // NOTE: the apex of the 'A' glyph is a single pixel in column 3
+    assert!(font.is_set(glyph, 3, 6));
+    assert!(!font.is_set(glyph, 0, 6));
+}
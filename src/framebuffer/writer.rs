@@ -0,0 +1,165 @@
+use super::font::{default_font, Font};
+use super::{Rgb, FRAMEBUFFER};
+use crate::console::{self, Console};
+use crate::cp437;
+use crate::sync::IrqMutex;
+use crate::vga_buffer::Color;
+use core::fmt;
+use lazy_static::lazy_static;
+
+const DEFAULT_FOREGROUND: Color = Color::YELLOW;
+const DEFAULT_BACKGROUND: Color = Color::BLACK;
+
+pub struct FramebufferWriter {
+    font: Font,
+    row: usize,
+    col: usize,
+    foreground: Rgb,
+    background: Rgb,
+}
+
+impl fmt::Write for FramebufferWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+
+        Ok(())
+    }
+}
+
+impl FramebufferWriter {
+    pub fn new(font: Font) -> FramebufferWriter {
+        FramebufferWriter {
+            font,
+            row: 0,
+            col: 0,
+            foreground: DEFAULT_FOREGROUND.into(),
+            background: DEFAULT_BACKGROUND.into(),
+        }
+    }
+
+    pub fn set_color(&mut self, foreground: Rgb, background: Rgb) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    // NOTE: text grid derived from the framebuffer resolution and the font size
+    pub fn dimensions(&self) -> (usize, usize) {
+        match FRAMEBUFFER.lock().as_ref() {
+            Some(framebuffer) => (
+                framebuffer.height() / self.font.height,
+                framebuffer.width() / self.font.width,
+            ),
+            None => (0, 0),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(framebuffer) = FRAMEBUFFER.lock().as_mut() {
+            framebuffer.clear(self.background);
+        }
+
+        self.row = 0;
+        self.col = 0;
+    }
+
+    fn write_string(&mut self, s: &str) {
+        let (rows, cols) = self.dimensions();
+
+        if rows == 0 || cols == 0 {
+            return;
+        }
+
+        for c in s.chars() {
+            if c == '\n' {
+                self.new_line(rows);
+
+                continue;
+            }
+
+            // NOTE: the PSF font is laid out in code page 437 order
+            cp437::encode(c, |glyph| {
+                if self.col >= cols {
+                    self.new_line(rows);
+                }
+
+                self.draw_glyph(glyph);
+                self.col += 1;
+            });
+        }
+    }
+
+    fn new_line(&mut self, rows: usize) {
+        self.col = 0;
+
+        if self.row + 1 < rows {
+            self.row += 1;
+        } else if let Some(framebuffer) = FRAMEBUFFER.lock().as_mut() {
+            framebuffer.scroll_up(self.font.height, self.background);
+        }
+    }
+
+    fn draw_glyph(&self, character: u8) {
+        let mut framebuffer = FRAMEBUFFER.lock();
+        let Some(framebuffer) = framebuffer.as_mut() else {
+            return;
+        };
+        let glyph = self.font.glyph(character);
+        let (origin_x, origin_y) = (self.col * self.font.width, self.row * self.font.height);
+
+        for y in 0..self.font.height {
+            for x in 0..self.font.width {
+                let color = if self.font.is_set(glyph, x, y) {
+                    self.foreground
+                } else {
+                    self.background
+                };
+
+                framebuffer.put_pixel(origin_x + x, origin_y + y, color);
+            }
+        }
+    }
+}
+
+impl Console for FramebufferWriter {
+    fn write_str(&mut self, s: &str) {
+        self.write_string(s);
+    }
+
+    fn clear(&mut self) {
+        FramebufferWriter::clear(self);
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        FramebufferWriter::set_color(self, foreground.into(), background.into());
+    }
+
+    // NOTE: None too for colors set as RGB that aren't in the palette
+    fn color(&self) -> Option<(Color, Color)> {
+        let find = |rgb: Rgb| Color::all().find(|&color| Rgb::from(color) == rgb);
+
+        Some((find(self.foreground)?, find(self.background)?))
+    }
+
+    fn reset_color(&mut self) {
+        Console::set_color(self, DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        FramebufferWriter::dimensions(self)
+    }
+}
+
+lazy_static! {
+    pub static ref FRAMEBUFFER_WRITER: IrqMutex<FramebufferWriter> =
+        IrqMutex::new(FramebufferWriter::new(default_font()));
+}
+
+// NOTE: once enabled, print!/println! render on the framebuffer instead of VGA text mode
+pub fn enable_console() {
+    FRAMEBUFFER_WRITER.lock().clear();
+    console::set_backend(&*FRAMEBUFFER_WRITER);
+}
+
+pub fn disable_console() {
+    console::reset_backend();
+}
@@ -0,0 +1,508 @@
+mod devfs;
+mod ext2;
+mod fat;
+mod file;
+mod path;
+mod ramfs;
+mod tarfs;
+
+pub use devfs::{Console, DevFs, Null, Zero};
+pub use ext2::Ext2;
+pub use fat::Fat32;
+pub use file::{OpenFile, OpenFlags, SeekFrom};
+pub use ramfs::RamFs;
+pub use tarfs::{TarError, TarFs};
+
+use crate::block::BlockError;
+use crate::sync::IrqMutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// NOTE: of a single component, and of a whole path in bytes
+pub const MAX_NAME: usize = 255;
+pub const MAX_PATH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotDirectory,
+    IsDirectory,
+    Exists,
+    // NOTE: removing a directory that still has entries
+    NotEmpty,
+    // NOTE: the filesystem or the inode can't be changed
+    ReadOnly,
+    // NOTE: the open file wasn't opened for this
+    AccessDenied,
+    // NOTE: relative, or with a NUL in it
+    InvalidPath,
+    NameTooLong,
+    // NOTE: seeking before the start
+    BadOffset,
+    // NOTE: a rename between two mounts
+    CrossDevice,
+    // NOTE: a mount point, or something mounted below it
+    Busy,
+    NoSpace,
+    // NOTE: the device behind the filesystem failed
+    Io,
+    // NOTE: the filesystem's structures on the device make no sense
+    Corrupt,
+    Unsupported,
+    // NOTE: a blocking read gave up for a signal
+    Interrupted,
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> FsError {
+        match error {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            _ => FsError::Io,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeKind {
+    File,
+    Directory,
+    Device,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: InodeKind,
+    // NOTE: in bytes, 0 for directories and devices
+    pub size: u64,
+    // NOTE: unique within its filesystem, 0 if it has no number
+    pub inode: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: InodeKind,
+}
+
+// NOTE: a file, directory or device of some filesystem. The defaults are what an inode of the
+// wrong kind or on a read only filesystem answers, each filesystem overrides what it supports.
+// Offsets are for files, devices are free to ignore them
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    // NOTE: lets a filesystem recognize its own inodes, for rename
+    fn as_any(&self) -> &dyn Any;
+
+    // NOTE: 0 at and past the end
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    // NOTE: writing past the end grows the file, the gap reads as zeros
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    // NOTE: never given `.` or `..`, the VFS resolves those itself
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    // NOTE: without `.` and `..`
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn create(&self, _name: &str, _kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    // NOTE: directories only once they are empty
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    // NOTE: `to_directory` belongs to the same filesystem, the VFS makes sure of that. Replaces
+    // a file at `to`, but not a directory
+    fn rename(&self, _from: &str, _to_directory: &dyn Inode, _to: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    // NOTE: writes back whatever is cached
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+struct Mount {
+    id: u64,
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+// NOTE: an inode as reached through the namespace, with the canonical path it was found under
+// and the mount it belongs to. A mount point's dentry has the mounted root as its inode
+#[derive(Clone)]
+pub struct Dentry {
+    path: String,
+    inode: Arc<dyn Inode>,
+    mount: u64,
+}
+
+impl Dentry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // NOTE: "/" for the root
+    pub fn name(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(index) if self.path.len() > 1 => &self.path[index + 1..],
+            _ => "/",
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+}
+
+impl fmt::Debug for Dentry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dentry")
+            .field("path", &self.path)
+            .field("mount", &self.mount)
+            .finish()
+    }
+}
+
+// NOTE: a namespace of mounted filesystems. Paths are absolute and resolved on their text, see
+// path::components; there is no caching, every lookup walks from the root
+pub struct Vfs {
+    mounts: IrqMutex<Vec<Mount>>,
+    next_id: AtomicU64,
+}
+
+static VFS: Vfs = Vfs::new();
+
+// NOTE: the one processes see
+pub fn vfs() -> &'static Vfs {
+    &VFS
+}
+
+// NOTE: a ramfs as the root until there are disks, with the devices on /dev and /tmp for
+// scratch files
+pub fn init() {
+    let vfs = vfs();
+
+    vfs.mount("/", Arc::new(RamFs::new()))
+        .expect("root filesystem already mounted");
+
+    for directory in ["/dev", "/tmp"] {
+        vfs.mkdir(directory).expect("root filesystem not writable");
+    }
+
+    vfs.mount("/dev", Arc::new(DevFs::new()))
+        .expect("/dev already mounted");
+}
+
+impl Vfs {
+    pub const fn new() -> Vfs {
+        Vfs {
+            mounts: IrqMutex::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    // NOTE: "/" first, then on an existing directory; later mounts can only go on a path that
+    // isn't mounted on already
+    pub fn mount(&self, path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+        let path = path::normalize(path)?;
+
+        if path != "/" && self.lookup(&path)?.metadata().kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let mut mounts = self.mounts.lock();
+
+        if mounts.iter().any(|mount| mount.path == path) {
+            return Err(FsError::Busy);
+        }
+
+        if path != "/" && mounts.is_empty() {
+            return Err(FsError::NotFound);
+        }
+
+        mounts.push(Mount {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            path,
+            fs,
+        });
+
+        Ok(())
+    }
+
+    // NOTE: refused while something is mounted below, open files keep the filesystem alive
+    pub fn unmount(&self, path: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+        let path = path::normalize(path)?;
+        let mut mounts = self.mounts.lock();
+        let index = mounts
+            .iter()
+            .position(|mount| mount.path == path)
+            .ok_or(FsError::NotFound)?;
+        let prefix = match path.as_str() {
+            "/" => String::from("/"),
+            path => String::from(path) + "/",
+        };
+
+        if mounts
+            .iter()
+            .any(|mount| mount.path != path && mount.path.starts_with(&prefix))
+        {
+            return Err(FsError::Busy);
+        }
+
+        Ok(mounts.remove(index).fs)
+    }
+
+    // NOTE: each mounted filesystem, stops at the first that fails
+    pub fn sync(&self) -> Result<(), FsError> {
+        let filesystems: Vec<_> = self
+            .mounts
+            .lock()
+            .iter()
+            .map(|mount| mount.fs.clone())
+            .collect();
+
+        filesystems.iter().try_for_each(|fs| fs.sync())
+    }
+
+    // NOTE: the filesystems are called without the table locked, it's copied instead, since they
+    // may block on a device
+    fn walk(&self, components: &[&str]) -> Result<Dentry, FsError> {
+        let mounts: Vec<(String, u64, Arc<dyn FileSystem>)> = self
+            .mounts
+            .lock()
+            .iter()
+            .map(|mount| (mount.path.clone(), mount.id, mount.fs.clone()))
+            .collect();
+        let mounted = |path: &str| mounts.iter().find(|(mounted, ..)| mounted == path);
+        let (_, id, fs) = mounted("/").ok_or(FsError::NotFound)?;
+        let mut dentry = Dentry {
+            path: String::from("/"),
+            inode: fs.root(),
+            mount: *id,
+        };
+
+        for (depth, name) in components.iter().enumerate() {
+            if dentry.metadata().kind != InodeKind::Directory {
+                return Err(FsError::NotDirectory);
+            }
+
+            let inode = dentry.inode.lookup(name)?;
+            let path = path::join(&components[..=depth]);
+
+            dentry = match mounted(&path) {
+                Some((_, id, fs)) => Dentry {
+                    path,
+                    inode: fs.root(),
+                    mount: *id,
+                },
+                None => Dentry {
+                    path,
+                    inode,
+                    mount: dentry.mount,
+                },
+            };
+        }
+
+        Ok(dentry)
+    }
+
+    pub fn lookup(&self, path: &str) -> Result<Dentry, FsError> {
+        self.walk(&path::components(path)?)
+    }
+
+    // NOTE: the directory `path` is in and its last component, which has to be there
+    fn lookup_parent<'a>(&self, path: &'a str) -> Result<(Dentry, &'a str), FsError> {
+        let mut components = path::components(path)?;
+        let name = components.pop().ok_or(FsError::Busy)?;
+        let parent = self.walk(&components)?;
+
+        match parent.metadata().kind {
+            InodeKind::Directory => Ok((parent, name)),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn is_mount_point(&self, path: &str) -> bool {
+        self.mounts.lock().iter().any(|mount| mount.path == path)
+    }
+
+    pub fn open(&self, path: &str, flags: OpenFlags) -> Result<Arc<OpenFile>, FsError> {
+        let dentry = match self.lookup(path) {
+            Err(FsError::NotFound) if flags.create => self.create(path, InodeKind::File)?,
+            result => result?,
+        };
+
+        OpenFile::open(dentry, flags).map(Arc::new)
+    }
+
+    pub fn create(&self, path: &str, kind: InodeKind) -> Result<Dentry, FsError> {
+        let (parent, name) = self.lookup_parent(path)?;
+
+        if parent.inode.lookup(name).is_ok() {
+            return Err(FsError::Exists);
+        }
+
+        let inode = parent.inode.create(name, kind)?;
+
+        Ok(Dentry {
+            path: path::child(&parent.path, name),
+            inode,
+            mount: parent.mount,
+        })
+    }
+
+    pub fn mkdir(&self, path: &str) -> Result<Dentry, FsError> {
+        self.create(path, InodeKind::Directory)
+    }
+
+    // NOTE: mount points stay until they are unmounted
+    pub fn unlink(&self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.lookup_parent(path)?;
+
+        if self.is_mount_point(&path::normalize(path)?) {
+            return Err(FsError::Busy);
+        }
+
+        parent.inode.unlink(name)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), FsError> {
+        let (from_parent, from_name) = self.lookup_parent(from)?;
+        let (to_parent, to_name) = self.lookup_parent(to)?;
+        let (from, to) = (path::normalize(from)?, path::normalize(to)?);
+
+        if self.is_mount_point(&from) || self.is_mount_point(&to) {
+            return Err(FsError::Busy);
+        }
+
+        if from_parent.mount != to_parent.mount {
+            return Err(FsError::CrossDevice);
+        }
+
+        // NOTE: a directory can't go inside itself, paths are canonical so the text tells
+        if to.starts_with(&(from + "/")) {
+            return Err(FsError::InvalidPath);
+        }
+
+        from_parent
+            .inode
+            .rename(from_name, to_parent.inode.as_ref(), to_name)
+    }
+
+    // NOTE: a mount point lists what the mounted filesystem has
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        self.lookup(path)?.inode.entries()
+    }
+}
+
+impl Default for Vfs {
+    fn default() -> Vfs {
+        Vfs::new()
+    }
+}
+
+#[test_case]
+fn test_mount_and_resolve() {
+    let vfs = Vfs::new();
+
+    assert_eq!(vfs.lookup("/").err(), Some(FsError::NotFound));
+    assert_eq!(
+        vfs.mount("/dev", Arc::new(DevFs::new())).err(),
+        Some(FsError::NotFound)
+    );
+    assert_eq!(vfs.mount("/", Arc::new(DevFs::new())), Ok(()));
+    assert_eq!(
+        vfs.mount("/", Arc::new(DevFs::new())).err(),
+        Some(FsError::Busy)
+    );
+    assert_eq!(
+        vfs.mount("/null", Arc::new(DevFs::new())).err(),
+        Some(FsError::NotDirectory)
+    );
+
+    let null = vfs.lookup("/./zero/../null").unwrap();
+
+    assert_eq!((null.path(), null.name()), ("/null", "null"));
+    assert_eq!(null.metadata().kind, InodeKind::Device);
+    assert_eq!(vfs.lookup("/null/x").err(), Some(FsError::NotDirectory));
+    assert_eq!(vfs.lookup("/missing").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.lookup("null").err(), Some(FsError::InvalidPath));
+    assert_eq!(vfs.lookup("/..").unwrap().name(), "/");
+    assert!(vfs
+        .read_dir("/")
+        .unwrap()
+        .iter()
+        .any(|entry| entry.name == "zero" && entry.kind == InodeKind::Device));
+    assert_eq!(vfs.unlink("/null"), Err(FsError::ReadOnly));
+    assert_eq!(vfs.unlink("/"), Err(FsError::Busy));
+    assert_eq!(
+        vfs.create("/new", InodeKind::File).err(),
+        Some(FsError::ReadOnly)
+    );
+    assert!(vfs.unmount("/").is_ok());
+    assert_eq!(vfs.lookup("/null").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_open_devices() {
+    let vfs = Vfs::new();
+
+    vfs.mount("/", Arc::new(DevFs::new())).unwrap();
+
+    let zero = vfs.open("/zero", OpenFlags::READ_ONLY).unwrap();
+    let mut buffer = [1; 8];
+
+    assert_eq!(zero.read(&mut buffer), Ok(8));
+    assert_eq!(buffer, [0; 8]);
+    assert_eq!(zero.write(b"no"), Err(FsError::AccessDenied));
+
+    let null = vfs.open("/null", OpenFlags::READ_WRITE).unwrap();
+
+    assert_eq!(null.write(b"gone"), Ok(4));
+    assert_eq!(null.read(&mut buffer), Ok(0));
+
+    let root = vfs.open("/", OpenFlags::READ_ONLY).unwrap();
+    let mut names = Vec::new();
+
+    while let Some(entry) = root.read_dir().unwrap() {
+        names.push(entry.name);
+    }
+
+    assert_eq!(names, ["console", "null", "zero"]);
+    assert_eq!(root.read(&mut buffer), Err(FsError::IsDirectory));
+    assert_eq!(
+        vfs.open("/", OpenFlags::READ_WRITE).err(),
+        Some(FsError::IsDirectory)
+    );
+}
@@ -0,0 +1,178 @@
+use super::{DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata, MAX_NAME};
+use crate::sync::IrqMutex;
+use crate::tty;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+
+const DEVICE: Metadata = Metadata {
+    kind: InodeKind::Device,
+    size: 0,
+    inode: 0,
+};
+
+// NOTE: reads nothing, swallows everything
+pub struct Null;
+
+impl Inode for Null {
+    fn metadata(&self) -> Metadata {
+        DEVICE
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        Ok(data.len())
+    }
+}
+
+// NOTE: reads zeros, swallows everything
+pub struct Zero;
+
+impl Inode for Zero {
+    fn metadata(&self) -> Metadata {
+        DEVICE
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        buffer.fill(0);
+
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        Ok(data.len())
+    }
+}
+
+// NOTE: the console TTY, the same one descriptors 0 to 2 start on
+pub struct Console;
+
+impl Inode for Console {
+    fn metadata(&self) -> Metadata {
+        DEVICE
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        tty::console()
+            .read(buffer)
+            .map_err(|_| FsError::Interrupted)
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        Ok(tty::console().write(data))
+    }
+}
+
+struct DevDirectory {
+    devices: IrqMutex<BTreeMap<String, Arc<dyn Inode>>>,
+}
+
+impl Inode for DevDirectory {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: InodeKind::Directory,
+            size: 0,
+            inode: 1,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.devices
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(FsError::NotFound)
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .devices
+            .lock()
+            .iter()
+            .map(|(name, device)| DirEntry {
+                name: name.clone(),
+                kind: device.metadata().kind,
+            })
+            .collect())
+    }
+}
+
+// NOTE: a flat directory of devices, drivers add theirs with register. Mounted on /dev, the
+// device files are the inodes themselves, there is nothing to create or remove through paths
+pub struct DevFs {
+    root: Arc<DevDirectory>,
+}
+
+impl DevFs {
+    // NOTE: with null, zero and console
+    pub fn new() -> DevFs {
+        let fs = DevFs {
+            root: Arc::new(DevDirectory {
+                devices: IrqMutex::new(BTreeMap::new()),
+            }),
+        };
+
+        for (name, device) in [
+            ("null", Arc::new(Null) as Arc<dyn Inode>),
+            ("zero", Arc::new(Zero)),
+            ("console", Arc::new(Console)),
+        ] {
+            let _ = fs.register(name, device);
+        }
+
+        fs
+    }
+
+    pub fn register(&self, name: &str, device: Arc<dyn Inode>) -> Result<(), FsError> {
+        if name.is_empty() || name.len() > MAX_NAME || name.contains(['/', '\0']) {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut devices = self.root.devices.lock();
+
+        if devices.contains_key(name) {
+            return Err(FsError::Exists);
+        }
+
+        devices.insert(String::from(name), device);
+
+        Ok(())
+    }
+}
+
+impl Default for DevFs {
+    fn default() -> DevFs {
+        DevFs::new()
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
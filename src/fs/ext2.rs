@@ -0,0 +1,432 @@
+use super::{DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata};
+use crate::block::{self, BlockDevice};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INODE: u32 = 2;
+// NOTE: revision 0 has fixed 128 byte inodes and no feature flags
+const GOOD_OLD_INODE_SIZE: u64 = 128;
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+
+// NOTE: file types in directory entries, and the only incompatible features that don't change
+// how anything read here is laid out. Compatible and read only compatible ones can be ignored
+// by a driver that doesn't write
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+const MODE_TYPE: u16 = 0xf000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_FILE: u16 = 0x8000;
+const TYPE_FILE: u8 = 1;
+const TYPE_DIRECTORY: u8 = 2;
+
+// NOTE: i_block has 12 direct blocks, then a single, double and triple indirect one
+const DIRECT_BLOCKS: u64 = 12;
+const BLOCK_POINTERS: usize = 15;
+const DIRECTORY_HEADER: usize = 8;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// NOTE: the geometry from the superblock, in bytes where that's handier
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    block_size: u64,
+    block_count: u64,
+    inode_count: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    // NOTE: device offset of each group's inode table
+    inode_tables: Vec<u64>,
+    filetype: bool,
+}
+
+impl Volume {
+    fn new(device: Arc<dyn BlockDevice>) -> Result<Volume, FsError> {
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+
+        block::read_bytes(device.as_ref(), SUPERBLOCK_OFFSET, &mut superblock)?;
+
+        let inode_count = u32_at(&superblock, 0);
+        let block_count = u32_at(&superblock, 4) as u64;
+        let first_data_block = u32_at(&superblock, 20) as u64;
+        let log_block_size = u32_at(&superblock, 24);
+        let blocks_per_group = u32_at(&superblock, 32) as u64;
+        let inodes_per_group = u32_at(&superblock, 40);
+        let revision = u32_at(&superblock, 76);
+
+        if u16_at(&superblock, 56) != MAGIC || log_block_size > 6 {
+            return Err(FsError::Corrupt);
+        }
+
+        let block_size = 1024 << log_block_size;
+        let (inode_size, incompat) = match revision {
+            0 => (GOOD_OLD_INODE_SIZE, 0),
+            _ => (u16_at(&superblock, 88) as u64, u32_at(&superblock, 96)),
+        };
+
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(FsError::Unsupported);
+        }
+
+        let device_size = device.block_count() * device.block_size() as u64;
+
+        if blocks_per_group == 0
+            || inodes_per_group == 0
+            || first_data_block >= block_count
+            || !inode_size.is_power_of_two()
+            || !(GOOD_OLD_INODE_SIZE..=block_size).contains(&inode_size)
+            || block_count * block_size > device_size
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let groups = (block_count - first_data_block).div_ceil(blocks_per_group);
+
+        if groups * inodes_per_group as u64 != inode_count as u64 {
+            return Err(FsError::Corrupt);
+        }
+
+        // NOTE: the descriptor table is in the block after the superblock's
+        let mut descriptors = vec![0; (groups * GROUP_DESCRIPTOR_SIZE) as usize];
+
+        block::read_bytes(
+            device.as_ref(),
+            (first_data_block + 1) * block_size,
+            &mut descriptors,
+        )?;
+
+        let table_blocks = (inodes_per_group as u64 * inode_size).div_ceil(block_size);
+        let inode_tables = descriptors
+            .chunks_exact(GROUP_DESCRIPTOR_SIZE as usize)
+            .map(|descriptor| match u32_at(descriptor, 8) as u64 {
+                table if table != 0 && table + table_blocks <= block_count => {
+                    Ok(table * block_size)
+                }
+                _ => Err(FsError::Corrupt),
+            })
+            .collect::<Result<Vec<u64>, FsError>>()?;
+
+        Ok(Volume {
+            device,
+            block_size,
+            block_count,
+            inode_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+        })
+    }
+
+    // NOTE: `buffer.len()` bytes from `offset` into block `block`
+    fn read(&self, block: u64, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        if block >= self.block_count {
+            return Err(FsError::Corrupt);
+        }
+
+        block::read_bytes(
+            self.device.as_ref(),
+            block * self.block_size + offset,
+            buffer,
+        )?;
+
+        Ok(())
+    }
+
+    // NOTE: regular files and directories, None for whatever else the number is
+    fn inode(self: &Arc<Volume>, number: u32) -> Result<Option<Ext2Inode>, FsError> {
+        if number == 0 || number > self.inode_count {
+            return Err(FsError::Corrupt);
+        }
+
+        let group = ((number - 1) / self.inodes_per_group) as usize;
+        let index = ((number - 1) % self.inodes_per_group) as u64;
+        let mut raw = [0; GOOD_OLD_INODE_SIZE as usize];
+
+        block::read_bytes(
+            self.device.as_ref(),
+            self.inode_tables[group] + index * self.inode_size,
+            &mut raw,
+        )?;
+
+        // NOTE: the high half of the size is only a file's, directories had an ACL block there
+        let (kind, size) = match u16_at(&raw, 0) & MODE_TYPE {
+            MODE_FILE => (
+                InodeKind::File,
+                (u32_at(&raw, 108) as u64) << 32 | u32_at(&raw, 4) as u64,
+            ),
+            MODE_DIRECTORY => (InodeKind::Directory, u32_at(&raw, 4) as u64),
+            _ => return Ok(None),
+        };
+        let mut blocks = [0; BLOCK_POINTERS];
+
+        for (index, block) in blocks.iter_mut().enumerate() {
+            *block = u32_at(&raw, 40 + index * 4);
+        }
+
+        Ok(Some(Ext2Inode {
+            volume: self.clone(),
+            number,
+            kind,
+            size,
+            blocks,
+        }))
+    }
+}
+
+// NOTE: read when it is looked up, nothing changes it after that
+struct Ext2Inode {
+    volume: Arc<Volume>,
+    number: u32,
+    kind: InodeKind,
+    size: u64,
+    blocks: [u32; BLOCK_POINTERS],
+}
+
+impl Ext2Inode {
+    // NOTE: the device block with block `index` of the data, 0 for a hole
+    fn block(&self, index: u64) -> Result<u64, FsError> {
+        let pointers = self.volume.block_size / 4;
+        let mut index = index;
+
+        if index < DIRECT_BLOCKS {
+            return Ok(self.blocks[index as usize] as u64);
+        }
+
+        index -= DIRECT_BLOCKS;
+
+        let mut span = 1;
+
+        for level in 0..3 {
+            span *= pointers;
+
+            if index >= span {
+                index -= span;
+
+                continue;
+            }
+
+            let mut block = self.blocks[DIRECT_BLOCKS as usize + level];
+
+            // NOTE: each level down picks the pointer for a smaller span of the data
+            while block != 0 && span > 1 {
+                let mut pointer = [0; 4];
+
+                span /= pointers;
+                self.volume
+                    .read(block as u64, index / span * 4, &mut pointer)?;
+                block = u32::from_le_bytes(pointer);
+                index %= span;
+            }
+
+            return Ok(block as u64);
+        }
+
+        Err(FsError::Corrupt)
+    }
+
+    fn read_data(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let block_size = self.volume.block_size;
+        let len = buffer.len().min(self.size.saturating_sub(offset) as usize);
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done as u64;
+            let within = position % block_size;
+            let piece = (len - done).min((block_size - within) as usize);
+            let buffer = &mut buffer[done..done + piece];
+
+            match self.block(position / block_size)? {
+                0 => buffer.fill(0),
+                block => self.volume.read(block, within, buffer)?,
+            }
+
+            done += piece;
+        }
+
+        Ok(len)
+    }
+
+    // NOTE: name, inode and file type of each entry but "." and "..". The file type is 0 when
+    // the volume doesn't keep them
+    fn records(&self) -> Result<Vec<(String, u32, u8)>, FsError> {
+        let block_size = self.volume.block_size as usize;
+
+        // NOTE: directories are whole blocks, and none is bigger than the volume
+        if !self.size.is_multiple_of(block_size as u64)
+            || self.size > self.volume.block_count * block_size as u64
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut data = vec![0; self.size as usize];
+        let mut records = Vec::new();
+
+        self.read_data(0, &mut data)?;
+
+        for block in data.chunks(block_size) {
+            let mut offset = 0;
+
+            while offset + DIRECTORY_HEADER <= block.len() {
+                let record = &block[offset..];
+                let inode = u32_at(record, 0);
+                let len = u16_at(record, 4) as usize;
+                let name_len = record[6] as usize;
+
+                if len < DIRECTORY_HEADER
+                    || !len.is_multiple_of(4)
+                    || len > record.len()
+                    || DIRECTORY_HEADER + name_len > len
+                {
+                    return Err(FsError::Corrupt);
+                }
+
+                let name = &record[DIRECTORY_HEADER..DIRECTORY_HEADER + name_len];
+
+                // NOTE: inode 0 marks a deleted entry or the free space at the end of a block
+                if inode != 0 && name != b"." && name != b".." {
+                    let file_type = match self.volume.filetype {
+                        true => record[7],
+                        false => 0,
+                    };
+
+                    records.push((String::from_utf8_lossy(name).into_owned(), inode, file_type));
+                }
+
+                offset += len;
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn check_directory(&self) -> Result<(), FsError> {
+        match self.kind {
+            InodeKind::Directory => Ok(()),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        let size = match self.kind {
+            InodeKind::File => self.size,
+            _ => 0,
+        };
+
+        Metadata {
+            kind: self.kind,
+            size,
+            inode: self.number as u64,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        match self.kind {
+            InodeKind::File => self.read_data(offset, buffer),
+            _ => Err(FsError::IsDirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+
+        let (_, number, _) = self
+            .records()?
+            .into_iter()
+            .find(|(entry, _, _)| entry == name)
+            .ok_or(FsError::NotFound)?;
+
+        match self.volume.inode(number)? {
+            Some(inode) => Ok(Arc::new(inode)),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_directory()?;
+
+        let mut entries = Vec::new();
+
+        for (name, number, file_type) in self.records()? {
+            let kind = match file_type {
+                TYPE_FILE => Some(InodeKind::File),
+                TYPE_DIRECTORY => Some(InodeKind::Directory),
+                0 => self.volume.inode(number)?.map(|inode| inode.kind),
+                _ => None,
+            };
+
+            if let Some(kind) = kind {
+                entries.push(DirEntry { name, kind });
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+// NOTE: a revision 0 or 1 ext2 volume served read only. Regular files and directories are
+// kept, symlinks, devices, fifos and sockets are skipped
+pub struct Ext2 {
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2 {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Ext2, FsError> {
+        let volume = Arc::new(Volume::new(device)?);
+
+        match volume.inode(ROOT_INODE)? {
+            Some(root) if root.kind == InodeKind::Directory => Ok(Ext2 {
+                root: Arc::new(root),
+            }),
+            _ => Err(FsError::Corrupt),
+        }
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+#[test_case]
+fn test_rejects_bad_volumes() {
+    use crate::block::RamDisk;
+
+    let image = include_bytes!("../../tests/data/ext2.img");
+    let mount = |image: Vec<u8>| Ext2::new(Arc::new(RamDisk::from_image(1024, image))).err();
+    let mut magic = image.to_vec();
+    let mut extents = image.to_vec();
+
+    magic[1024 + 56] = 0;
+    // NOTE: ext4's extents
+    extents[1024 + 96] |= 0x40;
+
+    assert_eq!(mount(image.to_vec()), None);
+    assert_eq!(mount(magic), Some(FsError::Corrupt));
+    assert_eq!(mount(extents), Some(FsError::Unsupported));
+    assert_eq!(mount(image[..64 * 1024].to_vec()), Some(FsError::Corrupt));
+}
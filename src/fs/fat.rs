@@ -0,0 +1,942 @@
+mod dir;
+mod volume;
+
+use self::dir::{RawEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ENTRY_SIZE, FREE_ENTRY};
+use self::volume::Volume;
+use super::{DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata};
+use crate::block::BlockDevice;
+use crate::sync::{IrqMutex, Mutex};
+use crate::tsc;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::iter;
+
+// NOTE: the root directory has no entry of its own to take a number from
+const ROOT_INODE: u64 = 1;
+// NOTE: sizes are 32 bits in directory entries
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+// NOTE: what the specification allows a directory, 2 MiB of entries
+const MAX_DIRECTORY_SLOTS: usize = 65536;
+
+struct State {
+    // NOTE: device offset of the short entry, None for the root and once unlinked
+    position: Option<u64>,
+    number: u64,
+    unlinked: bool,
+    first_cluster: u32,
+    size: u64,
+    // NOTE: looked up on first use
+    chain: Option<Vec<u32>>,
+}
+
+impl State {
+    fn chain(&mut self, volume: &Volume) -> Result<&mut Vec<u32>, FsError> {
+        if self.chain.is_none() {
+            self.chain = Some(volume.chain(self.first_cluster)?);
+        }
+
+        Ok(self.chain.as_mut().unwrap())
+    }
+}
+
+// NOTE: what the inodes of a volume share. Directories are changed one at a time under
+// `namespace` and files lock only their own state to change their data, so locks are taken
+// namespace first, then directories, then files and the FAT last
+struct Shared {
+    volume: Volume,
+    namespace: Mutex<()>,
+    // NOTE: by position of the short entry, so each entry has one inode and they agree on sizes
+    inodes: IrqMutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+fn slot_position(volume: &Volume, chain: &[u32], slot: usize) -> u64 {
+    let offset = (slot * ENTRY_SIZE) as u64;
+
+    volume.cluster_offset(chain[(offset / volume.cluster_size) as usize])
+        + offset % volume.cluster_size
+}
+
+// NOTE: the ".." cluster of a directory in the one with `cluster`, 0 for the root
+fn parent_cluster(volume: &Volume, cluster: u32) -> u32 {
+    match cluster == volume.root_cluster {
+        true => 0,
+        false => cluster,
+    }
+}
+
+// NOTE: a directory's clusters and the entries in them
+fn listing(volume: &Volume, state: &mut State) -> Result<(Vec<u8>, Vec<RawEntry>), FsError> {
+    let chain = state.chain(volume)?;
+    let mut data = vec![0; chain.len() * volume.cluster_size as usize];
+
+    volume.read_chain(chain, 0, &mut data)?;
+
+    let entries = dir::parse(&data);
+
+    Ok((data, entries))
+}
+
+fn find<'a>(entries: &'a [RawEntry], name: &str) -> Result<&'a RawEntry, FsError> {
+    entries
+        .iter()
+        .find(|entry| dir::same_name(&entry.name, name))
+        .ok_or(FsError::NotFound)
+}
+
+// NOTE: the size and first cluster into the short entry. It is the last write of a change to a
+// file, a crash before it leaves the file as it was and clusters fsck finds lost
+fn write_entry(volume: &Volume, state: &State) -> Result<(), FsError> {
+    let Some(position) = state.position else {
+        return Ok(());
+    };
+    let mut entry = [0; ENTRY_SIZE];
+
+    volume.read(position, &mut entry)?;
+    dir::set_cluster(&mut entry, state.first_cluster);
+    dir::set_size(&mut entry, state.size as u32);
+    volume.write(position, &entry)
+}
+
+// NOTE: makes the chain long enough for `size` bytes
+fn reserve(volume: &Volume, state: &mut State, size: u64) -> Result<(), FsError> {
+    let needed = size.div_ceil(volume.cluster_size) as usize;
+    let chain = state.chain(volume)?;
+
+    if needed <= chain.len() {
+        return Ok(());
+    }
+
+    let clusters = volume.allocate(needed - chain.len())?;
+    let tail = chain.last().copied();
+
+    if let Some(tail) = tail {
+        volume.link(tail, clusters[0])?;
+    }
+
+    chain.extend_from_slice(&clusters);
+
+    if tail.is_none() {
+        state.first_cluster = clusters[0];
+    }
+
+    Ok(())
+}
+
+fn write_data(volume: &Volume, state: &mut State, offset: u64, data: &[u8]) -> Result<(), FsError> {
+    let end = offset
+        .checked_add(data.len() as u64)
+        .filter(|&end| end <= MAX_FILE_SIZE)
+        .ok_or(FsError::NoSpace)?;
+
+    reserve(volume, state, end)?;
+
+    let size = state.size;
+    let chain = state.chain(volume)?;
+
+    // NOTE: what was in the clusters before mustn't show through a gap
+    if offset > size {
+        volume.zero_chain(chain, size, offset - size)?;
+    }
+
+    volume.write_chain(chain, offset, data)?;
+
+    if end > state.size {
+        state.size = end;
+        write_entry(volume, state)?;
+    }
+
+    Ok(())
+}
+
+// NOTE: the entry is written before the clusters are freed, a crash in between only loses them
+fn shrink(volume: &Volume, state: &mut State, size: u64) -> Result<(), FsError> {
+    let keep = size.div_ceil(volume.cluster_size) as usize;
+    let chain = state.chain(volume)?;
+    let removed = chain.split_off(keep.min(chain.len()));
+    let tail = chain.last().copied();
+
+    state.size = size;
+
+    if tail.is_none() {
+        state.first_cluster = 0;
+    }
+
+    write_entry(volume, state)?;
+    volume.free(&removed, tail)
+}
+
+// NOTE: `entries` into the first run of free slots long enough, the directory grows by zeroed
+// clusters if there is none. The short entry is written last, a crash before it leaves long
+// name entries without one, which fsck removes
+fn insert(
+    volume: &Volume,
+    state: &mut State,
+    data: &[u8],
+    entries: &[[u8; ENTRY_SIZE]],
+) -> Result<u64, FsError> {
+    let slots = data.len() / ENTRY_SIZE;
+    let mut run = 0;
+    let mut start = None;
+
+    for slot in 0..slots {
+        run = match data[slot * ENTRY_SIZE] {
+            0 | FREE_ENTRY => run + 1,
+            _ => 0,
+        };
+
+        if run == entries.len() {
+            start = Some(slot + 1 - run);
+
+            break;
+        }
+    }
+
+    let start = match start {
+        Some(start) => start,
+        None => {
+            let missing = ((entries.len() - run) * ENTRY_SIZE) as u64;
+            let count = missing.div_ceil(volume.cluster_size) as usize;
+            let per_cluster = volume.cluster_size as usize / ENTRY_SIZE;
+
+            if slots + count * per_cluster > MAX_DIRECTORY_SLOTS {
+                return Err(FsError::NoSpace);
+            }
+
+            let clusters = volume.allocate(count)?;
+            let chain = state.chain(volume)?;
+            let tail = *chain.last().ok_or(FsError::Corrupt)?;
+
+            volume.zero_chain(&clusters, 0, count as u64 * volume.cluster_size)?;
+            volume.link(tail, clusters[0])?;
+            chain.extend_from_slice(&clusters);
+
+            slots - run
+        }
+    };
+    let chain = state.chain(volume)?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        volume.write(slot_position(volume, chain, start + index), entry)?;
+    }
+
+    Ok(slot_position(volume, chain, start + entries.len() - 1))
+}
+
+// NOTE: the short entry first, long name entries left after a crash are removed by fsck
+fn remove(volume: &Volume, chain: &[u32], entry: &RawEntry) -> Result<(), FsError> {
+    let short = entry.short_slot();
+
+    for slot in iter::once(short).chain(entry.slots.start..short) {
+        volume.write(slot_position(volume, chain, slot), &[FREE_ENTRY])?;
+    }
+
+    Ok(())
+}
+
+// NOTE: a cluster with "." and ".." for a new directory, written before any entry points to it
+fn new_directory(volume: &Volume, parent: u32) -> Result<u32, FsError> {
+    let cluster = volume.allocate(1)?[0];
+    let mut data = vec![0; volume.cluster_size as usize];
+
+    data[..ENTRY_SIZE].copy_from_slice(&dir::short_entry(b".          ", ATTR_DIRECTORY, cluster));
+    data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&dir::short_entry(
+        b"..         ",
+        ATTR_DIRECTORY,
+        parent,
+    ));
+
+    if let Err(error) = volume.write_chain(&[cluster], 0, &data) {
+        let _ = volume.free(&[cluster], None);
+
+        return Err(error);
+    }
+
+    Ok(cluster)
+}
+
+struct FatInode {
+    shared: Arc<Shared>,
+    kind: InodeKind,
+    state: Mutex<State>,
+}
+
+impl FatInode {
+    fn new(shared: Arc<Shared>, short: &[u8; ENTRY_SIZE], position: u64) -> FatInode {
+        let kind = match short[11] & ATTR_DIRECTORY {
+            0 => InodeKind::File,
+            _ => InodeKind::Directory,
+        };
+        let state = State {
+            position: Some(position),
+            number: position,
+            unlinked: false,
+            first_cluster: dir::cluster(short),
+            size: match kind {
+                InodeKind::File => dir::size(short) as u64,
+                _ => 0,
+            },
+            chain: None,
+        };
+
+        FatInode {
+            shared,
+            kind,
+            state: Mutex::new(state),
+        }
+    }
+
+    // NOTE: the one inode for the entry at `position`
+    fn child(&self, short: &[u8; ENTRY_SIZE], position: u64) -> Arc<FatInode> {
+        let mut inodes = self.shared.inodes.lock();
+
+        if let Some(inode) = inodes.get(&position).and_then(Weak::upgrade) {
+            return inode;
+        }
+
+        let inode = Arc::new(FatInode::new(self.shared.clone(), short, position));
+
+        inodes.insert(position, Arc::downgrade(&inode));
+
+        inode
+    }
+
+    // NOTE: its entry is gone, the clusters go back once the last user drops it
+    fn orphan(&self) {
+        let mut state = self.state.lock();
+
+        if let Some(position) = state.position.take() {
+            self.shared.inodes.lock().remove(&position);
+        }
+
+        state.unlinked = true;
+    }
+
+    fn check_directory(&self) -> Result<(), FsError> {
+        match self.kind {
+            InodeKind::Directory => Ok(()),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    // NOTE: with the namespace and both directories locked, `destination` None is within
+    // `source`. A file replaces a file, nothing replaces a directory. The one replaced goes
+    // first and the old entry last, a crash in between leaves the target gone or two entries
+    // sharing clusters, which fsck sorts out
+    fn move_entry(
+        &self,
+        source: &mut State,
+        mut destination: Option<&mut State>,
+        from: &str,
+        to: &str,
+    ) -> Result<(), FsError> {
+        let volume = &self.shared.volume;
+        let (_, entries) = listing(volume, source)?;
+        let entry = find(&entries, from)?;
+        let position = slot_position(volume, source.chain(volume)?, entry.short_slot());
+        let inode = self.child(&entry.short, position);
+        let mut replaced = None;
+        let (new_position, parent) = {
+            let target = match destination.as_deref_mut() {
+                Some(destination) => destination,
+                None => &mut *source,
+            };
+            let (_, existing) = listing(volume, target)?;
+
+            if let Some(existing) = existing.iter().find(|e| dir::same_name(&e.name, to)) {
+                let existing_position =
+                    slot_position(volume, target.chain(volume)?, existing.short_slot());
+
+                // NOTE: the entry itself, unless only the case changes
+                if existing_position == position {
+                    if existing.name == to {
+                        return Ok(());
+                    }
+                } else {
+                    match (entry.is_directory(), existing.is_directory()) {
+                        (_, true) => return Err(FsError::IsDirectory),
+                        (true, _) => return Err(FsError::NotDirectory),
+                        _ => {}
+                    }
+
+                    remove(volume, target.chain(volume)?, existing)?;
+
+                    let old = self.child(&existing.short, existing_position);
+
+                    old.orphan();
+                    replaced = Some(old);
+                }
+            }
+
+            let (data, current) = listing(volume, target)?;
+            let mut new = dir::encode(to, &current)?;
+            let short = new.last_mut().unwrap();
+
+            short[11] = entry.short[11];
+            short[13..].copy_from_slice(&entry.short[13..]);
+
+            (
+                insert(volume, target, &data, &new)?,
+                parent_cluster(volume, target.first_cluster),
+            )
+        };
+
+        remove(volume, source.chain(volume)?, entry)?;
+
+        let mut state = inode.state.lock();
+
+        {
+            let mut inodes = self.shared.inodes.lock();
+
+            inodes.remove(&position);
+            inodes.insert(new_position, Arc::downgrade(&inode));
+        }
+
+        state.position = Some(new_position);
+        state.number = new_position;
+
+        // NOTE: a directory moved elsewhere has to point back at its new parent
+        if inode.kind == InodeKind::Directory && destination.is_some() {
+            let chain = state.chain(volume)?;
+            let first = *chain.first().ok_or(FsError::Corrupt)?;
+            let dot_dot = volume.cluster_offset(first) + ENTRY_SIZE as u64;
+            let mut entry = [0; ENTRY_SIZE];
+
+            volume.read(dot_dot, &mut entry)?;
+            dir::set_cluster(&mut entry, parent);
+            volume.write(dot_dot, &entry)?;
+        }
+
+        drop(state);
+        drop(replaced);
+
+        Ok(())
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+
+        Metadata {
+            kind: self.kind,
+            size: state.size,
+            inode: state.number,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind != InodeKind::File {
+            return Err(FsError::IsDirectory);
+        }
+
+        let volume = &self.shared.volume;
+        let mut state = self.state.lock();
+        let len = (buffer.len() as u64).min(state.size.saturating_sub(offset)) as usize;
+
+        if len > 0 {
+            volume.read_chain(state.chain(volume)?, offset, &mut buffer[..len])?;
+        }
+
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<usize, FsError> {
+        if self.kind != InodeKind::File {
+            return Err(FsError::IsDirectory);
+        }
+
+        if !bytes.is_empty() {
+            write_data(&self.shared.volume, &mut self.state.lock(), offset, bytes)?;
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if self.kind != InodeKind::File {
+            return Err(FsError::IsDirectory);
+        }
+
+        let volume = &self.shared.volume;
+        let mut state = self.state.lock();
+
+        match size.cmp(&state.size) {
+            core::cmp::Ordering::Less => shrink(volume, &mut state, size),
+            core::cmp::Ordering::Equal => Ok(()),
+            core::cmp::Ordering::Greater => write_data(volume, &mut state, size, &[]),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+
+        let volume = &self.shared.volume;
+        let _namespace = self.shared.namespace.lock();
+        let mut state = self.state.lock();
+        let (_, entries) = listing(volume, &mut state)?;
+        let entry = find(&entries, name)?;
+        let position = slot_position(volume, state.chain(volume)?, entry.short_slot());
+
+        Ok(self.child(&entry.short, position))
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_directory()?;
+
+        let _namespace = self.shared.namespace.lock();
+        let (_, entries) = listing(&self.shared.volume, &mut self.state.lock())?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| DirEntry {
+                kind: match entry.is_directory() {
+                    true => InodeKind::Directory,
+                    false => InodeKind::File,
+                },
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+
+        let attributes = match kind {
+            InodeKind::File => ATTR_ARCHIVE,
+            InodeKind::Directory => ATTR_DIRECTORY,
+            InodeKind::Device => return Err(FsError::Unsupported),
+        };
+        let volume = &self.shared.volume;
+        let _namespace = self.shared.namespace.lock();
+        let mut state = self.state.lock();
+        let (data, entries) = listing(volume, &mut state)?;
+
+        if entries
+            .iter()
+            .any(|entry| dir::same_name(&entry.name, name))
+        {
+            return Err(FsError::Exists);
+        }
+
+        let mut new = dir::encode(name, &entries)?;
+        let cluster = match kind {
+            InodeKind::Directory => {
+                new_directory(volume, parent_cluster(volume, state.first_cluster))?
+            }
+            _ => 0,
+        };
+        let short = new.last_mut().unwrap();
+
+        short[11] = attributes;
+        dir::set_cluster(short, cluster);
+
+        let position = match insert(volume, &mut state, &data, &new) {
+            Ok(position) => position,
+            Err(error) => {
+                if cluster != 0 {
+                    let _ = volume.free(&[cluster], None);
+                }
+
+                return Err(error);
+            }
+        };
+
+        Ok(self.child(&new[new.len() - 1], position))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.check_directory()?;
+
+        let volume = &self.shared.volume;
+        let _namespace = self.shared.namespace.lock();
+        let mut state = self.state.lock();
+        let (_, entries) = listing(volume, &mut state)?;
+        let entry = find(&entries, name)?;
+        let chain = state.chain(volume)?;
+        let inode = self.child(
+            &entry.short,
+            slot_position(volume, chain, entry.short_slot()),
+        );
+
+        if entry.is_directory() && !listing(volume, &mut inode.state.lock())?.1.is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+
+        remove(volume, chain, entry)?;
+        inode.orphan();
+
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to_directory: &dyn Inode, to: &str) -> Result<(), FsError> {
+        let target = to_directory
+            .as_any()
+            .downcast_ref::<FatInode>()
+            .filter(|target| Arc::ptr_eq(&target.shared, &self.shared))
+            .ok_or(FsError::CrossDevice)?;
+
+        self.check_directory()?;
+        target.check_directory()?;
+
+        let _namespace = self.shared.namespace.lock();
+        let mut source = self.state.lock();
+
+        if core::ptr::eq(self, target) {
+            return self.move_entry(&mut source, None, from, to);
+        }
+
+        self.move_entry(&mut source, Some(&mut target.state.lock()), from, to)
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+
+        if let Some(position) = state.position {
+            let mut inodes = self.shared.inodes.lock();
+
+            if inodes
+                .get(&position)
+                .is_some_and(|inode| inode.strong_count() == 0)
+            {
+                inodes.remove(&position);
+            }
+        }
+
+        if !state.unlinked {
+            return;
+        }
+
+        let volume = &self.shared.volume;
+        let chain = match state.chain.take() {
+            Some(chain) => Ok(chain),
+            None => volume.chain(state.first_cluster),
+        };
+
+        if let Err(error) = chain.and_then(|chain| volume.free(&chain, None)) {
+            log::warn!("fat32: lost the clusters of an unlinked file: {:?}", error);
+        }
+    }
+}
+
+// NOTE: a FAT32 volume on a block device. Changes go straight to the device in an order that
+// leaves at worst lost clusters or stray long name entries after a crash, both of which fsck
+// repairs, and the FSInfo counts are written on sync. Timestamps stay zero without a wall clock
+pub struct Fat32 {
+    root: Arc<FatInode>,
+}
+
+impl Fat32 {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Fat32, FsError> {
+        let volume = Volume::new(device)?;
+        let state = State {
+            position: None,
+            number: ROOT_INODE,
+            unlinked: false,
+            first_cluster: volume.root_cluster,
+            size: 0,
+            chain: None,
+        };
+        let shared = Arc::new(Shared {
+            volume,
+            namespace: Mutex::new(()),
+            inodes: IrqMutex::new(BTreeMap::new()),
+        });
+        let root = FatInode {
+            shared,
+            kind: InodeKind::Directory,
+            state: Mutex::new(state),
+        };
+
+        Ok(Fat32 {
+            root: Arc::new(root),
+        })
+    }
+
+    // NOTE: an empty volume over the whole of `device`, see volume::format
+    pub fn format(device: &dyn BlockDevice) -> Result<(), FsError> {
+        volume::format(device, tsc::read() as u32)
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.root.shared.volume.sync()
+    }
+}
+
+#[cfg(test)]
+use self::dir::{ATTR_VOLUME_ID, LOWER_BASE, LOWER_EXTENSION};
+#[cfg(test)]
+use self::volume::ENTRY_MASK;
+#[cfg(test)]
+const TEST_SECTOR: usize = 512;
+#[cfg(test)]
+const TEST_DATA: usize = 32 + 2 * 4;
+
+// NOTE: a 520 sector volume with one sector clusters: 32 reserved sectors, two FATs of 4
+// sectors and the data area, with the root directory on cluster 2
+#[cfg(test)]
+fn test_volume() -> Vec<u8> {
+    let mut image = vec![0; 520 * TEST_SECTOR];
+
+    image[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    image[3..11].copy_from_slice(b"RUSTOS  ");
+    image[11..13].copy_from_slice(&(TEST_SECTOR as u16).to_le_bytes());
+    image[13] = 1;
+    image[14..16].copy_from_slice(&32u16.to_le_bytes());
+    image[16] = 2;
+    image[32..36].copy_from_slice(&520u32.to_le_bytes());
+    image[36..40].copy_from_slice(&4u32.to_le_bytes());
+    image[44..48].copy_from_slice(&2u32.to_le_bytes());
+    image[82..90].copy_from_slice(b"FAT32   ");
+    image[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    for cluster in 0..2 {
+        set_test_fat(&mut image, cluster, ENTRY_MASK);
+    }
+
+    set_test_fat(&mut image, 2, ENTRY_MASK);
+
+    image
+}
+
+#[cfg(test)]
+fn set_test_fat(image: &mut [u8], cluster: u32, next: u32) {
+    for fat in 0..2 {
+        let offset = (32 + fat * 4) * TEST_SECTOR + cluster as usize * 4;
+
+        image[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+fn test_cluster(image: &mut [u8], cluster: u32) -> &mut [u8] {
+    let offset = (TEST_DATA + cluster as usize - 2) * TEST_SECTOR;
+
+    &mut image[offset..offset + TEST_SECTOR]
+}
+
+#[cfg(test)]
+fn test_entry(short: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; 32] {
+    let mut entry = dir::short_entry(short, attributes, cluster);
+
+    dir::set_size(&mut entry, size);
+
+    entry
+}
+
+#[test_case]
+fn test_read_volume() {
+    use super::{OpenFlags, Vfs};
+    use crate::block::RamDisk;
+    use alloc::string::String;
+
+    let mut image = test_volume();
+    let long = b"LONGFI~1TXT";
+    let mut readme = test_entry(b"README  TXT", 0, 5, 1100);
+    let mut deleted = test_entry(b"OLD     TXT", 0, 9, 1);
+
+    readme[12] = LOWER_BASE | LOWER_EXTENSION;
+    deleted[0] = FREE_ENTRY;
+
+    let mut root = vec![test_entry(b"RUSTOS     ", ATTR_VOLUME_ID, 0, 0)];
+
+    root.extend(dir::long_entries("Long File Name.txt", dir::checksum(long)));
+    root.push(test_entry(long, 0, 3, 30));
+    root.push(deleted);
+    root.push(readme);
+    root.push(test_entry(b"BIN        ", ATTR_DIRECTORY, 4, 0));
+    root.push(test_entry(b"EMPTY      ", 0, 0, 0));
+
+    let bin = [
+        test_entry(b".          ", ATTR_DIRECTORY, 4, 0),
+        test_entry(b"..         ", ATTR_DIRECTORY, 0, 0),
+        test_entry(b"HELLO      ", 0, 8, 4),
+    ];
+
+    for (cluster, entries) in [(2, &root[..]), (4, &bin[..])] {
+        test_cluster(&mut image, cluster).copy_from_slice(&[0; TEST_SECTOR]);
+
+        for (index, entry) in entries.iter().enumerate() {
+            test_cluster(&mut image, cluster)[index * 32..index * 32 + 32].copy_from_slice(entry);
+        }
+    }
+
+    test_cluster(&mut image, 3)[..30].copy_from_slice(b"the long named file's contents");
+    test_cluster(&mut image, 5).fill(b'a');
+    test_cluster(&mut image, 7).fill(b'b');
+    test_cluster(&mut image, 6).fill(b'c');
+    test_cluster(&mut image, 8)[..4].copy_from_slice(b"\x7fELF");
+
+    for (cluster, next) in [
+        (3, ENTRY_MASK),
+        (4, ENTRY_MASK),
+        (5, 7),
+        (7, 6),
+        (6, ENTRY_MASK),
+    ] {
+        set_test_fat(&mut image, cluster, next);
+    }
+
+    set_test_fat(&mut image, 8, ENTRY_MASK);
+
+    let disk = Arc::new(RamDisk::from_image(TEST_SECTOR, image));
+    let vfs = Vfs::new();
+
+    vfs.mount("/", Arc::new(Fat32::new(disk).unwrap())).unwrap();
+
+    let names: Vec<String> = vfs
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+
+    assert_eq!(names, ["Long File Name.txt", "readme.txt", "BIN", "EMPTY"]);
+    assert_eq!(
+        vfs.read_dir("/bin").unwrap(),
+        [DirEntry {
+            name: String::from("HELLO"),
+            kind: InodeKind::File,
+        }]
+    );
+
+    let file = vfs
+        .open("/LONG FILE NAME.TXT", OpenFlags::READ_ONLY)
+        .unwrap();
+    let mut buffer = [0; 64];
+
+    assert_eq!(file.read(&mut buffer), Ok(30));
+    assert_eq!(&buffer[..30], b"the long named file's contents");
+
+    let readme = vfs.open("/readme.txt", OpenFlags::READ_ONLY).unwrap();
+    let mut contents = vec![0; 2048];
+
+    assert_eq!(readme.read(&mut contents), Ok(1100));
+    assert!(contents[..512].iter().all(|&byte| byte == b'a'));
+    assert!(contents[512..1024].iter().all(|&byte| byte == b'b'));
+    assert!(contents[1024..1100].iter().all(|&byte| byte == b'c'));
+
+    let hello = vfs.open("/bin/hello", OpenFlags::READ_ONLY).unwrap();
+
+    assert_eq!(hello.read(&mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"\x7fELF");
+    assert_eq!(vfs.lookup("/empty").unwrap().metadata().size, 0);
+    assert_eq!(vfs.lookup("/old.txt").err(), Some(FsError::NotFound));
+    assert_eq!(
+        vfs.create("/null", InodeKind::Device).err(),
+        Some(FsError::Unsupported)
+    );
+}
+
+#[test_case]
+fn test_rejects_bad_volumes() {
+    use crate::block::RamDisk;
+
+    let mut fat16 = test_volume();
+
+    fat16[22] = 4;
+
+    let mut looped = test_volume();
+
+    set_test_fat(&mut looped, 2, 2);
+
+    for image in [fat16, vec![0; 520 * TEST_SECTOR]] {
+        assert_eq!(
+            Fat32::new(Arc::new(RamDisk::from_image(TEST_SECTOR, image))).err(),
+            Some(FsError::Corrupt)
+        );
+    }
+
+    let fs = Fat32::new(Arc::new(RamDisk::from_image(TEST_SECTOR, looped))).unwrap();
+
+    assert_eq!(fs.root().entries().err(), Some(FsError::Corrupt));
+}
+
+#[test_case]
+fn test_new_entries() {
+    use crate::block::{self, RamDisk};
+
+    let disk = Arc::new(RamDisk::new(TEST_SECTOR, 128));
+
+    assert_eq!(Fat32::format(disk.as_ref()), Ok(()));
+
+    let fs = Fat32::new(disk.clone()).unwrap();
+    let root = fs.root();
+
+    for name in [
+        "readme.txt",
+        "Long File Name.txt",
+        "long file name.txt2",
+        "KERNEL",
+    ] {
+        root.create(name, InodeKind::File).unwrap();
+    }
+
+    assert_eq!(
+        root.create("README.TXT", InodeKind::File).err(),
+        Some(FsError::Exists)
+    );
+    assert_eq!(
+        root.create("a:b", InodeKind::File).err(),
+        Some(FsError::InvalidPath)
+    );
+
+    // NOTE: the root directory is the first cluster, right after the FATs
+    let mut entries = [0; 8 * ENTRY_SIZE];
+
+    block::read_bytes(disk.as_ref(), (32 + 2) * TEST_SECTOR as u64, &mut entries).unwrap();
+
+    let shorts: Vec<&[u8]> = entries
+        .chunks_exact(ENTRY_SIZE)
+        .filter(|entry| entry[11] & dir::ATTR_LONG_NAME != dir::ATTR_LONG_NAME)
+        .map(|entry| &entry[..11])
+        .collect();
+
+    assert_eq!(
+        shorts,
+        [
+            &b"README  TXT"[..],
+            b"LONGFI~1TXT",
+            b"LONGFI~2TXT",
+            b"KERNEL     "
+        ]
+    );
+    assert_eq!(entries[12], LOWER_BASE | LOWER_EXTENSION);
+
+    let fs = Fat32::new(disk).unwrap();
+    let names: Vec<_> = fs
+        .root()
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+
+    assert_eq!(
+        names,
+        [
+            "readme.txt",
+            "Long File Name.txt",
+            "long file name.txt2",
+            "KERNEL"
+        ]
+    );
+}
@@ -0,0 +1,368 @@
+use super::volume::{u16_at, u32_at};
+use crate::cp437;
+use crate::fs::{FsError, MAX_NAME};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Range;
+
+pub(super) const ENTRY_SIZE: usize = 32;
+
+pub(super) const ATTR_VOLUME_ID: u8 = 0x08;
+pub(super) const ATTR_DIRECTORY: u8 = 0x10;
+pub(super) const ATTR_ARCHIVE: u8 = 0x20;
+// NOTE: read only, hidden, system and volume id together mark a long name entry
+pub(super) const ATTR_LONG_NAME: u8 = 0x0f;
+const ATTR_LONG_NAME_MASK: u8 = 0x3f;
+
+pub(super) const LAST_LONG_ENTRY: u8 = 0x40;
+pub(super) const FREE_ENTRY: u8 = 0xe5;
+// NOTE: a short name really starting with 0xe5 is stored with this instead
+const KANJI_E5: u8 = 0x05;
+// NOTE: NTRes bits Windows uses for short names that are all lower case
+pub(super) const LOWER_BASE: u8 = 0x08;
+pub(super) const LOWER_EXTENSION: u8 = 0x10;
+
+// NOTE: what short names may have besides upper case letters and digits
+const SHORT_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+// NOTE: what long names may not have besides control characters
+const LONG_FORBIDDEN: &str = "\"*/:<>?\\|";
+// NOTE: UCS-2 units in each long name entry
+const LONG_UNITS: usize = 13;
+
+// NOTE: a short entry with the long name before it if it had a valid one
+pub(super) struct RawEntry {
+    pub(super) name: String,
+    pub(super) short: [u8; ENTRY_SIZE],
+    // NOTE: in the directory, of the long name entries and the short entry last
+    pub(super) slots: Range<usize>,
+}
+
+impl RawEntry {
+    pub(super) fn is_directory(&self) -> bool {
+        self.short[11] & ATTR_DIRECTORY != 0
+    }
+
+    pub(super) fn short_slot(&self) -> usize {
+        self.slots.end - 1
+    }
+}
+
+pub(super) fn cluster(entry: &[u8]) -> u32 {
+    (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32
+}
+
+pub(super) fn size(entry: &[u8]) -> u32 {
+    u32_at(entry, 28)
+}
+
+pub(super) fn set_cluster(entry: &mut [u8], cluster: u32) {
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+pub(super) fn set_size(entry: &mut [u8], size: u32) {
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+// NOTE: a short entry with the 8.3 name in the first 11 bytes
+pub(super) fn short_entry(short: &[u8], attributes: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0; ENTRY_SIZE];
+
+    entry[..11].copy_from_slice(short);
+    entry[11] = attributes;
+    set_cluster(&mut entry, cluster);
+
+    entry
+}
+
+// NOTE: long name entries seen so far, they come last piece first and have to count down to 1
+// right before the short entry whose checksum they carry
+struct LongName {
+    checksum: u8,
+    next: u8,
+    first: usize,
+    units: Vec<u16>,
+}
+
+pub(super) fn checksum(short: &[u8]) -> u8 {
+    short[..11]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+fn short_name(entry: &[u8]) -> String {
+    let lower = |bytes: &[u8], flag: u8| {
+        bytes
+            .iter()
+            .map(|&byte| match entry[12] & flag {
+                0 => cp437::to_char(byte),
+                _ => cp437::to_char(byte.to_ascii_lowercase()),
+            })
+            .collect::<String>()
+    };
+    let mut base = [0; 8];
+
+    base.copy_from_slice(&entry[..8]);
+
+    if base[0] == KANJI_E5 {
+        base[0] = FREE_ENTRY;
+    }
+
+    let base = lower(base.trim_ascii_end(), LOWER_BASE);
+    let extension = lower(entry[8..11].trim_ascii_end(), LOWER_EXTENSION);
+
+    match extension.is_empty() {
+        true => base,
+        false => base + "." + &extension,
+    }
+}
+
+fn long_offsets() -> impl Iterator<Item = usize> {
+    (1..11).chain(14..26).chain(28..32).step_by(2)
+}
+
+// NOTE: the entries in a directory's clusters, without "." and "..", volume labels and deleted
+// entries. Long names are used where there are valid ones, short names are taken as CP437
+pub(super) fn parse(data: &[u8]) -> Vec<RawEntry> {
+    let mut entries = Vec::new();
+    let mut long: Option<LongName> = None;
+
+    for (slot, entry) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        let attributes = entry[11];
+
+        match entry[0] {
+            0 => break,
+            FREE_ENTRY => {
+                long = None;
+
+                continue;
+            }
+            _ => {}
+        }
+
+        if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+            let ordinal = entry[0] & !LAST_LONG_ENTRY;
+
+            long = match long.take() {
+                _ if ordinal == 0 => None,
+                _ if entry[0] & LAST_LONG_ENTRY != 0 => Some(LongName {
+                    checksum: entry[13],
+                    next: ordinal,
+                    first: slot,
+                    units: Vec::new(),
+                }),
+                Some(name) if name.next == ordinal && name.checksum == entry[13] => Some(name),
+                _ => None,
+            };
+
+            if let Some(name) = &mut long {
+                let mut units: Vec<u16> =
+                    long_offsets().map(|offset| u16_at(entry, offset)).collect();
+
+                units.append(&mut name.units);
+                name.units = units;
+                name.next = ordinal - 1;
+            }
+
+            continue;
+        }
+
+        let long_name = long
+            .take()
+            .filter(|name| name.next == 0 && name.checksum == checksum(entry));
+
+        if attributes & ATTR_VOLUME_ID != 0 {
+            continue;
+        }
+
+        let (name, first) = match long_name {
+            Some(long) => {
+                let end = long.units.iter().position(|&unit| unit == 0);
+                let units = &long.units[..end.unwrap_or(long.units.len())];
+                let name = char::decode_utf16(units.iter().copied())
+                    .map(|unit| unit.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+
+                (name, long.first)
+            }
+            None => (short_name(entry), slot),
+        };
+
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        entries.push(RawEntry {
+            name,
+            short: entry.try_into().unwrap(),
+            slots: first..slot + 1,
+        });
+    }
+
+    entries
+}
+
+// NOTE: FAT names are case insensitive
+pub(super) fn same_name(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+fn short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_SPECIAL.contains(&byte)
+}
+
+// NOTE: `name` as an 8.3 name and the NTRes case bits, if it is one as it is. Each part has to
+// be all upper or all lower case to keep its case without a long name
+fn exact_short(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    let mut short = [b' '; 11];
+    let mut case = 0;
+
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    for (part, start, flag) in [(base, 0, LOWER_BASE), (extension, 8, LOWER_EXTENSION)] {
+        let upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        let lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+
+        if upper && lower {
+            return None;
+        }
+
+        if lower {
+            case |= flag;
+        }
+
+        for (index, byte) in part.bytes().enumerate() {
+            let byte = byte.to_ascii_uppercase();
+
+            if !short_char(byte) {
+                return None;
+            }
+
+            short[start + index] = byte;
+        }
+    }
+
+    Some((short, case))
+}
+
+// NOTE: the short name that goes with a long one, the first of BASE~1.EXT, BASE~2.EXT and so on
+// not in the directory yet
+fn generate_short(name: &str, existing: &[RawEntry]) -> Result<[u8; 11], FsError> {
+    let convert = |c: char| match c.to_ascii_uppercase() {
+        c if c.is_ascii() && short_char(c as u8) => c as u8,
+        _ => b'_',
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = trimmed.rsplit_once('.').unwrap_or((trimmed, ""));
+    let base: Vec<u8> = base
+        .chars()
+        .filter(|&c| c != ' ' && c != '.')
+        .map(convert)
+        .take(8)
+        .collect();
+    let extension: Vec<u8> = extension
+        .chars()
+        .filter(|&c| c != ' ')
+        .map(convert)
+        .take(3)
+        .collect();
+    let mut tail = String::new();
+
+    for number in 1..1_000_000 {
+        let mut short = [b' '; 11];
+
+        tail.clear();
+        let _ = write!(tail, "~{}", number);
+
+        let kept = base.len().min(8 - tail.len());
+
+        short[..kept].copy_from_slice(&base[..kept]);
+        short[kept..kept + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + extension.len()].copy_from_slice(&extension);
+
+        if !existing.iter().any(|entry| entry.short[..11] == short) {
+            return Ok(short);
+        }
+    }
+
+    Err(FsError::NoSpace)
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    let forbidden = |c: char| c < ' ' || LONG_FORBIDDEN.contains(c);
+
+    if name.is_empty() || name.chars().any(forbidden) || name.ends_with('.') || name.ends_with(' ')
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    match name.encode_utf16().count() > MAX_NAME {
+        true => Err(FsError::NameTooLong),
+        false => Ok(()),
+    }
+}
+
+// NOTE: the long name entries for `name`, in the order they go in the directory
+pub(super) fn long_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+
+    // NOTE: NUL terminated unless it fills the last entry, then padded with 0xffff
+    if !units.len().is_multiple_of(LONG_UNITS) {
+        units.push(0);
+    }
+
+    units.resize(units.len().div_ceil(LONG_UNITS) * LONG_UNITS, 0xffff);
+
+    let count = units.len() / LONG_UNITS;
+
+    (0..count)
+        .rev()
+        .map(|index| {
+            let mut entry = [0; ENTRY_SIZE];
+
+            entry[0] = index as u8 + 1;
+
+            if index == count - 1 {
+                entry[0] |= LAST_LONG_ENTRY;
+            }
+
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+
+            for (offset, unit) in long_offsets().zip(&units[index * LONG_UNITS..]) {
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+
+            entry
+        })
+        .collect()
+}
+
+// NOTE: the entries for a new `name` in a directory with `existing` in it, long name entries if
+// it needs them and a short entry for the caller to fill in last
+pub(super) fn encode(name: &str, existing: &[RawEntry]) -> Result<Vec<[u8; ENTRY_SIZE]>, FsError> {
+    check_name(name)?;
+
+    if let Some((short, case)) = exact_short(name) {
+        if !existing.iter().any(|entry| entry.short[..11] == short) {
+            let mut entry = short_entry(&short, 0, 0);
+
+            entry[12] = case;
+
+            return Ok(Vec::from([entry]));
+        }
+    }
+
+    let short = generate_short(name, existing)?;
+    let mut entries = long_entries(name, checksum(&short));
+
+    entries.push(short_entry(&short, 0, 0));
+
+    Ok(entries)
+}
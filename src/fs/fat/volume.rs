@@ -0,0 +1,525 @@
+use crate::block::{self, BlockDevice};
+use crate::fs::FsError;
+use crate::sync::Mutex;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// NOTE: FAT32 entries are 28 bits, the top 4 are reserved and kept as they are
+pub(super) const ENTRY_MASK: u32 = 0x0fff_ffff;
+pub(super) const END_OF_CHAIN: u32 = 0x0fff_fff8;
+const FREE_CLUSTER: u32 = 0;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xaa55_0000;
+const FSINFO_FREE: usize = 488;
+const FSINFO_NEXT: usize = 492;
+// NOTE: for either count in the FSInfo sector
+const UNKNOWN: u32 = 0xffff_ffff;
+
+pub(super) fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(super) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+struct Allocator {
+    // NOTE: where to start looking for free clusters
+    next: u32,
+    // NOTE: None until it is counted, when the FSInfo sector had no usable count
+    free: Option<u32>,
+    // NOTE: whether the FSInfo sector still has the counts from mount or the last sync. It is
+    // marked unknown before the first change after either, a crash can't leave it wrong
+    fsinfo_current: bool,
+}
+
+// NOTE: the volume geometry from the BPB, in bytes where that's handier, and the FAT
+pub(super) struct Volume {
+    device: Arc<dyn BlockDevice>,
+    pub(super) cluster_size: u64,
+    // NOTE: every FAT changes are written to, the first is the one read. Only the active one
+    // when the BPB turns mirroring off
+    fat_offsets: Vec<u64>,
+    data_offset: u64,
+    cluster_count: u32,
+    pub(super) root_cluster: u32,
+    fsinfo_offset: Option<u64>,
+    allocator: Mutex<Allocator>,
+}
+
+impl Volume {
+    pub(super) fn new(device: Arc<dyn BlockDevice>) -> Result<Volume, FsError> {
+        let mut sector = [0; 512];
+
+        block::read_bytes(device.as_ref(), 0, &mut sector)?;
+
+        let bytes_per_sector = u16_at(&sector, 11) as u64;
+        let sectors_per_cluster = sector[13] as u64;
+        let reserved_sectors = u16_at(&sector, 14) as u64;
+        let fat_count = sector[16] as u64;
+        let total_sectors = match u16_at(&sector, 19) {
+            0 => u32_at(&sector, 32) as u64,
+            sectors => sectors as u64,
+        };
+        let fat_size = u32_at(&sector, 36) as u64;
+        let extended_flags = u16_at(&sector, 40);
+        let root_cluster = u32_at(&sector, 44);
+        let fsinfo_sector = u16_at(&sector, 48) as u64;
+
+        // NOTE: FAT12 and FAT16 have root entries outside the data area and a 16 bit FAT size
+        let fat32 = u16_at(&sector, 17) == 0 && u16_at(&sector, 22) == 0 && fat_size != 0;
+
+        if u16_at(&sector, 510) != 0xaa55
+            || !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || !fat32
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        // NOTE: with mirroring off only the FAT bit 7 of the flags doesn't clear is in use
+        let fats: Vec<u64> = match extended_flags & 0x80 {
+            0 => (0..fat_count).collect(),
+            _ => vec![(extended_flags & 0x0f) as u64],
+        };
+        let data_start = reserved_sectors + fat_count * fat_size;
+        let data_sectors = total_sectors
+            .checked_sub(data_start)
+            .ok_or(FsError::Corrupt)?;
+        let cluster_count = (data_sectors / sectors_per_cluster)
+            .min(fat_size * bytes_per_sector / 4 - 2)
+            .min(END_OF_CHAIN as u64 - 2) as u32;
+        let device_size = device.block_count() * device.block_size() as u64;
+
+        if fats[0] >= fat_count || total_sectors * bytes_per_sector > device_size {
+            return Err(FsError::Corrupt);
+        }
+
+        let fsinfo_offset = match fsinfo_sector {
+            0 | 0xffff => None,
+            _ if fsinfo_sector >= reserved_sectors => None,
+            sector => Some(sector * bytes_per_sector),
+        };
+        let mut volume = Volume {
+            device,
+            cluster_size: sectors_per_cluster * bytes_per_sector,
+            fat_offsets: fats
+                .iter()
+                .map(|fat| (reserved_sectors + fat * fat_size) * bytes_per_sector)
+                .collect(),
+            data_offset: data_start * bytes_per_sector,
+            cluster_count,
+            root_cluster,
+            fsinfo_offset: None,
+            allocator: Mutex::new(Allocator {
+                next: 2,
+                free: None,
+                fsinfo_current: false,
+            }),
+        };
+
+        volume.check(root_cluster)?;
+
+        // NOTE: the FSInfo counts are hints, ones out of range are ignored and a sector without
+        // the signatures isn't written to
+        if let Some(offset) = fsinfo_offset {
+            let mut fsinfo = [0; 512];
+
+            volume.read(offset, &mut fsinfo)?;
+
+            if u32_at(&fsinfo, 0) == FSINFO_LEAD
+                && u32_at(&fsinfo, 484) == FSINFO_STRUCT
+                && u32_at(&fsinfo, 508) == FSINFO_TRAIL
+            {
+                let free = u32_at(&fsinfo, FSINFO_FREE);
+                let next = volume.check(u32_at(&fsinfo, FSINFO_NEXT));
+                let allocator = volume.allocator.get_mut();
+
+                allocator.free = (free <= cluster_count).then_some(free);
+                allocator.fsinfo_current = true;
+
+                if let Ok(next) = next {
+                    allocator.next = next;
+                }
+
+                volume.fsinfo_offset = Some(offset);
+            }
+        }
+
+        Ok(volume)
+    }
+
+    pub(super) fn check(&self, cluster: u32) -> Result<u32, FsError> {
+        match (2..self.cluster_count + 2).contains(&cluster) {
+            true => Ok(cluster),
+            false => Err(FsError::Corrupt),
+        }
+    }
+
+    pub(super) fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster as u64 - 2) * self.cluster_size
+    }
+
+    pub(super) fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        Ok(block::read_bytes(self.device.as_ref(), offset, buffer)?)
+    }
+
+    pub(super) fn write(&self, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        Ok(block::write_bytes(self.device.as_ref(), offset, data)?)
+    }
+
+    fn entry(&self, cluster: u32) -> Result<u32, FsError> {
+        let mut entry = [0; 4];
+
+        self.read(self.fat_offsets[0] + cluster as u64 * 4, &mut entry)?;
+
+        Ok(u32::from_le_bytes(entry) & ENTRY_MASK)
+    }
+
+    fn set_entry(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        for fat in &self.fat_offsets {
+            let offset = fat + cluster as u64 * 4;
+            let mut entry = [0; 4];
+
+            self.read(offset, &mut entry)?;
+
+            let entry = u32::from_le_bytes(entry) & !ENTRY_MASK | value;
+
+            self.write(offset, &entry.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    // NOTE: None at the end of the chain; free or bad clusters in a chain mean it's broken
+    fn next(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        match self.entry(cluster)? {
+            next if next >= END_OF_CHAIN => Ok(None),
+            next => self.check(next).map(Some),
+        }
+    }
+
+    // NOTE: the clusters from `first` on, none for 0, which empty files have. A chain longer
+    // than the volume has clusters runs in a circle
+    pub(super) fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = match first {
+            0 => None,
+            first => Some(self.check(first)?),
+        };
+
+        while let Some(current) = cluster {
+            if chain.len() >= self.cluster_count as usize {
+                return Err(FsError::Corrupt);
+            }
+
+            chain.push(current);
+            cluster = self.next(current)?;
+        }
+
+        Ok(chain)
+    }
+
+    // NOTE: calls `transfer` with the device offset and length of each piece of `len` bytes from
+    // byte `offset` into the clusters of `chain`. Clusters that follow each other on the device
+    // are one piece
+    fn pieces(
+        &self,
+        chain: &[u32],
+        offset: u64,
+        len: usize,
+        mut transfer: impl FnMut(u64, usize, usize) -> Result<(), FsError>,
+    ) -> Result<(), FsError> {
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done as u64;
+            let index = (position / self.cluster_size) as usize;
+            let first = *chain.get(index).ok_or(FsError::Corrupt)?;
+            let run = chain[index..]
+                .iter()
+                .zip(first..)
+                .take_while(|(cluster, expected)| **cluster == *expected)
+                .count() as u64;
+            let available = run * self.cluster_size - position % self.cluster_size;
+            let piece = available.min((len - done) as u64) as usize;
+
+            transfer(
+                self.cluster_offset(first) + position % self.cluster_size,
+                done,
+                piece,
+            )?;
+            done += piece;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn read_chain(
+        &self,
+        chain: &[u32],
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), FsError> {
+        self.pieces(chain, offset, buffer.len(), |device, done, len| {
+            self.read(device, &mut buffer[done..done + len])
+        })
+    }
+
+    pub(super) fn write_chain(
+        &self,
+        chain: &[u32],
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        self.pieces(chain, offset, data.len(), |device, done, len| {
+            self.write(device, &data[done..done + len])
+        })
+    }
+
+    // NOTE: `len` zeroes from byte `offset`, a cluster at a time
+    pub(super) fn zero_chain(&self, chain: &[u32], offset: u64, len: u64) -> Result<(), FsError> {
+        let zeroes = vec![0; len.min(self.cluster_size) as usize];
+        let mut done = 0;
+
+        while done < len {
+            let piece = (len - done).min(zeroes.len() as u64) as usize;
+
+            self.write_chain(chain, offset + done, &zeroes[..piece])?;
+            done += piece as u64;
+        }
+
+        Ok(())
+    }
+
+    fn invalidate_fsinfo(&self, allocator: &mut Allocator) -> Result<(), FsError> {
+        if let (true, Some(offset)) = (allocator.fsinfo_current, self.fsinfo_offset) {
+            self.write(offset + FSINFO_FREE as u64, &UNKNOWN.to_le_bytes())?;
+            allocator.fsinfo_current = false;
+        }
+
+        Ok(())
+    }
+
+    // NOTE: `count` free clusters made into a chain of their own, the caller links it in once it
+    // has what it should. A crash before then only loses the clusters
+    pub(super) fn allocate(&self, count: usize) -> Result<Vec<u32>, FsError> {
+        let mut allocator = self.allocator.lock();
+        let end = self.cluster_count + 2;
+        let mut found = Vec::new();
+        let mut cluster = self.check(allocator.next).unwrap_or(2);
+        let mut scanned = 0;
+
+        // NOTE: a FAT sector's worth of entries at a time, round from the hint
+        while found.len() < count && scanned < self.cluster_count {
+            let group = (cluster / 128 + 1)
+                .saturating_mul(128)
+                .min(end)
+                .min(cluster + self.cluster_count - scanned);
+            let mut entries = vec![0; (group - cluster) as usize * 4];
+
+            self.read(self.fat_offsets[0] + cluster as u64 * 4, &mut entries)?;
+
+            let free = entries
+                .chunks_exact(4)
+                .zip(cluster..)
+                .filter(|(entry, _)| u32_at(entry, 0) & ENTRY_MASK == FREE_CLUSTER)
+                .map(|(_, cluster)| cluster);
+
+            found.extend(free.take(count - found.len()));
+            scanned += group - cluster;
+            cluster = match group {
+                group if group == end => 2,
+                group => group,
+            };
+        }
+
+        if found.len() < count {
+            return Err(FsError::NoSpace);
+        }
+
+        self.invalidate_fsinfo(&mut allocator)?;
+
+        // NOTE: from the end, so the chain is always whole as far as it goes
+        for (index, &cluster) in found.iter().enumerate().rev() {
+            self.set_entry(cluster, found.get(index + 1).copied().unwrap_or(ENTRY_MASK))?;
+        }
+
+        if let Some(&last) = found.last() {
+            allocator.next = last + 1;
+        }
+
+        if let Some(free) = &mut allocator.free {
+            *free = free.saturating_sub(count as u32);
+        }
+
+        Ok(found)
+    }
+
+    pub(super) fn link(&self, tail: u32, next: u32) -> Result<(), FsError> {
+        self.set_entry(tail, next)
+    }
+
+    // NOTE: `clusters` back to the free pool, with `tail` made the end of what is left of their
+    // chain first
+    pub(super) fn free(&self, clusters: &[u32], tail: Option<u32>) -> Result<(), FsError> {
+        let mut allocator = self.allocator.lock();
+
+        self.invalidate_fsinfo(&mut allocator)?;
+
+        if let Some(tail) = tail {
+            self.set_entry(tail, ENTRY_MASK)?;
+        }
+
+        for &cluster in clusters {
+            self.set_entry(cluster, FREE_CLUSTER)?;
+
+            if let Some(free) = &mut allocator.free {
+                *free += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count_free(&self) -> Result<u32, FsError> {
+        let mut entries = vec![0; self.cluster_size as usize];
+        let fat_len = (self.cluster_count as u64 + 2) * 4;
+        let mut free = 0;
+        // NOTE: entries 0 and 1 aren't clusters
+        let mut offset = 8;
+
+        while offset < fat_len {
+            let len = (fat_len - offset).min(entries.len() as u64) as usize;
+
+            self.read(self.fat_offsets[0] + offset, &mut entries[..len])?;
+            free += entries[..len]
+                .chunks_exact(4)
+                .filter(|entry| u32_at(entry, 0) & ENTRY_MASK == FREE_CLUSTER)
+                .count() as u32;
+            offset += len as u64;
+        }
+
+        Ok(free)
+    }
+
+    // NOTE: the FSInfo counts are written back and the device flushed
+    pub(super) fn sync(&self) -> Result<(), FsError> {
+        let mut allocator = self.allocator.lock();
+
+        if let (false, Some(offset)) = (allocator.fsinfo_current, self.fsinfo_offset) {
+            let free = match allocator.free {
+                Some(free) => free,
+                None => self.count_free()?,
+            };
+            let mut counts = [0; 8];
+
+            counts[..4].copy_from_slice(&free.to_le_bytes());
+            counts[4..].copy_from_slice(&allocator.next.to_le_bytes());
+            self.write(offset + FSINFO_FREE as u64, &counts)?;
+            allocator.free = Some(free);
+            allocator.fsinfo_current = true;
+        }
+
+        Ok(self.device.flush()?)
+    }
+}
+
+// NOTE: cluster sizes by volume size, from the table Microsoft's format uses
+fn cluster_size(bytes: u64) -> u64 {
+    match bytes >> 20 {
+        0..=260 => 512,
+        261..=8192 => 4096,
+        8193..=16384 => 8192,
+        16385..=32768 => 16384,
+        _ => 32768,
+    }
+}
+
+// NOTE: a fresh volume over the whole device with an empty root directory. Volumes under the
+// 65525 clusters the specification asks of FAT32 are made too, tools may warn about them
+pub(super) fn format(device: &dyn BlockDevice, volume_id: u32) -> Result<(), FsError> {
+    let bytes_per_sector = device.block_size() as u64;
+
+    if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+        return Err(FsError::Unsupported);
+    }
+
+    let total_sectors = device.block_count().min(u32::MAX as u64);
+    let sectors_per_cluster =
+        (cluster_size(total_sectors * bytes_per_sector) / bytes_per_sector).clamp(1, 128);
+    let reserved_sectors = 32;
+    // NOTE: room for every cluster the rest could hold, a sector or two more than needed
+    let fat_size = (total_sectors.saturating_sub(reserved_sectors) / sectors_per_cluster + 2)
+        .div_ceil(bytes_per_sector / 4);
+    let data_start = reserved_sectors + 2 * fat_size;
+    let clusters = total_sectors.saturating_sub(data_start) / sectors_per_cluster;
+
+    if clusters < 16 {
+        return Err(FsError::NoSpace);
+    }
+
+    let mut boot = vec![0; bytes_per_sector as usize];
+
+    boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"RUSTOS  ");
+    boot[11..13].copy_from_slice(&(bytes_per_sector as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(reserved_sectors as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[21] = 0xf8;
+    boot[24..26].copy_from_slice(&32u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&64u16.to_le_bytes());
+    boot[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&(fat_size as u32).to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    boot[67..71].copy_from_slice(&volume_id.to_le_bytes());
+    boot[71..82].copy_from_slice(b"NO NAME    ");
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    let mut fsinfo = vec![0; bytes_per_sector as usize];
+
+    fsinfo[0..4].copy_from_slice(&FSINFO_LEAD.to_le_bytes());
+    fsinfo[484..488].copy_from_slice(&FSINFO_STRUCT.to_le_bytes());
+    fsinfo[FSINFO_FREE..FSINFO_FREE + 4].copy_from_slice(&(clusters as u32 - 1).to_le_bytes());
+    fsinfo[FSINFO_NEXT..FSINFO_NEXT + 4].copy_from_slice(&3u32.to_le_bytes());
+    fsinfo[508..512].copy_from_slice(&FSINFO_TRAIL.to_le_bytes());
+
+    let zeroes = vec![0; (bytes_per_sector * sectors_per_cluster) as usize];
+    let sector = |number: u64| number * bytes_per_sector;
+
+    // NOTE: the FATs and the root directory first, the boot sector last, so a failed format
+    // doesn't leave something that mounts
+    for fat in 0..2 {
+        let start = reserved_sectors + fat * fat_size;
+        let mut first = vec![0; bytes_per_sector as usize];
+
+        first[0..4].copy_from_slice(&0x0fff_fff8u32.to_le_bytes());
+        first[4..8].copy_from_slice(&ENTRY_MASK.to_le_bytes());
+        first[8..12].copy_from_slice(&ENTRY_MASK.to_le_bytes());
+        device.write_blocks(start, &first)?;
+
+        for number in 1..fat_size {
+            device.write_blocks(start + number, &zeroes[..bytes_per_sector as usize])?;
+        }
+    }
+
+    device.write_blocks(data_start, &zeroes)?;
+
+    for backup in [6, 0] {
+        block::write_bytes(device, sector(backup + 1), &fsinfo)?;
+        block::write_bytes(device, sector(backup), &boot)?;
+    }
+
+    Ok(device.flush()?)
+}
@@ -0,0 +1,181 @@
+use super::{Dentry, DirEntry, FsError, InodeKind, Metadata};
+use crate::sync::IrqMutex;
+use crate::syscall::abi::{OPEN_APPEND, OPEN_CREATE, OPEN_READ, OPEN_TRUNCATE, OPEN_WRITE};
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    // NOTE: a missing file is created empty
+    pub create: bool,
+    // NOTE: a file opened for writing starts empty
+    pub truncate: bool,
+    // NOTE: every write goes to the end, whatever the offset
+    pub append: bool,
+}
+
+impl OpenFlags {
+    pub const READ_ONLY: OpenFlags = OpenFlags {
+        read: true,
+        write: false,
+        create: false,
+        truncate: false,
+        append: false,
+    };
+    pub const READ_WRITE: OpenFlags = OpenFlags {
+        write: true,
+        ..OpenFlags::READ_ONLY
+    };
+
+    pub fn from_bits(bits: u64) -> OpenFlags {
+        OpenFlags {
+            read: bits & OPEN_READ != 0,
+            write: bits & OPEN_WRITE != 0,
+            create: bits & OPEN_CREATE != 0,
+            truncate: bits & OPEN_TRUNCATE != 0,
+            append: bits & OPEN_APPEND != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        [
+            (self.read, OPEN_READ),
+            (self.write, OPEN_WRITE),
+            (self.create, OPEN_CREATE),
+            (self.truncate, OPEN_TRUNCATE),
+            (self.append, OPEN_APPEND),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+// NOTE: what a descriptor for a path refers to, the dentry it was opened as and where the next
+// read or write goes. For a directory the offset counts entries. Threads sharing one don't get
+// their reads and writes ordered, each just moves the offset by what it did
+pub struct OpenFile {
+    dentry: Dentry,
+    flags: OpenFlags,
+    offset: IrqMutex<u64>,
+}
+
+impl OpenFile {
+    pub(super) fn open(dentry: Dentry, flags: OpenFlags) -> Result<OpenFile, FsError> {
+        match dentry.metadata().kind {
+            InodeKind::Directory if flags.write => return Err(FsError::IsDirectory),
+            InodeKind::File if flags.write && flags.truncate => dentry.inode.truncate(0)?,
+            _ => {}
+        }
+
+        Ok(OpenFile {
+            dentry,
+            flags,
+            offset: IrqMutex::new(0),
+        })
+    }
+
+    pub fn dentry(&self) -> &Dentry {
+        &self.dentry
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.dentry.metadata()
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.read {
+            return Err(FsError::AccessDenied);
+        }
+
+        let offset = *self.offset.lock();
+        let read = self.dentry.inode.read_at(offset, buffer)?;
+
+        *self.offset.lock() = offset + read as u64;
+
+        Ok(read)
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.write {
+            return Err(FsError::AccessDenied);
+        }
+
+        let offset = match self.flags.append {
+            true => self.metadata().size,
+            false => *self.offset.lock(),
+        };
+        let written = self.dentry.inode.write_at(offset, data)?;
+
+        *self.offset.lock() = offset + written as u64;
+
+        Ok(written)
+    }
+
+    // NOTE: the new offset, which may be past the end of a file
+    pub fn seek(&self, position: SeekFrom) -> Result<u64, FsError> {
+        let size = self.metadata().size;
+        let mut offset = self.offset.lock();
+        let (base, delta) = match position {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(delta) => (*offset, delta),
+            SeekFrom::End(delta) => (size, delta),
+        };
+
+        *offset = base.checked_add_signed(delta).ok_or(FsError::BadOffset)?;
+
+        Ok(*offset)
+    }
+
+    // NOTE: the next entry, None after the last. Each call lists the directory again, so entries
+    // added or removed meanwhile can be skipped or seen twice
+    pub fn read_dir(&self) -> Result<Option<DirEntry>, FsError> {
+        if !self.flags.read {
+            return Err(FsError::AccessDenied);
+        }
+
+        let offset = *self.offset.lock();
+        let entry = self
+            .dentry
+            .inode
+            .entries()?
+            .into_iter()
+            .nth(offset as usize);
+
+        if entry.is_some() {
+            *self.offset.lock() = offset + 1;
+        }
+
+        Ok(entry)
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("dentry", &self.dentry)
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
+
+// NOTE: the same open file, clones of a descriptor share it
+impl PartialEq for OpenFile {
+    fn eq(&self, other: &OpenFile) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl Eq for OpenFile {}
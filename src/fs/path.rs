@@ -0,0 +1,64 @@
+use super::{FsError, MAX_NAME, MAX_PATH};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// NOTE: the components of an absolute path with `.` dropped and `..` taking the one before it
+// back. Done on the text alone, which is right as long as there are no symbolic links; `..`
+// at the root stays there
+pub fn components(path: &str) -> Result<Vec<&str>, FsError> {
+    if !path.starts_with('/') || path.contains('\0') {
+        return Err(FsError::InvalidPath);
+    }
+
+    if path.len() > MAX_PATH {
+        return Err(FsError::NameTooLong);
+    }
+
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name if name.len() > MAX_NAME => return Err(FsError::NameTooLong),
+            name => components.push(name),
+        }
+    }
+
+    Ok(components)
+}
+
+pub fn join(components: &[&str]) -> String {
+    let mut path = String::from("/");
+
+    path.push_str(&components.join("/"));
+
+    path
+}
+
+// NOTE: of a canonical directory path
+pub fn child(parent: &str, name: &str) -> String {
+    match parent {
+        "/" => String::from("/") + name,
+        parent => String::from(parent) + "/" + name,
+    }
+}
+
+// NOTE: the canonical form of an absolute path
+pub fn normalize(path: &str) -> Result<String, FsError> {
+    components(path).map(|components| join(&components))
+}
+
+#[test_case]
+fn test_normalize() {
+    assert_eq!(normalize("/").unwrap(), "/");
+    assert_eq!(normalize("//usr/./bin/../lib/").unwrap(), "/usr/lib");
+    assert_eq!(normalize("/../..").unwrap(), "/");
+    assert_eq!(normalize("relative").err(), Some(FsError::InvalidPath));
+    assert_eq!(normalize("/a\0b").err(), Some(FsError::InvalidPath));
+    assert_eq!(components("/a/b").unwrap(), ["a", "b"]);
+    assert_eq!(child("/", "a"), "/a");
+    assert_eq!(child("/a", "b"), "/a/b");
+}
@@ -0,0 +1,303 @@
+use super::{DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// NOTE: shared by all instances, numbers are unique within each then too
+static NEXT_INODE: AtomicU64 = AtomicU64::new(1);
+
+enum Node {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+}
+
+// NOTE: the kind is kept outside the lock, it never changes and renames check it with
+// directories locked that may include the inode itself
+struct RamInode {
+    number: u64,
+    kind: InodeKind,
+    node: Mutex<Node>,
+    // NOTE: the filesystem's, held by unlink and rename which lock more than one inode
+    tree: Arc<Mutex<()>>,
+}
+
+impl RamInode {
+    fn new(node: Node, tree: Arc<Mutex<()>>) -> Arc<RamInode> {
+        let kind = match node {
+            Node::File(_) => InodeKind::File,
+            Node::Directory(_) => InodeKind::Directory,
+        };
+
+        Arc::new(RamInode {
+            number: NEXT_INODE.fetch_add(1, Ordering::Relaxed),
+            kind,
+            node: Mutex::new(node),
+            tree,
+        })
+    }
+}
+
+// NOTE: makes room for `size` bytes, NoSpace instead of a panic once the heap is exhausted
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<(), FsError> {
+    data.try_reserve(size.saturating_sub(data.len()))
+        .map_err(|_| FsError::NoSpace)?;
+    data.resize(size, 0);
+
+    Ok(())
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let size = match &*self.node.lock() {
+            Node::File(data) => data.len() as u64,
+            Node::Directory(_) => 0,
+        };
+
+        Metadata {
+            kind: self.kind,
+            size,
+            inode: self.number,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let Node::File(data) = &*self.node.lock() else {
+            return Err(FsError::IsDirectory);
+        };
+        let start = data.len().min(offset as usize);
+        let len = buffer.len().min(data.len() - start);
+
+        buffer[..len].copy_from_slice(&data[start..start + len]);
+
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<usize, FsError> {
+        let Node::File(data) = &mut *self.node.lock() else {
+            return Err(FsError::IsDirectory);
+        };
+        let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+        let end = start.checked_add(bytes.len()).ok_or(FsError::NoSpace)?;
+
+        if end > data.len() {
+            resize(data, end)?;
+        }
+
+        data[start..end].copy_from_slice(bytes);
+
+        Ok(bytes.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let Node::File(data) = &mut *self.node.lock() else {
+            return Err(FsError::IsDirectory);
+        };
+
+        resize(data, usize::try_from(size).map_err(|_| FsError::NoSpace)?)?;
+        data.shrink_to_fit();
+
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let Node::Directory(entries) = &*self.node.lock() else {
+            return Err(FsError::NotDirectory);
+        };
+
+        match entries.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        let Node::Directory(entries) = &*self.node.lock() else {
+            return Err(FsError::NotDirectory);
+        };
+
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                kind: inode.kind,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        let Node::Directory(entries) = &mut *self.node.lock() else {
+            return Err(FsError::NotDirectory);
+        };
+
+        if entries.contains_key(name) {
+            return Err(FsError::Exists);
+        }
+
+        let inode = match kind {
+            InodeKind::File => RamInode::new(Node::File(Vec::new()), self.tree.clone()),
+            InodeKind::Directory => {
+                RamInode::new(Node::Directory(BTreeMap::new()), self.tree.clone())
+            }
+            InodeKind::Device => return Err(FsError::Unsupported),
+        };
+
+        entries.insert(String::from(name), inode.clone());
+
+        Ok(inode)
+    }
+
+    // NOTE: open files keep the inode and its data until they are closed
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let _tree = self.tree.lock();
+        let Node::Directory(entries) = &mut *self.node.lock() else {
+            return Err(FsError::NotDirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+
+        if let Node::Directory(children) = &*inode.node.lock() {
+            if !children.is_empty() {
+                return Err(FsError::NotEmpty);
+            }
+        }
+
+        entries.remove(name);
+
+        Ok(())
+    }
+
+    // NOTE: with two directories there is no order to lock them in that unlink, which locks a
+    // directory and then its child, keeps too once directories move, the tree lock orders them
+    fn rename(&self, from: &str, to_directory: &dyn Inode, to: &str) -> Result<(), FsError> {
+        let target = to_directory
+            .as_any()
+            .downcast_ref::<RamInode>()
+            .filter(|target| Arc::ptr_eq(&self.tree, &target.tree))
+            .ok_or(FsError::CrossDevice)?;
+        let _tree = self.tree.lock();
+
+        if core::ptr::eq(self, target) {
+            let Node::Directory(entries) = &mut *self.node.lock() else {
+                return Err(FsError::NotDirectory);
+            };
+
+            return move_entry(entries, None, from, to);
+        }
+
+        let (mut source, mut destination) = (self.node.lock(), target.node.lock());
+        let (Node::Directory(source), Node::Directory(destination)) =
+            (&mut *source, &mut *destination)
+        else {
+            return Err(FsError::NotDirectory);
+        };
+
+        move_entry(source, Some(destination), from, to)
+    }
+}
+
+// NOTE: `destination` None is within `source`. A file replaces a file, nothing replaces a
+// directory
+fn move_entry(
+    source: &mut BTreeMap<String, Arc<RamInode>>,
+    destination: Option<&mut BTreeMap<String, Arc<RamInode>>>,
+    from: &str,
+    to: &str,
+) -> Result<(), FsError> {
+    let inode = source.get(from).ok_or(FsError::NotFound)?.clone();
+    let existing = match &destination {
+        Some(destination) => destination.get(to),
+        None => source.get(to),
+    };
+
+    if let Some(existing) = existing {
+        if Arc::ptr_eq(existing, &inode) {
+            return Ok(());
+        }
+
+        match (inode.kind, existing.kind) {
+            (_, InodeKind::Directory) => return Err(FsError::IsDirectory),
+            (InodeKind::Directory, _) => return Err(FsError::NotDirectory),
+            _ => {}
+        }
+    }
+
+    source.remove(from);
+
+    match destination {
+        Some(destination) => destination.insert(String::from(to), inode),
+        None => source.insert(String::from(to), inode),
+    };
+
+    Ok(())
+}
+
+// NOTE: everything lives on the kernel heap and is gone with the last reference, the root
+// filesystem until there are disks
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> RamFs {
+        RamFs {
+            root: RamInode::new(Node::Directory(BTreeMap::new()), Arc::new(Mutex::new(()))),
+        }
+    }
+}
+
+impl Default for RamFs {
+    fn default() -> RamFs {
+        RamFs::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+#[test_case]
+fn test_sparse_write() {
+    let fs = RamFs::new();
+    let file = fs.root().create("file", InodeKind::File).unwrap();
+    let mut buffer = [1; 8];
+
+    assert_eq!(file.write_at(4, b"data"), Ok(4));
+    assert_eq!(file.metadata().size, 8);
+    assert_eq!(file.read_at(0, &mut buffer), Ok(8));
+    assert_eq!(&buffer, b"\0\0\0\0data");
+    assert_eq!(file.read_at(6, &mut buffer), Ok(2));
+    assert_eq!(file.read_at(100, &mut buffer), Ok(0));
+    assert_eq!(file.truncate(2), Ok(()));
+    assert_eq!(file.metadata().size, 2);
+    assert_eq!(
+        fs.root().create("file", InodeKind::Directory).err(),
+        Some(FsError::Exists)
+    );
+    assert_eq!(file.lookup("x").err(), Some(FsError::NotDirectory));
+}
+
+#[test_case]
+fn test_rename_across_instances() {
+    let (first, second) = (RamFs::new(), RamFs::new());
+
+    first.root().create("file", InodeKind::File).unwrap();
+
+    assert_eq!(
+        first.root().rename("file", &*second.root(), "file"),
+        Err(FsError::CrossDevice)
+    );
+    assert!(first.root().lookup("file").is_ok());
+}
@@ -0,0 +1,359 @@
+use super::{path, DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TarError {
+    // NOTE: the header at this offset has no ustar magic, a bad checksum or a bad number
+    BadHeader(usize),
+    // NOTE: a file's data runs past the end of the archive
+    Truncated(usize),
+    // NOTE: a name that isn't UTF-8, or that a file and a directory both claim
+    BadName(usize),
+}
+
+enum Node {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Arc<TarInode>>),
+}
+
+struct TarInode {
+    number: u64,
+    node: Node,
+}
+
+impl TarInode {
+    fn kind(&self) -> InodeKind {
+        match self.node {
+            Node::File(_) => InodeKind::File,
+            Node::Directory(_) => InodeKind::Directory,
+        }
+    }
+}
+
+impl Inode for TarInode {
+    fn metadata(&self) -> Metadata {
+        let size = match self.node {
+            Node::File(data) => data.len() as u64,
+            Node::Directory(_) => 0,
+        };
+
+        Metadata {
+            kind: self.kind(),
+            size,
+            inode: self.number,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let Node::File(data) = self.node else {
+            return Err(FsError::IsDirectory);
+        };
+        let start = data.len().min(offset as usize);
+        let len = buffer.len().min(data.len() - start);
+
+        buffer[..len].copy_from_slice(&data[start..start + len]);
+
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let Node::Directory(entries) = &self.node else {
+            return Err(FsError::NotDirectory);
+        };
+
+        match entries.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        let Node::Directory(entries) = &self.node else {
+            return Err(FsError::NotDirectory);
+        };
+
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                kind: inode.kind(),
+            })
+            .collect())
+    }
+}
+
+// NOTE: what the archive says before it is frozen into inodes, directories get their numbers
+// once the tree is complete
+enum Entry {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Entry>),
+}
+
+impl Entry {
+    fn freeze(self, numbers: &mut u64) -> Arc<TarInode> {
+        let number = *numbers;
+
+        *numbers += 1;
+
+        let node = match self {
+            Entry::File(data) => Node::File(data),
+            Entry::Directory(entries) => Node::Directory(
+                entries
+                    .into_iter()
+                    .map(|(name, entry)| (name, entry.freeze(numbers)))
+                    .collect(),
+            ),
+        };
+
+        Arc::new(TarInode { number, node })
+    }
+}
+
+// NOTE: an octal field, NUL or space terminated and possibly space padded in front
+fn octal(field: &[u8]) -> Option<u64> {
+    let mut digits = field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ');
+
+    digits.try_fold(0u64, |value, &digit| match digit {
+        b'0'..=b'7' => value.checked_mul(8)?.checked_add((digit - b'0') as u64),
+        _ => None,
+    })
+}
+
+fn field(bytes: &[u8]) -> &[u8] {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+
+    &bytes[..len]
+}
+
+// NOTE: the sum of the header's bytes with the checksum field taken as spaces
+fn checksum_matches(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| match index {
+            148..156 => b' ' as u64,
+            _ => byte as u64,
+        })
+        .sum();
+
+    octal(&header[148..156]) == Some(sum)
+}
+
+fn insert(
+    root: &mut BTreeMap<String, Entry>,
+    name: &[u8],
+    entry: Entry,
+    offset: usize,
+) -> Result<(), TarError> {
+    let name = core::str::from_utf8(name).map_err(|_| TarError::BadName(offset))?;
+    let full = String::from("/") + name;
+    let components = path::components(&full).map_err(|_| TarError::BadName(offset))?;
+    let Some((last, parents)) = components.split_last() else {
+        // NOTE: "./" for the root itself
+        return Ok(());
+    };
+    let mut directory = root;
+
+    for parent in parents {
+        let parent = directory
+            .entry(String::from(*parent))
+            .or_insert_with(|| Entry::Directory(BTreeMap::new()));
+
+        directory = match parent {
+            Entry::Directory(entries) => entries,
+            Entry::File(_) => return Err(TarError::BadName(offset)),
+        };
+    }
+
+    // NOTE: a directory listed after files in it is already there, a later file replaces an
+    // earlier one like tar extracting over it would
+    match (directory.get(*last), &entry) {
+        (Some(Entry::Directory(_)), Entry::Directory(_)) => Ok(()),
+        (Some(Entry::Directory(_)), Entry::File(_))
+        | (Some(Entry::File(_)), Entry::Directory(_)) => Err(TarError::BadName(offset)),
+        _ => {
+            directory.insert(String::from(*last), entry);
+
+            Ok(())
+        }
+    }
+}
+
+// NOTE: a ustar archive served read only, straight out of the archive's memory. Regular files
+// and directories are kept, links, devices and GNU or pax extension headers are skipped, so
+// names are limited to what fits in the prefix and name fields
+pub struct TarFs {
+    root: Arc<TarInode>,
+}
+
+impl TarFs {
+    pub fn new(archive: &'static [u8]) -> Result<TarFs, TarError> {
+        let mut root = BTreeMap::new();
+        let mut offset = 0;
+
+        // NOTE: ends at the first zeroed block, or with the data if the end blocks are missing
+        while offset + BLOCK_SIZE <= archive.len() {
+            let header = &archive[offset..offset + BLOCK_SIZE];
+
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+
+            if &header[257..262] != b"ustar" || !checksum_matches(header) {
+                return Err(TarError::BadHeader(offset));
+            }
+
+            let size = octal(&header[124..136]).ok_or(TarError::BadHeader(offset))? as usize;
+            let data_start = offset + BLOCK_SIZE;
+            let data = archive
+                .get(data_start..data_start.saturating_add(size))
+                .ok_or(TarError::Truncated(offset))?;
+            let mut name = Vec::from(field(&header[345..500]));
+
+            if !name.is_empty() {
+                name.push(b'/');
+            }
+
+            name.extend_from_slice(field(&header[0..100]));
+
+            match header[156] {
+                b'0' | 0 | b'7' => insert(&mut root, &name, Entry::File(data), offset)?,
+                b'5' => insert(&mut root, &name, Entry::Directory(BTreeMap::new()), offset)?,
+                _ => {}
+            }
+
+            offset = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        }
+
+        Ok(TarFs {
+            root: Entry::Directory(root).freeze(&mut 1),
+        })
+    }
+}
+
+impl FileSystem for TarFs {
+    fn name(&self) -> &'static str {
+        "tarfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+// NOTE: a header for `name` in the archive being built, followed by `data` padded to blocks
+#[cfg(test)]
+fn append(archive: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+    use core::fmt::Write;
+
+    let mut header = [0u8; BLOCK_SIZE];
+    let mut number = |start: usize, len: usize, value: usize| {
+        let mut text = String::new();
+
+        let _ = write!(text, "{:0width$o}", value, width = len - 1);
+        header[start..start + len - 1].copy_from_slice(text.as_bytes());
+    };
+
+    number(100, 8, 0o644);
+    number(124, 12, data.len());
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].fill(b' ');
+
+    let sum: usize = header.iter().map(|&byte| byte as usize).sum();
+    let mut checksum = String::new();
+    let _ = write!(checksum, "{:06o}\0 ", sum);
+
+    header[148..156].copy_from_slice(checksum.as_bytes());
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+}
+
+#[test_case]
+fn test_archive() {
+    use super::{OpenFlags, Vfs};
+
+    let mut archive = Vec::new();
+
+    append(&mut archive, "bin/", b'5', b"");
+    append(&mut archive, "bin/hello", b'0', b"\x7fELF");
+    append(&mut archive, "./etc/motd", b'0', &[b'x'; 600]);
+    append(&mut archive, "link", b'2', b"");
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+
+    let fs = TarFs::new(archive.leak()).unwrap();
+    let vfs = Vfs::new();
+
+    vfs.mount("/", Arc::new(fs)).unwrap();
+
+    let names: Vec<String> = vfs
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+
+    assert_eq!(names, ["bin", "etc"]);
+    assert_eq!(vfs.lookup("/etc/motd").unwrap().metadata().size, 600);
+
+    let hello = vfs.open("/bin/hello", OpenFlags::READ_ONLY).unwrap();
+    let mut buffer = [0; 8];
+
+    assert_eq!(hello.read(&mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"\x7fELF");
+    assert_eq!(
+        vfs.open("/bin/hello", OpenFlags::READ_WRITE)
+            .unwrap()
+            .write(b"x"),
+        Err(FsError::ReadOnly)
+    );
+    assert_eq!(vfs.unlink("/bin/hello"), Err(FsError::ReadOnly));
+    assert_eq!(vfs.mkdir("/new").err(), Some(FsError::ReadOnly));
+}
+
+#[test_case]
+fn test_bad_archives() {
+    let mut archive = Vec::new();
+
+    append(&mut archive, "file", b'0', b"data");
+
+    let mut corrupt = archive.clone();
+
+    corrupt[0] = b'g';
+
+    assert_eq!(
+        TarFs::new(corrupt.leak()).err(),
+        Some(TarError::BadHeader(0))
+    );
+    assert_eq!(
+        TarFs::new(archive[..BLOCK_SIZE].to_vec().leak()).err(),
+        Some(TarError::Truncated(0))
+    );
+
+    append(&mut archive, "file/inner", b'0', b"");
+
+    assert_eq!(
+        TarFs::new(archive.leak()).err(),
+        Some(TarError::BadName(2 * BLOCK_SIZE))
+    );
+    assert!(TarFs::new(&[]).is_ok());
+}
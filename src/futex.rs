@@ -0,0 +1,154 @@
+use crate::process::ProcessId;
+use crate::signal;
+use crate::sync::IrqMutex;
+use crate::thread::{self, ThreadId};
+use crate::uaccess::{self, AccessError};
+use alloc::vec::Vec;
+
+const BUCKET_COUNT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    // NOTE: futex words are u32, naturally aligned
+    Unaligned,
+    // NOTE: the word didn't hold the expected value, so whatever the caller waits for happened
+    WouldBlock,
+    // NOTE: a signal became deliverable to the waiter's process
+    Interrupted,
+    Access(AccessError),
+}
+
+impl From<AccessError> for FutexError {
+    fn from(error: AccessError) -> FutexError {
+        FutexError::Access(error)
+    }
+}
+
+// NOTE: futexes are private to a process, keyed by the user address in its address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    process: ProcessId,
+    address: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    key: Key,
+    thread: ThreadId,
+}
+
+// NOTE: a waiter stays in its bucket until a wake takes it out, which is how it knows it was
+// woken rather than anything else unblocking it
+static BUCKETS: [IrqMutex<Vec<Waiter>>; BUCKET_COUNT] =
+    [const { IrqMutex::new(Vec::new()) }; BUCKET_COUNT];
+
+fn bucket(key: &Key) -> &'static IrqMutex<Vec<Waiter>> {
+    let hash = (key.process.as_u64() ^ (key.address >> 2)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+    &BUCKETS[(hash >> 58) as usize % BUCKET_COUNT]
+}
+
+fn key(process: ProcessId, address: u64) -> Result<Key, FutexError> {
+    match address % 4 {
+        0 => Ok(Key { process, address }),
+        _ => Err(FutexError::Unaligned),
+    }
+}
+
+// NOTE: blocks the current thread until a wake for `address` while the u32 there is `expected`,
+// read through the active address space, which has to be `process`'s. Queued before the
+// word is read, so a wake after the waker changed the word can't be missed
+pub fn wait(process: ProcessId, address: u64, expected: u32) -> Result<(), FutexError> {
+    let key = key(process, address)?;
+    let thread = thread::current_id().expect("futex wait outside a thread");
+    let bucket = bucket(&key);
+    let queued = || bucket.lock().iter().any(|waiter| waiter.thread == thread);
+    let dequeue = || bucket.lock().retain(|waiter| waiter.thread != thread);
+
+    bucket.lock().push(Waiter { key, thread });
+
+    let result = match uaccess::read_value::<u32>(address) {
+        Ok(value) if value == expected => loop {
+            if !queued() {
+                break Ok(());
+            }
+
+            if signal::has_deliverable() {
+                break Err(FutexError::Interrupted);
+            }
+
+            thread::block();
+        },
+        Ok(_) => Err(FutexError::WouldBlock),
+        Err(error) => Err(error.into()),
+    };
+
+    dequeue();
+
+    result
+}
+
+// NOTE: wakes up to `count` threads waiting on `address`, longest waiting first, and returns
+// how many there were
+pub fn wake(process: ProcessId, address: u64, count: usize) -> Result<usize, FutexError> {
+    let key = key(process, address)?;
+    let mut woken = Vec::new();
+
+    {
+        let mut waiters = bucket(&key).lock();
+
+        while woken.len() < count {
+            let Some(index) = waiters.iter().position(|waiter| waiter.key == key) else {
+                break;
+            };
+
+            woken.push(waiters.remove(index).thread);
+        }
+    }
+
+    for thread in &woken {
+        thread::wake(*thread);
+    }
+
+    Ok(woken.len())
+}
+
+#[test_case]
+fn test_wait_and_wake() {
+    use crate::memory::address_space::AddressSpace;
+    use crate::process::Process;
+    use crate::usermode::USER_MMAP_BASE;
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    let process = Process::new("futex", AddressSpace::new().unwrap());
+    let id = process.id();
+    let word = USER_MMAP_BASE;
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
+
+    process
+        .address_space()
+        .map(Page::containing_address(VirtAddr::new(word)), flags)
+        .unwrap();
+
+    let waiter = process
+        .spawn_thread("waiter", move || assert_eq!(wait(id, word, 0), Ok(())))
+        .unwrap();
+
+    while wake(id, word, 1) == Ok(0) {
+        thread::yield_now();
+    }
+
+    assert_eq!(waiter.join(), 0);
+
+    let changed = process
+        .spawn_thread("changed", move || {
+            assert_eq!(wait(id, word, 1), Err(FutexError::WouldBlock));
+        })
+        .unwrap();
+
+    assert_eq!(changed.join(), 0);
+    assert_eq!(wake(id, word + 2, 1), Err(FutexError::Unaligned));
+    assert_eq!(wake(id, word, usize::MAX), Ok(0));
+}
@@ -0,0 +1,120 @@
+use crate::percpu;
+use alloc::boxed::Box;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::Ordering;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+// NOTE: IST slot of the double fault handler, a known good stack even when the kernel stack
+// overflowed into its guard page
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+// NOTE: NMIs and machine checks can arrive at any instruction, even right after a syscall
+// switched stacks, so they get their own stacks too
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+// NOTE: a stack overflow faults on the guard page, the handler can't run on that stack
+pub const PAGE_FAULT_IST_INDEX: u16 = 3;
+
+const IST_INDICES: [u16; 4] = [
+    DOUBLE_FAULT_IST_INDEX,
+    NMI_IST_INDEX,
+    MACHINE_CHECK_IST_INDEX,
+    PAGE_FAULT_IST_INDEX,
+];
+
+const STACK_SIZE: usize = 4096 * 5;
+
+// NOTE: every CPU's GDT has the same layout. User data comes right before user code, the order
+// sysret expects
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
+const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring0);
+
+// NOTE: the boot CPU's tables are static since they are loaded before the heap is needed
+static mut BOOT_TSS: TaskStateSegment = TaskStateSegment::new();
+static mut BOOT_GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+static mut BOOT_IST_STACKS: [[u8; STACK_SIZE]; IST_INDICES.len()] =
+    [[0; STACK_SIZE]; IST_INDICES.len()];
+
+pub fn init() {
+    let tss = unsafe { &mut *addr_of_mut!(BOOT_TSS) };
+
+    for (slot, index) in IST_INDICES.into_iter().enumerate() {
+        let stack = unsafe { addr_of!(BOOT_IST_STACKS[slot]) };
+
+        // NOTE: stacks grow downwards, the table holds the top address
+        tss.interrupt_stack_table[index as usize] = VirtAddr::from_ptr(stack) + STACK_SIZE;
+    }
+
+    unsafe { install(&mut *addr_of_mut!(BOOT_GDT), tss) };
+}
+
+// NOTE: application processors get their own TSS and IST stacks in a GDT of their own. Both
+// live as long as the CPU, so they are leaked
+pub fn init_ap() {
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+
+    for index in IST_INDICES {
+        let stack = alloc::vec![0u8; STACK_SIZE].leak();
+
+        tss.interrupt_stack_table[index as usize] = VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE;
+    }
+
+    install(Box::leak(Box::new(GlobalDescriptorTable::new())), tss);
+}
+
+fn install(gdt: &'static mut GlobalDescriptorTable, tss: &'static mut TaskStateSegment) {
+    let tss: *mut TaskStateSegment = tss;
+
+    assert_eq!(
+        gdt.add_entry(Descriptor::kernel_code_segment()),
+        KERNEL_CODE_SELECTOR
+    );
+    assert_eq!(
+        gdt.add_entry(Descriptor::kernel_data_segment()),
+        KERNEL_DATA_SELECTOR
+    );
+    assert_eq!(
+        gdt.add_entry(Descriptor::user_data_segment()),
+        USER_DATA_SELECTOR
+    );
+    assert_eq!(
+        gdt.add_entry(Descriptor::user_code_segment()),
+        USER_CODE_SELECTOR
+    );
+    assert_eq!(
+        gdt.add_entry(Descriptor::tss_segment(unsafe { &*tss })),
+        TSS_SELECTOR
+    );
+
+    percpu::current().tss.store(tss, Ordering::Relaxed);
+    gdt.load();
+
+    unsafe {
+        CS::set_reg(KERNEL_CODE_SELECTOR);
+        SS::set_reg(KERNEL_DATA_SELECTOR);
+        DS::set_reg(KERNEL_DATA_SELECTOR);
+        ES::set_reg(KERNEL_DATA_SELECTOR);
+        load_tss(TSS_SELECTOR);
+    }
+}
+
+// NOTE: the stack the CPU switches to when an interrupt or a syscall arrives in ring 3, the top
+// of the running thread's kernel stack
+pub fn set_kernel_stack(top: VirtAddr) {
+    let cpu = percpu::current();
+    let tss = cpu.tss.load(Ordering::Relaxed);
+
+    cpu.syscall
+        .kernel_rsp
+        .store(top.as_u64(), Ordering::Relaxed);
+
+    if !tss.is_null() {
+        unsafe { (*tss).privilege_stack_table[0] = top };
+    }
+}
@@ -0,0 +1,272 @@
+use crate::framebuffer::{FrameBuffer, Rgb, FRAMEBUFFER};
+
+// NOTE: anything pixels can be drawn on, coordinates outside of `size` are never passed in
+pub trait Canvas {
+    fn size(&self) -> (usize, usize);
+    fn put_pixel(&mut self, x: usize, y: usize, color: Rgb);
+}
+
+impl Canvas for FrameBuffer {
+    fn size(&self) -> (usize, usize) {
+        (self.width(), self.height())
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        FrameBuffer::put_pixel(self, x, y, color);
+    }
+}
+
+// NOTE: signed so shapes may hang off any edge of the canvas, the outside part is clipped
+pub fn pixel(canvas: &mut impl Canvas, x: i32, y: i32, color: Rgb) {
+    let (width, height) = canvas.size();
+
+    if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+        return;
+    }
+
+    canvas.put_pixel(x as usize, y as usize, color);
+}
+
+// NOTE: Bresenham, both end points are drawn
+pub fn line(canvas: &mut impl Canvas, x0: i32, y0: i32, x1: i32, y1: i32, color: Rgb) {
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let step_x = if x0 < x1 { 1 } else { -1 };
+    let step_y = if y0 < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+
+    loop {
+        pixel(canvas, x, y, color);
+
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let doubled = 2 * error;
+
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+pub fn rect(canvas: &mut impl Canvas, x: i32, y: i32, width: i32, height: i32, color: Rgb) {
+    if width <= 0 || height <= 0 {
+        return;
+    }
+
+    let (right, bottom) = (x + width - 1, y + height - 1);
+
+    line(canvas, x, y, right, y, color);
+    line(canvas, x, bottom, right, bottom, color);
+    line(canvas, x, y, x, bottom, color);
+    line(canvas, right, y, right, bottom, color);
+}
+
+pub fn fill_rect(canvas: &mut impl Canvas, x: i32, y: i32, width: i32, height: i32, color: Rgb) {
+    let (canvas_width, canvas_height) = canvas.size();
+    let x_start = x.max(0);
+    let y_start = y.max(0);
+    let x_end = (x + width).min(canvas_width as i32);
+    let y_end = (y + height).min(canvas_height as i32);
+
+    for py in y_start..y_end {
+        for px in x_start..x_end {
+            canvas.put_pixel(px as usize, py as usize, color);
+        }
+    }
+}
+
+// NOTE: midpoint circle, `visit` gets the offsets of one octant point
+fn midpoint_circle(radius: i32, mut visit: impl FnMut(i32, i32)) {
+    let (mut x, mut y) = (radius, 0);
+    let mut error = 1 - radius;
+
+    while x >= y {
+        visit(x, y);
+        y += 1;
+
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+}
+
+pub fn circle(canvas: &mut impl Canvas, cx: i32, cy: i32, radius: i32, color: Rgb) {
+    if radius < 0 {
+        return;
+    }
+
+    midpoint_circle(radius, |x, y| {
+        for (px, py) in [
+            (x, y),
+            (y, x),
+            (-y, x),
+            (-x, y),
+            (-x, -y),
+            (-y, -x),
+            (y, -x),
+            (x, -y),
+        ] {
+            pixel(canvas, cx + px, cy + py, color);
+        }
+    });
+}
+
+pub fn fill_circle(canvas: &mut impl Canvas, cx: i32, cy: i32, radius: i32, color: Rgb) {
+    if radius < 0 {
+        return;
+    }
+
+    midpoint_circle(radius, |x, y| {
+        fill_rect(canvas, cx - x, cy + y, 2 * x + 1, 1, color);
+        fill_rect(canvas, cx - x, cy - y, 2 * x + 1, 1, color);
+        fill_rect(canvas, cx - y, cy + x, 2 * y + 1, 1, color);
+        fill_rect(canvas, cx - y, cy - x, 2 * y + 1, 1, color);
+    });
+}
+
+// NOTE: 1 bit per pixel, most significant bit first, every row padded to a whole byte;
+// clear bits are left untouched unless a background is given
+pub fn bitmap(
+    canvas: &mut impl Canvas,
+    x: i32,
+    y: i32,
+    width: usize,
+    bits: &[u8],
+    foreground: Rgb,
+    background: Option<Rgb>,
+) {
+    let bytes_per_row = width.div_ceil(8);
+
+    if bytes_per_row == 0 {
+        return;
+    }
+
+    for (row, line) in bits.chunks(bytes_per_row).enumerate() {
+        for col in 0..width {
+            let set = line
+                .get(col / 8)
+                .is_some_and(|byte| byte & (0x80 >> (col % 8)) != 0);
+            let color = if set { Some(foreground) } else { background };
+
+            if let Some(color) = color {
+                pixel(canvas, x + col as i32, y + row as i32, color);
+            }
+        }
+    }
+}
+
+// NOTE: framed bar filled `done / total` from the left
+#[allow(clippy::too_many_arguments)]
+pub fn progress_bar(
+    canvas: &mut impl Canvas,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    done: usize,
+    total: usize,
+    foreground: Rgb,
+    background: Rgb,
+) {
+    let inner_width = width - 4;
+    let filled = match total {
+        0 => inner_width,
+        _ => (inner_width as i64 * done.min(total) as i64 / total as i64) as i32,
+    };
+
+    rect(canvas, x, y, width, height, foreground);
+    fill_rect(canvas, x + 1, y + 1, width - 2, height - 2, background);
+    fill_rect(canvas, x + 2, y + 2, filled, height - 4, foreground);
+}
+
+// NOTE: runs `draw` on the global framebuffer, None when no framebuffer is set up
+pub fn with_framebuffer<R>(draw: impl FnOnce(&mut FrameBuffer) -> R) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| FRAMEBUFFER.lock().as_mut().map(draw))
+}
+
+#[cfg(test)]
+struct TestCanvas {
+    pixels: [[bool; 8]; 8],
+}
+
+#[cfg(test)]
+impl Canvas for TestCanvas {
+    fn size(&self) -> (usize, usize) {
+        (8, 8)
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        self.pixels[y][x] = color != Rgb::BLACK;
+    }
+}
+
+#[cfg(test)]
+fn test_canvas() -> TestCanvas {
+    TestCanvas {
+        pixels: [[false; 8]; 8],
+    }
+}
+
+#[test_case]
+fn test_line() {
+    let mut canvas = test_canvas();
+
+    line(&mut canvas, -2, -2, 9, 9, Rgb::WHITE);
+
+    for i in 0..8 {
+        assert!(canvas.pixels[i][i]);
+    }
+
+    assert_eq!(
+        canvas.pixels.iter().flatten().filter(|&&set| set).count(),
+        8
+    );
+}
+
+#[test_case]
+fn test_circle_and_rect() {
+    let mut canvas = test_canvas();
+
+    circle(&mut canvas, 3, 3, 2, Rgb::WHITE);
+
+    assert!(canvas.pixels[3][5] && canvas.pixels[1][3] && canvas.pixels[3][1]);
+    assert!(!canvas.pixels[3][3]);
+
+    fill_circle(&mut canvas, 3, 3, 2, Rgb::WHITE);
+    assert!(canvas.pixels[3][3]);
+
+    let mut canvas = test_canvas();
+
+    rect(&mut canvas, 1, 1, 4, 3, Rgb::WHITE);
+    assert!(canvas.pixels[1][4] && canvas.pixels[3][1] && canvas.pixels[3][4]);
+    assert!(!canvas.pixels[2][2]);
+}
+
+#[test_case]
+fn test_bitmap() {
+    let mut canvas = test_canvas();
+
+    bitmap(
+        &mut canvas,
+        6,
+        0,
+        3,
+        &[0b1010_0000, 0b0100_0000],
+        Rgb::WHITE,
+        None,
+    );
+
+    assert!(canvas.pixels[0][6] && !canvas.pixels[0][7] && canvas.pixels[1][7]);
+}
@@ -0,0 +1,225 @@
+use crate::acpi::HpetTable;
+use crate::ioapic::{self, Polarity, TriggerMode};
+use crate::memory::{self, MmioRegion};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Once;
+use x86_64::PhysAddr;
+
+const CAPABILITIES: usize = 0x000;
+const CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+
+const fn timer_configuration(timer: usize) -> usize {
+    0x100 + 0x20 * timer
+}
+
+const fn timer_comparator(timer: usize) -> usize {
+    0x108 + 0x20 * timer
+}
+
+const ENABLE: u64 = 1 << 0;
+const COUNTER_64_BIT: u64 = 1 << 13;
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_32_BIT_MODE: u64 = 1 << 8;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1f << TIMER_ROUTE_SHIFT;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+
+// NOTE: only comparator 0 is used for one-shot interrupts
+const ONE_SHOT_TIMER: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    NotPresent,
+    // NOTE: the comparator has no IOAPIC input it can be routed to
+    NoInterruptRoute,
+}
+
+// NOTE: the 1 KiB register block, set once the HPET is enabled
+static REGISTERS: Once<MmioRegion> = Once::new();
+// NOTE: counter period in femtoseconds
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+static ROUTED: AtomicBool = AtomicBool::new(false);
+static ONE_SHOT_CALLBACK: AtomicUsize = AtomicUsize::new(0);
+
+fn registers() -> &'static MmioRegion {
+    REGISTERS.r#try().expect("HPET used before hpet::init")
+}
+
+fn read(offset: usize) -> u64 {
+    registers().register(offset).read()
+}
+
+fn write(offset: usize, value: u64) {
+    registers().register(offset).write(value);
+}
+
+// NOTE: finds the HPET through ACPI and starts its main counter from 0, returns false when
+// there is none
+pub fn init() -> bool {
+    let Some(table) = HpetTable::find() else {
+        return false;
+    };
+
+    if REGISTERS.r#try().is_none() {
+        let Ok(registers) = memory::map_mmio(PhysAddr::new(table.address), 1024) else {
+            return false;
+        };
+
+        REGISTERS.call_once(|| registers);
+    }
+
+    let capabilities = read(CAPABILITIES);
+
+    PERIOD_FS.store(capabilities >> 32, Ordering::Relaxed);
+
+    write(CONFIGURATION, read(CONFIGURATION) & !ENABLE);
+    write(MAIN_COUNTER, 0);
+
+    for timer in 0..=timers_from(capabilities) {
+        let configuration = read(timer_configuration(timer as usize));
+
+        write(
+            timer_configuration(timer as usize),
+            configuration & !TIMER_INTERRUPT_ENABLE,
+        );
+    }
+
+    write(CONFIGURATION, read(CONFIGURATION) | ENABLE);
+
+    true
+}
+
+// NOTE: index of the last comparator, bits 8-12 of the capabilities
+fn timers_from(capabilities: u64) -> u8 {
+    ((capabilities >> 8) & 0x1f) as u8
+}
+
+pub fn is_present() -> bool {
+    REGISTERS.r#try().is_some()
+}
+
+pub fn timers() -> u8 {
+    match is_present() {
+        true => timers_from(read(CAPABILITIES)) + 1,
+        false => 0,
+    }
+}
+
+pub fn period_fs() -> u64 {
+    PERIOD_FS.load(Ordering::Relaxed)
+}
+
+// NOTE: raw main counter, wraps after 2^32 ticks on HPETs without a 64 bit counter
+pub fn counter() -> u64 {
+    match is_present() {
+        true => read(MAIN_COUNTER),
+        false => 0,
+    }
+}
+
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    (ticks as u128 * period_fs() as u128 / FEMTOSECONDS_PER_NANOSECOND as u128) as u64
+}
+
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    match period_fs() {
+        0 => 0,
+        period => (ns as u128 * FEMTOSECONDS_PER_NANOSECOND as u128 / period as u128) as u64,
+    }
+}
+
+// NOTE: nanoseconds since init, 0 without an HPET
+pub fn nanoseconds() -> u64 {
+    ticks_to_ns(counter())
+}
+
+// NOTE: routes comparator 0 to `vector` through the lowest IOAPIC input it supports
+pub fn enable_interrupts(vector: u8) -> Result<(), HpetError> {
+    if !is_present() {
+        return Err(HpetError::NotPresent);
+    }
+
+    let configuration = read(timer_configuration(ONE_SHOT_TIMER));
+    let routes = (configuration >> 32) as u32;
+
+    if routes == 0 {
+        return Err(HpetError::NoInterruptRoute);
+    }
+
+    let gsi = routes.trailing_zeros();
+
+    ioapic::route(gsi, vector, Polarity::ActiveHigh, TriggerMode::Edge)
+        .map_err(|_| HpetError::NoInterruptRoute)?;
+
+    let mut configuration = configuration & !(TIMER_ROUTE_MASK | TIMER_INTERRUPT_ENABLE);
+
+    configuration |= (gsi as u64) << TIMER_ROUTE_SHIFT;
+
+    if read(CAPABILITIES) & COUNTER_64_BIT == 0 {
+        configuration |= TIMER_32_BIT_MODE;
+    }
+
+    write(timer_configuration(ONE_SHOT_TIMER), configuration);
+    ROUTED.store(true, Ordering::Relaxed);
+
+    Ok(())
+}
+
+// NOTE: `callback` runs in interrupt context once `delay_ns` have passed, arming again
+// replaces a pending one-shot
+pub fn arm_one_shot(delay_ns: u64, callback: fn()) -> Result<(), HpetError> {
+    if !is_present() {
+        return Err(HpetError::NotPresent);
+    }
+
+    if !ROUTED.load(Ordering::Relaxed) {
+        return Err(HpetError::NoInterruptRoute);
+    }
+
+    let configuration = read(timer_configuration(ONE_SHOT_TIMER));
+
+    ONE_SHOT_CALLBACK.store(callback as usize, Ordering::Release);
+
+    write(
+        timer_comparator(ONE_SHOT_TIMER),
+        counter().wrapping_add(ns_to_ticks(delay_ns).max(1)),
+    );
+    write(
+        timer_configuration(ONE_SHOT_TIMER),
+        configuration | TIMER_INTERRUPT_ENABLE,
+    );
+
+    Ok(())
+}
+
+// NOTE: to be called by the HPET interrupt handler only
+pub fn handle_interrupt() {
+    let configuration = read(timer_configuration(ONE_SHOT_TIMER));
+
+    write(
+        timer_configuration(ONE_SHOT_TIMER),
+        configuration & !TIMER_INTERRUPT_ENABLE,
+    );
+
+    match ONE_SHOT_CALLBACK.swap(0, Ordering::Acquire) {
+        0 => {}
+        callback => unsafe { core::mem::transmute::<usize, fn()>(callback)() },
+    }
+}
+
+#[test_case]
+fn test_hpet_counter() {
+    if !is_present() {
+        return;
+    }
+
+    let start = nanoseconds();
+
+    crate::pit::wait_polled_ms(10);
+
+    let elapsed = nanoseconds() - start;
+
+    assert!((5_000_000..50_000_000).contains(&elapsed));
+}
@@ -0,0 +1,29 @@
+use crate::fs::{self, TarFs};
+use alloc::sync::Arc;
+
+pub const MOUNT_POINT: &str = "/initrd";
+
+// NOTE: bootloader 0.9 loads the kernel and nothing else, so instead of coming as a boot module
+// the initrd is linked into the kernel by build.rs: the ustar archive RUSTOS_INITRD names at
+// build time, empty without one
+static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
+
+pub fn archive() -> &'static [u8] {
+    ARCHIVE
+}
+
+// NOTE: read only on MOUNT_POINT, a bad archive is reported and left out
+pub fn init() {
+    let vfs = fs::vfs();
+    let initrd = match TarFs::new(ARCHIVE) {
+        Ok(initrd) => initrd,
+        Err(error) => return log::warn!("initrd: bad archive, {:?}", error),
+    };
+
+    vfs.mkdir(MOUNT_POINT)
+        .expect("root filesystem not writable");
+    vfs.mount(MOUNT_POINT, Arc::new(initrd))
+        .expect("initrd already mounted");
+
+    log::info!("initrd: {} bytes on {}", ARCHIVE.len(), MOUNT_POINT);
+}
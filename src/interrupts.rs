@@ -0,0 +1,341 @@
+mod exceptions;
+mod machine_check;
+pub mod nmi;
+pub mod stats;
+pub mod tickless;
+
+use crate::memory::tlb;
+use crate::pic::{self, PIC_1_OFFSET, PIC_2_OFFSET};
+use crate::serial::{self, ComPort};
+use crate::sync::rcu;
+use crate::{apic, hpet, ioapic, percpu, pit, tsc};
+use crate::{keyboard, screensaver, signal, thread, time};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard = PIC_1_OFFSET + 1,
+    Com1 = PIC_1_OFFSET + 4,
+    // NOTE: lowest priority line of each PIC, where spurious IRQs show up
+    PrimarySpurious = PIC_1_OFFSET + 7,
+    SecondarySpurious = PIC_2_OFFSET + 7,
+    // NOTE: past the PIC vectors, only delivered through the IOAPIC
+    Hpet = PIC_2_OFFSET + 8,
+}
+
+impl InterruptIndex {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+
+    // NOTE: ISA IRQ line on the PIC
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+// NOTE: vectors handed out at runtime, e.g. for MSI; they are only ever acknowledged through
+// the local APIC
+pub const DYNAMIC_VECTOR_BASE: u8 = 0x50;
+const DYNAMIC_VECTORS: usize = 16;
+
+// NOTE: fn() pointers, 0 for a free vector
+static DYNAMIC_HANDLERS: [AtomicUsize; DYNAMIC_VECTORS] =
+    [const { AtomicUsize::new(0) }; DYNAMIC_VECTORS];
+
+macro_rules! install_dynamic_handlers {
+    ($idt:ident; $($slot:literal)*) => {
+        $(
+            $idt[DYNAMIC_VECTOR_BASE as usize + $slot]
+                .set_handler_fn(dynamic_interrupt_handler::<$slot>);
+        )*
+    };
+}
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+
+        exceptions::install(&mut idt);
+
+        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Com1.as_usize()].set_handler_fn(com1_interrupt_handler);
+        idt[InterruptIndex::Hpet.as_usize()].set_handler_fn(hpet_interrupt_handler);
+        idt[InterruptIndex::PrimarySpurious.as_usize()]
+            .set_handler_fn(primary_spurious_interrupt_handler);
+        idt[InterruptIndex::SecondarySpurious.as_usize()]
+            .set_handler_fn(secondary_spurious_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
+        idt[tlb::SHOOTDOWN_VECTOR as usize].set_handler_fn(shootdown_interrupt_handler);
+
+        install_dynamic_handlers!(idt; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+        idt
+    };
+}
+
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: spurious interrupts from both PICs and the local APIC
+pub fn spurious_count() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+pub fn init_idt() {
+    IDT.load();
+    machine_check::init();
+}
+
+// NOTE: true once the local APIC drives the timer instead of the PIT
+static APIC_TIMER: AtomicBool = AtomicBool::new(false);
+
+// NOTE: whether the periodic tick comes from the local APIC timer
+pub fn has_apic_timer() -> bool {
+    APIC_TIMER.load(Ordering::Relaxed)
+}
+// NOTE: true once device IRQs come through the IOAPIC instead of the PIC
+static IOAPIC_ROUTING: AtomicBool = AtomicBool::new(false);
+
+const DEVICE_IRQS: [InterruptIndex; 2] = [InterruptIndex::Keyboard, InterruptIndex::Com1];
+
+// NOTE: remaps the PIC and unmasks the IRQs that have a handler, interrupts stay disabled;
+// ticks come from the local APIC timer and device IRQs from the IOAPIC unless they are missing
+// or legacy-pic is enabled
+pub fn init_hardware() {
+    pic::init();
+
+    if !cfg!(feature = "legacy-pic") && apic::init() {
+        apic::start_timer(InterruptIndex::Timer.as_u8(), time::TIMER_HZ as u32);
+        APIC_TIMER.store(true, Ordering::Relaxed);
+        percpu::current()
+            .apic_id
+            .store(apic::id() as u32, Ordering::Relaxed);
+    } else {
+        pit::set_frequency(time::TIMER_HZ as u32);
+        pic::unmask(InterruptIndex::Timer.irq());
+    }
+
+    serial::enable_receive_interrupt(ComPort::Com1);
+
+    if APIC_TIMER.load(Ordering::Relaxed) && ioapic::init() && route_device_irqs() {
+        pic::disable();
+        IOAPIC_ROUTING.store(true, Ordering::Relaxed);
+    } else {
+        for index in DEVICE_IRQS {
+            pic::unmask(index.irq());
+        }
+    }
+
+    // NOTE: without the IOAPIC the HPET is only usable as a counter
+    if hpet::init() && IOAPIC_ROUTING.load(Ordering::Relaxed) {
+        let _ = hpet::enable_interrupts(InterruptIndex::Hpet.as_u8());
+    }
+}
+
+// NOTE: for application processors, the IDT is shared and the APIC timer runs at the rate the
+// boot CPU measured. Device IRQs keep going to the boot CPU
+pub fn init_ap() {
+    init_idt();
+
+    if apic::init_ap() {
+        percpu::current()
+            .apic_id
+            .store(apic::id() as u32, Ordering::Relaxed);
+        apic::resume_timer();
+    }
+}
+
+fn route_device_irqs() -> bool {
+    DEVICE_IRQS
+        .into_iter()
+        .all(|index| ioapic::route_isa(index.irq(), index.as_u8()).is_ok())
+}
+
+// NOTE: `handler` runs in interrupt context, returns None when every dynamic vector is taken
+pub fn allocate_vector(handler: fn()) -> Option<u8> {
+    DYNAMIC_HANDLERS
+        .iter()
+        .position(|slot| {
+            slot.compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })
+        .map(|slot| DYNAMIC_VECTOR_BASE + slot as u8)
+}
+
+// NOTE: the device must no longer raise the vector. Handlers run as RCU readers, so once this
+// returns no CPU is still in the old one and the state it uses can go
+pub fn free_vector(vector: u8) {
+    if let Some(slot) = vector
+        .checked_sub(DYNAMIC_VECTOR_BASE)
+        .and_then(|slot| DYNAMIC_HANDLERS.get(slot as usize))
+    {
+        slot.store(0, Ordering::SeqCst);
+        rcu::synchronize();
+    }
+}
+
+fn end_of_interrupt(index: InterruptIndex) {
+    let apic = match index {
+        InterruptIndex::Timer => &APIC_TIMER,
+        _ => &IOAPIC_ROUTING,
+    };
+
+    match apic.load(Ordering::Relaxed) {
+        true => apic::end_of_interrupt(),
+        false => pic::end_of_interrupt(index.as_u8()),
+    }
+}
+
+// NOTE: runs the handler body, acknowledges the IRQ and records how long it took
+fn handle(index: InterruptIndex, handler: impl FnOnce()) {
+    let start = tsc::read();
+
+    rcu::exit_idle();
+
+    handler();
+    end_of_interrupt(index);
+
+    stats::record(index.as_u8(), tsc::read().wrapping_sub(start));
+}
+
+// NOTE: what each timer interrupt does, run once per tick skipped while the tick was stopped
+// NOTE: every CPU accounts its own thread, only the boot CPU keeps time
+fn timer_tick(ticks: u64) {
+    let boot_cpu = percpu::current().index() == 0;
+
+    for _ in 0..ticks {
+        if boot_cpu {
+            time::tick();
+        }
+
+        thread::tick();
+    }
+
+    if boot_cpu {
+        screensaver::tick(ticks * 1000 / time::TIMER_HZ);
+    }
+}
+
+// NOTE: the tick is also when a signal sent to a thread spinning in ring 3 reaches it
+extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle(InterruptIndex::Timer, || {
+        if !tickless::resume() {
+            timer_tick(1);
+        }
+    });
+
+    thread::preempt_if_needed();
+    signal::on_interrupt_return(&mut stack_frame);
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle(InterruptIndex::Keyboard, keyboard::handle_interrupt);
+}
+
+extern "x86-interrupt" fn com1_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle(InterruptIndex::Com1, || {
+        serial::handle_interrupt(ComPort::Com1)
+    });
+}
+
+extern "x86-interrupt" fn hpet_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle(InterruptIndex::Hpet, hpet::handle_interrupt);
+}
+
+extern "x86-interrupt" fn dynamic_interrupt_handler<const SLOT: usize>(
+    stack_frame: InterruptStackFrame,
+) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    let start = tsc::read();
+
+    rcu::exit_idle();
+
+    match DYNAMIC_HANDLERS[SLOT].load(Ordering::Acquire) {
+        0 => {}
+        handler => unsafe { core::mem::transmute::<usize, fn()>(handler)() },
+    }
+
+    apic::end_of_interrupt();
+    stats::record(
+        DYNAMIC_VECTOR_BASE + SLOT as u8,
+        tsc::read().wrapping_sub(start),
+    );
+}
+
+// NOTE: nothing is attached to IRQ 7 or 15, a real one is only acknowledged; a spurious one
+// must not get an EOI, which would end whatever lower priority IRQ is in service instead
+fn handle_pic_spurious(index: InterruptIndex) {
+    let spurious = match IOAPIC_ROUTING.load(Ordering::Relaxed) {
+        // NOTE: with the PIC masked nothing real comes from it
+        true => true,
+        false => pic::is_spurious(index.irq()),
+    };
+
+    if !spurious {
+        return handle(index, || {});
+    }
+
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+    stats::record(index.as_u8(), 0);
+
+    if index == InterruptIndex::SecondarySpurious {
+        pic::end_of_cascade();
+    }
+}
+
+extern "x86-interrupt" fn primary_spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle_pic_spurious(InterruptIndex::PrimarySpurious);
+}
+
+extern "x86-interrupt" fn secondary_spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    handle_pic_spurious(InterruptIndex::SecondarySpurious);
+}
+
+// NOTE: a spurious APIC interrupt must not be acknowledged with an EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+    stats::record(apic::SPURIOUS_VECTOR, 0);
+}
+
+extern "x86-interrupt" fn shootdown_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    tlb::service();
+    apic::end_of_interrupt();
+}
+
+#[test_case]
+fn test_allocate_vector() {
+    fn handler() {}
+
+    let vector = allocate_vector(handler).unwrap();
+
+    assert!((DYNAMIC_VECTOR_BASE..DYNAMIC_VECTOR_BASE + DYNAMIC_VECTORS as u8).contains(&vector));
+    free_vector(vector);
+    assert_eq!(allocate_vector(handler), Some(vector));
+    free_vector(vector);
+}
@@ -0,0 +1,343 @@
+use super::{machine_check, nmi};
+use crate::gdt;
+use crate::memory::{cow, demand, stack};
+use crate::{hlt_loop, klog, percpu, println, process, signal, uaccess};
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.vmm_communication_exception
+        .set_handler_fn(vmm_communication_handler);
+    idt.security_exception
+        .set_handler_fn(security_exception_handler);
+
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt
+            .set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.machine_check
+            .set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+}
+
+// NOTE: error code pushed by exceptions that refer to a segment selector or IDT vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SelectorErrorCode(u64);
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "none");
+        }
+
+        let table = match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        };
+        let origin = if self.0 & 1 != 0 { ", external" } else { "" };
+
+        write!(f, "{} index {:#x}{}", table, (self.0 >> 3) & 0x1fff, origin)
+    }
+}
+
+fn report_header(name: &str, error: Option<&dyn fmt::Display>) {
+    println!("EXCEPTION: {}", name);
+
+    if let Some(error) = error {
+        println!("  error:   {}", error);
+    }
+}
+
+fn report_frame(stack_frame: &InterruptStackFrame) {
+    println!("  rip:     {:?}", stack_frame.instruction_pointer);
+    println!("  rsp:     {:?}", stack_frame.stack_pointer);
+    println!("{:#?}", stack_frame);
+}
+
+fn report(name: &str, stack_frame: &InterruptStackFrame, error: Option<&dyn fmt::Display>) {
+    report_header(name, error);
+    report_frame(stack_frame);
+}
+
+// NOTE: faults restart the faulting instruction, returning would only fault again; one in ring
+// 3 raises SIGSEGV in the process instead, which the handler returns to deliver
+macro_rules! fatal_handler {
+    ($handler:ident, $name:expr) => {
+        extern "x86-interrupt" fn $handler(mut stack_frame: InterruptStackFrame) {
+            let _gs = percpu::KernelGs::enter(&stack_frame);
+
+            report($name, &stack_frame, None);
+            if signal::user_fault(&mut stack_frame) {
+                return;
+            }
+
+            hlt_loop();
+        }
+    };
+    ($handler:ident, $name:expr, selector) => {
+        extern "x86-interrupt" fn $handler(mut stack_frame: InterruptStackFrame, error_code: u64) {
+            let _gs = percpu::KernelGs::enter(&stack_frame);
+
+            report($name, &stack_frame, Some(&SelectorErrorCode(error_code)));
+            if signal::user_fault(&mut stack_frame) {
+                return;
+            }
+
+            hlt_loop();
+        }
+    };
+    ($handler:ident, $name:expr, error_code) => {
+        extern "x86-interrupt" fn $handler(mut stack_frame: InterruptStackFrame, error_code: u64) {
+            let _gs = percpu::KernelGs::enter(&stack_frame);
+
+            report(
+                $name,
+                &stack_frame,
+                Some(&format_args!("{:#x}", error_code)),
+            );
+            if signal::user_fault(&mut stack_frame) {
+                return;
+            }
+
+            hlt_loop();
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, "DIVIDE ERROR");
+fatal_handler!(overflow_handler, "OVERFLOW");
+fatal_handler!(bound_range_exceeded_handler, "BOUND RANGE EXCEEDED");
+fatal_handler!(invalid_opcode_handler, "INVALID OPCODE");
+fatal_handler!(device_not_available_handler, "DEVICE NOT AVAILABLE");
+fatal_handler!(invalid_tss_handler, "INVALID TSS", selector);
+fatal_handler!(segment_not_present_handler, "SEGMENT NOT PRESENT", selector);
+fatal_handler!(stack_segment_fault_handler, "STACK SEGMENT FAULT", selector);
+fatal_handler!(
+    general_protection_fault_handler,
+    "GENERAL PROTECTION FAULT",
+    selector
+);
+fatal_handler!(x87_floating_point_handler, "X87 FLOATING POINT");
+fatal_handler!(alignment_check_handler, "ALIGNMENT CHECK", error_code);
+fatal_handler!(simd_floating_point_handler, "SIMD FLOATING POINT");
+fatal_handler!(virtualization_handler, "VIRTUALIZATION");
+fatal_handler!(vmm_communication_handler, "VMM COMMUNICATION", error_code);
+fatal_handler!(security_exception_handler, "SECURITY EXCEPTION", error_code);
+
+// NOTE: debug exceptions are traps (single step, breakpoints in DR0-DR3), execution goes on
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    report("DEBUG", &stack_frame, None);
+}
+
+// NOTE: NMIs the watchdog doesn't claim are reported but not fatal, execution goes on. The NMI
+// may have come while this CPU holds a console lock, so the report only goes into the lock free
+// klog ring and shows up with the next print
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    if nmi::run_watchdog(&stack_frame) {
+        return;
+    }
+
+    let (system_error, io_check) = nmi::legacy_reasons();
+
+    klog::push_only(format_args!(
+        "EXCEPTION: NON MASKABLE INTERRUPT\n  reason:  system error {}, i/o channel check {}\n  \
+         rip:     {:?}\n  rsp:     {:?}\n",
+        system_error, io_check, stack_frame.instruction_pointer, stack_frame.stack_pointer
+    ));
+}
+
+// NOTE: the error banks tell what failed, the context may be corrupt so nothing is resumed
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    report_header("MACHINE CHECK", None);
+    machine_check::report_banks();
+    report_frame(&stack_frame);
+    hlt_loop();
+}
+
+// NOTE: int3 is a trap, execution continues right after the instruction
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    report("BREAKPOINT", &stack_frame, None);
+}
+
+// NOTE: the error code of a double fault is always 0
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+// NOTE: (access, page state, privilege) in words, e.g. ("write", "not present", "kernel")
+fn describe_page_fault(
+    error_code: PageFaultErrorCode,
+) -> (&'static str, &'static str, &'static str) {
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write"
+    } else {
+        "read"
+    };
+    let page = if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+        "reserved bit set in page table"
+    } else if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation"
+    } else {
+        "not present"
+    };
+    let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+
+    (access, page, mode)
+}
+
+// NOTE: only faults on demand paged regions, the areas of user processes and copy-on-write
+// pages are resolved, anything else would just fault again
+extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let _gs = percpu::KernelGs::enter(&stack_frame);
+
+    let (access, page, mode) = describe_page_fault(error_code);
+    let address = Cr2::read();
+
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && (demand::handle_page_fault(address) || process::handle_page_fault(address))
+    {
+        return;
+    }
+
+    if error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && (cow::handle_write_fault(address) || process::handle_write_fault(address))
+    {
+        return;
+    }
+
+    if uaccess::fixup_fault(&mut stack_frame) {
+        return;
+    }
+
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        report_header("PAGE FAULT", Some(&format_args!("{:?}", error_code)));
+        println!("  address: {:?}", address);
+        println!("  cause:   {} in {} mode, {}", access, mode, page);
+
+        if signal::user_fault(&mut stack_frame) {
+            return;
+        }
+    }
+
+    if let Some(owner) = stack::guard_page_owner(address) {
+        report_header(
+            "KERNEL STACK OVERFLOW",
+            Some(&format_args!("in task {}", owner)),
+        );
+        println!("  address: {:?}", address);
+        report_frame(&stack_frame);
+
+        hlt_loop();
+    }
+
+    report_header("PAGE FAULT", Some(&format_args!("{:?}", error_code)));
+    println!("  address: {:?}", address);
+    println!("  cause:   {} in {} mode, {}", access, mode, page);
+    report_frame(&stack_frame);
+
+    hlt_loop();
+}
+
+#[test_case]
+fn test_describe_page_fault() {
+    assert_eq!(
+        describe_page_fault(PageFaultErrorCode::CAUSED_BY_WRITE),
+        ("write", "not present", "kernel")
+    );
+    assert_eq!(
+        describe_page_fault(
+            PageFaultErrorCode::PROTECTION_VIOLATION
+                | PageFaultErrorCode::INSTRUCTION_FETCH
+                | PageFaultErrorCode::USER_MODE
+        ),
+        ("instruction fetch", "protection violation", "user")
+    );
+}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn test_selector_error_code() {
+    use core::fmt::Write;
+
+    struct Text {
+        bytes: [u8; 32],
+        len: usize,
+    }
+
+    impl Write for Text {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+
+            Ok(())
+        }
+    }
+
+    let mut text = Text {
+        bytes: [0; 32],
+        len: 0,
+    };
+
+    // NOTE: IDT entry 0x0d, raised by an external event
+    write!(text, "{}", SelectorErrorCode(0x0d << 3 | 0b011)).unwrap();
+
+    assert_eq!(&text.bytes[..text.len], b"IDT index 0xd, external");
+}
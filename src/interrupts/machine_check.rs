@@ -0,0 +1,111 @@
+use crate::println;
+use core::arch::x86_64::__cpuid;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MC0_STATUS: u32 = 0x401;
+
+const MCG_BANK_COUNT: u64 = 0xff;
+// NOTE: restart IP valid, error IP valid, machine check in progress
+const MCG_RIPV: u64 = 1 << 0;
+const MCG_EIPV: u64 = 1 << 1;
+const MCG_MCIP: u64 = 1 << 2;
+
+const MCI_VALID: u64 = 1 << 63;
+const MCI_OVERFLOW: u64 = 1 << 62;
+const MCI_UNCORRECTED: u64 = 1 << 61;
+const MCI_MISC_VALID: u64 = 1 << 59;
+const MCI_ADDRESS_VALID: u64 = 1 << 58;
+const MCI_CONTEXT_CORRUPT: u64 = 1 << 57;
+
+// NOTE: CPUID leaf 1, EDX bit 7 (machine check exception) and 14 (architecture)
+const CPUID_MCE: u32 = 1 << 7;
+const CPUID_MCA: u32 = 1 << 14;
+
+pub fn is_supported() -> bool {
+    __cpuid(1).edx & (CPUID_MCE | CPUID_MCA) == CPUID_MCE | CPUID_MCA
+}
+
+// NOTE: without CR4.MCE a machine check shuts the CPU down instead of raising #MC
+pub(super) fn init() {
+    if is_supported() {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+    }
+}
+
+fn read(msr: u32) -> u64 {
+    unsafe { Msr::new(msr).read() }
+}
+
+fn bank_count() -> u32 {
+    match is_supported() {
+        true => (read(IA32_MCG_CAP) & MCG_BANK_COUNT) as u32,
+        false => 0,
+    }
+}
+
+// NOTE: ADDR and MISC are only readable when the status says they hold something
+pub(super) fn report_banks() {
+    if !is_supported() {
+        println!("  machine check architecture not supported");
+
+        return;
+    }
+
+    let status = read(IA32_MCG_STATUS);
+
+    println!(
+        "  MCG_STATUS: {:#x} (ripv {}, eipv {}, mcip {})",
+        status,
+        status & MCG_RIPV != 0,
+        status & MCG_EIPV != 0,
+        status & MCG_MCIP != 0
+    );
+
+    for bank in 0..bank_count() {
+        let status = read(IA32_MC0_STATUS + bank * 4);
+
+        if status & MCI_VALID == 0 {
+            continue;
+        }
+
+        println!(
+            "  MC{}_STATUS: {:#018x}{}{}{}",
+            bank,
+            status,
+            if status & MCI_UNCORRECTED != 0 {
+                " uncorrected"
+            } else {
+                ""
+            },
+            if status & MCI_CONTEXT_CORRUPT != 0 {
+                " context-corrupt"
+            } else {
+                ""
+            },
+            if status & MCI_OVERFLOW != 0 {
+                " overflow"
+            } else {
+                ""
+            }
+        );
+
+        if status & MCI_ADDRESS_VALID != 0 {
+            println!(
+                "  MC{}_ADDR:   {:#018x}",
+                bank,
+                read(IA32_MC0_STATUS + bank * 4 + 1)
+            );
+        }
+
+        if status & MCI_MISC_VALID != 0 {
+            println!(
+                "  MC{}_MISC:   {:#018x}",
+                bank,
+                read(IA32_MC0_STATUS + bank * 4 + 2)
+            );
+        }
+    }
+}
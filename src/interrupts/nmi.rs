@@ -0,0 +1,43 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+// NOTE: system control port B, NMI sources in the high bits
+const SYSTEM_CONTROL_PORT: u16 = 0x61;
+const SERR: u8 = 1 << 7;
+const IOCHK: u8 = 1 << 6;
+
+// NOTE: returns true when it recognised the NMI as its own, e.g. a perf counter overflow
+pub type NmiCallback = fn(&InterruptStackFrame) -> bool;
+
+// NOTE: NmiCallback pointer, 0 when none is registered
+static WATCHDOG: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: replaces the previous callback, None removes it
+pub fn set_watchdog(callback: Option<NmiCallback>) {
+    WATCHDOG.store(
+        callback.map_or(0, |callback| callback as usize),
+        Ordering::Release,
+    );
+}
+
+pub fn count() -> u64 {
+    COUNT.load(Ordering::Relaxed)
+}
+
+pub(super) fn run_watchdog(stack_frame: &InterruptStackFrame) -> bool {
+    COUNT.fetch_add(1, Ordering::Relaxed);
+
+    match WATCHDOG.load(Ordering::Acquire) {
+        0 => false,
+        callback => unsafe { core::mem::transmute::<usize, NmiCallback>(callback)(stack_frame) },
+    }
+}
+
+// NOTE: (memory/system error, I/O channel check) from the legacy NMI status bits
+pub(super) fn legacy_reasons() -> (bool, bool) {
+    let status = unsafe { Port::<u8>::new(SYSTEM_CONTROL_PORT).read() };
+
+    (status & SERR != 0, status & IOCHK != 0)
+}
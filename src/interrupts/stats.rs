@@ -0,0 +1,77 @@
+use crate::tsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const VECTORS: usize = 256;
+
+// NOTE: handler latency is kept in TSC cycles so recording stays cheap, it is only converted
+// to nanoseconds when taking a snapshot
+static COUNTS: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+static MAX_CYCLES: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+
+pub(super) fn record(vector: u8, cycles: u64) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+    MAX_CYCLES[vector as usize].fetch_max(cycles, Ordering::Relaxed);
+}
+
+pub fn count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorStats {
+    pub vector: u8,
+    pub count: u64,
+    pub max_latency_ns: u64,
+}
+
+pub struct Snapshot {
+    counts: [u64; VECTORS],
+    max_cycles: [u64; VECTORS],
+}
+
+impl Snapshot {
+    pub fn get(&self, vector: u8) -> VectorStats {
+        VectorStats {
+            vector,
+            count: self.counts[vector as usize],
+            max_latency_ns: tsc::cycles_to_ns(self.max_cycles[vector as usize]),
+        }
+    }
+
+    // NOTE: only vectors that fired at least once
+    pub fn iter(&self) -> impl Iterator<Item = VectorStats> + '_ {
+        (0..=u8::MAX)
+            .map(|vector| self.get(vector))
+            .filter(|stats| stats.count > 0)
+    }
+}
+
+// NOTE: vectors are read one by one, counts of different vectors may be a few IRQs apart
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        counts: core::array::from_fn(|vector| COUNTS[vector].load(Ordering::Relaxed)),
+        max_cycles: core::array::from_fn(|vector| MAX_CYCLES[vector].load(Ordering::Relaxed)),
+    }
+}
+
+// NOTE: clears counts and latencies, e.g. before measuring a workload
+pub fn reset() {
+    for vector in 0..VECTORS {
+        COUNTS[vector].store(0, Ordering::Relaxed);
+        MAX_CYCLES[vector].store(0, Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_timer_counted() {
+    let timer = super::InterruptIndex::Timer.as_u8();
+    let start = count(timer);
+    let start_ticks = crate::time::ticks();
+
+    while crate::time::ticks() == start_ticks {
+        x86_64::instructions::hlt();
+    }
+
+    assert!(count(timer) > start);
+    assert!(snapshot().iter().any(|stats| stats.vector == timer));
+}
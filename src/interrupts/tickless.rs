@@ -0,0 +1,101 @@
+use crate::sync::rcu;
+use crate::{apic, percpu, time};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+// NOTE: longest the tick stays stopped without a deadline, bounds how late tick driven work
+// like the screensaver notices the idle time
+pub const MAX_IDLE_TICKS: u64 = time::TIMER_HZ;
+
+const NS_PER_TICK: u64 = 1_000_000_000 / time::TIMER_HZ;
+
+static STOPPED: AtomicBool = AtomicBool::new(false);
+// NOTE: tick count and monotonic time when the tick was stopped
+static STOPPED_AT_TICK: AtomicU64 = AtomicU64::new(0);
+static STOPPED_AT_NS: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: the ticks skipped are counted from a steady clock, without one the tick keeps running.
+// Only the boot CPU keeps time, the others just halt
+fn can_stop() -> bool {
+    super::has_apic_timer()
+        && time::clock_source() != time::ClockSource::Ticks
+        && percpu::current().index() == 0
+}
+
+pub fn is_stopped() -> bool {
+    STOPPED.load(Ordering::Relaxed)
+}
+
+// NOTE: to be called with interrupts disabled when there is nothing to do, returns with them
+// enabled after the next interrupt. The periodic tick is replaced by a one-shot timer for the
+// next sleep deadline meanwhile, so an idle CPU is not woken a thousand times a second
+pub fn idle_halt() {
+    rcu::enter_idle();
+    halt();
+    rcu::exit_idle();
+}
+
+fn halt() {
+    let now = time::ticks();
+    let idle_ticks = time::next_deadline()
+        .map_or(MAX_IDLE_TICKS, |deadline| deadline.saturating_sub(now))
+        .min(MAX_IDLE_TICKS);
+
+    if !can_stop() || idle_ticks <= 1 {
+        return interrupts::enable_and_hlt();
+    }
+
+    STOPPED_AT_TICK.store(now, Ordering::Relaxed);
+    STOPPED_AT_NS.store(time::monotonic_ns(), Ordering::Relaxed);
+    STOPPED.store(true, Ordering::Relaxed);
+    apic::oneshot_timer(idle_ticks * NS_PER_TICK / 1000);
+
+    interrupts::enable_and_hlt();
+    interrupts::without_interrupts(resume);
+}
+
+// NOTE: restarts the periodic tick and accounts the ticks that passed since it was stopped,
+// false if it was running. Called by the timer interrupt handler too, whichever interrupt
+// ends the halt; interrupts have to be disabled
+pub(super) fn resume() -> bool {
+    if percpu::current().index() != 0 || !STOPPED.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+
+    apic::resume_timer();
+
+    let elapsed = time::monotonic_ns().saturating_sub(STOPPED_AT_NS.load(Ordering::Relaxed));
+    let target =
+        STOPPED_AT_TICK.load(Ordering::Relaxed) + (elapsed + NS_PER_TICK / 2) / NS_PER_TICK;
+
+    super::timer_tick(target.saturating_sub(time::ticks()));
+
+    true
+}
+
+#[test_case]
+fn test_idle_halt_keeps_time() {
+    let start_ticks = time::ticks();
+    let start = time::monotonic_ns();
+    use core::future::Future;
+
+    let mut sleep = core::pin::pin!(time::sleep(core::time::Duration::from_millis(20)));
+    let waker = futures_util::task::noop_waker();
+
+    assert!(sleep
+        .as_mut()
+        .poll(&mut core::task::Context::from_waker(&waker))
+        .is_pending());
+
+    while time::ticks() < start_ticks + 20 {
+        interrupts::disable();
+        idle_halt();
+
+        assert!(!is_stopped());
+    }
+
+    let elapsed_ticks = time::ticks() - start_ticks;
+    let elapsed_ms = (time::monotonic_ns() - start) / 1_000_000;
+
+    assert!(elapsed_ticks.abs_diff(elapsed_ms) <= 2);
+}
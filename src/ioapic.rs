@@ -0,0 +1,270 @@
+use crate::acpi::{Madt, MadtEntry};
+use crate::apic;
+use crate::memory::{self, MmioRegion};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::PhysAddr;
+
+const MAX_IO_APICS: usize = 8;
+const ISA_IRQS: usize = 16;
+
+// NOTE: registers are accessed indirectly, select one through IOREGSEL then use IOWIN
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    NotPresent,
+    InvalidGsi(u32),
+}
+
+// NOTE: how an ISA IRQ is wired, the MADT overrides the identity mapping for some of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl IsaRoute {
+    const fn identity(irq: u8) -> IsaRoute {
+        IsaRoute {
+            gsi: irq as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger: TriggerMode::Edge,
+        }
+    }
+
+    // NOTE: MPS INTI flags, 0b00 means conforming to the bus (edge, active high for ISA)
+    fn from_flags(gsi: u32, flags: u16) -> IsaRoute {
+        IsaRoute {
+            gsi,
+            polarity: match flags & 0b11 {
+                0b11 => Polarity::ActiveLow,
+                _ => Polarity::ActiveHigh,
+            },
+            trigger: match (flags >> 2) & 0b11 {
+                0b11 => TriggerMode::Level,
+                _ => TriggerMode::Edge,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IoApic {
+    registers: MmioRegion,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        self.registers.register(IOREGSEL).write(register);
+        self.registers.register(IOWIN).read()
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        self.registers.register(IOREGSEL).write(register);
+        self.registers.register(IOWIN).write(value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+
+        (self.read(register + 1) as u64) << 32 | self.read(register) as u64
+    }
+
+    // NOTE: the low half holds the mask bit, write it last so the entry is never half updated
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+
+        self.write(register, MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+struct Routing {
+    io_apics: [Option<IoApic>; MAX_IO_APICS],
+    isa: [IsaRoute; ISA_IRQS],
+}
+
+impl Routing {
+    fn io_apic(&self, gsi: u32) -> Result<&IoApic, IoApicError> {
+        if self.io_apics[0].is_none() {
+            return Err(IoApicError::NotPresent);
+        }
+
+        self.io_apics
+            .iter()
+            .flatten()
+            .find(|io_apic| io_apic.handles(gsi))
+            .ok_or(IoApicError::InvalidGsi(gsi))
+    }
+}
+
+static ROUTING: Mutex<Routing> = Mutex::new(Routing {
+    io_apics: [None; MAX_IO_APICS],
+    isa: {
+        let mut isa = [IsaRoute::identity(0); ISA_IRQS];
+        let mut irq = 0;
+
+        while irq < ISA_IRQS {
+            isa[irq] = IsaRoute::identity(irq as u8);
+            irq += 1;
+        }
+
+        isa
+    },
+});
+
+// NOTE: fixed delivery to a single APIC in physical destination mode
+fn redirection_entry(vector: u8, polarity: Polarity, trigger: TriggerMode, destination: u8) -> u64 {
+    let mut entry = vector as u64 | (destination as u64) << 56;
+
+    if polarity == Polarity::ActiveLow {
+        entry |= ACTIVE_LOW;
+    }
+
+    if trigger == TriggerMode::Level {
+        entry |= LEVEL_TRIGGERED;
+    }
+
+    entry
+}
+
+// NOTE: finds every IOAPIC and ISA override in the MADT and masks all their inputs, returns
+// false when there is no IOAPIC
+pub fn init() -> bool {
+    let Some(madt) = Madt::find() else {
+        return false;
+    };
+
+    interrupts::without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let mut count = 0;
+
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::IoApic {
+                    address, gsi_base, ..
+                } if count < MAX_IO_APICS => {
+                    let Ok(registers) = memory::map_mmio(PhysAddr::new(address as u64), 0x20)
+                    else {
+                        continue;
+                    };
+                    let mut io_apic = IoApic {
+                        registers,
+                        gsi_base,
+                        entries: 0,
+                    };
+
+                    io_apic.entries = ((io_apic.read(IOAPICVER) >> 16) & 0xff) + 1;
+
+                    for gsi in gsi_base..gsi_base + io_apic.entries {
+                        io_apic.write_entry(gsi, MASKED);
+                    }
+
+                    routing.io_apics[count] = Some(io_apic);
+                    count += 1;
+                }
+                MadtEntry::InterruptOverride {
+                    source, gsi, flags, ..
+                } if (source as usize) < ISA_IRQS => {
+                    routing.isa[source as usize] = IsaRoute::from_flags(gsi, flags);
+                }
+                _ => {}
+            }
+        }
+
+        count > 0
+    })
+}
+
+pub fn isa_route(irq: u8) -> IsaRoute {
+    interrupts::without_interrupts(|| ROUTING.lock().isa[irq as usize % ISA_IRQS])
+}
+
+// NOTE: delivers `gsi` to the current CPU's local APIC, the entry starts out unmasked
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    polarity: Polarity,
+    trigger: TriggerMode,
+) -> Result<(), IoApicError> {
+    let entry = redirection_entry(vector, polarity, trigger, apic::id());
+
+    interrupts::without_interrupts(|| {
+        ROUTING.lock().io_apic(gsi)?.write_entry(gsi, entry);
+
+        Ok(())
+    })
+}
+
+// NOTE: like route, but uses the GSI, polarity and trigger mode the firmware reported
+pub fn route_isa(irq: u8, vector: u8) -> Result<(), IoApicError> {
+    let isa = isa_route(irq);
+
+    route(isa.gsi, vector, isa.polarity, isa.trigger)
+}
+
+pub fn set_masked(gsi: u32, masked: bool) -> Result<(), IoApicError> {
+    interrupts::without_interrupts(|| {
+        let routing = ROUTING.lock();
+        let io_apic = routing.io_apic(gsi)?;
+        let entry = io_apic.read_entry(gsi);
+
+        io_apic.write_entry(
+            gsi,
+            match masked {
+                true => entry | MASKED,
+                false => entry & !MASKED,
+            },
+        );
+
+        Ok(())
+    })
+}
+
+#[test_case]
+fn test_redirection_entry() {
+    assert_eq!(
+        redirection_entry(0x21, Polarity::ActiveHigh, TriggerMode::Edge, 0),
+        0x21
+    );
+    assert_eq!(
+        redirection_entry(0x30, Polarity::ActiveLow, TriggerMode::Level, 3),
+        0x0300_0000_0000_a030
+    );
+    assert_eq!(
+        IsaRoute::from_flags(2, 0x0d),
+        IsaRoute {
+            gsi: 2,
+            polarity: Polarity::ActiveHigh,
+            trigger: TriggerMode::Level
+        }
+    );
+}
@@ -0,0 +1,409 @@
+use crate::queue::ByteQueue;
+use crate::screensaver;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+// NOTE: filled by the IRQ 1 handler, decoded by whoever consumes the key events
+static SCANCODES: ByteQueue<SCANCODE_QUEUE_SIZE> = ByteQueue::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+// NOTE: set while a KeyEventStream exists, the queue has a single consumer
+static CLAIMED: AtomicBool = AtomicBool::new(false);
+
+// NOTE: to be called by the keyboard interrupt handler
+pub fn handle_interrupt() {
+    let scancode = unsafe { Port::<u8>::new(DATA_PORT).read() };
+
+    if !SCANCODES.push(scancode) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    SCANCODES.wake();
+    screensaver::activity();
+}
+
+// NOTE: scancodes lost because nobody read the queue in time
+pub fn dropped_scancodes() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    // NOTE: printable key, holding the character it produces without modifiers
+    Char(char),
+    // NOTE: keypad digits and operators, keypad Enter is reported as Enter
+    Keypad(char),
+    Escape,
+    Backspace,
+    Tab,
+    Enter,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    RightAlt,
+    LeftSuper,
+    RightSuper,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    F(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Pressed,
+    Released,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub caps_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+
+    fn update(&mut self, code: KeyCode, state: KeyState) {
+        let pressed = state == KeyState::Pressed;
+
+        match code {
+            KeyCode::LeftShift => self.left_shift = pressed,
+            KeyCode::RightShift => self.right_shift = pressed,
+            KeyCode::LeftCtrl => self.left_ctrl = pressed,
+            KeyCode::RightCtrl => self.right_ctrl = pressed,
+            KeyCode::LeftAlt => self.left_alt = pressed,
+            KeyCode::RightAlt => self.right_alt = pressed,
+            KeyCode::CapsLock if pressed => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    // NOTE: modifier state after this event was applied
+    pub modifiers: Modifiers,
+}
+
+// NOTE: US layout, shifted counterpart of every unshifted printable key
+fn shifted(c: char) -> char {
+    match c {
+        'a'..='z' => c.to_ascii_uppercase(),
+        '1' => '!',
+        '2' => '@',
+        '3' => '#',
+        '4' => '$',
+        '5' => '%',
+        '6' => '^',
+        '7' => '&',
+        '8' => '*',
+        '9' => '(',
+        '0' => ')',
+        '-' => '_',
+        '=' => '+',
+        '[' => '{',
+        ']' => '}',
+        '\\' => '|',
+        ';' => ':',
+        '\'' => '"',
+        '`' => '~',
+        ',' => '<',
+        '.' => '>',
+        '/' => '?',
+        c => c,
+    }
+}
+
+impl KeyEvent {
+    // NOTE: the character typed by a key press, Ctrl+letter gives the matching control
+    // character (Ctrl+C = 0x03)
+    pub fn character(&self) -> Option<char> {
+        if self.state != KeyState::Pressed {
+            return None;
+        }
+
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.ctrl() && c.is_ascii_lowercase() => {
+                Some(char::from(c as u8 - b'a' + 1))
+            }
+            KeyCode::Char(c) => {
+                let upper =
+                    self.modifiers.shift() ^ (self.modifiers.caps_lock && c.is_ascii_lowercase());
+
+                Some(if upper { shifted(c) } else { c })
+            }
+            KeyCode::Keypad(c) => Some(c),
+            KeyCode::Enter => Some('\n'),
+            KeyCode::Tab => Some('\t'),
+            KeyCode::Backspace => Some('\x08'),
+            KeyCode::Escape => Some('\x1b'),
+            _ => None,
+        }
+    }
+}
+
+const EXTENDED_PREFIX: u8 = 0xe0;
+const RELEASE_BIT: u8 = 0x80;
+
+const LETTER_ROWS: [(u8, &str); 4] = [
+    (0x02, "1234567890-="),
+    (0x10, "qwertyuiop[]"),
+    (0x1e, "asdfghjkl;'`"),
+    (0x2b, "\\zxcvbnm,./"),
+];
+
+fn key_code(make: u8, extended: bool) -> Option<KeyCode> {
+    if extended {
+        return Some(match make {
+            0x1c => KeyCode::Enter,
+            0x1d => KeyCode::RightCtrl,
+            0x35 => KeyCode::Keypad('/'),
+            0x38 => KeyCode::RightAlt,
+            0x47 => KeyCode::Home,
+            0x48 => KeyCode::Up,
+            0x49 => KeyCode::PageUp,
+            0x4b => KeyCode::Left,
+            0x4d => KeyCode::Right,
+            0x4f => KeyCode::End,
+            0x50 => KeyCode::Down,
+            0x51 => KeyCode::PageDown,
+            0x52 => KeyCode::Insert,
+            0x53 => KeyCode::Delete,
+            0x5b => KeyCode::LeftSuper,
+            0x5c => KeyCode::RightSuper,
+            // NOTE: includes the fake shifts sent around Print Screen
+            _ => return None,
+        });
+    }
+
+    for (first, keys) in LETTER_ROWS {
+        if let Some(c) = make
+            .checked_sub(first)
+            .and_then(|index| keys.chars().nth(index as usize))
+        {
+            return Some(KeyCode::Char(c));
+        }
+    }
+
+    Some(match make {
+        0x01 => KeyCode::Escape,
+        0x0e => KeyCode::Backspace,
+        0x0f => KeyCode::Tab,
+        0x1c => KeyCode::Enter,
+        0x1d => KeyCode::LeftCtrl,
+        0x2a => KeyCode::LeftShift,
+        0x36 => KeyCode::RightShift,
+        0x37 => KeyCode::Keypad('*'),
+        0x38 => KeyCode::LeftAlt,
+        0x39 => KeyCode::Char(' '),
+        0x3a => KeyCode::CapsLock,
+        0x3b..=0x44 => KeyCode::F(make - 0x3b + 1),
+        0x45 => KeyCode::NumLock,
+        0x46 => KeyCode::ScrollLock,
+        0x47..=0x53 => KeyCode::Keypad(b"789-456+1230."[(make - 0x47) as usize] as char),
+        0x57 => KeyCode::F(11),
+        0x58 => KeyCode::F(12),
+        _ => return None,
+    })
+}
+
+// NOTE: scancode set 1 state machine, feed it one byte at a time
+#[derive(Debug, Default)]
+pub struct Decoder {
+    extended: bool,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder {
+            extended: false,
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                left_alt: false,
+                right_alt: false,
+                caps_lock: false,
+            },
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+
+            return None;
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let state = match scancode & RELEASE_BIT {
+            0 => KeyState::Pressed,
+            _ => KeyState::Released,
+        };
+        let code = key_code(scancode & !RELEASE_BIT, extended)?;
+
+        self.modifiers.update(code, state);
+
+        Some(KeyEvent {
+            code,
+            state,
+            modifiers: self.modifiers,
+        })
+    }
+}
+
+// NOTE: raw scancodes, there should be only one consumer
+pub fn try_read_scancode() -> Option<u8> {
+    SCANCODES.pop()
+}
+
+pub struct ScancodeStream;
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        SCANCODES.poll_pop(cx).map(Some)
+    }
+}
+
+// NOTE: decoded key events, e.g. `while let Some(key) = keys.next().await`; the task is woken
+// by the IRQ handler, nothing polls in between
+pub struct KeyEventStream {
+    decoder: Decoder,
+}
+
+// NOTE: None while another KeyEventStream is alive
+pub fn keys() -> Option<KeyEventStream> {
+    CLAIMED
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .ok()?;
+
+    Some(KeyEventStream {
+        decoder: Decoder::new(),
+    })
+}
+
+impl Drop for KeyEventStream {
+    fn drop(&mut self) {
+        CLAIMED.store(false, Ordering::Release);
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        loop {
+            let scancode = match SCANCODES.poll_pop(cx) {
+                Poll::Ready(scancode) => scancode,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Some(event) = self.decoder.add_byte(scancode) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_key_event_stream() {
+    use futures_util::stream::StreamExt;
+
+    let mut stream = keys().unwrap();
+    let mut context = Context::from_waker(futures_util::task::noop_waker_ref());
+
+    assert!(keys().is_none());
+    assert_eq!(stream.poll_next_unpin(&mut context), Poll::Pending);
+
+    SCANCODES.push(0x1e);
+
+    match stream.poll_next_unpin(&mut context) {
+        Poll::Ready(Some(event)) => assert_eq!(event.character(), Some('a')),
+        other => panic!("expected a key event, got {:?}", other),
+    }
+
+    drop(stream);
+
+    assert!(keys().is_some());
+}
+
+#[test_case]
+fn test_decoder() {
+    let mut decoder = Decoder::new();
+    let mut feed = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.add_byte(*byte))
+            .last()
+    };
+
+    // NOTE: 'a', shift + '1', caps lock + 'q', ctrl + 'c'
+    assert_eq!(feed(&[0x1e]).and_then(|event| event.character()), Some('a'));
+    assert_eq!(
+        feed(&[0x2a, 0x02]).and_then(|event| event.character()),
+        Some('!')
+    );
+    assert_eq!(
+        feed(&[0x82, 0xaa]).map(|event| event.state),
+        Some(KeyState::Released)
+    );
+    assert_eq!(
+        feed(&[0x3a, 0xba, 0x10]).and_then(|event| event.character()),
+        Some('Q')
+    );
+    assert_eq!(
+        feed(&[0x3a, 0xba, 0x1d, 0x2e]).and_then(|event| event.character()),
+        Some('\x03')
+    );
+    assert_eq!(
+        feed(&[0x9d, 0xe0, 0x48]).map(|event| event.code),
+        Some(KeyCode::Up)
+    );
+    assert_eq!(
+        feed(&[0xe0, 0xc8]).and_then(|event| event.character()),
+        None
+    );
+}
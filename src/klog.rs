@@ -0,0 +1,398 @@
+use crate::console::{self, Console, ConsoleAdapter};
+use crate::serial;
+use crate::vga_buffer::Color;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+// NOTE: text bytes carried by one ring slot, longer output is split over several slots
+const CHUNK_SIZE: usize = 112;
+const SLOT_COUNT: usize = 256;
+const HISTORY_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Copy)]
+struct Chunk {
+    len: usize,
+    color: Option<(Color, Color)>,
+    bytes: [u8; CHUNK_SIZE],
+}
+
+impl Chunk {
+    const fn empty() -> Chunk {
+        Chunk {
+            len: 0,
+            color: None,
+            bytes: [0; CHUNK_SIZE],
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // NOTE: chunks are only ever cut at char boundaries
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("?")
+    }
+}
+
+struct Slot {
+    // NOTE: equals the position for a free slot and position + 1 once a chunk is published
+    sequence: AtomicUsize,
+    chunk: UnsafeCell<Chunk>,
+}
+
+// NOTE: bounded multi-producer queue (Vyukov style), producers never block or take locks so
+// interrupt and NMI handlers can log freely; a single drainer consumes it
+struct Ring<const SLOTS: usize> {
+    slots: [Slot; SLOTS],
+    enqueue_position: AtomicUsize,
+    dequeue_position: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+unsafe impl<const SLOTS: usize> Sync for Ring<SLOTS> {}
+
+impl<const SLOTS: usize> Ring<SLOTS> {
+    const fn new() -> Ring<SLOTS> {
+        let mut slots = [const {
+            Slot {
+                sequence: AtomicUsize::new(0),
+                chunk: UnsafeCell::new(Chunk::empty()),
+            }
+        }; SLOTS];
+        let mut index = 0;
+
+        while index < SLOTS {
+            slots[index].sequence = AtomicUsize::new(index);
+            index += 1;
+        }
+
+        Ring {
+            slots,
+            enqueue_position: AtomicUsize::new(0),
+            dequeue_position: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // NOTE: returns false and counts the chunk as dropped when the ring is full
+    fn push(&self, chunk: &Chunk) -> bool {
+        let mut position = self.enqueue_position.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[position % SLOTS];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            if sequence == position {
+                match self.enqueue_position.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { *slot.chunk.get() = *chunk };
+                        slot.sequence.store(position + 1, Ordering::Release);
+
+                        return true;
+                    }
+                    Err(current) => position = current,
+                }
+            } else if sequence < position {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+
+                return false;
+            } else {
+                position = self.enqueue_position.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // NOTE: callers must guarantee a single consumer, see DRAINING
+    fn pop(&self) -> Option<Chunk> {
+        let position = self.dequeue_position.load(Ordering::Relaxed);
+        let slot = &self.slots[position % SLOTS];
+
+        if slot.sequence.load(Ordering::Acquire) != position + 1 {
+            return None;
+        }
+
+        let chunk = unsafe { *slot.chunk.get() };
+
+        slot.sequence.store(position + SLOTS, Ordering::Release);
+        self.dequeue_position.store(position + 1, Ordering::Relaxed);
+
+        Some(chunk)
+    }
+}
+
+// NOTE: keeps the most recent output as whole lines, the oldest lines are discarded first
+struct History<const SIZE: usize> {
+    bytes: [u8; SIZE],
+    len: usize,
+}
+
+impl<const SIZE: usize> History<SIZE> {
+    const fn new() -> History<SIZE> {
+        History {
+            bytes: [0; SIZE],
+            len: 0,
+        }
+    }
+
+    fn append(&mut self, text: &str) {
+        let text = &text.as_bytes()[text.len().saturating_sub(SIZE)..];
+
+        if self.len + text.len() > SIZE {
+            let overflow = self.len + text.len() - SIZE;
+            let cut = self.bytes[overflow..self.len]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.len, |newline| overflow + newline + 1);
+
+            self.bytes.copy_within(cut..self.len, 0);
+            self.len -= cut;
+        }
+
+        self.bytes[self.len..self.len + text.len()].copy_from_slice(text);
+        self.len += text.len();
+    }
+
+    fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len];
+
+        // NOTE: a cut through a multi-byte char only happens when a single append overflows
+        match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(error) => unsafe { core::str::from_utf8_unchecked(&bytes[..error.valid_up_to()]) },
+        }
+    }
+}
+
+static RING: Ring<SLOT_COUNT> = Ring::new();
+static HISTORY: Mutex<History<HISTORY_SIZE>> = Mutex::new(History::new());
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+// NOTE: holds the history lock, output printed meanwhile stays queued in the ring and is
+// drained once the snapshot is dropped
+pub struct Snapshot {
+    history: Option<MutexGuard<'static, History<HISTORY_SIZE>>>,
+}
+
+impl Snapshot {
+    pub fn lines(&self) -> core::str::Lines<'_> {
+        self.deref().lines()
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.history.as_ref().map_or("", |history| history.as_str())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        drop(self.history.take());
+        drain();
+    }
+}
+
+// NOTE: the last HISTORY_SIZE bytes of kernel output, like dmesg
+pub fn snapshot() -> Snapshot {
+    drain();
+
+    Snapshot {
+        history: Some(interrupts::without_interrupts(|| HISTORY.lock())),
+    }
+}
+
+struct ChunkWriter {
+    chunk: Chunk,
+    // NOTE: false where not even drain's try_lock is safe, a full ring then drops the chunk
+    may_drain: bool,
+}
+
+impl ChunkWriter {
+    fn publish(&mut self) {
+        if self.chunk.len == 0 {
+            return;
+        }
+
+        // NOTE: a full ring gets one chance to drain before the chunk is dropped
+        if !RING.push(&self.chunk) && self.may_drain && drain() {
+            RING.push(&self.chunk);
+        }
+
+        self.chunk.len = 0;
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut encoded = [0; 4];
+            let encoded = c.encode_utf8(&mut encoded).as_bytes();
+
+            if self.chunk.len + encoded.len() > CHUNK_SIZE {
+                self.publish();
+            }
+
+            self.chunk.bytes[self.chunk.len..self.chunk.len + encoded.len()]
+                .copy_from_slice(encoded);
+            self.chunk.len += encoded.len();
+        }
+
+        Ok(())
+    }
+}
+
+// NOTE: safe from any context, the text shows up once the ring is drained
+pub fn push(color: Option<(Color, Color)>, args: fmt::Arguments) {
+    push_chunks(color, args, true);
+}
+
+// NOTE: never touches a lock, for NMI handlers that may have interrupted a console lock holder
+pub fn push_only(args: fmt::Arguments) {
+    push_chunks(None, args, false);
+}
+
+fn push_chunks(color: Option<(Color, Color)>, args: fmt::Arguments, may_drain: bool) {
+    let mut writer = ChunkWriter {
+        chunk: Chunk {
+            color,
+            ..Chunk::empty()
+        },
+        may_drain,
+    };
+
+    let _ = writer.write_fmt(args);
+    writer.publish();
+}
+
+fn write_chunk(console: &mut dyn Console, chunk: &Chunk) {
+    match chunk.color {
+        Some((foreground, background)) => {
+            let previous = console.color();
+
+            console.set_color(foreground, background);
+            let _ = ConsoleAdapter(console).write_str(chunk.as_str());
+
+            match previous {
+                Some((foreground, background)) => console.set_color(foreground, background),
+                None => console.reset_color(),
+            }
+        }
+        None => {
+            let _ = ConsoleAdapter(console).write_str(chunk.as_str());
+        }
+    }
+}
+
+// NOTE: flushes queued chunks to the console backend (and the serial mirror), returns false if
+// another context is already draining (e.g. the code this interrupt handler interrupted) or a
+// snapshot is held
+pub fn drain() -> bool {
+    if DRAINING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+
+    let drained = interrupts::without_interrupts(|| {
+        let mut history = match HISTORY.try_lock() {
+            Some(history) => history,
+            None => return false,
+        };
+        let backend = console::backend();
+        // NOTE: no mirroring when the backend already is that serial port
+        let mirror = console::serial_mirror()
+            .map(serial::port)
+            .filter(|port| !core::ptr::addr_eq(*port as *const _, backend as *const _));
+        let mut console = backend.lock();
+        let mut mirror = mirror.map(|port| port.lock());
+
+        while let Some(chunk) = RING.pop() {
+            history.append(chunk.as_str());
+            write_chunk(&mut *console, &chunk);
+
+            if let Some(port) = mirror.as_mut() {
+                write_chunk(&mut **port, &chunk);
+            }
+        }
+
+        true
+    });
+
+    DRAINING.store(false, Ordering::Release);
+
+    drained
+}
+
+pub fn dropped() -> usize {
+    RING.dropped.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_ring_order_and_overflow() {
+    static TEST_RING: Ring<4> = Ring::new();
+
+    let mut chunk = Chunk::empty();
+
+    for value in 0..5u8 {
+        chunk.bytes[0] = value;
+        chunk.len = 1;
+
+        assert_eq!(TEST_RING.push(&chunk), value < 4);
+    }
+
+    assert_eq!(TEST_RING.dropped.load(Ordering::Relaxed), 1);
+
+    for value in 0..4u8 {
+        assert_eq!(TEST_RING.pop().map(|chunk| chunk.bytes[0]), Some(value));
+    }
+
+    assert!(TEST_RING.pop().is_none());
+    assert!(TEST_RING.push(&chunk));
+}
+
+#[test_case]
+fn test_history_keeps_whole_lines() {
+    static mut TEST_HISTORY: History<16> = History::new();
+
+    let history = unsafe { &mut *core::ptr::addr_of_mut!(TEST_HISTORY) };
+
+    history.append("first\n");
+    history.append("second\n");
+    assert_eq!(history.as_str(), "first\nsecond\n");
+
+    history.append("third\n");
+    assert_eq!(history.as_str(), "second\nthird\n");
+}
+
+#[test_case]
+fn test_snapshot() {
+    push(None, format_args!("klog snapshot test\n"));
+
+    assert!(snapshot().lines().any(|line| line == "klog snapshot test"));
+}
+
+#[test_case]
+fn test_colored_chunk_restores_color() {
+    use crate::vga_buffer::{ScreenStorage, Writer, BLANK_SCREEN};
+
+    static mut TEST_SCREEN: ScreenStorage = BLANK_SCREEN;
+
+    let mut writer = Writer::offscreen(unsafe { &mut *core::ptr::addr_of_mut!(TEST_SCREEN) });
+    let mut chunk = Chunk {
+        color: Some((Color::WHITE, Color::RED)),
+        ..Chunk::empty()
+    };
+
+    chunk.bytes[0] = b'x';
+    chunk.len = 1;
+    writer.set_color(Color::GREEN, Color::BLUE);
+    write_chunk(&mut writer, &chunk);
+
+    assert_eq!(Console::color(&writer), Some((Color::GREEN, Color::BLUE)));
+}
@@ -1,14 +1,109 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
+#![feature(abi_x86_interrupt)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod apic;
+pub mod banner;
+pub mod block;
+pub mod console;
+mod cp437;
+pub mod debugcon;
+pub mod elf;
+pub mod framebuffer;
+pub mod fs;
+pub mod futex;
+pub mod gdt;
+pub mod gfx;
+pub mod hpet;
+pub mod initrd;
+pub mod interrupts;
+pub mod ioapic;
+pub mod keyboard;
+pub mod klog;
+pub mod logger;
+pub mod memory;
+pub mod panic_screen;
+pub mod pci;
+pub mod percpu;
+pub mod pic;
+pub mod pipe;
+pub mod pit;
+pub mod process;
+pub mod queue;
+pub mod screensaver;
 pub mod serial;
+pub mod shm;
+pub mod signal;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod thread;
+pub mod time;
+pub mod tsc;
+pub mod tty;
+pub mod uaccess;
+pub mod usermode;
 pub mod vga_buffer;
+mod vga_registers;
+pub mod workqueue;
 
+pub use vga_buffer::{clear_screen, set_position, set_status, write_at};
+
+#[cfg(test)]
+use bootloader::entry_point;
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
 
+// NOTE: sleeps until the next interrupt instead of spinning
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// NOTE: like hlt_loop, but runs ready async tasks and other threads, the workqueue worker
+// included, with interrupts enabled between interrupts; checking for work and halting happen
+// atomically so a wakeup is never missed, the halt stops the periodic tick when it can
+pub fn idle_loop() -> ! {
+    use x86_64::instructions::interrupts;
+
+    loop {
+        task::run_ready();
+        thread::yield_now();
+        interrupts::disable();
+
+        match task::executor::has_ready() || thread::has_ready() {
+            true => interrupts::enable(),
+            false => crate::interrupts::tickless::idle_halt(),
+        }
+    }
+}
+
+pub fn init(boot_info: &'static BootInfo) {
+    percpu::init_boot_cpu();
+    memory::init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+    fs::init();
+    initrd::init();
+    thread::init();
+    workqueue::init();
+    gdt::init();
+    syscall::init();
+    interrupts::init_idt();
+    interrupts::init_hardware();
+    time::init();
+    x86_64::instructions::interrupts::enable();
+    smp::init();
+}
+
 pub trait Testable {
     fn run(&self) {}
 }
@@ -43,11 +138,14 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 }
 
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
     test_main();
 
-    loop {}
+    idle_loop();
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
@@ -56,7 +154,7 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 
     exit_qemu(QemuExitCode::FAILED);
 
-    loop {}
+    hlt_loop();
 }
 
 #[cfg(test)]
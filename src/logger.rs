@@ -0,0 +1,124 @@
+use crate::console;
+use crate::klog;
+use crate::serial::{self, ComPort};
+use crate::vga_buffer::Color;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU16, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+// NOTE: base of the port records are copied to, 0 when serial logging is off
+static SERIAL_PORT: AtomicU16 = AtomicU16::new(ComPort::Com1 as u16);
+
+pub fn set_serial_port(port: Option<ComPort>) {
+    SERIAL_PORT.store(port.map_or(0, |port| port as u16), Ordering::Relaxed);
+}
+
+fn serial_port() -> Option<ComPort> {
+    ComPort::from_base(SERIAL_PORT.load(Ordering::Relaxed))
+}
+
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::LIGHTRED,
+        Level::Warn => Color::YELLOW,
+        Level::Info => Color::LIGHTGREEN,
+        Level::Debug => Color::LIGHTCYAN,
+        Level::Trace => Color::DARKGRAY,
+    }
+}
+
+// NOTE: "[LEVEL] module: message"
+fn write_record(out: &mut dyn Write, record: &Record) -> fmt::Result {
+    write!(
+        out,
+        "[{:<5}] {}: {}",
+        record.level(),
+        record.module_path().unwrap_or("?"),
+        record.args()
+    )
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        klog::push(
+            Some((level_color(record.level()), Color::BLACK)),
+            format_args!("{}\n", DisplayRecord(record)),
+        );
+        klog::drain();
+
+        // NOTE: a mirrored console already copied the record to that port
+        if let Some(port) = serial_port().filter(|port| console::serial_mirror() != Some(*port)) {
+            serial::_print_to(port, format_args!("{}\n", DisplayRecord(record)));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+struct DisplayRecord<'a, 'b>(&'a Record<'b>);
+
+impl fmt::Display for DisplayRecord<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_record(f, self.0)
+    }
+}
+
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER).map(|()| log::set_max_level(level))
+}
+
+#[cfg(test)]
+struct FixedBuffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+#[cfg(test)]
+impl Write for FixedBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_record_format() {
+    let mut buffer = FixedBuffer {
+        bytes: [0; 64],
+        len: 0,
+    };
+
+    write_record(
+        &mut buffer,
+        &Record::builder()
+            .level(Level::Warn)
+            .module_path(Some("rustos::test"))
+            .args(format_args!("disk {} missing", 0))
+            .build(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        &buffer.bytes[..buffer.len],
+        b"[WARN ] rustos::test: disk 0 missing"
+    );
+}
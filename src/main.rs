@@ -5,25 +5,32 @@
 // NOTE: renaming the test entrypoint function
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use rustos::println;
+use rustos::banner::Banner;
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    println!("Lorem Ipsum!");
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    rustos::logger::init(log::LevelFilter::Info).expect("logger already initialized");
+    rustos::init(boot_info);
+
+    Banner::default()
+        .with_memory_map(&boot_info.memory_map)
+        .print();
 
     #[cfg(test)]
     test_main();
 
-    loop {}
+    rustos::task::spawn(rustos::tty::run_console());
+
+    rustos::idle_loop();
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-
-    loop {}
+    rustos::panic_screen::show(info)
 }
 
 #[cfg(test)]
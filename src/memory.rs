@@ -0,0 +1,63 @@
+pub mod address_space;
+pub mod cow;
+pub mod demand;
+pub mod dma;
+pub mod frame_allocator;
+pub mod kaslr;
+pub mod mmio;
+pub mod oom;
+pub mod paging;
+pub mod protection;
+pub mod stack;
+pub mod tlb;
+
+use crate::allocator::{self, HeapStats};
+use bootloader::BootInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use frame_allocator::FrameStats;
+use x86_64::{PhysAddr, VirtAddr};
+
+pub use mmio::{map_mmio, MmioRegion, VolatileCell};
+pub use paging::{virt_to_phys, with_page_table};
+
+// NOTE: the bootloader maps all of physical memory starting at this virtual address
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub fn init(boot_info: &'static BootInfo) {
+    let physical_memory_end = boot_info
+        .memory_map
+        .iter()
+        .map(|region| region.range.end_addr())
+        .max()
+        .unwrap_or(0);
+    let offset = kaslr::init(boot_info.physical_memory_offset, physical_memory_end);
+
+    PHYSICAL_MEMORY_OFFSET.store(offset, Ordering::Relaxed);
+    frame_allocator::init(&boot_info.memory_map);
+    paging::init(physical_memory_offset());
+    paging::use_huge_physical_mapping(PhysAddr::new(physical_memory_end));
+    protection::init(physical_memory_end).expect("kernel W^X remapping failed");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    pub frames: FrameStats,
+    pub heap: HeapStats,
+}
+
+// NOTE: a snapshot for leak checks and the like, frames and heap are read one after the other
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        frames: frame_allocator::stats(),
+        heap: allocator::stats(),
+    }
+}
+
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+// NOTE: valid for any physical address, unlike virt_to_phys which needs a page table walk
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + address.as_u64()
+}
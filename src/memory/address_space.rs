@@ -0,0 +1,477 @@
+use super::cow::{self, COPY_ON_WRITE};
+use super::frame_allocator::{self, BuddyFrameAllocator};
+use super::paging::{self, MapError};
+use super::tlb::{self, Target};
+use super::{phys_to_virt, physical_memory_offset};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Translate,
+};
+use x86_64::VirtAddr;
+
+// NOTE: marks leaf frames the address space allocated itself and frees on destroy, frames
+// given to map_to belong to the caller
+const OWNED: PageTableFlags = PageTableFlags::BIT_10;
+// NOTE: on pages of memory shared between address spaces, which a fork leaves writable
+// instead of making them copy-on-write
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_11;
+
+fn table(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+// NOTE: a set of page tables with a PML4 of its own. The level 4 entries the kernel uses when
+// it is created are shared, so the kernel keeps running once it is activated; everything else
+// is private. Kernel mappings made later in new level 4 entries don't show up in it
+pub struct AddressSpace {
+    level_4: PhysFrame,
+    // NOTE: bit n set when level 4 entry n is private
+    private: [u64; 8],
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<AddressSpace, MapError> {
+        let level_4 = frame_allocator::allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+        let kernel = table(paging::kernel_level_4());
+        let new = table(level_4);
+        let mut private = [0; 8];
+
+        for (index, entry) in kernel.iter().enumerate() {
+            if entry.is_unused() {
+                private[index / 64] |= 1 << (index % 64);
+            }
+
+            new[index] = entry.clone();
+        }
+
+        Ok(AddressSpace { level_4, private })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4
+    }
+
+    fn check_private(&self, page: Page) -> Result<(), MapError> {
+        let index = usize::from(page.p4_index());
+
+        match self.private[index / 64] & (1 << (index % 64)) {
+            0 => Err(MapError::Shared),
+            _ => Ok(()),
+        }
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'static> {
+        unsafe { OffsetPageTable::new(table(self.level_4), physical_memory_offset()) }
+    }
+
+    // NOTE: backs `page` with a fresh zeroed frame the address space owns
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapError> {
+        self.check_private(page)?;
+
+        let mut mapper = self.mapper();
+
+        frame_allocator::with_frame_allocator(|frames| {
+            let frame = frames
+                .allocate_frame()
+                .ok_or(MapError::FrameAllocationFailed)?;
+
+            unsafe {
+                core::ptr::write_bytes(
+                    phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                    0,
+                    4096,
+                );
+            }
+
+            match unsafe { mapper.map_to(page, frame, flags | OWNED, frames) } {
+                Ok(flush) => {
+                    flush.ignore();
+
+                    Ok(frame)
+                }
+                Err(error) => {
+                    unsafe { frames.deallocate_frame(frame) };
+
+                    Err(MapError::from(error))
+                }
+            }
+        })
+        .ok_or(MapError::FrameAllocationFailed)?
+    }
+
+    /// # Safety
+    ///
+    /// Same as `paging::map_to`, the frame stays the caller's.
+    pub unsafe fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        self.check_private(page)?;
+
+        let mut mapper = self.mapper();
+
+        frame_allocator::with_frame_allocator(|frames| {
+            mapper
+                .map_to(page, frame, flags - OWNED, frames)
+                .map(|flush| flush.ignore())
+        })
+        .ok_or(MapError::FrameAllocationFailed)?
+        .map_err(MapError::from)
+    }
+
+    // NOTE: frees the frame when the address space owned it
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, MapError> {
+        self.check_private(page)?;
+
+        let (_, flags) = self.translate(page)?;
+        let (frame, flush) = self.mapper().unmap(page)?;
+
+        flush.ignore();
+        tlb::flush_page(Target::Space(self.level_4), page);
+
+        if flags.contains(OWNED) {
+            unsafe { frame_allocator::with_frame_allocator(|frames| frames.release(frame)) };
+        }
+
+        Ok(frame)
+    }
+
+    // NOTE: unmap for `pages` pages from `start`, pages that aren't mapped are skipped. Owned
+    // frames are only freed once a single shootdown has reached every CPU running on the
+    // address space, for munmap and brk that may free many pages at once
+    pub fn unmap_range(&mut self, start: Page, pages: u64) {
+        let mut mapper = self.mapper();
+        let frames: alloc::vec::Vec<PhysFrame> = (0..pages)
+            .map(|index| start + index)
+            .filter(|&page| self.check_private(page).is_ok())
+            .filter_map(|page| {
+                let flags = match mapper.translate(page.start_address()) {
+                    TranslateResult::Mapped { flags, .. } => flags,
+                    _ => return None,
+                };
+                let (frame, flush) = mapper.unmap(page).ok()?;
+
+                flush.ignore();
+
+                flags.contains(OWNED).then_some(frame)
+            })
+            .collect();
+
+        tlb::shootdown(Target::Space(self.level_4), start, pages);
+
+        frame_allocator::with_frame_allocator(|allocator| {
+            for frame in frames {
+                unsafe { allocator.release(frame) };
+            }
+        });
+    }
+
+    // NOTE: maps a frame someone else allocated as one more of its owners, the mapping takes a
+    // share of it that unmap and destroy release. The page is SHARED
+    pub fn map_shared(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        self.check_private(page)?;
+
+        let mut mapper = self.mapper();
+
+        frame_allocator::with_frame_allocator(|frames| {
+            if !frames.share(frame) {
+                return Err(MapError::FrameAllocationFailed);
+            }
+
+            match unsafe { mapper.map_to(page, frame, flags | OWNED | SHARED, frames) } {
+                Ok(flush) => {
+                    flush.ignore();
+
+                    Ok(())
+                }
+                Err(error) => {
+                    unsafe { frames.release(frame) };
+
+                    Err(MapError::from(error))
+                }
+            }
+        })
+        .ok_or(MapError::FrameAllocationFailed)?
+    }
+
+    pub fn translate(&mut self, page: Page) -> Result<(PhysFrame, PageTableFlags), MapError> {
+        match self.mapper().translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => Ok((frame, flags)),
+            TranslateResult::Mapped { .. } => Err(MapError::HugePage),
+            TranslateResult::NotMapped => Err(MapError::NotMapped),
+            TranslateResult::InvalidFrameAddress(address) => {
+                Err(MapError::InvalidFrameAddress(address))
+            }
+        }
+    }
+
+    // NOTE: copies `bytes` to `address` through the physical memory mapping, so it works while
+    // another address space is active. The pages have to be mapped, their flags don't matter;
+    // a copy-on-write page would be changed for every address space sharing it
+    pub fn write(&mut self, address: VirtAddr, bytes: &[u8]) -> Result<(), MapError> {
+        let mut address = address;
+        let mut remaining = bytes;
+
+        while !remaining.is_empty() {
+            let page = Page::containing_address(address);
+            let (frame, _) = self.translate(page)?;
+            let offset = address - page.start_address();
+            let length = remaining.len().min((4096 - offset) as usize);
+            let destination = phys_to_virt(frame.start_address() + offset).as_mut_ptr::<u8>();
+
+            unsafe { core::ptr::copy_nonoverlapping(remaining.as_ptr(), destination, length) };
+
+            remaining = &remaining[length..];
+            address += length as u64;
+        }
+
+        Ok(())
+    }
+
+    // NOTE: a copy sharing every private frame, writable ones become copy-on-write on both
+    // sides unless they are SHARED. Every CPU running the address space drops its writable
+    // entries before this returns, even when the copy fails halfway
+    pub fn fork(&mut self) -> Result<AddressSpace, MapError> {
+        let mut child = AddressSpace::new()?;
+        let mut pages = alloc::vec::Vec::new();
+
+        for (index, entry) in table(self.level_4).iter().enumerate() {
+            if self.private[index / 64] & (1 << (index % 64)) == 0 || entry.is_unused() {
+                continue;
+            }
+
+            if let Ok(level_3) = entry.frame() {
+                collect_pages(level_3, 3, index as u64, &mut pages);
+            }
+        }
+
+        let mut parent = self.mapper();
+        let mut mapper = child.mapper();
+
+        let copied = frame_allocator::with_frame_allocator(|frames| {
+            for (page, frame, flags) in pages {
+                let flags = match flags.contains(PageTableFlags::WRITABLE) {
+                    true if !flags.contains(SHARED) => cow::cow_flags(flags),
+                    _ => flags,
+                };
+
+                if flags.contains(OWNED) && !frames.share(frame) {
+                    return Err(MapError::FrameAllocationFailed);
+                }
+
+                unsafe {
+                    if flags.contains(COPY_ON_WRITE) {
+                        parent.update_flags(page, flags)?.ignore();
+                    }
+
+                    // NOTE: the tables stay writable so resolving the fault only touches the leaf
+                    let table_flags = (flags | PageTableFlags::WRITABLE)
+                        & (PageTableFlags::PRESENT
+                            | PageTableFlags::WRITABLE
+                            | PageTableFlags::USER_ACCESSIBLE);
+
+                    if let Err(error) =
+                        mapper.map_to_with_table_flags(page, frame, flags, table_flags, frames)
+                    {
+                        if flags.contains(OWNED) {
+                            frames.release(frame);
+                        }
+
+                        return Err(MapError::from(error));
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        tlb::flush_all(Target::Space(self.level_4));
+        copied.ok_or(MapError::FrameAllocationFailed)??;
+
+        Ok(child)
+    }
+
+    // NOTE: resolves a write fault on a copy-on-write page, the address space has to be active
+    pub fn handle_write_fault(&mut self, address: VirtAddr) -> bool {
+        let page = Page::containing_address(address);
+
+        if self.check_private(page).is_err() || !self.is_active() {
+            return false;
+        }
+
+        let mut mapper = self.mapper();
+
+        let target = Target::Space(self.level_4);
+
+        frame_allocator::with_frame_allocator(|frames| {
+            cow::resolve(&mut mapper, frames, page, target)
+        })
+        .flatten()
+        .is_some()
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4
+    }
+
+    /// # Safety
+    ///
+    /// Nothing running may rely on the private mappings of the address space it replaces.
+    pub unsafe fn activate(&self) {
+        tlb::load_level_4(self.level_4);
+    }
+
+    /// # Safety
+    ///
+    /// Same as `activate`.
+    pub unsafe fn activate_kernel() {
+        tlb::load_level_4(paging::kernel_level_4());
+    }
+}
+
+// NOTE: every 4 KiB leaf under the table at `level`, `prefix` holding the indices above it
+fn collect_pages(
+    frame: PhysFrame,
+    level: u8,
+    prefix: u64,
+    pages: &mut alloc::vec::Vec<(Page, PhysFrame, PageTableFlags)>,
+) {
+    for (index, entry) in table(frame).iter().enumerate() {
+        let Ok(child) = entry.frame() else {
+            continue;
+        };
+        let prefix = prefix << 9 | index as u64;
+
+        match level {
+            1 => {
+                let address = VirtAddr::new_truncate(prefix << 12);
+
+                pages.push((Page::containing_address(address), child, entry.flags()));
+            }
+            _ => collect_pages(child, level - 1, prefix, pages),
+        }
+    }
+}
+
+// NOTE: frees the private tables bottom up along with the owned frames in them
+unsafe fn free_table(frames: &mut BuddyFrameAllocator, frame: PhysFrame, level: u8) {
+    for entry in table(frame).iter() {
+        if entry.is_unused() {
+            continue;
+        }
+
+        let Ok(child) = entry.frame() else {
+            continue;
+        };
+
+        match level {
+            1 if entry.flags().contains(OWNED) => frames.release(child),
+            1 => {}
+            _ => free_table(frames, child, level - 1),
+        }
+    }
+
+    frames.deallocate_frame(frame);
+}
+
+// NOTE: destroys the address space, switching back to the kernel's if it is active
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { AddressSpace::activate_kernel() };
+        }
+
+        // NOTE: no other CPU should still be running on it, nothing may cache its tables either
+        tlb::flush_all(Target::Space(self.level_4));
+
+        let level_4 = self.level_4;
+        let private = self.private;
+
+        frame_allocator::with_frame_allocator(|frames| unsafe {
+            for (index, entry) in table(level_4).iter().enumerate() {
+                if private[index / 64] & (1 << (index % 64)) == 0 || entry.is_unused() {
+                    continue;
+                }
+
+                if let Ok(level_3) = entry.frame() {
+                    free_table(frames, level_3, 3);
+                }
+            }
+
+            frames.deallocate_frame(level_4);
+        });
+    }
+}
+
+#[test_case]
+fn test_private_mappings() {
+    use x86_64::VirtAddr;
+
+    let before = frame_allocator::stats();
+    let page = Page::containing_address(VirtAddr::new(0x6000_0000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mut space = AddressSpace::new().unwrap();
+    let frame = space.map(page, flags).unwrap();
+    let pointer: *mut u64 = page.start_address().as_mut_ptr();
+
+    assert_eq!(
+        space.map(
+            Page::containing_address(VirtAddr::new(0x4444_4440_0000)),
+            flags
+        ),
+        Err(MapError::Shared)
+    );
+
+    unsafe {
+        phys_to_virt(frame.start_address())
+            .as_mut_ptr::<u64>()
+            .write_volatile(0xcafe);
+
+        space.activate();
+
+        assert!(space.is_active());
+        assert_eq!(pointer.read_volatile(), 0xcafe);
+
+        AddressSpace::activate_kernel();
+    }
+
+    assert_eq!(paging::translate(page), Err(MapError::NotMapped));
+
+    drop(space);
+
+    assert_eq!(frame_allocator::stats(), before);
+}
+
+#[test_case]
+fn test_unmap_range() {
+    use x86_64::VirtAddr;
+
+    let page = Page::containing_address(VirtAddr::new(0x6000_0001_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mut space = AddressSpace::new().unwrap();
+
+    // NOTE: a hole in the middle of the range, which is skipped
+    space.map(page, flags).unwrap();
+    space.map(page + 2, flags).unwrap();
+
+    let mapped = frame_allocator::stats();
+
+    space.unmap_range(page, 4);
+
+    assert_eq!(frame_allocator::stats().used, mapped.used - 2);
+    assert_eq!(space.translate(page), Err(MapError::NotMapped));
+    assert_eq!(space.translate(page + 2), Err(MapError::NotMapped));
+}
@@ -0,0 +1,165 @@
+use super::frame_allocator::BuddyFrameAllocator;
+use super::paging::{self, MapError};
+use super::phys_to_virt;
+use super::tlb::{self, Target};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Translate,
+};
+use x86_64::VirtAddr;
+
+// NOTE: one of the bits the CPU ignores, set on read-only pages that become private copies on
+// the first write
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+fn mapping(table: &OffsetPageTable, page: Page) -> Result<(PhysFrame, PageTableFlags), MapError> {
+    match table.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => Ok((frame, flags)),
+        TranslateResult::Mapped { .. } => Err(MapError::HugePage),
+        _ => Err(MapError::NotMapped),
+    }
+}
+
+pub(crate) fn cow_flags(flags: PageTableFlags) -> PageTableFlags {
+    (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE
+}
+
+fn private_flags(flags: PageTableFlags) -> PageTableFlags {
+    (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE
+}
+
+// NOTE: maps `target` to the frame behind `source` and turns both into copy-on-write pages,
+// what fork does for every writable page
+pub fn share(source: Page, target: Page) -> Result<(), MapError> {
+    paging::with_page_table_and_frames(|table, frames| {
+        let (frame, flags) = mapping(table, source)?;
+        let flags = cow_flags(flags);
+
+        if !frames.share(frame) {
+            return Err(MapError::FrameAllocationFailed);
+        }
+
+        unsafe {
+            table.update_flags(source, flags)?.ignore();
+            tlb::flush_page(Target::Kernel, source);
+
+            match table.map_to(target, frame, flags, frames) {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    frames.release(frame);
+
+                    return Err(error.into());
+                }
+            }
+        }
+
+        Ok(())
+    })?
+}
+
+// NOTE: unmaps a page that may be shared, its frame is freed with the last mapping
+pub fn unmap(page: Page) -> Result<(), MapError> {
+    paging::with_page_table_and_frames(|table, frames| {
+        let (frame, flush) = table.unmap(page)?;
+
+        flush.ignore();
+        tlb::flush_page(Target::Kernel, page);
+        unsafe { frames.release(frame) };
+
+        Ok(())
+    })?
+}
+
+// NOTE: resolves a write to a copy-on-write page from the page fault handler; the last user
+// of a frame takes it over, everyone else gets a copy. False when `address` isn't such a page
+pub(crate) fn handle_write_fault(address: VirtAddr) -> bool {
+    let page = Page::containing_address(address);
+
+    paging::try_with_page_table_and_frames(|table, frames| {
+        resolve(table, frames, page, Target::Kernel)
+    })
+    .ok()
+    .flatten()
+    .is_some()
+}
+
+// NOTE: the copy for a write fault on `page` in `table`, which must be the active one and which
+// `target` names for the shootdown. Other flags, e.g. the address space's ownership bit, carry
+// over to the copy
+pub(crate) fn resolve(
+    table: &mut OffsetPageTable,
+    frames: &mut BuddyFrameAllocator,
+    page: Page,
+    target: Target,
+) -> Option<()> {
+    let (frame, flags) = mapping(table, page).ok()?;
+
+    if !flags.contains(COPY_ON_WRITE) {
+        return None;
+    }
+
+    if frames.share_count(frame) == 0 {
+        unsafe { table.update_flags(page, private_flags(flags)).ok()?.flush() };
+
+        return Some(());
+    }
+
+    let copy = frames.allocate_frame()?;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+            phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+            4096,
+        );
+
+        table.unmap(page).ok()?.1.ignore();
+        table
+            .map_to(page, copy, private_flags(flags), frames)
+            .ok()?
+            .ignore();
+        // NOTE: other CPUs may still read the shared frame while this one writes the copy
+        tlb::flush_page(target, page);
+        frames.release(frame);
+    }
+
+    Some(())
+}
+
+#[test_case]
+fn test_copy_on_write() {
+    let source = Page::containing_address(VirtAddr::new(0x5555_6666_0000));
+    let target = source + 1;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let frame = paging::map(source, flags).unwrap();
+    let source_pointer: *mut u64 = source.start_address().as_mut_ptr();
+    let target_pointer: *mut u64 = target.start_address().as_mut_ptr();
+
+    unsafe { source_pointer.write_volatile(1) };
+
+    share(source, target).unwrap();
+
+    assert_eq!(paging::translate(target).unwrap().0, frame);
+
+    unsafe {
+        assert_eq!(target_pointer.read_volatile(), 1);
+        target_pointer.write_volatile(2);
+
+        assert_eq!(source_pointer.read_volatile(), 1);
+        assert_eq!(target_pointer.read_volatile(), 2);
+    }
+
+    assert_ne!(paging::translate(target).unwrap().0, frame);
+
+    // NOTE: the source is the frame's last user now and takes it over without a copy
+    unsafe { source_pointer.write_volatile(3) };
+
+    assert_eq!(paging::translate(source).unwrap().0, frame);
+
+    unmap(source).unwrap();
+    unmap(target).unwrap();
+}
@@ -0,0 +1,167 @@
+use super::frame_allocator;
+use super::paging::{self, MapError};
+use crate::sync::IrqMutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+// NOTE: reserved regions are carved from here upwards and their addresses are never reused
+const DEMAND_REGION_START: u64 = 0x3000_0000_0000;
+const DEMAND_REGION_END: u64 = 0x4000_0000_0000;
+const MAX_REGIONS: usize = 32;
+
+static NEXT_START: AtomicU64 = AtomicU64::new(DEMAND_REGION_START);
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    name: &'static str,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+    resident: usize,
+}
+
+static REGIONS: IrqMutex<[Option<Region>; MAX_REGIONS]> = IrqMutex::new([None; MAX_REGIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemandError {
+    NoFreeSlot,
+    OutOfAddressSpace,
+}
+
+// NOTE: virtual memory whose pages get a zeroed frame on first touch; dropping it unmaps and
+// frees whatever became resident
+#[derive(Debug)]
+pub struct DemandRegion {
+    slot: usize,
+    start: VirtAddr,
+    size: u64,
+}
+
+impl DemandRegion {
+    // NOTE: `size` is rounded up to whole pages, PRESENT is added to `flags`
+    pub fn reserve(
+        name: &'static str,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<DemandRegion, DemandError> {
+        let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let start = NEXT_START
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |start| {
+                start
+                    .checked_add(size)
+                    .filter(|end| *end <= DEMAND_REGION_END)
+            })
+            .map_err(|_| DemandError::OutOfAddressSpace)?;
+        let region = Region {
+            name,
+            start,
+            end: start + size,
+            flags: flags | PageTableFlags::PRESENT,
+            resident: 0,
+        };
+
+        let mut regions = REGIONS.lock();
+        let slot = regions
+            .iter()
+            .position(Option::is_none)
+            .ok_or(DemandError::NoFreeSlot)?;
+
+        regions[slot] = Some(region);
+        drop(regions);
+
+        Ok(DemandRegion {
+            slot,
+            start: VirtAddr::new(start),
+            size,
+        })
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn name(&self) -> &'static str {
+        REGIONS.lock()[self.slot].map_or("", |region| region.name)
+    }
+
+    // NOTE: pages that have been touched and are backed by a frame
+    pub fn resident_pages(&self) -> usize {
+        REGIONS.lock()[self.slot].map_or(0, |region| region.resident)
+    }
+}
+
+impl Drop for DemandRegion {
+    fn drop(&mut self) {
+        REGIONS.lock()[self.slot] = None;
+
+        let start = Page::containing_address(self.start);
+        let end = Page::containing_address(self.start + (self.size - 1));
+
+        paging::unmap_range(start, end - start + 1, |frame| unsafe {
+            frame_allocator::free_frame(frame)
+        });
+    }
+}
+
+// NOTE: called by the page fault handler for not-present faults, true when the fault hit a
+// reserved region and the page is now mapped
+pub(crate) fn handle_page_fault(address: VirtAddr) -> bool {
+    let Some(mut regions) = REGIONS.lock_unless_held() else {
+        return false;
+    };
+    let Some(region) = regions
+        .iter_mut()
+        .flatten()
+        .find(|region| (region.start..region.end).contains(&address.as_u64()))
+    else {
+        return false;
+    };
+
+    match paging::map_zeroed_in_fault(Page::containing_address(address), region.flags) {
+        Ok(_) => {
+            region.resident += 1;
+
+            true
+        }
+        Err(MapError::FrameAllocationFailed) => {
+            log::error!("demand paging: out of memory in region {}", region.name);
+
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+#[test_case]
+fn test_pages_mapped_on_touch() {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = DemandRegion::reserve("test", 64 * 1024 * 1024, flags).unwrap();
+    let before = frame_allocator::stats();
+    let first: *mut u64 = region.start().as_mut_ptr();
+    let last: *mut u64 = (region.start() + (region.size() - 8)).as_mut_ptr();
+
+    assert_eq!(region.resident_pages(), 0);
+
+    unsafe {
+        assert_eq!(first.read_volatile(), 0);
+        last.write_volatile(42);
+        assert_eq!(last.read_volatile(), 42);
+    }
+
+    // NOTE: the page tables mapping the region stay around after it is dropped
+    let touched = frame_allocator::stats();
+
+    assert_eq!(region.resident_pages(), 2);
+    assert!(touched.used >= before.used + 2);
+
+    drop(region);
+
+    assert_eq!(frame_allocator::stats().used, touched.used - 2);
+}
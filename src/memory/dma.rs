@@ -0,0 +1,102 @@
+use super::frame_allocator::{self, MAX_ORDER};
+use super::phys_to_virt;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaZone {
+    Any,
+    // NOTE: for devices with 32 bit address registers, e.g. legacy ATA bus masters
+    Below4GiB,
+}
+
+// NOTE: physically contiguous, zeroed memory for descriptor rings and transfer buffers, seen
+// through the physical memory mapping; x86 keeps DMA cache coherent so no uncached alias is
+// needed. The frames are freed on drop
+pub struct DmaBuffer {
+    frame: PhysFrame,
+    order: usize,
+    len: usize,
+}
+
+impl DmaBuffer {
+    // NOTE: `align` must be a power of two, blocks are aligned to their size so anything up to
+    // the rounded up length comes for free
+    pub fn allocate(len: usize, align: usize, zone: DmaZone) -> Option<DmaBuffer> {
+        let frames = len.max(align).max(1).div_ceil(FRAME_SIZE);
+        let order = frames.next_power_of_two().trailing_zeros() as usize;
+
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let frame = match zone {
+            DmaZone::Any => frame_allocator::allocate_frames(order)?,
+            DmaZone::Below4GiB => {
+                frame_allocator::allocate_frames_below(order, PhysAddr::new(1 << 32))?
+            }
+        };
+        let buffer = DmaBuffer { frame, order, len };
+
+        unsafe { core::ptr::write_bytes(buffer.virt().as_mut_ptr::<u8>(), 0, buffer.capacity()) };
+
+        Some(buffer)
+    }
+
+    pub fn physical(&self) -> PhysAddr {
+        self.frame.start_address()
+    }
+
+    pub fn virt(&self) -> VirtAddr {
+        phys_to_virt(self.physical())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // NOTE: whole frames, at least `len`
+    pub fn capacity(&self) -> usize {
+        FRAME_SIZE << self.order
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt().as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt().as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { frame_allocator::free_frames(self.frame, self.order) };
+    }
+}
+
+#[test_case]
+fn test_dma_buffer() {
+    let before = frame_allocator::stats();
+    let mut buffer = DmaBuffer::allocate(5000, 16 * 1024, DmaZone::Below4GiB).unwrap();
+
+    assert_eq!(buffer.capacity(), 16 * 1024);
+    assert_eq!(buffer.physical().as_u64() % (16 * 1024), 0);
+    assert!(buffer.physical().as_u64() + buffer.capacity() as u64 <= 1 << 32);
+    assert_eq!(super::virt_to_phys(buffer.virt()), Some(buffer.physical()));
+    assert!(buffer.as_slice().iter().all(|byte| *byte == 0));
+
+    buffer.as_mut_slice()[4999] = 0xaa;
+
+    assert_eq!(buffer.as_slice()[4999], 0xaa);
+
+    drop(buffer);
+
+    assert_eq!(frame_allocator::stats(), before);
+}
@@ -0,0 +1,365 @@
+use super::oom::{self, OomRequest};
+use super::phys_to_virt;
+use crate::sync::IrqMutex;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+const FRAME_SIZE: u64 = 4096;
+
+// NOTE: blocks of 2^0 up to 2^10 frames, i.e. 4 KiB to 4 MiB
+pub const MAX_ORDER: usize = 10;
+
+// NOTE: per frame state, the order with FREE_HEAD set on the first frame of a free block
+const FREE_HEAD: u8 = 0x80;
+const NOT_FREE_HEAD: u8 = 0;
+
+// NOTE: end of the free lists, frame 0 is never handed out
+const NIL: u64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameStats {
+    pub usable: u64,
+    pub used: u64,
+}
+
+impl FrameStats {
+    pub fn free(&self) -> u64 {
+        self.usable - self.used
+    }
+}
+
+// NOTE: doubly linked free list entry written into the first frame of each free block, so a
+// block can be unlinked when its buddy is freed
+struct FreeBlock {
+    next: u64,
+    previous: u64,
+}
+
+fn block(frame_number: u64) -> *mut FreeBlock {
+    phys_to_virt(PhysAddr::new(frame_number * FRAME_SIZE)).as_mut_ptr()
+}
+
+// NOTE: a free block of order n is 2^n frames aligned to its size; freeing a block merges it
+// with its buddy as long as that one is free too. The per frame state and share count arrays
+// are carved out of the first usable region large enough
+pub struct BuddyFrameAllocator {
+    free_lists: [u64; MAX_ORDER + 1],
+    state: &'static mut [u8],
+    // NOTE: how many extra mappings share each frame, e.g. copy-on-write pages
+    shares: &'static mut [u16],
+    stats: FrameStats,
+}
+
+impl BuddyFrameAllocator {
+    /// # Safety
+    ///
+    /// Every frame the map marks as usable must really be unused.
+    pub unsafe fn new(memory_map: &'static MemoryMap) -> Option<BuddyFrameAllocator> {
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|region| region.region_type == MemoryRegionType::Usable)
+        };
+        let frames = usable().map(|region| region.range.end_frame_number).max()?;
+        let shares_offset = frames.next_multiple_of(2);
+        let state_frames = (shares_offset + frames * 2).div_ceil(FRAME_SIZE);
+        let state_region = usable().find(|region| {
+            region.range.end_frame_number - region.range.start_frame_number.max(1) >= state_frames
+        })?;
+        let state_start = state_region.range.start_frame_number.max(1);
+        let base = phys_to_virt(PhysAddr::new(state_start * FRAME_SIZE));
+        let state = core::slice::from_raw_parts_mut(base.as_mut_ptr(), frames as usize);
+        let shares =
+            core::slice::from_raw_parts_mut((base + shares_offset).as_mut_ptr(), frames as usize);
+
+        state.fill(NOT_FREE_HEAD);
+        shares.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            free_lists: [NIL; MAX_ORDER + 1],
+            state,
+            shares,
+            stats: FrameStats::default(),
+        };
+
+        for region in usable() {
+            let mut start = region.range.start_frame_number.max(1);
+            let end = region.range.end_frame_number;
+
+            if region.range == state_region.range {
+                start = state_start + state_frames;
+            }
+
+            allocator.stats.usable += end.saturating_sub(start);
+            allocator.stats.used += end.saturating_sub(start);
+
+            while start < end {
+                let order = (0..=MAX_ORDER)
+                    .rev()
+                    .find(|order| start % (1 << order) == 0 && start + (1 << order) <= end)
+                    .unwrap_or(0);
+
+                allocator.free_block(start, order);
+                start += 1 << order;
+            }
+        }
+
+        Some(allocator)
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    unsafe fn push(&mut self, frame_number: u64, order: usize) {
+        let head = self.free_lists[order];
+
+        block(frame_number).write(FreeBlock {
+            next: head,
+            previous: NIL,
+        });
+
+        if head != NIL {
+            (*block(head)).previous = frame_number;
+        }
+
+        self.free_lists[order] = frame_number;
+        self.state[frame_number as usize] = FREE_HEAD | order as u8;
+    }
+
+    unsafe fn unlink(&mut self, frame_number: u64, order: usize) {
+        let FreeBlock { next, previous } = block(frame_number).read();
+
+        match previous {
+            NIL => self.free_lists[order] = next,
+            previous => (*block(previous)).next = next,
+        }
+
+        if next != NIL {
+            (*block(next)).previous = previous;
+        }
+
+        self.state[frame_number as usize] = NOT_FREE_HEAD;
+    }
+
+    unsafe fn free_block(&mut self, mut frame_number: u64, mut order: usize) {
+        self.stats.used -= 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = frame_number ^ (1 << order);
+
+            if self.state.get(buddy as usize) != Some(&(FREE_HEAD | order as u8)) {
+                break;
+            }
+
+            self.unlink(buddy, order);
+            frame_number = frame_number.min(buddy);
+            order += 1;
+        }
+
+        self.push(frame_number, order);
+    }
+
+    // NOTE: 2^order physically contiguous frames, aligned to their size
+    pub fn allocate_frames(&mut self, order: usize) -> Option<PhysFrame> {
+        let found = (order..=MAX_ORDER).find(|order| self.free_lists[*order] != NIL)?;
+
+        Some(self.take(self.free_lists[found], found, order))
+    }
+
+    // NOTE: like allocate_frames but the whole block ends at or below `limit`, e.g. for devices
+    // that can only address the low 4 GiB
+    pub fn allocate_frames_below(&mut self, order: usize, limit: PhysAddr) -> Option<PhysFrame> {
+        let limit = limit.as_u64() / FRAME_SIZE;
+
+        for found in order..=MAX_ORDER {
+            let mut frame_number = self.free_lists[found];
+
+            // NOTE: only the lowest 2^order frames of a bigger block are handed out
+            while frame_number != NIL {
+                if frame_number + (1 << order) <= limit {
+                    return Some(self.take(frame_number, found, order));
+                }
+
+                frame_number = unsafe { (*block(frame_number)).next };
+            }
+        }
+
+        None
+    }
+
+    // NOTE: unlinks the free block of order `found` and splits it down to `order`
+    fn take(&mut self, frame_number: u64, found: usize, order: usize) -> PhysFrame {
+        unsafe {
+            self.unlink(frame_number, found);
+
+            // NOTE: the upper halves of a split block stay free one order lower
+            for split in (order..found).rev() {
+                self.push(frame_number + (1 << split), split);
+            }
+        }
+
+        self.stats.used += 1 << order;
+
+        PhysFrame::containing_address(PhysAddr::new(frame_number * FRAME_SIZE))
+    }
+
+    /// # Safety
+    ///
+    /// `frame` must come from `allocate_frames` with the same `order` and no longer be used.
+    pub unsafe fn free_frames(&mut self, frame: PhysFrame, order: usize) {
+        self.free_block(frame.start_address().as_u64() / FRAME_SIZE, order);
+    }
+}
+
+impl BuddyFrameAllocator {
+    fn share_slot(&mut self, frame: PhysFrame) -> &mut u16 {
+        &mut self.shares[(frame.start_address().as_u64() / FRAME_SIZE) as usize]
+    }
+
+    pub fn share_count(&mut self, frame: PhysFrame) -> u16 {
+        *self.share_slot(frame)
+    }
+
+    // NOTE: one more mapping uses `frame`, false when the count would overflow
+    pub fn share(&mut self, frame: PhysFrame) -> bool {
+        let count = self.share_slot(frame);
+
+        match count.checked_add(1) {
+            Some(shared) => {
+                *count = shared;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must be dropping one mapping of an allocated order 0 `frame`, which is freed
+    /// once nothing shares it anymore.
+    pub unsafe fn release(&mut self, frame: PhysFrame) {
+        match self.share_slot(frame) {
+            0 => self.free_frames(frame, 0),
+            count => *count -= 1,
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_frames(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free_frames(frame, 0);
+    }
+}
+
+static FRAME_ALLOCATOR: IrqMutex<Option<BuddyFrameAllocator>> = IrqMutex::new(None);
+
+pub(super) fn init(memory_map: &'static MemoryMap) {
+    let allocator = unsafe { BuddyFrameAllocator::new(memory_map) };
+
+    *FRAME_ALLOCATOR.lock() = allocator;
+}
+
+// NOTE: runs `f` on the global allocator, e.g. to pass it to the paging code
+pub fn with_frame_allocator<R>(f: impl FnOnce(&mut BuddyFrameAllocator) -> R) -> Option<R> {
+    FRAME_ALLOCATOR.lock().as_mut().map(f)
+}
+
+// NOTE: for exception handlers, None when the code they interrupted holds the lock; a holder
+// on another CPU is waited for
+pub(crate) fn try_with_frame_allocator<R>(
+    f: impl FnOnce(&mut BuddyFrameAllocator) -> R,
+) -> Option<R> {
+    FRAME_ALLOCATOR.lock_unless_held()?.as_mut().map(f)
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    allocate_frames(0)
+}
+
+// NOTE: exhaustion goes to the OOM handler, which gets one chance to free frames
+pub fn allocate_frames(order: usize) -> Option<PhysFrame> {
+    let allocate = || with_frame_allocator(|allocator| allocator.allocate_frames(order)).flatten();
+
+    allocate().or_else(|| {
+        let retry = order <= MAX_ORDER && oom::report(OomRequest::Frames(order));
+
+        retry.then(allocate).flatten()
+    })
+}
+
+pub fn allocate_frames_below(order: usize, limit: PhysAddr) -> Option<PhysFrame> {
+    with_frame_allocator(|allocator| allocator.allocate_frames_below(order, limit)).flatten()
+}
+
+/// # Safety
+///
+/// `frame` must come from `allocate_frame` and no longer be mapped or used.
+pub unsafe fn free_frame(frame: PhysFrame) {
+    free_frames(frame, 0);
+}
+
+/// # Safety
+///
+/// `frame` must come from `allocate_frames` with the same `order` and no longer be used.
+pub unsafe fn free_frames(frame: PhysFrame, order: usize) {
+    with_frame_allocator(|allocator| allocator.free_frames(frame, order));
+}
+
+pub fn stats() -> FrameStats {
+    with_frame_allocator(|allocator| allocator.stats()).unwrap_or_default()
+}
+
+#[test_case]
+fn test_allocate_and_free() {
+    let before = stats();
+    let first = allocate_frame().unwrap();
+    let second = allocate_frame().unwrap();
+
+    assert_ne!(first, second);
+    assert_eq!(stats().used, before.used + 2);
+
+    unsafe { free_frame(first) };
+
+    assert_eq!(allocate_frame(), Some(first));
+
+    unsafe {
+        free_frame(first);
+        free_frame(second);
+    }
+
+    assert_eq!(stats(), before);
+}
+
+#[test_case]
+fn test_contiguous_frames() {
+    let before = stats();
+    let block = allocate_frames(4).unwrap();
+
+    assert_eq!(block.start_address().as_u64() % (16 * FRAME_SIZE), 0);
+    assert_eq!(stats().used, before.used + 16);
+    assert!(allocate_frames(MAX_ORDER + 1).is_none());
+
+    unsafe { free_frames(block, 4) };
+
+    assert_eq!(stats(), before);
+}
+
+#[test_case]
+fn test_frames_below() {
+    let limit = PhysAddr::new(16 * 1024 * 1024);
+    let block = allocate_frames_below(2, limit).unwrap();
+
+    assert!(block.start_address() + 4 * FRAME_SIZE <= limit);
+    assert!(allocate_frames_below(0, PhysAddr::new(FRAME_SIZE)).is_none());
+
+    unsafe { free_frames(block, 2) };
+}
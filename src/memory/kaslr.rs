@@ -0,0 +1,145 @@
+use crate::tsc;
+use core::arch::x86_64::{__cpuid, _rdrand64_step};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PageTable;
+use x86_64::VirtAddr;
+
+// NOTE: CPUID leaf 1, ECX bit 30
+const CPUID_RDRAND: u32 = 1 << 30;
+const RDRAND_RETRIES: usize = 10;
+
+// NOTE: a level 4 entry covers 512 GiB
+const LEVEL_4_SIZE: u64 = 1 << 39;
+
+// NOTE: the heap and stack slides stay within 64 GiB, well inside the level 4 entry of each
+// region; both are multiples of 2 MiB so the heap still fits a huge page
+const SLIDE_RANGE: u64 = 64 * 1024 * 1024 * 1024;
+const SLIDE_ALIGN: u64 = 2 * 1024 * 1024;
+
+static HEAP_SLIDE: AtomicU64 = AtomicU64::new(0);
+static STACK_SLIDE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Offsets {
+    pub physical_memory: u64,
+    pub heap: u64,
+    pub stacks: u64,
+}
+
+fn rdrand() -> Option<u64> {
+    if __cpuid(1).ecx & CPUID_RDRAND == 0 {
+        return None;
+    }
+
+    let mut value = 0;
+
+    (0..RDRAND_RETRIES).find_map(|_| (unsafe { _rdrand64_step(&mut value) } == 1).then_some(value))
+}
+
+// NOTE: RDRAND when the CPU has it, otherwise the TSC at boot, which varies enough between
+// runs to be better than nothing; mixed with splitmix64 either way
+fn entropy() -> u64 {
+    let mut value = rdrand().unwrap_or_else(tsc::read) ^ tsc::read().rotate_left(32);
+
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    value ^ (value >> 31)
+}
+
+fn slide() -> u64 {
+    entropy() % (SLIDE_RANGE / SLIDE_ALIGN) * SLIDE_ALIGN
+}
+
+// NOTE: moves the bootloader's physical memory mapping to random unused level 4 entries of
+// the higher half by copying its entries over and returns the new offset; has to run before
+// anything keeps a pointer into the old mapping
+fn relocate_physical_memory(offset: u64, physical_memory_end: u64) -> u64 {
+    let (frame, _) = Cr3::read();
+    let level_4 = unsafe { &mut *((offset + frame.start_address().as_u64()) as *mut PageTable) };
+    let first = VirtAddr::new(offset).p4_index();
+    let entries = physical_memory_end.max(1).div_ceil(LEVEL_4_SIZE) as usize;
+    let target = {
+        let free =
+            |start: &usize| (*start..start + entries).all(|index| level_4[index].is_unused());
+        let count = (256..=512 - entries).filter(free).count();
+
+        if count == 0 {
+            return offset;
+        }
+
+        (256..=512 - entries)
+            .filter(free)
+            .nth(entropy() as usize % count)
+    };
+    let Some(target) = target else {
+        return offset;
+    };
+
+    for index in 0..entries {
+        let entry = level_4[usize::from(first) + index].clone();
+
+        level_4[target + index] = entry;
+    }
+
+    tlb::flush_all();
+
+    let relocated =
+        VirtAddr::new_truncate(target as u64 * LEVEL_4_SIZE).as_u64() + offset % LEVEL_4_SIZE;
+    let level_4 = unsafe { &mut *((relocated + frame.start_address().as_u64()) as *mut PageTable) };
+
+    for index in 0..entries {
+        level_4[usize::from(first) + index].set_unused();
+    }
+
+    tlb::flush_all();
+
+    relocated
+}
+
+// NOTE: returns the physical memory offset to use from now on
+pub(super) fn init(offset: u64, physical_memory_end: u64) -> u64 {
+    if cfg!(feature = "no-kaslr") {
+        return offset;
+    }
+
+    HEAP_SLIDE.store(slide(), Ordering::Relaxed);
+    STACK_SLIDE.store(slide(), Ordering::Relaxed);
+
+    relocate_physical_memory(offset, physical_memory_end)
+}
+
+pub fn heap_slide() -> u64 {
+    HEAP_SLIDE.load(Ordering::Relaxed)
+}
+
+pub fn stack_slide() -> u64 {
+    STACK_SLIDE.load(Ordering::Relaxed)
+}
+
+// NOTE: shown on the panic screen, addresses in a report are relative to these
+pub fn offsets() -> Offsets {
+    Offsets {
+        physical_memory: super::physical_memory_offset().as_u64(),
+        heap: heap_slide(),
+        stacks: stack_slide(),
+    }
+}
+
+pub fn is_enabled() -> bool {
+    !cfg!(feature = "no-kaslr")
+}
+
+#[test_case]
+fn test_offsets() {
+    let offsets = offsets();
+
+    assert!(offsets.heap < SLIDE_RANGE && offsets.heap.is_multiple_of(SLIDE_ALIGN));
+    assert!(offsets.stacks < SLIDE_RANGE && offsets.stacks.is_multiple_of(SLIDE_ALIGN));
+    assert_eq!(
+        super::virt_to_phys(super::phys_to_virt(x86_64::PhysAddr::new(0xb8000))),
+        Some(x86_64::PhysAddr::new(0xb8000))
+    );
+}
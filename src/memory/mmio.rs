@@ -0,0 +1,141 @@
+use super::paging::{self, MapError};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+// NOTE: device memory gets its own window, mappings are never torn down
+const MMIO_START: u64 = 0x5000_0000_0000;
+const MMIO_END: u64 = 0x5100_0000_0000;
+
+static NEXT: AtomicU64 = AtomicU64::new(MMIO_START);
+
+// NOTE: caching device registers would reorder or drop reads and writes
+pub const MMIO_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH)
+    .union(PageTableFlags::NO_EXECUTE);
+
+// NOTE: a register, every access is a single volatile read or write
+#[repr(transparent)]
+pub struct VolatileCell<T> {
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for VolatileCell<T> {}
+
+impl<T: Copy> VolatileCell<T> {
+    pub fn read(&self) -> T {
+        unsafe { self.value.get().read_volatile() }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { self.value.get().write_volatile(value) };
+    }
+
+    pub fn update(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MmioRegion {
+    base: VirtAddr,
+    len: usize,
+}
+
+impl MmioRegion {
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // NOTE: panics when the register isn't naturally aligned or doesn't fit in the region
+    pub fn register<T: Copy>(&self, offset: usize) -> &'static VolatileCell<T> {
+        assert!(offset + core::mem::size_of::<T>() <= self.len);
+        assert_eq!(offset % core::mem::align_of::<T>(), 0);
+
+        unsafe { &*(self.base + offset as u64).as_ptr() }
+    }
+
+    /// # Safety
+    ///
+    /// `T` must describe the device's register layout, made of `VolatileCell`s, and fit in the
+    /// region.
+    pub unsafe fn view<T>(&self) -> &'static T {
+        &*self.base.as_ptr()
+    }
+}
+
+// NOTE: maps the device registers at `phys..phys + len` uncached, `phys` needn't be page
+// aligned
+pub fn map_mmio(phys: PhysAddr, len: usize) -> Result<MmioRegion, MapError> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + len.max(1) as u64 - 1u64);
+    let size = last.start_address() - first.start_address() + Size4KiB::SIZE;
+    let start = NEXT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(size).filter(|&end| end <= MMIO_END)
+        })
+        .map_err(|_| MapError::FrameAllocationFailed)?;
+    let page = |index: usize| {
+        Page::<Size4KiB>::containing_address(VirtAddr::new(start) + index as u64 * Size4KiB::SIZE)
+    };
+
+    for (index, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        // NOTE: the frames are device memory, nothing else owns them
+        if let Err(error) = unsafe { paging::map_to(page(index), frame, MMIO_FLAGS) } {
+            // NOTE: nothing has used the pages mapped so far. The window space only goes back
+            // if no other mapping was made after it
+            for index in 0..index {
+                let _ = paging::unmap(page(index));
+            }
+
+            let _ =
+                NEXT.compare_exchange(start + size, start, Ordering::Relaxed, Ordering::Relaxed);
+
+            return Err(error);
+        }
+    }
+
+    Ok(MmioRegion {
+        base: VirtAddr::new(start) + (phys - first.start_address()),
+        len,
+    })
+}
+
+#[test_case]
+fn test_map_mmio() {
+    // NOTE: the VGA text buffer, which also shows up through the physical memory mapping
+    let region = map_mmio(PhysAddr::new(0xb8000 + 2), 160).unwrap();
+    let cell = region.register::<u16>(0);
+    let alias = super::phys_to_virt(PhysAddr::new(0xb8000 + 2)).as_ptr::<u16>();
+    let before = cell.read();
+
+    assert_eq!(
+        paging::virt_to_phys(region.base()),
+        Some(PhysAddr::new(0xb8002))
+    );
+
+    cell.write(0x0f21);
+
+    assert_eq!(unsafe { alias.read_volatile() }, 0x0f21);
+
+    cell.write(before);
+}
+
+#[test_case]
+fn test_map_mmio_too_large() {
+    let next = NEXT.load(Ordering::Relaxed);
+
+    assert!(map_mmio(PhysAddr::new(0), (MMIO_END - MMIO_START) as usize + 1).is_err());
+    assert_eq!(NEXT.load(Ordering::Relaxed), next);
+}
@@ -0,0 +1,41 @@
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomRequest {
+    Heap(Layout),
+    // NOTE: 2^order contiguous frames
+    Frames(usize),
+}
+
+// NOTE: called outside of the allocator's lock, returns true when it freed memory so the
+// allocation is worth retrying once, e.g. after dropping caches
+pub type OomHandler = fn(OomRequest) -> bool;
+
+// NOTE: OomHandler pointer, 0 when none is registered
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: replaces the previous handler, None removes it
+pub fn set_handler(handler: Option<OomHandler>) {
+    HANDLER.store(
+        handler.map_or(0, |handler| handler as usize),
+        Ordering::Release,
+    );
+}
+
+// NOTE: failed allocations so far, whether they were retried or not
+pub fn count() -> u64 {
+    COUNT.load(Ordering::Relaxed)
+}
+
+// NOTE: what happens after a false return is up to the caller, infallible heap allocations
+// panic in handle_alloc_error and the try APIs hand the failure back
+pub(crate) fn report(request: OomRequest) -> bool {
+    COUNT.fetch_add(1, Ordering::Relaxed);
+
+    match HANDLER.load(Ordering::Acquire) {
+        0 => false,
+        handler => unsafe { core::mem::transmute::<usize, OomHandler>(handler)(request) },
+    }
+}
@@ -0,0 +1,451 @@
+use super::frame_allocator::{self, BuddyFrameAllocator};
+use super::tlb::{self, Target};
+use crate::sync::IrqMutex;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    FrameAllocationFailed,
+    AlreadyMapped(PhysFrame),
+    NotMapped,
+    // NOTE: the page is part of a 2 MiB or 1 GiB mapping, which this API leaves alone
+    HugePage,
+    InvalidFrameAddress(PhysAddr),
+    // NOTE: only from fault handlers, the page table or frame allocator lock was held
+    Locked,
+    // NOTE: the page is in a level 4 entry an address space shares with the kernel
+    Shared,
+}
+
+// NOTE: the size of the page an address is mapped with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+// NOTE: a huge frame that is already mapped is reported by its first 4 KiB frame
+impl<S: PageSize> From<MapToError<S>> for MapError {
+    fn from(error: MapToError<S>) -> MapError {
+        match error {
+            MapToError::FrameAllocationFailed => MapError::FrameAllocationFailed,
+            MapToError::PageAlreadyMapped(frame) => {
+                MapError::AlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+            }
+            MapToError::ParentEntryHugePage => MapError::HugePage,
+        }
+    }
+}
+
+impl From<UnmapError> for MapError {
+    fn from(error: UnmapError) -> MapError {
+        match error {
+            UnmapError::PageNotMapped => MapError::NotMapped,
+            UnmapError::ParentEntryHugePage => MapError::HugePage,
+            UnmapError::InvalidFrameAddress(address) => MapError::InvalidFrameAddress(address),
+        }
+    }
+}
+
+impl From<FlagUpdateError> for MapError {
+    fn from(error: FlagUpdateError) -> MapError {
+        match error {
+            FlagUpdateError::PageNotMapped => MapError::NotMapped,
+            FlagUpdateError::ParentEntryHugePage => MapError::HugePage,
+        }
+    }
+}
+
+static PAGE_TABLE: IrqMutex<Option<OffsetPageTable<'static>>> = IrqMutex::new(None);
+// NOTE: physical address of the kernel's PML4, the one active at init
+static KERNEL_LEVEL_4: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: the active level 4 table through the physical memory mapping
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (frame, _) = Cr3::read();
+    let table = physical_memory_offset + frame.start_address().as_u64();
+
+    &mut *table.as_mut_ptr()
+}
+
+pub(super) fn init(physical_memory_offset: VirtAddr) {
+    KERNEL_LEVEL_4.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+
+    let table = unsafe {
+        OffsetPageTable::new(
+            active_level_4_table(physical_memory_offset),
+            physical_memory_offset,
+        )
+    };
+
+    *PAGE_TABLE.lock() = Some(table);
+}
+
+pub fn kernel_level_4() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_LEVEL_4.load(Ordering::Relaxed)))
+}
+
+// NOTE: runs `f` on the kernel's page table, panics before memory::init
+pub fn with_page_table<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> R {
+    f(PAGE_TABLE
+        .lock()
+        .as_mut()
+        .expect("page table used before memory::init"))
+}
+
+// NOTE: maps a zeroed frame at `page` from an exception handler, without ever spinning on the
+// locks of the code it interrupted
+pub(crate) fn map_zeroed_in_fault(
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, MapError> {
+    try_with_page_table_and_frames(|table, frames| {
+        let frame = frames
+            .allocate_frame()
+            .ok_or(MapError::FrameAllocationFailed)?;
+
+        unsafe {
+            core::ptr::write_bytes(
+                super::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                0,
+                4096,
+            );
+        }
+
+        match unsafe { table.map_to(page, frame, flags, frames) } {
+            Ok(flush) => {
+                flush.flush();
+
+                Ok(frame)
+            }
+            Err(error) => {
+                unsafe { frames.deallocate_frame(frame) };
+
+                Err(MapError::from(error))
+            }
+        }
+    })?
+}
+
+// NOTE: walks the page table, None for unmapped addresses; handles huge pages too
+pub fn virt_to_phys(address: VirtAddr) -> Option<PhysAddr> {
+    with_page_table(|table| table.translate_addr(address))
+}
+
+// NOTE: like with_page_table_and_frames but for exception handlers, fails instead of spinning
+// on a lock the interrupted code holds; one another CPU holds is waited for
+pub(crate) fn try_with_page_table_and_frames<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BuddyFrameAllocator) -> R,
+) -> Result<R, MapError> {
+    let mut table = PAGE_TABLE.lock_unless_held().ok_or(MapError::Locked)?;
+    let table = table.as_mut().ok_or(MapError::Locked)?;
+
+    frame_allocator::try_with_frame_allocator(|frames| f(table, frames)).ok_or(MapError::Locked)
+}
+
+// NOTE: lock order is page table first, then frame allocator
+pub(crate) fn with_page_table_and_frames<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BuddyFrameAllocator) -> R,
+) -> Result<R, MapError> {
+    with_page_table(|table| {
+        frame_allocator::with_frame_allocator(|frames| f(table, frames))
+            .ok_or(MapError::FrameAllocationFailed)
+    })
+}
+
+/// # Safety
+///
+/// `frame` must not be mapped anywhere else in a way that breaks aliasing rules, e.g. a frame
+/// the heap or another page already owns.
+pub unsafe fn map_to(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MapError> {
+    with_page_table_and_frames(|table, frames| {
+        table
+            .map_to(page, frame, flags, frames)
+            .map(|flush| flush.flush())
+    })?
+    .map_err(MapError::from)
+}
+
+// NOTE: backs `page` with a fresh frame, the frame is not zeroed
+pub fn map(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapError> {
+    with_page_table_and_frames(|table, frames| {
+        let frame = frames
+            .allocate_frame()
+            .ok_or(MapError::FrameAllocationFailed)?;
+
+        match unsafe { table.map_to(page, frame, flags, frames) } {
+            Ok(flush) => {
+                flush.flush();
+
+                Ok(frame)
+            }
+            Err(error) => {
+                unsafe { frames.deallocate_frame(frame) };
+
+                Err(MapError::from(error))
+            }
+        }
+    })?
+}
+
+// NOTE: backs `page` with 512 contiguous fresh frames, not zeroed either
+pub fn map_huge(
+    page: Page<Size2MiB>,
+    flags: PageTableFlags,
+) -> Result<PhysFrame<Size2MiB>, MapError> {
+    with_page_table_and_frames(|table, frames| {
+        let first = frames
+            .allocate_frames(9)
+            .ok_or(MapError::FrameAllocationFailed)?;
+        let frame = PhysFrame::<Size2MiB>::containing_address(first.start_address());
+
+        match unsafe { table.map_to(page, frame, flags | PageTableFlags::HUGE_PAGE, frames) } {
+            Ok(flush) => {
+                flush.flush();
+
+                Ok(frame)
+            }
+            Err(error) => {
+                unsafe { frames.free_frames(first, 9) };
+
+                Err(MapError::from(error))
+            }
+        }
+    })?
+}
+
+// NOTE: maps fresh frames over `start..start + size`, with 2 MiB pages wherever the range is
+// aligned for one and 4 KiB pages at the edges or when no contiguous block is left
+pub fn map_range(start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapError> {
+    let end = start + size;
+    let mut address = start.align_down(Size4KiB::SIZE);
+
+    while address < end {
+        if address.is_aligned(Size2MiB::SIZE) && end - address >= Size2MiB::SIZE {
+            match map_huge(Page::containing_address(address), flags) {
+                Ok(_) => {
+                    address += Size2MiB::SIZE;
+
+                    continue;
+                }
+                Err(MapError::FrameAllocationFailed) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        map(Page::containing_address(address), flags)?;
+        address += Size4KiB::SIZE;
+    }
+
+    Ok(())
+}
+
+// NOTE: None for unmapped addresses
+pub fn mapping_size(address: VirtAddr) -> Option<MappingSize> {
+    with_page_table(|table| match table.translate(address) {
+        TranslateResult::Mapped { frame, .. } => Some(match frame {
+            MappedFrame::Size4KiB(_) => MappingSize::Size4KiB,
+            MappedFrame::Size2MiB(_) => MappingSize::Size2MiB,
+            MappedFrame::Size1GiB(_) => MappingSize::Size1GiB,
+        }),
+        _ => None,
+    })
+}
+
+pub fn is_huge(address: VirtAddr) -> bool {
+    matches!(
+        mapping_size(address),
+        Some(MappingSize::Size2MiB | MappingSize::Size1GiB)
+    )
+}
+
+// NOTE: the table an entry points to, through the physical memory mapping
+unsafe fn next_table(entry: &PageTableEntry) -> Option<&'static mut PageTable> {
+    let flags = entry.flags();
+
+    if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+        return None;
+    }
+
+    Some(&mut *super::phys_to_virt(entry.addr()).as_mut_ptr())
+}
+
+// NOTE: turns every 2 MiB of the physical memory mapping below `end` that is made of 4 KiB
+// pages into one 2 MiB page, returns how many; the page tables that drop out belong to the
+// bootloader's memory and stay there. Chunks with a PAT bit or mixed flags are left alone
+pub(super) fn use_huge_physical_mapping(end: PhysAddr) -> usize {
+    let mut merged = 0;
+
+    with_page_table(|table| {
+        let level_4 = table.level_4_table();
+
+        for physical in (0..end.as_u64()).step_by(Size2MiB::SIZE as usize) {
+            let address = super::phys_to_virt(PhysAddr::new(physical));
+            let Some(level_3) = (unsafe { next_table(&level_4[address.p4_index()]) }) else {
+                continue;
+            };
+            let Some(level_2) = (unsafe { next_table(&level_3[address.p3_index()]) }) else {
+                continue;
+            };
+            let entry = &mut level_2[address.p2_index()];
+            let Some(level_1) = (unsafe { next_table(entry) }) else {
+                continue;
+            };
+            let flags = level_1[0].flags();
+            let contiguous = level_1.iter().enumerate().all(|(index, page)| {
+                page.flags() == flags && page.addr().as_u64() == physical + index as u64 * 4096
+            });
+
+            // NOTE: bit 7 of a 4 KiB entry is PAT, the same bit says huge page one level up
+            if !contiguous
+                || !flags.contains(PageTableFlags::PRESENT)
+                || flags.contains(PageTableFlags::HUGE_PAGE)
+            {
+                continue;
+            }
+
+            entry.set_addr(
+                PhysAddr::new(physical),
+                (flags - PageTableFlags::ACCESSED - PageTableFlags::DIRTY)
+                    | PageTableFlags::HUGE_PAGE,
+            );
+            merged += 1;
+        }
+    });
+
+    x86_64::instructions::tlb::flush_all();
+
+    merged
+}
+
+// NOTE: returns the frame that backed `page`, which the caller may free; the TLB entry is
+// shot down on every CPU
+pub fn unmap(page: Page) -> Result<PhysFrame, MapError> {
+    let frame = with_page_table(|table| {
+        let (frame, flush) = table.unmap(page)?;
+
+        flush.ignore();
+
+        Ok::<_, MapError>(frame)
+    })?;
+
+    tlb::flush_page(Target::Kernel, page);
+
+    Ok(frame)
+}
+
+// NOTE: unmap for `pages` pages from `start` with a single shootdown, pages that aren't mapped
+// are skipped. `f` gets each frame once no CPU can reach it any more
+pub fn unmap_range(start: Page, pages: u64, f: impl FnMut(PhysFrame)) {
+    let frames: Vec<PhysFrame> = with_page_table(|table| {
+        (0..pages)
+            .filter_map(|index| {
+                let (frame, flush) = table.unmap(start + index).ok()?;
+
+                flush.ignore();
+
+                Some(frame)
+            })
+            .collect()
+    });
+
+    tlb::shootdown(Target::Kernel, start, pages);
+    frames.into_iter().for_each(f);
+}
+
+pub fn update_flags(page: Page, flags: PageTableFlags) -> Result<(), MapError> {
+    with_page_table(|table| {
+        unsafe { table.update_flags(page, flags) }?.ignore();
+
+        Ok::<_, MapError>(())
+    })?;
+
+    tlb::flush_page(Target::Kernel, page);
+
+    Ok(())
+}
+
+// NOTE: the frame and flags of a 4 KiB mapping
+pub fn translate(page: Page) -> Result<(PhysFrame, PageTableFlags), MapError> {
+    with_page_table(|table| match table.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => Ok((frame, flags)),
+        TranslateResult::Mapped { .. } => Err(MapError::HugePage),
+        TranslateResult::NotMapped => Err(MapError::NotMapped),
+        TranslateResult::InvalidFrameAddress(address) => {
+            Err(MapError::InvalidFrameAddress(address))
+        }
+    })
+}
+
+#[test_case]
+fn test_virt_to_phys() {
+    let physical = PhysAddr::new(0xb8000);
+
+    assert_eq!(virt_to_phys(super::phys_to_virt(physical)), Some(physical));
+    assert_eq!(virt_to_phys(VirtAddr::new(0)), None);
+}
+
+#[test_case]
+fn test_huge_mappings() {
+    // NOTE: memory::init leaves all of the physical memory mapping on 2 MiB pages
+    assert_eq!(
+        use_huge_physical_mapping(PhysAddr::new(64 * 1024 * 1024)),
+        0
+    );
+    assert!(is_huge(super::phys_to_virt(PhysAddr::new(0xb8000))));
+    assert_eq!(mapping_size(VirtAddr::new(0)), None);
+
+    let start = VirtAddr::new(0x5555_5560_0000 - 4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    map_range(start, Size2MiB::SIZE + 4096, flags).unwrap();
+
+    assert_eq!(mapping_size(start), Some(MappingSize::Size4KiB));
+    assert_eq!(mapping_size(start + 4096u64), Some(MappingSize::Size2MiB));
+
+    unsafe {
+        let huge = Page::<Size2MiB>::containing_address(start + 4096u64);
+        let (frame, flush) = with_page_table(|table| table.unmap(huge)).unwrap();
+
+        flush.flush();
+        frame_allocator::free_frames(PhysFrame::containing_address(frame.start_address()), 9);
+        frame_allocator::free_frame(unmap(Page::containing_address(start)).unwrap());
+    }
+}
+
+#[test_case]
+fn test_map_update_unmap() {
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x5555_5555_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let frame = map(page, flags).unwrap();
+    let pointer: *mut u64 = page.start_address().as_mut_ptr();
+
+    unsafe { pointer.write_volatile(0xf00d) };
+
+    assert_eq!(unsafe { pointer.read_volatile() }, 0xf00d);
+    assert_eq!(map(page, flags), Err(MapError::AlreadyMapped(frame)));
+
+    update_flags(page, PageTableFlags::PRESENT).unwrap();
+
+    assert_eq!(translate(page), Ok((frame, PageTableFlags::PRESENT)));
+    assert_eq!(unmap(page), Ok(frame));
+    assert_eq!(translate(page), Err(MapError::NotMapped));
+
+    unsafe { frame_allocator::free_frame(frame) };
+}
@@ -0,0 +1,175 @@
+use super::paging::{self, MapError};
+use x86_64::instructions::tlb;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+extern "C" {
+    // NOTE: defined by the linker, the kernel's own ELF header is part of its first segment
+    static __ehdr_start: u8;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    address: u64,
+    physical_address: u64,
+    file_size: u64,
+    memory_size: u64,
+    align: u64,
+}
+
+// NOTE: the loadable segments of the running kernel, relocated to where they were loaded
+fn segments() -> impl Iterator<Item = (VirtAddr, VirtAddr, u32)> {
+    let header = core::ptr::addr_of!(__ehdr_start) as u64;
+    let (table, count) = unsafe {
+        (
+            ((header + 32) as *const u64).read_unaligned(),
+            ((header + 56) as *const u16).read_unaligned(),
+        )
+    };
+    let headers = unsafe {
+        core::slice::from_raw_parts((header + table) as *const ProgramHeader, count as usize)
+    };
+    let loads = headers
+        .iter()
+        .filter(|program| program.kind == PT_LOAD && program.memory_size > 0);
+    let bias = loads
+        .clone()
+        .find(|program| program.offset == 0)
+        .map_or(0, |program| header - program.address);
+
+    loads.map(move |program| {
+        let start = VirtAddr::new(program.address + bias);
+
+        (start, start + program.memory_size, program.flags)
+    })
+}
+
+fn pages(start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = Page> {
+    Page::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(end - 1u64),
+    )
+}
+
+// NOTE: a page two segments share gets the permissions of both
+fn kernel_page_flags(page: Page) -> PageTableFlags {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+
+    for (start, end, segment) in segments() {
+        if (Page::containing_address(start)..=Page::containing_address(end - 1u64)).contains(&page)
+        {
+            if segment & PF_W != 0 {
+                flags |= PageTableFlags::WRITABLE;
+            }
+
+            if segment & PF_X != 0 {
+                flags -= PageTableFlags::NO_EXECUTE;
+            }
+        }
+    }
+
+    flags
+}
+
+// NOTE: the bootloader maps its stack writable and executable; it is the run of mapped pages
+// around the current stack pointer, between the guard page and the boot info. What is found
+// is kept for stack::bounds
+fn protect_boot_stack() {
+    let marker = 0u8;
+    let here = Page::<Size4KiB>::containing_address(VirtAddr::from_ptr(&marker));
+    let protect = |page: Page| match paging::translate(page) {
+        Ok((_, flags)) => paging::update_flags(page, flags | PageTableFlags::NO_EXECUTE).is_ok(),
+        Err(_) => false,
+    };
+    let mut top = here;
+
+    while protect(top) {
+        top += 1;
+    }
+
+    let mut bottom = here;
+
+    while bottom.start_address().as_u64() >= Size4KiB::SIZE && protect(bottom - 1) {
+        bottom -= 1;
+    }
+
+    super::stack::set_boot_stack(bottom.start_address(), top.start_address());
+}
+
+// NOTE: NX on the level 4 entries of the physical memory mapping covers all of it at once,
+// the bootloader gives the mapping entries of its own
+fn protect_physical_memory(physical_memory_end: u64) {
+    let start = super::physical_memory_offset();
+    let end = start + physical_memory_end.max(1) - 1u64;
+
+    paging::with_page_table(|table| {
+        let level_4 = table.level_4_table();
+
+        for index in u16::from(start.p4_index())..=u16::from(end.p4_index()) {
+            let entry = &mut level_4[index as usize];
+
+            if !entry.is_unused() {
+                entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+            }
+        }
+    });
+
+    tlb::flush_all();
+}
+
+// NOTE: turns on NX and enforces W^X on the kernel image: text is read-execute, rodata
+// read-only and data and bss no-execute. The heap, kernel stacks and MMIO are mapped NX
+// already; the physical memory mapping stays writable since everything writes through it
+pub(super) fn init(physical_memory_end: u64) -> Result<(), MapError> {
+    unsafe { Efer::update(|flags| *flags |= EferFlags::NO_EXECUTE_ENABLE) };
+
+    for (start, end, _) in segments() {
+        for page in pages(start, end) {
+            paging::update_flags(page, kernel_page_flags(page))?;
+        }
+    }
+
+    protect_boot_stack();
+    protect_physical_memory(physical_memory_end);
+
+    Ok(())
+}
+
+// NOTE: whether `address` can be written and executed at the same time, W^X says never
+pub fn is_writable_and_executable(address: VirtAddr) -> bool {
+    match paging::translate(Page::containing_address(address)) {
+        Ok((_, flags)) => {
+            flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE)
+        }
+        Err(_) => false,
+    }
+}
+
+#[test_case]
+fn test_kernel_sections() {
+    static DATA: u8 = 0;
+    static mut BSS: u8 = 0;
+
+    let text = VirtAddr::from_ptr(is_writable_and_executable as *const ());
+    let text_flags = paging::translate(Page::containing_address(text)).unwrap().1;
+    let rodata_flags = paging::translate(Page::containing_address(VirtAddr::from_ptr(&DATA)))
+        .unwrap()
+        .1;
+    let bss = VirtAddr::new(core::ptr::addr_of!(BSS) as u64);
+
+    assert!(Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE));
+    assert!(!text_flags.contains(PageTableFlags::WRITABLE));
+    assert!(!text_flags.contains(PageTableFlags::NO_EXECUTE));
+    assert!(!rodata_flags.contains(PageTableFlags::WRITABLE));
+    assert!(rodata_flags.contains(PageTableFlags::NO_EXECUTE));
+    assert!(!is_writable_and_executable(bss));
+}
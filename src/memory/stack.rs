@@ -0,0 +1,177 @@
+use super::frame_allocator;
+use super::paging::{self, MapError};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+// NOTE: stacks live in fixed size slots of an otherwise unused part of the lower half, slid
+// by KASLR; the lowest page of every slot is never mapped and catches overflows
+const STACK_REGION_BASE: u64 = 0x2000_0000_0000;
+const SLOT_PAGES: u64 = 32;
+const MAX_STACKS: usize = 64;
+
+pub const MAX_STACK_PAGES: usize = SLOT_PAGES as usize - 1;
+
+// NOTE: owner and page count of each slot, None when free
+static SLOTS: Mutex<[Option<(&'static str, usize)>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    NoFreeSlot,
+    TooLarge(usize),
+    Map(MapError),
+}
+
+fn region_start() -> u64 {
+    STACK_REGION_BASE + super::kaslr::stack_slide()
+}
+
+fn slot_start(slot: usize) -> u64 {
+    region_start() + slot as u64 * SLOT_PAGES * PAGE_SIZE
+}
+
+// NOTE: unmaps and frees its pages when dropped, nothing may still run on it by then
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    pages: usize,
+}
+
+impl KernelStack {
+    // NOTE: `owner` shows up in the page fault report when the stack overflows
+    pub fn allocate(owner: &'static str, pages: usize) -> Result<KernelStack, StackError> {
+        if pages == 0 || pages > MAX_STACK_PAGES {
+            return Err(StackError::TooLarge(pages));
+        }
+
+        let slot = interrupts::without_interrupts(|| {
+            let mut slots = SLOTS.lock();
+            let slot = slots.iter().position(Option::is_none)?;
+
+            slots[slot] = Some((owner, pages));
+
+            Some(slot)
+        })
+        .ok_or(StackError::NoFreeSlot)?;
+
+        let mut stack = KernelStack { slot, pages: 0 };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        // NOTE: on failure the drop releases whatever was mapped so far
+        for _ in 0..pages {
+            paging::map(stack.page(stack.pages), flags).map_err(StackError::Map)?;
+            stack.pages += 1;
+        }
+
+        Ok(stack)
+    }
+
+    // NOTE: `index` 0 is the highest page
+    fn page(&self, index: usize) -> Page {
+        Page::containing_address(self.top() - (index as u64 + 1) * PAGE_SIZE)
+    }
+
+    // NOTE: stacks grow down, this is the initial stack pointer
+    pub fn top(&self) -> VirtAddr {
+        VirtAddr::new(slot_start(self.slot + 1))
+    }
+
+    pub fn bottom(&self) -> VirtAddr {
+        self.top() - self.pages as u64 * PAGE_SIZE
+    }
+
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(VirtAddr::new(slot_start(self.slot)))
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        if self.pages > 0 {
+            paging::unmap_range(
+                self.page(self.pages - 1),
+                self.pages as u64,
+                |frame| unsafe { frame_allocator::free_frame(frame) },
+            );
+        }
+
+        interrupts::without_interrupts(|| SLOTS.lock()[self.slot] = None);
+    }
+}
+
+// NOTE: the bootloader's stack as memory::protection found it, bottom and top
+static BOOT_STACK: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+pub(super) fn set_boot_stack(bottom: VirtAddr, top: VirtAddr) {
+    BOOT_STACK[0].store(bottom.as_u64(), Ordering::Relaxed);
+    BOOT_STACK[1].store(top.as_u64(), Ordering::Relaxed);
+}
+
+// NOTE: bottom and top of the thread or boot stack `address` is on, without taking locks.
+// Everything from an address on the stack up to the top is mapped
+pub fn bounds(address: VirtAddr) -> Option<(VirtAddr, VirtAddr)> {
+    let boot = (
+        BOOT_STACK[0].load(Ordering::Relaxed),
+        BOOT_STACK[1].load(Ordering::Relaxed),
+    );
+
+    if (boot.0..boot.1).contains(&address.as_u64()) {
+        return Some((VirtAddr::new(boot.0), VirtAddr::new(boot.1)));
+    }
+
+    let slot = (address.as_u64().checked_sub(region_start())? / (SLOT_PAGES * PAGE_SIZE)) as usize;
+
+    // NOTE: the guard page itself is never mapped
+    match slot < MAX_STACKS && address.as_u64() >= slot_start(slot) + PAGE_SIZE {
+        true => Some((
+            VirtAddr::new(slot_start(slot) + PAGE_SIZE),
+            VirtAddr::new(slot_start(slot + 1)),
+        )),
+        false => None,
+    }
+}
+
+// NOTE: anything below the lowest mapped page of a slot counts as hitting the guard, returns
+// the owner of that stack. Called from the page fault handler, so only try_lock
+pub fn guard_page_owner(address: VirtAddr) -> Option<&'static str> {
+    let offset = address.as_u64().checked_sub(region_start())?;
+    let slot = (offset / (SLOT_PAGES * PAGE_SIZE)) as usize;
+    let page = offset / PAGE_SIZE % SLOT_PAGES;
+
+    if slot >= MAX_STACKS {
+        return None;
+    }
+
+    let (owner, pages) = SLOTS.try_lock()?[slot]?;
+
+    (page < SLOT_PAGES - pages as u64).then_some(owner)
+}
+
+#[test_case]
+fn test_stack_guard_page() {
+    let stack = KernelStack::allocate("test", 4).unwrap();
+    let top: *mut u64 = (stack.top() - 8u64).as_mut_ptr();
+
+    unsafe { top.write_volatile(7) };
+
+    assert_eq!(unsafe { top.read_volatile() }, 7);
+    assert_eq!(stack.bottom(), stack.top() - 4 * PAGE_SIZE);
+    assert_eq!(guard_page_owner(stack.bottom() - 8u64), Some("test"));
+    assert_eq!(guard_page_owner(stack.top() - 8u64), None);
+    assert!(paging::translate(stack.guard_page()).is_err());
+    assert_eq!(
+        bounds(stack.top() - 8u64),
+        Some((stack.guard_page().start_address() + PAGE_SIZE, stack.top()))
+    );
+    assert_eq!(bounds(stack.guard_page().start_address()), None);
+
+    let before = frame_allocator::stats();
+
+    drop(stack);
+
+    assert_eq!(frame_allocator::stats().used, before.used - 4);
+}
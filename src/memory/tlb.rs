@@ -0,0 +1,156 @@
+use crate::apic;
+use crate::percpu;
+use crate::percpu::PerCpu;
+use crate::sync::IrqMutex;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PhysFrame};
+use x86_64::VirtAddr;
+
+// NOTE: just below the APIC's spurious vector
+pub const SHOOTDOWN_VECTOR: u8 = 0xfe;
+
+// NOTE: more pages than this are dropped with a full flush instead of one invlpg each
+const MAX_SINGLE_PAGES: u64 = 32;
+
+// NOTE: which CPUs can have stale entries for a change, every CPU for the kernel's own page
+// table and the CPUs that have the level 4 table loaded for an address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Kernel,
+    Space(PhysFrame),
+}
+
+// NOTE: one shootdown at a time, its range stays put until every CPU is done with it. 0 pages
+// is everything
+static REQUEST: IrqMutex<()> = IrqMutex::new(());
+static START: AtomicU64 = AtomicU64::new(0);
+static PAGES: AtomicU64 = AtomicU64::new(0);
+
+fn flush_local(start: u64, pages: u64) {
+    if pages == 0 || pages > MAX_SINGLE_PAGES {
+        return x86_64::instructions::tlb::flush_all();
+    }
+
+    for index in 0..pages {
+        x86_64::instructions::tlb::flush(VirtAddr::new(start + index * 4096));
+    }
+}
+
+fn hit(cpu: &PerCpu, target: Target) -> bool {
+    match target {
+        Target::Kernel => true,
+        Target::Space(level_4) => {
+            cpu.tlb.level_4.load(Ordering::SeqCst) == level_4.start_address().as_u64()
+        }
+    }
+}
+
+// NOTE: drops the entries for `pages` pages from `start` on every CPU `target` names, this one
+// included, and returns once all of them have. Frames the pages mapped may be freed after
+pub fn shootdown(target: Target, start: Page, pages: u64) {
+    let start = start.start_address().as_u64();
+    let _request = REQUEST.lock();
+    let this = percpu::current();
+
+    if target == Target::Kernel || target == Target::Space(Cr3::read().0) {
+        flush_local(start, pages);
+    }
+
+    // NOTE: pairs with the store in load_level_4, a CPU not seen with the table yet loads it
+    // after the change
+    fence(Ordering::SeqCst);
+
+    START.store(start, Ordering::Relaxed);
+    PAGES.store(pages, Ordering::Relaxed);
+
+    let mut others = false;
+
+    for cpu in (0..percpu::online()).filter_map(percpu::cpu) {
+        if cpu.index() != this.index() && cpu.tlb.ready.load(Ordering::SeqCst) && hit(cpu, target) {
+            cpu.tlb.pending.store(true, Ordering::Release);
+            others = true;
+        }
+    }
+
+    if !others {
+        return;
+    }
+
+    apic::send_all_but_self(SHOOTDOWN_VECTOR);
+
+    for cpu in (0..percpu::online()).filter_map(percpu::cpu) {
+        while cpu.tlb.pending.load(Ordering::Acquire) {
+            service();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub fn flush_page(target: Target, page: Page) {
+    shootdown(target, page, 1);
+}
+
+pub fn flush_all(target: Target) {
+    shootdown(target, Page::containing_address(VirtAddr::zero()), 0);
+}
+
+// NOTE: runs a shootdown waiting on this CPU, from the IPI handler and from anywhere that
+// spins with interrupts off, which the CPU asking may be waiting on
+pub(crate) fn service() {
+    let tlb = &percpu::current().tlb;
+
+    if tlb.pending.load(Ordering::Acquire) {
+        flush_local(START.load(Ordering::Relaxed), PAGES.load(Ordering::Relaxed));
+        tlb.pending.store(false, Ordering::Release);
+    }
+}
+
+/// # Safety
+///
+/// Same as `Cr3::write`, nothing running may rely on mappings only the current table has.
+pub unsafe fn load_level_4(level_4: PhysFrame) {
+    let (_, flags) = Cr3::read();
+
+    percpu!(tlb.level_4).store(level_4.start_address().as_u64(), Ordering::SeqCst);
+    Cr3::write(level_4, flags);
+}
+
+// NOTE: for each CPU once its local APIC takes interrupts, shootdowns only wait for CPUs that
+// have done this. Whatever the CPU cached before is dropped here
+pub(crate) fn init_cpu() {
+    let (level_4, _) = Cr3::read();
+    let tlb = &percpu::current().tlb;
+
+    tlb.level_4
+        .store(level_4.start_address().as_u64(), Ordering::SeqCst);
+    tlb.ready.store(true, Ordering::SeqCst);
+    x86_64::instructions::tlb::flush_all();
+}
+
+#[test_case]
+fn test_shootdown() {
+    use super::{frame_allocator, paging};
+    use x86_64::structures::paging::PageTableFlags;
+    use x86_64::PhysAddr;
+
+    let page = Page::containing_address(VirtAddr::new(0x5555_7000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    paging::map(page, flags).unwrap();
+    unsafe { page.start_address().as_mut_ptr::<u64>().write_volatile(7) };
+
+    let frame = paging::unmap(page).unwrap();
+
+    unsafe { frame_allocator::free_frame(frame) };
+
+    // NOTE: no CPU has this table loaded, nothing is waited for
+    flush_all(Target::Space(PhysFrame::containing_address(PhysAddr::new(
+        0,
+    ))));
+
+    assert_eq!(paging::virt_to_phys(page.start_address()), None);
+    assert!((0..percpu::online())
+        .filter_map(percpu::cpu)
+        .all(|cpu| !cpu.tlb.pending.load(Ordering::Relaxed)));
+}
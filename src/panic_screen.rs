@@ -0,0 +1,127 @@
+use crate::console::{self, ConsoleAdapter};
+use crate::memory::kaslr;
+use crate::vga_buffer::Color;
+use core::arch::asm;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use x86_64::registers::control::{Cr2, Cr3};
+use x86_64::registers::rflags;
+
+// NOTE: number of 8 byte stack slots dumped, 4 per line
+const STACK_DUMP_QWORDS: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rflags: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+}
+
+impl Registers {
+    // NOTE: rip/rsp/rbp are the ones of this function's frame, close enough for a panic
+    #[inline(always)]
+    pub fn capture() -> Registers {
+        let (rip, rsp, rbp): (u64, u64, u64);
+
+        unsafe {
+            asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack));
+            asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+            asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+        }
+
+        Registers {
+            rip,
+            rsp,
+            rbp,
+            rflags: rflags::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+        }
+    }
+}
+
+pub fn show(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    // NOTE: lands in the debugcon log even if the console itself is what broke
+    crate::debugcon_println!("KERNEL PANIC: {}", info);
+
+    let registers = Registers::capture();
+    let backend = console::backend();
+
+    // NOTE: the panicking code may have been holding the console lock
+    unsafe { backend.force_unlock() };
+    crate::screensaver::unblank();
+
+    let mut console = backend.lock();
+    let (_, cols) = console.dimensions();
+
+    console.set_color(Color::WHITE, Color::RED);
+    console.clear();
+    draw(&mut ConsoleAdapter(&mut *console), cols, info, &registers);
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+fn draw(out: &mut ConsoleAdapter, cols: usize, info: &PanicInfo, registers: &Registers) {
+    let banner = " KERNEL PANIC ";
+    let padding = cols.saturating_sub(banner.len()) / 2;
+
+    let _ = writeln!(out, "{:padding$}{}\n", "", banner, padding = padding);
+    let _ = writeln!(out, "message:  {}", info.message());
+
+    match info.location() {
+        Some(location) => {
+            let _ = writeln!(
+                out,
+                "location: {}:{}:{}\n",
+                location.file(),
+                location.line(),
+                location.column()
+            );
+        }
+        None => {
+            let _ = writeln!(out, "location: unknown\n");
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "RIP {:#018x}  RSP {:#018x}  RBP {:#018x}",
+        registers.rip, registers.rsp, registers.rbp
+    );
+    let _ = writeln!(
+        out,
+        "CR2 {:#018x}  CR3 {:#018x}  RFLAGS {:#010x}",
+        registers.cr2, registers.cr3, registers.rflags
+    );
+
+    let offsets = kaslr::offsets();
+
+    let _ = writeln!(
+        out,
+        "KASLR phys {:#018x}  heap +{:#x}  stacks +{:#x}\n",
+        offsets.physical_memory, offsets.heap, offsets.stacks
+    );
+    let _ = writeln!(out, "stack:");
+
+    let stack = registers.rsp as *const u64;
+
+    for line in 0..STACK_DUMP_QWORDS / 4 {
+        let _ = write!(out, "{:#018x}:", registers.rsp + line as u64 * 32);
+
+        for slot in 0..4 {
+            // NOTE: the slots above rsp belong to the live panic call chain
+            let value = unsafe { stack.add(line * 4 + slot).read_volatile() };
+
+            let _ = write!(out, " {:016x}", value);
+        }
+
+        let _ = writeln!(out);
+    }
+}
@@ -0,0 +1,192 @@
+pub mod msi;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const VENDOR_ID: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const STATUS: u8 = 0x06;
+const CLASS: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0e;
+const BAR0: u8 = 0x10;
+const CAPABILITIES_POINTER: u8 = 0x34;
+
+const NO_DEVICE: u16 = 0xffff;
+const MULTI_FUNCTION: u8 = 0x80;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+// NOTE: the address and data ports have to be used as a pair
+static CONFIG: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS), Port::new(CONFIG_DATA)));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> PciAddress {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        CONFIG_ENABLE
+            | (self.bus as u32) << 16
+            | (self.device as u32 & 0x1f) << 11
+            | (self.function as u32 & 0x07) << 8
+            | (offset as u32 & 0xfc)
+    }
+
+    pub fn read_u32(self, offset: u8) -> u32 {
+        interrupts::without_interrupts(|| {
+            let mut config = CONFIG.lock();
+
+            unsafe {
+                config.0.write(self.config_address(offset));
+                config.1.read()
+            }
+        })
+    }
+
+    pub fn write_u32(self, offset: u8, value: u32) {
+        interrupts::without_interrupts(|| {
+            let mut config = CONFIG.lock();
+
+            unsafe {
+                config.0.write(self.config_address(offset));
+                config.1.write(value);
+            }
+        });
+    }
+
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    // NOTE: read-modify-write of the containing dword
+    pub fn write_u16(self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xffff << shift);
+
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    pub fn vendor_id(self) -> u16 {
+        self.read_u16(VENDOR_ID)
+    }
+
+    pub fn device_id(self) -> u16 {
+        self.read_u16(VENDOR_ID + 2)
+    }
+
+    pub fn exists(self) -> bool {
+        self.vendor_id() != NO_DEVICE
+    }
+
+    // NOTE: class, subclass and programming interface
+    pub fn class(self) -> (u8, u8, u8) {
+        let class = self.read_u32(CLASS);
+
+        ((class >> 24) as u8, (class >> 16) as u8, (class >> 8) as u8)
+    }
+
+    pub fn command(self) -> u16 {
+        self.read_u16(COMMAND)
+    }
+
+    pub fn set_command(self, command: u16) {
+        self.write_u16(COMMAND, command);
+    }
+
+    // NOTE: physical base of a memory BAR, None for I/O BARs; 64 bit BARs use the next slot too
+    pub fn memory_bar(self, index: u8) -> Option<u64> {
+        let offset = BAR0 + index * 4;
+        let bar = self.read_u32(offset);
+
+        if bar & 1 != 0 {
+            return None;
+        }
+
+        let low = (bar & !0xf) as u64;
+
+        match (bar >> 1) & 0b11 {
+            0b10 => Some(low | (self.read_u32(offset + 4) as u64) << 32),
+            _ => Some(low),
+        }
+    }
+
+    // NOTE: (capability id, config space offset) for every entry of the capability list
+    pub fn capabilities(self) -> impl Iterator<Item = (u8, u8)> {
+        let mut next = match self.read_u16(STATUS) & STATUS_CAPABILITIES {
+            0 => 0,
+            _ => self.read_u8(CAPABILITIES_POINTER) & 0xfc,
+        };
+        // NOTE: bounds the walk on a broken, circular list
+        let mut remaining = 48;
+
+        core::iter::from_fn(move || {
+            if next == 0 || remaining == 0 {
+                return None;
+            }
+
+            let offset = next;
+
+            next = self.read_u8(offset + 1) & 0xfc;
+            remaining -= 1;
+
+            Some((self.read_u8(offset), offset))
+        })
+    }
+
+    pub fn find_capability(self, id: u8) -> Option<u8> {
+        self.capabilities()
+            .find(|(capability, _)| *capability == id)
+            .map(|(_, offset)| offset)
+    }
+}
+
+// NOTE: brute force scan of every bus, device and function
+pub fn devices() -> impl Iterator<Item = PciAddress> {
+    (0..=255u8)
+        .flat_map(|bus| (0..32u8).map(move |device| PciAddress::new(bus, device, 0)))
+        .filter(|address| address.exists())
+        .flat_map(|address| {
+            let functions = match address.read_u8(HEADER_TYPE) & MULTI_FUNCTION {
+                0 => 1,
+                _ => 8,
+            };
+
+            (0..functions)
+                .map(move |function| PciAddress::new(address.bus, address.device, function))
+        })
+        .filter(|address| address.exists())
+}
+
+// NOTE: QEMU always has a host bridge at 00:00.0
+#[test_case]
+fn test_host_bridge() {
+    let host_bridge = PciAddress::new(0, 0, 0);
+
+    assert!(host_bridge.exists());
+    assert_eq!(host_bridge.class().0, 0x06);
+    assert!(devices().any(|address| address == host_bridge));
+}
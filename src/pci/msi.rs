@@ -0,0 +1,188 @@
+use super::{PciAddress, COMMAND_BUS_MASTER, COMMAND_INTX_DISABLE, COMMAND_MEMORY_SPACE};
+use crate::apic;
+use crate::memory::{self, MmioRegion};
+use x86_64::PhysAddr;
+
+const CAPABILITY_MSI: u8 = 0x05;
+const CAPABILITY_MSIX: u8 = 0x11;
+
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+const MSI_64_BIT: u16 = 1 << 7;
+
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_MASKED: u32 = 1;
+
+// NOTE: messages go to the local APIC window, fixed delivery, edge triggered
+const MESSAGE_ADDRESS_BASE: u32 = 0xfee0_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    NotSupported,
+    // NOTE: MSI needs a local APIC to deliver to
+    NoLocalApic,
+    InvalidEntry(u16),
+    InvalidBar,
+}
+
+fn message(vector: u8) -> (u32, u32) {
+    (
+        MESSAGE_ADDRESS_BASE | (apic::id() as u32) << 12,
+        vector as u32,
+    )
+}
+
+fn disable_intx(address: PciAddress) {
+    address.set_command(address.command() | COMMAND_INTX_DISABLE | COMMAND_BUS_MASTER);
+}
+
+// NOTE: a single message on `vector`, legacy INTx is turned off
+pub fn enable_msi(address: PciAddress, vector: u8) -> Result<(), MsiError> {
+    if !apic::is_enabled() {
+        return Err(MsiError::NoLocalApic);
+    }
+
+    let capability = address
+        .find_capability(CAPABILITY_MSI)
+        .ok_or(MsiError::NotSupported)?;
+    let control = address.read_u16(capability + 2);
+    let (message_address, data) = message(vector);
+
+    address.write_u32(capability + 4, message_address);
+
+    match control & MSI_64_BIT {
+        0 => address.write_u16(capability + 8, data as u16),
+        _ => {
+            address.write_u32(capability + 8, 0);
+            address.write_u16(capability + 12, data as u16);
+        }
+    }
+
+    address.write_u16(
+        capability + 2,
+        (control & !MSI_MULTIPLE_MESSAGE_ENABLE) | MSI_ENABLE,
+    );
+    disable_intx(address);
+
+    Ok(())
+}
+
+pub fn disable_msi(address: PciAddress) {
+    if let Some(capability) = address.find_capability(CAPABILITY_MSI) {
+        let control = address.read_u16(capability + 2);
+
+        address.write_u16(capability + 2, control & !MSI_ENABLE);
+    }
+}
+
+// NOTE: an MSI-X table lives in one of the device's memory BARs
+pub struct MsixTable {
+    address: PciAddress,
+    capability: u8,
+    table: MmioRegion,
+    size: u16,
+}
+
+impl MsixTable {
+    // NOTE: enables MSI-X with the function masked and every entry masked, unmask entries once
+    // they are set
+    pub fn enable(address: PciAddress) -> Result<MsixTable, MsiError> {
+        if !apic::is_enabled() {
+            return Err(MsiError::NoLocalApic);
+        }
+
+        let capability = address
+            .find_capability(CAPABILITY_MSIX)
+            .ok_or(MsiError::NotSupported)?;
+        let control = address.read_u16(capability + 2);
+        let location = address.read_u32(capability + 4);
+        let bar = address
+            .memory_bar((location & 0b111) as u8)
+            .ok_or(MsiError::InvalidBar)?;
+        let physical = PhysAddr::new(bar + (location & !0b111) as u64);
+        let size = (control & MSIX_TABLE_SIZE) + 1;
+        let table = memory::map_mmio(physical, size as usize * MSIX_ENTRY_SIZE as usize)
+            .map_err(|_| MsiError::InvalidBar)?;
+
+        address.set_command(address.command() | COMMAND_MEMORY_SPACE);
+        address.write_u16(capability + 2, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+        disable_intx(address);
+
+        let table = MsixTable {
+            address,
+            capability,
+            table,
+            size,
+        };
+
+        for entry in 0..table.size {
+            table.write(entry, 12, MSIX_VECTOR_MASKED);
+        }
+
+        address.write_u16(
+            capability + 2,
+            (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+        );
+
+        Ok(table)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn write(&self, entry: u16, offset: u64, value: u32) {
+        let offset = entry as u64 * MSIX_ENTRY_SIZE + offset;
+
+        self.table.register(offset as usize).write(value);
+    }
+
+    // NOTE: programs `entry` to deliver on `vector` and unmasks it
+    pub fn set_vector(&self, entry: u16, vector: u8) -> Result<(), MsiError> {
+        if entry >= self.size {
+            return Err(MsiError::InvalidEntry(entry));
+        }
+
+        let (message_address, data) = message(vector);
+
+        self.write(entry, 12, MSIX_VECTOR_MASKED);
+        self.write(entry, 0, message_address);
+        self.write(entry, 4, 0);
+        self.write(entry, 8, data);
+        self.write(entry, 12, 0);
+
+        Ok(())
+    }
+
+    pub fn mask(&self, entry: u16) -> Result<(), MsiError> {
+        if entry >= self.size {
+            return Err(MsiError::InvalidEntry(entry));
+        }
+
+        self.write(entry, 12, MSIX_VECTOR_MASKED);
+
+        Ok(())
+    }
+
+    pub fn disable(self) {
+        let control = self.address.read_u16(self.capability + 2);
+
+        self.address
+            .write_u16(self.capability + 2, control & !MSIX_ENABLE);
+    }
+}
+
+#[test_case]
+fn test_message() {
+    if !apic::is_enabled() {
+        return;
+    }
+
+    let (address, data) = message(0x51);
+
+    assert_eq!(address & 0xfff0_0fff, MESSAGE_ADDRESS_BASE);
+    assert_eq!(data, 0x51);
+}
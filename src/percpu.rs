@@ -0,0 +1,232 @@
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::segmentation::GS;
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::idt::{InterruptStackFrame, InterruptStackFrameValue};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+pub const MAX_CPUS: usize = 16;
+
+// NOTE: per CPU half of the scheduler, the run queues themselves are shared
+pub struct SchedulerState {
+    pub slice_left: AtomicUsize,
+    pub need_resched: AtomicBool,
+    // NOTE: ThreadId of the thread running on this CPU, u64::MAX before threads exist
+    pub current_thread: AtomicU64,
+    // NOTE: TSC value when the current thread was switched to
+    pub switched_at: AtomicU64,
+}
+
+// NOTE: used by the syscall entry stub through gs, which runs before there is a stack
+#[repr(C)]
+pub struct SyscallState {
+    pub kernel_rsp: AtomicU64,
+    pub user_rsp: AtomicU64,
+}
+
+// NOTE: the interrupted ring 3 frame an interrupt handler leaves for the signal entry stub,
+// which picks it up before interrupts are enabled again
+#[repr(C)]
+pub struct SignalState {
+    pub rip: AtomicU64,
+    pub cs: AtomicU64,
+    pub rflags: AtomicU64,
+    pub rsp: AtomicU64,
+    pub ss: AtomicU64,
+}
+
+// NOTE: what an RCU grace period waits on, see sync::rcu
+pub struct RcuState {
+    pub quiescent: AtomicU64,
+    pub idle: AtomicBool,
+}
+
+// NOTE: see memory::tlb
+pub struct TlbState {
+    // NOTE: physical address of the level 4 table the CPU has loaded
+    pub level_4: AtomicU64,
+    // NOTE: the CPU takes shootdown IPIs
+    pub ready: AtomicBool,
+    // NOTE: a shootdown waits for this CPU to flush
+    pub pending: AtomicBool,
+}
+
+#[derive(Default)]
+pub struct CpuStats {
+    pub ticks: AtomicU64,
+    pub idle_ticks: AtomicU64,
+    // NOTE: times the idle thread found nothing to run and halted
+    pub idle_halts: AtomicU64,
+    pub context_switches: AtomicU64,
+}
+
+// NOTE: GS base points at the CPU's own PerCpu, whose first field points back at it so a
+// single `mov reg, gs:[0]` finds it. Fields are atomics so a thread preempted and resumed on
+// another CPU still only ever touches valid data, just possibly another CPU's
+#[repr(C)]
+pub struct PerCpu {
+    this: AtomicPtr<PerCpu>,
+    pub index: AtomicUsize,
+    pub apic_id: AtomicU32,
+    // NOTE: set by gdt::init, where the kernel stack for ring 3 interrupts is updated
+    pub tss: AtomicPtr<TaskStateSegment>,
+    pub syscall: SyscallState,
+    pub signal: SignalState,
+    pub scheduler: SchedulerState,
+    pub rcu: RcuState,
+    pub tlb: TlbState,
+    pub stats: CpuStats,
+}
+
+impl PerCpu {
+    const fn new() -> PerCpu {
+        PerCpu {
+            this: AtomicPtr::new(ptr::null_mut()),
+            index: AtomicUsize::new(0),
+            apic_id: AtomicU32::new(0),
+            tss: AtomicPtr::new(ptr::null_mut()),
+            syscall: SyscallState {
+                kernel_rsp: AtomicU64::new(0),
+                user_rsp: AtomicU64::new(0),
+            },
+            signal: SignalState {
+                rip: AtomicU64::new(0),
+                cs: AtomicU64::new(0),
+                rflags: AtomicU64::new(0),
+                rsp: AtomicU64::new(0),
+                ss: AtomicU64::new(0),
+            },
+            scheduler: SchedulerState {
+                slice_left: AtomicUsize::new(0),
+                need_resched: AtomicBool::new(false),
+                current_thread: AtomicU64::new(u64::MAX),
+                switched_at: AtomicU64::new(0),
+            },
+            rcu: RcuState {
+                quiescent: AtomicU64::new(0),
+                idle: AtomicBool::new(false),
+            },
+            tlb: TlbState {
+                level_4: AtomicU64::new(0),
+                ready: AtomicBool::new(false),
+                pending: AtomicBool::new(false),
+            },
+            stats: CpuStats {
+                ticks: AtomicU64::new(0),
+                idle_ticks: AtomicU64::new(0),
+                idle_halts: AtomicU64::new(0),
+                context_switches: AtomicU64::new(0),
+            },
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+}
+
+static CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+// NOTE: GS base is 0 until the boot CPU is set up, current() falls back to CPU 0 before that
+static READY: AtomicBool = AtomicBool::new(false);
+
+// NOTE: points GS base at the data of CPU `index`, to be called once on every CPU before it
+// touches per CPU data
+pub fn init(index: usize, apic_id: u32) {
+    let cpu = &CPUS[index];
+
+    cpu.this
+        .store(ptr::from_ref(cpu).cast_mut(), Ordering::Relaxed);
+    cpu.index.store(index, Ordering::Relaxed);
+    cpu.apic_id.store(apic_id, Ordering::Relaxed);
+    GsBase::write(VirtAddr::from_ptr(cpu));
+    KernelGsBase::write(VirtAddr::zero());
+    ONLINE.fetch_max(index + 1, Ordering::AcqRel);
+    READY.store(true, Ordering::Release);
+}
+
+// NOTE: the boot CPU is always index 0, its APIC id is filled in once the APIC is known
+pub fn init_boot_cpu() {
+    init(0, 0);
+}
+
+pub fn current() -> &'static PerCpu {
+    if !READY.load(Ordering::Acquire) {
+        return &CPUS[0];
+    }
+
+    let this: *const PerCpu;
+
+    unsafe { asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags)) };
+
+    unsafe { &*this }
+}
+
+pub fn cpu(index: usize) -> Option<&'static PerCpu> {
+    CPUS[..online()].get(index)
+}
+
+// NOTE: CPUs whose data has been set up, they are numbered from 0
+pub fn online() -> usize {
+    ONLINE.load(Ordering::Acquire).max(1)
+}
+
+// NOTE: ring 3 runs with a GS base of 0, user code has no way to set another, and the per CPU
+// pointer parked in IA32_KERNEL_GS_BASE. Interrupt and exception handlers make one of these
+// before anything else: it swaps the pointer in when GS isn't it yet, from ring 3 or from an
+// NMI between the swap and the return in an exit stub, and back when the handler returns to
+// the same ring. A frame redirected from ring 3 to the signal entry stub keeps the kernel's
+pub(crate) struct KernelGs {
+    frame: *const InterruptStackFrameValue,
+    ring: u64,
+    swapped: bool,
+}
+
+impl KernelGs {
+    #[inline(always)]
+    pub(crate) fn enter(stack_frame: &InterruptStackFrame) -> KernelGs {
+        let swapped = GsBase::read().is_null();
+
+        if swapped {
+            unsafe { GS::swap() };
+        }
+
+        KernelGs {
+            frame: &**stack_frame,
+            ring: stack_frame.code_segment & 3,
+            swapped,
+        }
+    }
+}
+
+impl Drop for KernelGs {
+    #[inline(always)]
+    fn drop(&mut self) {
+        let ring = unsafe { ptr::read_volatile(ptr::addr_of!((*self.frame).code_segment)) } & 3;
+
+        if self.swapped && ring == self.ring {
+            unsafe { GS::swap() };
+        }
+    }
+}
+
+// NOTE: `percpu!(scheduler.need_resched)` is a reference to the field of the running CPU
+#[macro_export]
+macro_rules! percpu {
+    ($($field:ident).+) => {
+        &$crate::percpu::current().$($field).+
+    };
+}
+
+#[test_case]
+fn test_current_cpu() {
+    let this = current();
+
+    assert_eq!(this.index(), 0);
+    assert!(ptr::eq(this, &CPUS[0]));
+    assert_eq!(GsBase::read(), VirtAddr::from_ptr(this));
+    assert!(ptr::eq(percpu!(stats.ticks), &CPUS[0].stats.ticks));
+    assert!(cpu(online()).is_none());
+}
@@ -0,0 +1,182 @@
+use crate::sync::IrqMutex;
+use x86_64::instructions::port::Port;
+
+// NOTE: IRQs 0..=15 are remapped right after the 32 CPU exception vectors
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+// NOTE: the secondary PIC is chained to IRQ 2 of the primary one
+const CASCADE_IRQ: u8 = 2;
+
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+const END_OF_INTERRUPT: u8 = 0x20;
+const OCW3_READ_IRR: u8 = 0x0a;
+const OCW3_READ_ISR: u8 = 0x0b;
+
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    const fn new(offset: u8, command: u16, data: u16) -> Pic {
+        Pic {
+            offset,
+            command: Port::new(command),
+            data: Port::new(data),
+        }
+    }
+
+    fn handles(&self, vector: u8) -> bool {
+        (self.offset..self.offset + 8).contains(&vector)
+    }
+
+    // NOTE: ICW1 on the command port, then ICW2 (vector offset), ICW3 (cascade) and ICW4
+    fn init_write(&mut self, icw1: u8, offset: u8, cascade: u8) {
+        unsafe { self.command.write(icw1) };
+        io_wait();
+
+        for value in [offset, cascade, ICW4_8086] {
+            self.set_mask(value);
+            io_wait();
+        }
+    }
+
+    fn end_of_interrupt(&mut self) {
+        unsafe { self.command.write(END_OF_INTERRUPT) };
+    }
+
+    fn read_register(&mut self, ocw3: u8) -> u8 {
+        unsafe {
+            self.command.write(ocw3);
+
+            self.command.read()
+        }
+    }
+
+    fn mask(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    fn set_mask(&mut self, mask: u8) {
+        unsafe { self.data.write(mask) };
+    }
+}
+
+pub struct ChainedPics {
+    primary: Pic,
+    secondary: Pic,
+}
+
+// NOTE: writes to the unused port 0x80 give the old PICs time to settle between commands
+fn io_wait() {
+    unsafe { Port::<u8>::new(0x80).write(0) };
+}
+
+impl ChainedPics {
+    const fn new(primary_offset: u8, secondary_offset: u8) -> ChainedPics {
+        ChainedPics {
+            primary: Pic::new(primary_offset, 0x20, 0x21),
+            secondary: Pic::new(secondary_offset, 0xa0, 0xa1),
+        }
+    }
+
+    // NOTE: every IRQ but the cascade starts out masked
+    fn init(&mut self) {
+        let (primary_offset, secondary_offset) = (self.primary.offset, self.secondary.offset);
+
+        self.primary
+            .init_write(ICW1_INIT, primary_offset, 1 << CASCADE_IRQ);
+        self.secondary
+            .init_write(ICW1_INIT, secondary_offset, CASCADE_IRQ);
+
+        self.primary.set_mask(!(1 << CASCADE_IRQ));
+        self.secondary.set_mask(0xff);
+    }
+
+    fn set_masked(&mut self, irq: u8, masked: bool) {
+        let (pic, bit) = match irq {
+            0..=7 => (&mut self.primary, irq),
+            _ => (&mut self.secondary, irq - 8),
+        };
+        let mask = pic.mask();
+
+        pic.set_mask(match masked {
+            true => mask | 1 << bit,
+            false => mask & !(1 << bit),
+        });
+    }
+
+    // NOTE: both PICs need the EOI for IRQs coming from the secondary one
+    fn end_of_interrupt(&mut self, vector: u8) {
+        if self.secondary.handles(vector) {
+            self.secondary.end_of_interrupt();
+        }
+
+        if self.primary.handles(vector) || self.secondary.handles(vector) {
+            self.primary.end_of_interrupt();
+        }
+    }
+
+    // NOTE: one bit per IRQ, secondary PIC in the high byte
+    fn read_register(&mut self, ocw3: u8) -> u16 {
+        (self.secondary.read_register(ocw3) as u16) << 8 | self.primary.read_register(ocw3) as u16
+    }
+}
+
+static PICS: IrqMutex<ChainedPics> = IrqMutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+
+// NOTE: like every lock shared with interrupt handlers, PICS is an IrqMutex
+pub fn init() {
+    PICS.lock().init();
+}
+
+pub fn unmask(irq: u8) {
+    PICS.lock().set_masked(irq, false);
+}
+
+pub fn mask(irq: u8) {
+    PICS.lock().set_masked(irq, true);
+}
+
+// NOTE: masks every IRQ including the cascade, for when the IOAPIC takes over
+pub fn disable() {
+    let mut pics = PICS.lock();
+
+    pics.primary.set_mask(0xff);
+    pics.secondary.set_mask(0xff);
+}
+
+// NOTE: `vector` is the IDT vector the IRQ was delivered on
+pub fn end_of_interrupt(vector: u8) {
+    PICS.lock().end_of_interrupt(vector);
+}
+
+// NOTE: IRQs currently being serviced
+pub fn in_service() -> u16 {
+    PICS.lock().read_register(OCW3_READ_ISR)
+}
+
+// NOTE: a PIC reports an IRQ that went away before it was acknowledged on its lowest priority
+// line (7 or 15) without setting the ISR bit
+pub fn is_spurious(irq: u8) -> bool {
+    in_service() & (1 << irq) == 0
+}
+
+// NOTE: a spurious IRQ 15 still went through the cascade, only the primary PIC needs an EOI
+pub fn end_of_cascade() {
+    PICS.lock().primary.end_of_interrupt();
+}
+
+// NOTE: IRQs raised but not yet delivered
+pub fn requested() -> u16 {
+    PICS.lock().read_register(OCW3_READ_IRR)
+}
+
+#[test_case]
+fn test_nothing_in_service() {
+    assert!(is_spurious(7));
+    assert!(is_spurious(15));
+}
@@ -0,0 +1,339 @@
+use crate::signal;
+use crate::sync::{IrqMutex, WaitQueue};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+
+// NOTE: bytes a pipe holds before writers block
+pub const PIPE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeError {
+    // NOTE: every read end is gone, nothing would ever take the bytes
+    Closed,
+    // NOTE: gave up waiting for a signal the current thread has to act on
+    Interrupted,
+}
+
+struct Buffer {
+    bytes: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    // NOTE: bytes read out so far, tells a reader that copied bytes out without taking them
+    // whether they are still the first ones
+    taken: u64,
+}
+
+struct Pipe {
+    buffer: IrqMutex<Buffer>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+// NOTE: the two ends of a pipe, each one counted so that a reader sees end of file once every
+// writer is dropped and a writer gets Closed once every reader is. Cloning an end, e.g. for a
+// forked descriptor table, counts as one more
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buffer: IrqMutex::new(Buffer {
+            bytes: VecDeque::with_capacity(PIPE_CAPACITY),
+            readers: 1,
+            writers: 1,
+            taken: 0,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+pub struct PipeReader(Arc<Pipe>);
+
+impl PipeReader {
+    // NOTE: copies the first bytes into `buffer` and leaves them in the pipe, with the count
+    // of bytes taken so far. None when the pipe is empty but still has writers
+    fn peek(&self, buffer: &mut [u8]) -> Option<(usize, u64)> {
+        let state = self.0.buffer.lock();
+
+        if state.bytes.is_empty() {
+            return (state.writers == 0 || buffer.is_empty()).then_some((0, state.taken));
+        }
+
+        let read = buffer.len().min(state.bytes.len());
+
+        for (slot, byte) in buffer.iter_mut().zip(state.bytes.range(..read)) {
+            *slot = *byte;
+        }
+
+        Some((read, state.taken))
+    }
+
+    // NOTE: takes `count` peeked bytes out, false when another reader took bytes since
+    fn consume(&self, count: usize, taken: u64) -> bool {
+        {
+            let mut state = self.0.buffer.lock();
+
+            if state.taken != taken {
+                return false;
+            }
+
+            state.bytes.drain(..count);
+            state.taken += count as u64;
+        }
+
+        if count > 0 {
+            self.0.writable.notify_all();
+        }
+
+        true
+    }
+
+    // NOTE: None when the pipe is empty but still has writers, Some(0) is end of file
+    pub fn try_read(&self, buffer: &mut [u8]) -> Option<usize> {
+        loop {
+            let (read, taken) = self.peek(buffer)?;
+
+            if self.consume(read, taken) {
+                return Some(read);
+            }
+        }
+    }
+
+    // NOTE: blocks until there is at least one byte or end of file
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, PipeError> {
+        self.read_with(buffer, |_| Ok(()))
+    }
+
+    // NOTE: read, but the bytes go to `take` before they leave the pipe, e.g. for a copy to
+    // user memory that may fault. When it fails they stay for the next read; when another
+    // reader took bytes meanwhile it is run again with the ones that are first now
+    pub fn read_with<E: From<PipeError>>(
+        &self,
+        buffer: &mut [u8],
+        mut take: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        loop {
+            let mut peeked = None;
+
+            self.0.readable.wait_until(|| {
+                peeked = match self.peek(buffer) {
+                    Some(peeked) => Some(Ok(peeked)),
+                    None if signal::has_deliverable() => Some(Err(PipeError::Interrupted)),
+                    None => None,
+                };
+
+                peeked.is_some()
+            });
+
+            let (read, taken) = peeked.unwrap_or(Ok((0, 0)))?;
+
+            take(&buffer[..read])?;
+
+            if self.consume(read, taken) {
+                return Ok(read);
+            }
+        }
+    }
+
+    pub async fn read_async(&self, buffer: &mut [u8]) -> usize {
+        let mut read = None;
+
+        self.0
+            .readable
+            .wait_until_async(|| {
+                read = self.try_read(buffer);
+                read.is_some()
+            })
+            .await;
+
+        read.unwrap_or(0)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> PipeReader {
+        self.0.buffer.lock().readers += 1;
+
+        PipeReader(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.buffer.lock().readers -= 1;
+        self.0.writable.notify_all();
+    }
+}
+
+// NOTE: ends compare equal when they belong to the same pipe
+impl PartialEq for PipeReader {
+    fn eq(&self, other: &PipeReader) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for PipeReader {}
+
+impl fmt::Debug for PipeReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PipeReader({:p})", Arc::as_ptr(&self.0))
+    }
+}
+
+pub struct PipeWriter(Arc<Pipe>);
+
+impl PipeWriter {
+    // NOTE: as much of `bytes` as fits, 0 when the pipe is full
+    pub fn try_write(&self, bytes: &[u8]) -> Result<usize, PipeError> {
+        let written = {
+            let mut state = self.0.buffer.lock();
+
+            if state.readers == 0 {
+                return Err(PipeError::Closed);
+            }
+
+            let written = bytes.len().min(PIPE_CAPACITY - state.bytes.len());
+
+            state.bytes.extend(&bytes[..written]);
+
+            written
+        };
+
+        if written > 0 {
+            self.0.readable.notify_all();
+        }
+
+        Ok(written)
+    }
+
+    // NOTE: blocks until all of `bytes` is in the pipe; when the readers go away or a signal
+    // interrupts it halfway the part that made it in is still reported
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, PipeError> {
+        let mut written = 0;
+        let mut result = Ok(());
+
+        while written < bytes.len() {
+            self.0
+                .writable
+                .wait_until(|| match self.try_write(&bytes[written..]) {
+                    Ok(0) if signal::has_deliverable() => {
+                        result = Err(PipeError::Interrupted);
+                        true
+                    }
+                    Ok(0) => false,
+                    Ok(count) => {
+                        written += count;
+                        true
+                    }
+                    Err(error) => {
+                        result = Err(error);
+                        true
+                    }
+                });
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match (written, result) {
+            (0, Err(error)) if !bytes.is_empty() => Err(error),
+            _ => Ok(written),
+        }
+    }
+
+    pub async fn write_async(&self, bytes: &[u8]) -> Result<usize, PipeError> {
+        let mut written = 0;
+        let mut result = Ok(());
+
+        while written < bytes.len() && result.is_ok() {
+            self.0
+                .writable
+                .wait_until_async(|| match self.try_write(&bytes[written..]) {
+                    Ok(0) => false,
+                    Ok(count) => {
+                        written += count;
+                        true
+                    }
+                    Err(error) => {
+                        result = Err(error);
+                        true
+                    }
+                })
+                .await;
+        }
+
+        match (written, result) {
+            (0, Err(error)) if !bytes.is_empty() => Err(error),
+            _ => Ok(written),
+        }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> PipeWriter {
+        self.0.buffer.lock().writers += 1;
+
+        PipeWriter(self.0.clone())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.buffer.lock().writers -= 1;
+        self.0.readable.notify_all();
+    }
+}
+
+impl PartialEq for PipeWriter {
+    fn eq(&self, other: &PipeWriter) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for PipeWriter {}
+
+impl fmt::Debug for PipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PipeWriter({:p})", Arc::as_ptr(&self.0))
+    }
+}
+
+#[test_case]
+fn test_pipe_blocks_and_ends() {
+    let (reader, writer) = pipe();
+    let bytes = [7u8; PIPE_CAPACITY + 100];
+    let producer = crate::thread::spawn("pipe writer", move || {
+        assert_eq!(writer.write(&bytes), Ok(bytes.len()));
+    })
+    .unwrap();
+    let mut buffer = [0u8; 512];
+    let mut total = 0;
+
+    loop {
+        match reader.read(&mut buffer).unwrap() {
+            0 => break,
+            read => total += read,
+        }
+    }
+
+    assert_eq!(producer.join(), 0);
+    assert_eq!(total, PIPE_CAPACITY + 100);
+    assert_eq!(reader.try_read(&mut buffer), Some(0));
+
+    let (reader, writer) = pipe();
+
+    assert_eq!(reader.try_read(&mut buffer), None);
+    assert_eq!(writer.try_write(b"abc"), Ok(3));
+    assert_eq!(
+        reader.read_with(&mut buffer, |_| Err(PipeError::Closed)),
+        Err(PipeError::Closed)
+    );
+    assert_eq!(reader.try_read(&mut buffer), Some(3));
+    assert_eq!(&buffer[..3], b"abc");
+
+    drop(reader);
+
+    assert_eq!(writer.try_write(b"x"), Err(PipeError::Closed));
+}
@@ -0,0 +1,67 @@
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+// NOTE: input clock of the 8253/8254 programmable interval timer
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+// NOTE: channel 0, low then high divisor byte, mode 2 (rate generator), binary
+const CHANNEL_0_RATE_GENERATOR: u8 = 0x34;
+
+// NOTE: returns the frequency actually programmed, the divisor is rounded to an integer
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = (PIT_FREQUENCY / hz.max(1)).clamp(1, 0xffff);
+
+    interrupts::without_interrupts(|| unsafe {
+        let mut channel = Port::<u8>::new(CHANNEL_0_PORT);
+
+        Port::<u8>::new(COMMAND_PORT).write(CHANNEL_0_RATE_GENERATOR);
+        channel.write((divisor & 0xff) as u8);
+        channel.write((divisor >> 8) as u8);
+    });
+
+    PIT_FREQUENCY / divisor
+}
+
+const CHANNEL_2_PORT: u16 = 0x42;
+// NOTE: channel 2, low then high divisor byte, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0xb0;
+// NOTE: bit 0 gates channel 2, bit 1 connects it to the speaker, bit 5 reads its output
+const SPEAKER_PORT: u16 = 0x61;
+const CHANNEL_2_GATE: u8 = 0x01;
+const SPEAKER_ENABLE: u8 = 0x02;
+const CHANNEL_2_OUTPUT: u8 = 0x20;
+
+// NOTE: the longest wait a single channel 2 countdown covers
+pub const MAX_POLLED_WAIT_US: u32 = 54_000;
+
+pub fn wait_polled_ms(ms: u32) {
+    wait_polled_us(ms.saturating_mul(1000));
+}
+
+// NOTE: busy waits on channel 2 without interrupts, for calibrating other timers; channel 0
+// keeps running. At most MAX_POLLED_WAIT_US, longer waits are cut short
+pub fn wait_polled_us(us: u32) {
+    let count = (PIT_FREQUENCY as u64 * us as u64 / 1_000_000).clamp(1, 0xffff) as u16;
+
+    interrupts::without_interrupts(|| unsafe {
+        let mut speaker = Port::<u8>::new(SPEAKER_PORT);
+        let mut channel = Port::<u8>::new(CHANNEL_2_PORT);
+        let control = speaker.read() & !(CHANNEL_2_GATE | SPEAKER_ENABLE);
+
+        speaker.write(control);
+        Port::<u8>::new(COMMAND_PORT).write(CHANNEL_2_ONE_SHOT);
+        channel.write((count & 0xff) as u8);
+        channel.write((count >> 8) as u8);
+
+        // NOTE: the count starts once the gate goes high
+        speaker.write(control | CHANNEL_2_GATE);
+
+        while speaker.read() & CHANNEL_2_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        speaker.write(control);
+    });
+}
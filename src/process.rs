@@ -0,0 +1,655 @@
+mod arguments;
+mod files;
+mod signals;
+mod vma;
+
+use crate::elf::{self, ElfError, ElfFile};
+use crate::memory::address_space::AddressSpace;
+use crate::memory::paging::MapError;
+use crate::memory::stack::StackError;
+use crate::shm::SharedMemory;
+use crate::sync::{IrqMutex, IrqMutexGuard, WaitQueue};
+use crate::thread::{self, JoinHandle, ThreadId};
+use crate::usermode::{self, UserImage};
+use crate::{signal, tty};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub use arguments::{ArgumentError, Arguments, MAX_ARGUMENTS_SIZE};
+pub use files::{File, FileError, FileTable, MAX_FILES};
+pub use signals::{exit_code, Action, Delivery, SignalError, Signals};
+pub use vma::{Vma, VmaError, VmaKind, Vmas};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    // NOTE: the last thread is gone, a process can't be brought back
+    Exited,
+    // NOTE: exec while other threads run on the address space it would free
+    Busy,
+    // NOTE: waiting for a process that isn't a child of the caller
+    NoChild,
+    // NOTE: a signal became deliverable while waiting
+    Interrupted,
+    Map(MapError),
+    Stack(StackError),
+    Elf(ElfError),
+    Vma(VmaError),
+}
+
+impl From<MapError> for ProcessError {
+    fn from(error: MapError) -> ProcessError {
+        ProcessError::Map(error)
+    }
+}
+
+impl From<StackError> for ProcessError {
+    fn from(error: StackError) -> ProcessError {
+        ProcessError::Stack(error)
+    }
+}
+
+impl From<VmaError> for ProcessError {
+    fn from(error: VmaError) -> ProcessError {
+        ProcessError::Vma(error)
+    }
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(error: ElfError) -> ProcessError {
+        ProcessError::Elf(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(u64);
+
+impl ProcessId {
+    fn new() -> ProcessId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(id: u64) -> ProcessId {
+        ProcessId(id)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+// NOTE: a user program: the address space and descriptors its threads share. It lives as long
+// as one of its threads or a handle to it, and has exited once its last thread did. An exited
+// child is a zombie holding on to its status until its parent reaps it
+pub struct Process {
+    id: ProcessId,
+    name: &'static str,
+    // NOTE: what a switch to one of the threads loads into CR3, without taking `space`
+    level_4: AtomicU64,
+    space: IrqMutex<AddressSpace>,
+    // NOTE: taken before `space` when both are needed
+    vmas: IrqMutex<Vmas>,
+    threads: IrqMutex<Vec<ThreadId>>,
+    // NOTE: the process that forked or adopted this one, reached through its `children`
+    parent: IrqMutex<Weak<Process>>,
+    // NOTE: forked off this one or adopted and not reaped yet
+    children: IrqMutex<Vec<Arc<Process>>>,
+    // NOTE: notified when a child exits or is adopted
+    child_exited: WaitQueue,
+    files: IrqMutex<FileTable>,
+    signals: IrqMutex<Signals>,
+    // NOTE: the code of the last thread to exit
+    status: Once<i32>,
+    exited: WaitQueue,
+}
+
+// NOTE: every process there is a handle to, for looking one up by id
+static PROCESSES: IrqMutex<BTreeMap<ProcessId, Weak<Process>>> = IrqMutex::new(BTreeMap::new());
+// NOTE: adopts the children of processes that exit before them and has to reap them
+static INIT: IrqMutex<Weak<Process>> = IrqMutex::new(Weak::new());
+
+impl Process {
+    pub fn new(name: &'static str, space: AddressSpace) -> Arc<Process> {
+        let process = Arc::new(Process {
+            id: ProcessId::new(),
+            name,
+            level_4: AtomicU64::new(space.level_4_frame().start_address().as_u64()),
+            space: IrqMutex::new(space),
+            vmas: IrqMutex::new(Vmas::new(VirtAddr::new(usermode::USER_CODE_BASE))),
+            threads: IrqMutex::new(Vec::new()),
+            parent: IrqMutex::new(Weak::new()),
+            children: IrqMutex::new(Vec::new()),
+            child_exited: WaitQueue::new(),
+            files: IrqMutex::new(FileTable::new()),
+            signals: IrqMutex::new(Signals::new()),
+            status: Once::new(),
+            exited: WaitQueue::new(),
+        });
+
+        PROCESSES
+            .lock()
+            .insert(process.id, Arc::downgrade(&process));
+
+        process
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn level_4(&self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.level_4.load(Ordering::Relaxed)))
+    }
+
+    pub fn address_space(&self) -> IrqMutexGuard<'_, AddressSpace> {
+        self.space.lock()
+    }
+
+    pub fn files(&self) -> IrqMutexGuard<'_, FileTable> {
+        self.files.lock()
+    }
+
+    pub fn vmas(&self) -> IrqMutexGuard<'_, Vmas> {
+        self.vmas.lock()
+    }
+
+    pub fn signals(&self) -> IrqMutexGuard<'_, Signals> {
+        self.signals.lock()
+    }
+
+    // NOTE: None for a process the kernel started, or once the parent is gone
+    pub fn parent(&self) -> Option<Arc<Process>> {
+        self.parent.lock().upgrade()
+    }
+
+    // NOTE: makes `signal` pending, each thread acts on it when it next returns to ring 3. The
+    // ones blocked in the kernel are woken, interruptible waits then give up with Interrupted
+    // and the others go back to sleep
+    pub fn signal(&self, signal: u64) -> Result<(), SignalError> {
+        self.signals.lock().raise(signal)?;
+
+        for thread in self.threads() {
+            thread::wake(thread);
+        }
+
+        Ok(())
+    }
+
+    // NOTE: moves the break when given one, returns where it is
+    pub fn brk(&self, brk: Option<VirtAddr>) -> Result<VirtAddr, VmaError> {
+        let mut vmas = self.vmas.lock();
+
+        if let Some(brk) = brk {
+            let released = vmas.set_brk(brk)?;
+
+            self.unmap_pages(released);
+        }
+
+        Ok(vmas.brk())
+    }
+
+    // NOTE: anonymous memory, mapped page by page as it is touched
+    pub fn map_anonymous(&self, len: u64, flags: PageTableFlags) -> Result<VirtAddr, VmaError> {
+        self.vmas.lock().map_anonymous(len, flags)
+    }
+
+    // NOTE: all of `object` at a new address, its pages present from the start
+    pub fn map_shared(
+        &self,
+        object: &SharedMemory,
+        flags: PageTableFlags,
+    ) -> Result<VirtAddr, ProcessError> {
+        let mut vmas = self.vmas.lock();
+        let start = vmas.map_shared(object.size(), flags)?;
+        let flags = vmas.find(start).map_or(flags, |area| area.flags);
+        let mut space = self.space.lock();
+
+        for (index, frame) in object.frames().iter().enumerate() {
+            let page = Page::containing_address(start + index as u64 * 4096);
+
+            if let Err(error) = space.map_shared(page, *frame, flags) {
+                drop(space);
+
+                let _ = vmas.unmap(start, object.size());
+
+                self.unmap_pages(start.as_u64()..start.as_u64() + object.size());
+
+                return Err(error.into());
+            }
+        }
+
+        Ok(start)
+    }
+
+    pub fn unmap(&self, start: VirtAddr, len: u64) -> Result<(), VmaError> {
+        let mut vmas = self.vmas.lock();
+
+        vmas.unmap(start, len)?;
+        self.unmap_pages(start.as_u64()..start.as_u64() + len.next_multiple_of(4096));
+
+        Ok(())
+    }
+
+    // NOTE: pages of the range that were never touched aren't mapped, which is fine. Their
+    // frames are freed once no CPU running the process can reach them
+    fn unmap_pages(&self, range: core::ops::Range<u64>) {
+        let start = Page::containing_address(VirtAddr::new(range.start));
+
+        self.space
+            .lock()
+            .unmap_range(start, range.end.saturating_sub(range.start).div_ceil(4096));
+    }
+
+    pub fn threads(&self) -> Vec<ThreadId> {
+        self.threads.lock().clone()
+    }
+
+    // NOTE: a new thread running `f` in ring 0 on the process's page tables, e.g. to enter
+    // ring 3
+    pub fn spawn_thread(
+        self: &Arc<Process>,
+        name: &'static str,
+        f: impl FnOnce() + Send + 'static,
+    ) -> Result<JoinHandle, ProcessError> {
+        thread::spawn_in(self, name, f)
+    }
+
+    // NOTE: false once the process has exited, checked under the lock thread_exited takes
+    pub(crate) fn add_thread(&self, id: ThreadId) -> bool {
+        let mut threads = self.threads.lock();
+
+        if self.has_exited() {
+            return false;
+        }
+
+        threads.push(id);
+
+        true
+    }
+
+    // NOTE: for a thread that never got to run
+    pub(crate) fn remove_thread(&self, id: ThreadId) {
+        self.threads.lock().retain(|thread| *thread != id);
+    }
+
+    pub(crate) fn thread_exited(&self, id: ThreadId, code: i32) {
+        {
+            let mut threads = self.threads.lock();
+
+            threads.retain(|thread| *thread != id);
+
+            if !threads.is_empty() {
+                return;
+            }
+
+            self.status.call_once(|| code);
+        }
+
+        // NOTE: closed like on Unix, so e.g. pipe readers see end of file while the process
+        // itself is kept around for whoever waits on it
+        let files = core::mem::replace(&mut *self.files.lock(), FileTable::empty());
+
+        drop(files);
+        self.release_memory();
+        self.adopt_out_children();
+        self.exited.notify_all();
+
+        if let Some(parent) = self.parent() {
+            parent.child_exited.notify_all();
+        }
+    }
+
+    // NOTE: a zombie keeps an empty address space, the old one switches to the kernel's page
+    // tables as it goes since the exiting thread still runs on it. The areas stay as a record
+    fn release_memory(&self) {
+        let Ok(empty) = AddressSpace::new() else {
+            return;
+        };
+        let old = {
+            let mut space = self.space.lock();
+
+            self.level_4.store(
+                empty.level_4_frame().start_address().as_u64(),
+                Ordering::Relaxed,
+            );
+
+            core::mem::replace(&mut *space, empty)
+        };
+
+        drop(old);
+    }
+
+    // NOTE: the children go to the init process, or have no parent if there is none and are
+    // freed with their last handle once they exit
+    fn adopt_out_children(&self) {
+        let orphans = core::mem::take(&mut *self.children.lock());
+
+        if orphans.is_empty() {
+            return;
+        }
+
+        let init = init_process().filter(|init| init.id != self.id && !init.has_exited());
+        let parent = init.as_ref().map_or_else(Weak::new, Arc::downgrade);
+
+        for orphan in &orphans {
+            *orphan.parent.lock() = parent.clone();
+        }
+
+        if let Some(init) = init {
+            init.children.lock().extend(orphans);
+            init.child_exited.notify_all();
+        }
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.status.r#try().is_some()
+    }
+
+    pub fn try_wait(&self) -> Option<i32> {
+        self.status.r#try().copied()
+    }
+
+    // NOTE: blocks until the last thread has exited and returns its code
+    pub fn wait(&self) -> i32 {
+        self.exited.wait_until(|| self.has_exited());
+
+        self.try_wait().expect("process exited without a status")
+    }
+
+    pub async fn wait_async(&self) -> i32 {
+        self.exited.wait_until_async(|| self.has_exited()).await;
+
+        self.try_wait().expect("process exited without a status")
+    }
+
+    // NOTE: a child with a copy-on-write copy of the address space and the same descriptors,
+    // whose first thread runs `f`
+    pub fn fork(
+        self: &Arc<Process>,
+        f: impl FnOnce() + Send + 'static,
+    ) -> Result<Arc<Process>, ProcessError> {
+        let space = self.space.lock().fork()?;
+        let child = Process::new(self.name, space);
+
+        *child.vmas.lock() = self.vmas.lock().clone();
+        *child.files.lock() = self.files.lock().clone();
+        *child.signals.lock() = self.signals.lock().fork();
+        *child.parent.lock() = Arc::downgrade(self);
+        self.children.lock().push(child.clone());
+
+        // NOTE: listed first so the child's exit always finds its parent
+        if let Err(error) = child.spawn_thread(self.name, f) {
+            self.children.lock().retain(|other| other.id != child.id);
+
+            return Err(error);
+        }
+
+        Ok(child)
+    }
+
+    // NOTE: blocks until the child `id` has exited and reaps it
+    pub fn wait_child(&self, id: ProcessId) -> Result<i32, ProcessError> {
+        self.reap(Some(id), true)
+            .map(|reaped| reaped.map_or(0, |(_, code)| code))
+    }
+
+    // NOTE: the id and code of an exited child, `id` or any, which is forgotten about. Without
+    // `block` None when there is no such child yet, otherwise waits for one. A wait by a thread
+    // of a process with a signal to act on gives up with Interrupted
+    pub fn reap(
+        &self,
+        id: Option<ProcessId>,
+        block: bool,
+    ) -> Result<Option<(ProcessId, i32)>, ProcessError> {
+        let mut result = None;
+
+        self.child_exited.wait_until(|| {
+            let mut children = self.children.lock();
+            let wanted = |child: &Arc<Process>| id.is_none_or(|id| child.id == id);
+
+            result = match children
+                .iter()
+                .position(|child| wanted(child) && child.has_exited())
+            {
+                Some(index) => {
+                    let child = children.remove(index);
+
+                    Some(Ok(child.try_wait().map(|code| (child.id, code))))
+                }
+                None if !children.iter().any(wanted) => Some(Err(ProcessError::NoChild)),
+                None if !block => Some(Ok(None)),
+                None if signal::has_deliverable() => Some(Err(ProcessError::Interrupted)),
+                None => None,
+            };
+
+            result.is_some()
+        });
+
+        result.unwrap_or(Ok(None))
+    }
+
+    pub fn children(&self) -> Vec<ProcessId> {
+        self.children.lock().iter().map(|child| child.id).collect()
+    }
+
+    // NOTE: replaces the address space with one holding the executable in `data`, which may
+    // point into the old one, started with `arguments`. Descriptors are kept; the caller enters
+    // the returned image
+    pub fn exec(&self, data: &[u8], arguments: &Arguments) -> Result<UserImage, ProcessError> {
+        if self.threads.lock().len() > 1 {
+            return Err(ProcessError::Busy);
+        }
+
+        let file = ElfFile::parse(data)?;
+        let mut space = AddressSpace::new()?;
+        let image = elf::load(&mut space, &file, arguments)?;
+
+        signal::map_trampoline(&mut space)?;
+
+        let old = {
+            let mut vmas = self.vmas.lock();
+            let mut current = self.space.lock();
+
+            *vmas = Vmas::new(image.heap_start);
+
+            self.level_4.store(
+                space.level_4_frame().start_address().as_u64(),
+                Ordering::Relaxed,
+            );
+
+            if current.is_active() {
+                unsafe { space.activate() };
+            }
+
+            core::mem::replace(&mut *current, space)
+        };
+
+        drop(old);
+        self.signals.lock().exec();
+
+        Ok(image)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        PROCESSES.lock().remove(&self.id);
+    }
+}
+
+pub fn find(id: ProcessId) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&id).and_then(Weak::upgrade)
+}
+
+// NOTE: makes `process` the one orphans are handed to, it has to reap them like its own
+pub fn set_init_process(process: &Arc<Process>) {
+    *INIT.lock() = Arc::downgrade(process);
+}
+
+pub fn init_process() -> Option<Arc<Process>> {
+    INIT.lock().upgrade()
+}
+
+// NOTE: the process the current thread belongs to, None for kernel threads
+pub fn current() -> Option<Arc<Process>> {
+    thread::current_process()
+}
+
+// NOTE: only user addresses get as far as looking the process up in the fault handlers, so a
+// bad kernel access with the scheduler locked is still reported
+fn is_user_address(address: VirtAddr) -> bool {
+    (usermode::USER_CODE_BASE..usermode::USER_REGION_END).contains(&address.as_u64())
+}
+
+// NOTE: for the page fault handler, a write to a copy-on-write page of the current process
+pub(crate) fn handle_write_fault(address: VirtAddr) -> bool {
+    is_user_address(address)
+        && current().is_some_and(|process| process.space.lock().handle_write_fault(address))
+}
+
+// NOTE: for not-present faults, true when `address` is in one of the current process's areas
+// and now mapped; anything else is a wild pointer
+pub(crate) fn handle_page_fault(address: VirtAddr) -> bool {
+    if !is_user_address(address) {
+        return false;
+    }
+
+    let Some(process) = current() else {
+        return false;
+    };
+    let vmas = process.vmas.lock();
+    // NOTE: shared memory is mapped up front, there is no frame to make up for it
+    let Some(area) = vmas
+        .find(address)
+        .filter(|area| area.kind != VmaKind::Shared)
+    else {
+        return false;
+    };
+    let mapped = process
+        .space
+        .lock()
+        .map(Page::containing_address(address), area.flags);
+
+    match mapped {
+        Ok(_) => true,
+        // NOTE: another thread of the process got there first
+        Err(MapError::AlreadyMapped(_)) => true,
+        Err(MapError::FrameAllocationFailed) => {
+            log::error!("demand paging: out of memory in {}", process.name);
+
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+// NOTE: a new process running `image` from `space` in ring 3 on its first thread. It becomes
+// the foreground process Ctrl+C interrupts
+pub(crate) fn start(
+    name: &'static str,
+    mut space: AddressSpace,
+    image: UserImage,
+) -> Result<Arc<Process>, ProcessError> {
+    signal::map_trampoline(&mut space)?;
+
+    let process = Process::new(name, space);
+
+    *process.vmas.lock() = Vmas::new(image.heap_start);
+    process.spawn_thread(name, move || unsafe {
+        usermode::enter(image.entry, image.stack_top)
+    })?;
+    tty::console().set_foreground(Some(process.id));
+
+    Ok(process)
+}
+
+// NOTE: loads the static executable in `data`, e.g. a file from the initrd, into a new
+// process started with `arguments`
+pub fn spawn(
+    name: &'static str,
+    data: &[u8],
+    arguments: &Arguments,
+) -> Result<Arc<Process>, ProcessError> {
+    let file = ElfFile::parse(data)?;
+    let mut space = AddressSpace::new()?;
+    let image = elf::load(&mut space, &file, arguments)?;
+
+    start(name, space, image)
+}
+
+#[test_case]
+fn test_spawn_and_wait() {
+    // NOTE: writes 1 to the first bss byte, reads it back and exits with it plus 41
+    let code = [
+        0x48, 0xb8, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00,
+        0x00, // movabs rax, base + 0x1000
+        0xc6, 0x00, 0x01, // mov byte [rax], 1
+        0x0f, 0xb6, 0x38, // movzx edi, byte [rax]
+        0x83, 0xc7, 0x29, // add edi, 41
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let data = elf::build_executable(&code, elf::PF_R | elf::PF_W | elf::PF_X, 0x2000);
+    let process = spawn("elf", &data, &Arguments::new()).unwrap();
+
+    assert_eq!(process.wait(), 42);
+    assert!(process.threads().is_empty());
+    assert_eq!(
+        process.spawn_thread("late", || {}).err(),
+        Some(ProcessError::Exited)
+    );
+}
+
+#[test_case]
+fn test_threads_share_process() {
+    let process = Process::new("shared", AddressSpace::new().unwrap());
+    let inner = process.clone();
+    let first = process
+        .spawn_thread("first", move || {
+            let current = current().unwrap();
+
+            assert_eq!(current.id(), inner.id());
+            assert_eq!(current.files().get(1), Some(File::Console));
+        })
+        .unwrap();
+    let second = process.spawn_thread("second", || thread::exit(7)).unwrap();
+
+    assert_eq!(first.join(), 0);
+    assert_eq!(second.join(), 7);
+    assert!(process.wait() == 0 || process.wait() == 7);
+    assert!(current().is_none());
+}
+
+#[test_case]
+fn test_reap_and_adopt() {
+    let init = Process::new("init", AddressSpace::new().unwrap());
+    let parent = Process::new("parent", AddressSpace::new().unwrap());
+
+    set_init_process(&init);
+
+    let child = parent.fork(|| thread::exit(5)).unwrap();
+
+    assert_eq!(child.parent().map(|parent| parent.id()), Some(parent.id()));
+    assert_eq!(parent.reap(None, true), Ok(Some((child.id(), 5))));
+    assert_eq!(parent.reap(None, false), Err(ProcessError::NoChild));
+
+    let orphan = parent.fork(|| thread::exit(3)).unwrap();
+
+    parent.spawn_thread("last", || {}).unwrap().join();
+
+    assert!(parent.has_exited());
+    assert!(parent.children().is_empty());
+    assert_eq!(orphan.parent().map(|parent| parent.id()), Some(init.id()));
+    assert_eq!(init.wait_child(orphan.id()), Ok(3));
+    assert!(init.children().is_empty());
+}
@@ -0,0 +1,177 @@
+use crate::memory::address_space::AddressSpace;
+use crate::memory::paging::MapError;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+
+// NOTE: the strings with their NULs plus a pointer to each, well inside the user stack
+pub const MAX_ARGUMENTS_SIZE: usize = 16 * 1024;
+
+// NOTE: auxiliary vector entry types, the same numbers as on Linux
+const AT_NULL: u64 = 0;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentError {
+    // NOTE: a string can't contain the NUL that ends it on the stack
+    Nul,
+    TooLong,
+}
+
+// NOTE: what a program is started with, argv and envp, placed on its stack the way the
+// System V ABI has _start find them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Arguments {
+    args: Vec<Vec<u8>>,
+    env: Vec<Vec<u8>>,
+    size: usize,
+}
+
+impl Arguments {
+    pub fn new() -> Arguments {
+        Arguments::default()
+    }
+
+    pub fn from_slices(args: &[&[u8]], env: &[&[u8]]) -> Result<Arguments, ArgumentError> {
+        let mut arguments = Arguments::new();
+
+        for arg in args {
+            arguments.push_arg(arg)?;
+        }
+
+        for var in env {
+            arguments.push_env(var)?;
+        }
+
+        Ok(arguments)
+    }
+
+    pub fn push_arg(&mut self, arg: &[u8]) -> Result<(), ArgumentError> {
+        let arg = self.reserve(arg)?;
+
+        self.args.push(arg);
+
+        Ok(())
+    }
+
+    // NOTE: NAME=value by convention, nothing checks it
+    pub fn push_env(&mut self, var: &[u8]) -> Result<(), ArgumentError> {
+        let var = self.reserve(var)?;
+
+        self.env.push(var);
+
+        Ok(())
+    }
+
+    fn reserve(&mut self, string: &[u8]) -> Result<Vec<u8>, ArgumentError> {
+        if string.contains(&0) {
+            return Err(ArgumentError::Nul);
+        }
+
+        let size = self.size + string.len() + 1 + 8;
+
+        if size > MAX_ARGUMENTS_SIZE {
+            return Err(ArgumentError::TooLong);
+        }
+
+        self.size = size;
+
+        Ok(string.to_vec())
+    }
+
+    pub fn args(&self) -> impl Iterator<Item = &[u8]> {
+        self.args.iter().map(Vec::as_slice)
+    }
+
+    pub fn env(&self) -> impl Iterator<Item = &[u8]> {
+        self.env.iter().map(Vec::as_slice)
+    }
+
+    // NOTE: the bytes that go right below `stack_top` and the address of the first one, where
+    // rsp starts. From there up: argc, argv and envp each ending with a null pointer, the
+    // auxiliary vector, padding keeping rsp 16 byte aligned and the strings at the top
+    fn layout(&self, stack_top: u64, entry: u64) -> (u64, Vec<u8>) {
+        let strings_size: usize = self
+            .args
+            .iter()
+            .chain(&self.env)
+            .map(|string| string.len() + 1)
+            .sum();
+        let strings_start = stack_top - strings_size as u64;
+        let mut words = vec![self.args.len() as u64];
+        let mut strings = Vec::with_capacity(strings_size);
+
+        for list in [&self.args, &self.env] {
+            for string in list {
+                words.push(strings_start + strings.len() as u64);
+                strings.extend_from_slice(string);
+                strings.push(0);
+            }
+
+            words.push(0);
+        }
+
+        words.extend_from_slice(&[AT_PAGESZ, 4096, AT_ENTRY, entry, AT_NULL, 0]);
+
+        let rsp = (strings_start - words.len() as u64 * 8) & !15;
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        bytes.resize((strings_start - rsp) as usize, 0);
+        bytes.extend_from_slice(&strings);
+
+        (rsp, bytes)
+    }
+
+    // NOTE: onto the stack mapped below `stack_top` in `space`, returns the initial rsp
+    pub fn push(
+        &self,
+        space: &mut AddressSpace,
+        stack_top: VirtAddr,
+        entry: VirtAddr,
+    ) -> Result<VirtAddr, MapError> {
+        let (rsp, bytes) = self.layout(stack_top.as_u64(), entry.as_u64());
+
+        space.write(VirtAddr::new(rsp), &bytes)?;
+
+        Ok(VirtAddr::new(rsp))
+    }
+}
+
+#[test_case]
+fn test_stack_layout() {
+    let arguments = Arguments::from_slices(&[b"sh", b"-c"], &[b"PATH=/bin"]).unwrap();
+    let top = 0x10_0000;
+    let (rsp, bytes) = arguments.layout(top, 0x4000);
+    let word = |address: u64| {
+        let offset = (address - rsp) as usize;
+
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    };
+    let string = |address: u64| {
+        let offset = (address - rsp) as usize;
+        let len = bytes[offset..].iter().position(|&byte| byte == 0).unwrap();
+
+        &bytes[offset..offset + len]
+    };
+
+    assert_eq!(rsp % 16, 0);
+    assert_eq!(rsp + bytes.len() as u64, top);
+    assert_eq!(word(rsp), 2);
+    assert_eq!(string(word(rsp + 8)), b"sh");
+    assert_eq!(string(word(rsp + 16)), b"-c");
+    assert_eq!(word(rsp + 24), 0);
+    assert_eq!(string(word(rsp + 32)), b"PATH=/bin");
+    assert_eq!(word(rsp + 40), 0);
+    assert_eq!((word(rsp + 64), word(rsp + 72)), (AT_ENTRY, 0x4000));
+    assert_eq!((word(rsp + 80), word(rsp + 88)), (AT_NULL, 0));
+
+    let mut arguments = Arguments::new();
+
+    assert_eq!(arguments.push_arg(b"a\0b"), Err(ArgumentError::Nul));
+    assert_eq!(
+        arguments.push_env(&[b'x'; MAX_ARGUMENTS_SIZE]),
+        Err(ArgumentError::TooLong)
+    );
+    assert_eq!(arguments.args().count() + arguments.env().count(), 0);
+}
@@ -0,0 +1,143 @@
+use crate::fs::OpenFile;
+use crate::pipe::{PipeReader, PipeWriter};
+use crate::shm::SharedMemory;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const MAX_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileError {
+    BadDescriptor,
+}
+
+// NOTE: what a descriptor refers to, Console being the console TTY. Clones share the
+// underlying object, a pipe end stays open until its last clone is closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum File {
+    Console,
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+    // NOTE: can only be mapped writable if opened writable
+    SharedMemory {
+        object: Arc<SharedMemory>,
+        writable: bool,
+    },
+    // NOTE: a path opened through the VFS, clones share the offset
+    Vfs(Arc<OpenFile>),
+}
+
+// NOTE: indexed by descriptor, closed slots are None and get reused lowest first
+#[derive(Debug, Clone)]
+pub struct FileTable {
+    files: Vec<Option<File>>,
+}
+
+impl FileTable {
+    // NOTE: stdin, stdout and stderr on the console
+    pub fn new() -> FileTable {
+        FileTable {
+            files: vec![Some(File::Console); 3],
+        }
+    }
+
+    // NOTE: for a process that exited, which keeps nothing open
+    pub fn empty() -> FileTable {
+        FileTable { files: Vec::new() }
+    }
+
+    pub fn get(&self, descriptor: usize) -> Option<File> {
+        self.files.get(descriptor).cloned().flatten()
+    }
+
+    // NOTE: the new descriptor, None once MAX_FILES are open
+    pub fn insert(&mut self, file: File) -> Option<usize> {
+        match self.files.iter().position(Option::is_none) {
+            Some(descriptor) => {
+                self.files[descriptor] = Some(file);
+
+                Some(descriptor)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(Some(file));
+
+                Some(self.files.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    pub fn close(&mut self, descriptor: usize) -> Option<File> {
+        self.files.get_mut(descriptor)?.take()
+    }
+
+    // NOTE: makes `target` refer to what `source` does, closing whatever it had; returns the
+    // replaced file so the caller can drop it outside the table's lock
+    pub fn duplicate(&mut self, source: usize, target: usize) -> Result<Option<File>, FileError> {
+        let file = self.get(source).ok_or(FileError::BadDescriptor)?;
+
+        if target >= MAX_FILES {
+            return Err(FileError::BadDescriptor);
+        }
+
+        if target >= self.files.len() {
+            self.files.resize(target + 1, None);
+        }
+
+        Ok(self.files[target].replace(file))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> FileTable {
+        FileTable::new()
+    }
+}
+
+#[test_case]
+fn test_descriptors_are_reused() {
+    let mut files = FileTable::new();
+
+    assert_eq!(files.len(), 3);
+    assert_eq!(files.close(1), Some(File::Console));
+    assert_eq!(files.get(1), None);
+    assert_eq!(files.insert(File::Console), Some(1));
+    assert_eq!(files.insert(File::Console), Some(3));
+    assert_eq!(files.close(9), None);
+}
+
+#[test_case]
+fn test_duplicate_shares_pipe() {
+    let (reader, writer) = crate::pipe::pipe();
+    let mut files = FileTable::new();
+    let read = files.insert(File::PipeRead(reader)).unwrap();
+    let write = files.insert(File::PipeWrite(writer)).unwrap();
+
+    assert!(matches!(files.duplicate(write, 1), Ok(Some(File::Console))));
+    assert_eq!(files.get(1), files.get(write));
+    assert_eq!(files.duplicate(9, 1), Err(FileError::BadDescriptor));
+    assert_eq!(
+        files.duplicate(read, MAX_FILES),
+        Err(FileError::BadDescriptor)
+    );
+    assert!(files.close(write).is_some());
+
+    let Some(File::PipeRead(reader)) = files.get(read) else {
+        panic!("descriptor {} lost its pipe", read);
+    };
+    let Some(File::PipeWrite(writer)) = files.get(1) else {
+        panic!("descriptor 1 isn't the pipe");
+    };
+
+    assert_eq!(writer.try_write(b"ok"), Ok(2));
+    assert_eq!(reader.try_read(&mut [0; 4]), Some(2));
+}
@@ -0,0 +1,247 @@
+use crate::syscall::abi::{NSIG, SIGKILL};
+use x86_64::VirtAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    // NOTE: 0 or NSIG and above
+    BadSignal,
+    // NOTE: SIGKILL can't be handled, ignored or blocked
+    Uncatchable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // NOTE: ends the process, for every signal there is so far
+    Default,
+    Ignore,
+    // NOTE: a user function taking the signal number, called on the thread's own stack
+    Handler(VirtAddr),
+}
+
+// NOTE: what a thread about to return to ring 3 has to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    // NOTE: the process was killed by the signal, every thread exits with exit_code(signal)
+    Exit(u64),
+    // NOTE: the signal is blocked from here on, `blocked` is the mask to restore afterwards
+    Handle {
+        signal: u64,
+        handler: VirtAddr,
+        blocked: u64,
+    },
+}
+
+// NOTE: what a process killed by `signal` exits with, like a Unix shell reports it
+pub const fn exit_code(signal: u64) -> i32 {
+    128 + signal as i32
+}
+
+fn bit(signal: u64) -> Result<u64, SignalError> {
+    match signal {
+        1..NSIG => Ok(1 << signal),
+        _ => Err(SignalError::BadSignal),
+    }
+}
+
+// NOTE: per process, bit n of the masks stands for signal n. A pending signal stays pending
+// while it is blocked; raising it again before delivery doesn't queue a second one
+#[derive(Debug, Clone)]
+pub struct Signals {
+    pending: u64,
+    blocked: u64,
+    actions: [Action; NSIG as usize],
+    // NOTE: set once a signal's default action ends the process
+    killed: Option<u64>,
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals {
+            pending: 0,
+            blocked: 0,
+            actions: [Action::Default; NSIG as usize],
+            killed: None,
+        }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    pub fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    pub fn killed(&self) -> Option<u64> {
+        self.killed
+    }
+
+    pub fn action(&self, signal: u64) -> Result<Action, SignalError> {
+        bit(signal)?;
+
+        Ok(self.actions[signal as usize])
+    }
+
+    // NOTE: returns the previous action; ignoring a signal drops it if it is pending
+    pub fn set_action(&mut self, signal: u64, action: Action) -> Result<Action, SignalError> {
+        let mask = bit(signal)?;
+
+        if signal == SIGKILL {
+            return Err(SignalError::Uncatchable);
+        }
+
+        if action == Action::Ignore {
+            self.pending &= !mask;
+        }
+
+        Ok(core::mem::replace(
+            &mut self.actions[signal as usize],
+            action,
+        ))
+    }
+
+    // NOTE: returns the previous mask, SIGKILL is left out of it
+    pub fn set_blocked(&mut self, blocked: u64) -> u64 {
+        core::mem::replace(&mut self.blocked, blocked & !(1 << SIGKILL | 1))
+    }
+
+    pub fn raise(&mut self, signal: u64) -> Result<(), SignalError> {
+        let mask = bit(signal)?;
+
+        if self.actions[signal as usize] != Action::Ignore {
+            self.pending |= mask;
+        }
+
+        Ok(())
+    }
+
+    // NOTE: for a fault, which would only repeat if the signal were blocked or ignored, so in
+    // that case its default action applies
+    pub fn force(&mut self, signal: u64) -> Result<(), SignalError> {
+        let mask = bit(signal)?;
+
+        if self.blocked & mask != 0 || self.actions[signal as usize] == Action::Ignore {
+            self.blocked &= !mask;
+            self.actions[signal as usize] = Action::Default;
+        }
+
+        self.pending |= mask;
+
+        Ok(())
+    }
+
+    // NOTE: ends the process with `signal` whatever its action, e.g. when a handler's frame
+    // can't be written
+    pub fn kill(&mut self, signal: u64) {
+        self.killed.get_or_insert(signal);
+    }
+
+    pub fn has_deliverable(&self) -> bool {
+        self.killed.is_some() || self.pending & !self.blocked != 0
+    }
+
+    // NOTE: takes the lowest deliverable signal off the pending mask
+    pub fn take(&mut self) -> Option<Delivery> {
+        if let Some(signal) = self.killed {
+            return Some(Delivery::Exit(signal));
+        }
+
+        loop {
+            let deliverable = self.pending & !self.blocked;
+
+            if deliverable == 0 {
+                return None;
+            }
+
+            let signal = deliverable.trailing_zeros() as u64;
+
+            self.pending &= !(1 << signal);
+
+            match self.actions[signal as usize] {
+                Action::Handler(handler) => {
+                    let blocked = self.blocked;
+
+                    self.blocked |= 1 << signal;
+
+                    return Some(Delivery::Handle {
+                        signal,
+                        handler,
+                        blocked,
+                    });
+                }
+                Action::Ignore => continue,
+                Action::Default => {
+                    self.killed = Some(signal);
+
+                    return Some(Delivery::Exit(signal));
+                }
+            }
+        }
+    }
+
+    // NOTE: a forked child keeps the actions and the mask but none of the pending signals
+    pub fn fork(&self) -> Signals {
+        Signals {
+            pending: 0,
+            killed: None,
+            ..self.clone()
+        }
+    }
+
+    // NOTE: handlers point into the old program, exec resets them while ignored signals stay
+    // ignored
+    pub fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if let Action::Handler(_) = action {
+                *action = Action::Default;
+            }
+        }
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Signals {
+        Signals::new()
+    }
+}
+
+#[test_case]
+fn test_signal_masks() {
+    use crate::syscall::abi::{SIGINT, SIGSEGV};
+
+    let handler = VirtAddr::new(crate::usermode::USER_CODE_BASE);
+    let mut signals = Signals::new();
+
+    assert_eq!(signals.raise(0), Err(SignalError::BadSignal));
+    assert_eq!(
+        signals.set_action(SIGKILL, Action::Ignore),
+        Err(SignalError::Uncatchable)
+    );
+    assert_eq!(
+        signals.set_action(SIGINT, Action::Handler(handler)),
+        Ok(Action::Default)
+    );
+
+    signals.set_blocked(1 << SIGINT | 1 << SIGKILL);
+    signals.raise(SIGINT).unwrap();
+
+    assert!(!signals.has_deliverable());
+    assert_eq!(signals.blocked(), 1 << SIGINT);
+    assert_eq!(signals.set_blocked(0), 1 << SIGINT);
+    assert_eq!(
+        signals.take(),
+        Some(Delivery::Handle {
+            signal: SIGINT,
+            handler,
+            blocked: 0
+        })
+    );
+    assert_eq!(signals.take(), None);
+
+    signals.set_blocked(1 << SIGSEGV);
+    signals.force(SIGSEGV).unwrap();
+
+    assert_eq!(signals.take(), Some(Delivery::Exit(SIGSEGV)));
+    assert_eq!(signals.killed(), Some(SIGSEGV));
+    assert_eq!(signals.fork().killed(), None);
+}
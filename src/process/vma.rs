@@ -0,0 +1,219 @@
+use crate::usermode::{USER_DATA_END, USER_MMAP_BASE, USER_REGION_END};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::Range;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    // NOTE: unaligned, empty or outside what the call may touch
+    BadRange,
+    OutOfAddressSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    Heap,
+    Anonymous,
+    // NOTE: a shared memory object, mapped in full up front rather than on first touch
+    Shared,
+}
+
+// NOTE: a range of user memory whose pages get a zeroed frame on first touch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: u64,
+    pub end: u64,
+    pub flags: PageTableFlags,
+    pub kind: VmaKind,
+}
+
+// NOTE: the lazily mapped areas of a process keyed by start, besides the eagerly loaded image
+// and stack. The heap is the area from heap_start up to the break rounded to a page; anonymous
+// mappings are carved upwards from USER_MMAP_BASE and their addresses are never reused
+#[derive(Debug, Clone)]
+pub struct Vmas {
+    areas: BTreeMap<u64, Vma>,
+    heap_start: u64,
+    brk: u64,
+    next_mmap: u64,
+}
+
+fn heap_flags() -> PageTableFlags {
+    PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+}
+
+impl Vmas {
+    pub fn new(heap_start: VirtAddr) -> Vmas {
+        Vmas {
+            areas: BTreeMap::new(),
+            heap_start: heap_start.as_u64(),
+            brk: heap_start.as_u64(),
+            next_mmap: USER_MMAP_BASE,
+        }
+    }
+
+    pub fn find(&self, address: VirtAddr) -> Option<&Vma> {
+        let address = address.as_u64();
+
+        self.areas
+            .range(..=address)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| address < area.end)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    pub fn brk(&self) -> VirtAddr {
+        VirtAddr::new(self.brk)
+    }
+
+    // NOTE: moves the break, returns the pages the heap gave up that the caller has to unmap
+    pub fn set_brk(&mut self, brk: VirtAddr) -> Result<Range<u64>, VmaError> {
+        let brk = brk.as_u64();
+
+        if brk < self.heap_start || brk > USER_DATA_END {
+            return Err(VmaError::BadRange);
+        }
+
+        let old_end = self.brk.next_multiple_of(PAGE_SIZE);
+        let end = brk.next_multiple_of(PAGE_SIZE);
+
+        self.areas.remove(&self.heap_start);
+
+        if end > self.heap_start {
+            self.areas.insert(
+                self.heap_start,
+                Vma {
+                    start: self.heap_start,
+                    end,
+                    flags: heap_flags(),
+                    kind: VmaKind::Heap,
+                },
+            );
+        }
+
+        self.brk = brk;
+
+        Ok(end.min(old_end)..old_end)
+    }
+
+    // NOTE: `len` bytes rounded up to whole pages, PRESENT and USER_ACCESSIBLE are added to
+    // `flags`
+    pub fn map_anonymous(&mut self, len: u64, flags: PageTableFlags) -> Result<VirtAddr, VmaError> {
+        self.map_area(len, flags, VmaKind::Anonymous)
+    }
+
+    // NOTE: room for a shared memory object, the caller maps its frames
+    pub fn map_shared(&mut self, len: u64, flags: PageTableFlags) -> Result<VirtAddr, VmaError> {
+        self.map_area(len, flags, VmaKind::Shared)
+    }
+
+    fn map_area(
+        &mut self,
+        len: u64,
+        flags: PageTableFlags,
+        kind: VmaKind,
+    ) -> Result<VirtAddr, VmaError> {
+        if len == 0 {
+            return Err(VmaError::BadRange);
+        }
+
+        let start = self.next_mmap;
+        let end = len
+            .checked_next_multiple_of(PAGE_SIZE)
+            .and_then(|len| start.checked_add(len))
+            .filter(|end| *end <= USER_REGION_END)
+            .ok_or(VmaError::OutOfAddressSpace)?;
+
+        self.areas.insert(
+            start,
+            Vma {
+                start,
+                end,
+                flags: flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                kind,
+            },
+        );
+        self.next_mmap = end;
+
+        Ok(VirtAddr::new(start))
+    }
+
+    // NOTE: drops the anonymous and shared mappings in the range, splitting areas it cuts
+    // through; the caller unmaps the pages. The heap only shrinks through set_brk
+    pub fn unmap(&mut self, start: VirtAddr, len: u64) -> Result<(), VmaError> {
+        let start = start.as_u64();
+        let end = len
+            .checked_next_multiple_of(PAGE_SIZE)
+            .and_then(|len| start.checked_add(len))
+            .ok_or(VmaError::BadRange)?;
+
+        if len == 0
+            || !start.is_multiple_of(PAGE_SIZE)
+            || start < USER_MMAP_BASE
+            || end > USER_REGION_END
+        {
+            return Err(VmaError::BadRange);
+        }
+
+        let cut: Vec<Vma> = self
+            .areas
+            .values()
+            .filter(|area| area.start < end && start < area.end)
+            .copied()
+            .collect();
+
+        for area in cut {
+            self.areas.remove(&area.start);
+
+            if area.start < start {
+                self.areas.insert(area.start, Vma { end: start, ..area });
+            }
+
+            if end < area.end {
+                self.areas.insert(end, Vma { start: end, ..area });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_heap_and_mappings() {
+    let heap = VirtAddr::new(crate::usermode::USER_CODE_BASE + 0x3000);
+    let mut vmas = Vmas::new(heap);
+
+    assert_eq!(vmas.set_brk(heap + 10u64), Ok(heap.as_u64()..heap.as_u64()));
+    assert!(vmas.find(heap + 9u64).is_some());
+    assert!(vmas.find(heap + 0x1000u64).is_none());
+    assert_eq!(vmas.set_brk(heap - 1u64), Err(VmaError::BadRange));
+    assert_eq!(
+        vmas.set_brk(heap),
+        Ok(heap.as_u64()..heap.as_u64() + 0x1000)
+    );
+    assert!(vmas.find(heap).is_none());
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mapping = vmas.map_anonymous(3 * PAGE_SIZE, flags).unwrap();
+
+    assert_eq!(mapping.as_u64(), USER_MMAP_BASE);
+    assert_eq!(vmas.unmap(mapping + PAGE_SIZE, 1), Ok(()));
+    assert!(vmas.find(mapping).is_some());
+    assert!(vmas.find(mapping + PAGE_SIZE).is_none());
+    assert_eq!(
+        vmas.find(mapping + 2 * PAGE_SIZE).unwrap().start,
+        mapping.as_u64() + 0x2000
+    );
+    assert_eq!(vmas.iter().count(), 2);
+}
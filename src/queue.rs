@@ -0,0 +1,97 @@
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+
+// NOTE: single producer (an interrupt handler) single consumer byte ring that never blocks,
+// the consumer can wait for bytes through the waker
+pub struct ByteQueue<const SIZE: usize> {
+    bytes: [AtomicU8; SIZE],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl<const SIZE: usize> ByteQueue<SIZE> {
+    pub const fn new() -> ByteQueue<SIZE> {
+        ByteQueue {
+            bytes: [const { AtomicU8::new(0) }; SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    // NOTE: returns false and drops the byte when the queue is full
+    pub fn push(&self, byte: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if tail - self.head.load(Ordering::Acquire) == SIZE {
+            return false;
+        }
+
+        self.bytes[tail % SIZE].store(byte, Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+
+        true
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let byte = self.bytes[head % SIZE].load(Ordering::Relaxed);
+
+        self.head.store(head + 1, Ordering::Release);
+
+        Some(byte)
+    }
+
+    // NOTE: wakes the consumer waiting in poll_pop, producers call it after pushing
+    pub fn wake(&self) {
+        self.waker.wake();
+    }
+
+    pub fn poll_pop(&self, cx: &mut Context) -> Poll<u8> {
+        if let Some(byte) = self.pop() {
+            return Poll::Ready(byte);
+        }
+
+        self.waker.register(cx.waker());
+
+        // NOTE: a byte may have arrived between the pop and the register
+        match self.pop() {
+            Some(byte) => {
+                self.waker.take();
+
+                Poll::Ready(byte)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for ByteQueue<SIZE> {
+    fn default() -> Self {
+        ByteQueue::new()
+    }
+}
+
+#[test_case]
+fn test_byte_queue() {
+    static TEST_QUEUE: ByteQueue<4> = ByteQueue::new();
+
+    for byte in 0..4 {
+        assert!(TEST_QUEUE.push(byte));
+    }
+
+    assert!(!TEST_QUEUE.push(0xff));
+
+    for byte in 0..4 {
+        assert_eq!(TEST_QUEUE.pop(), Some(byte));
+    }
+
+    assert_eq!(TEST_QUEUE.pop(), None);
+}
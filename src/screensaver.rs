@@ -0,0 +1,82 @@
+use crate::vga_registers::{sequencer_read, sequencer_write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+// NOTE: bit 5 of the sequencer clocking mode register turns the display off, the VGA memory
+// keeps its contents and is still written to, so nothing has to be saved for the blank screen
+const SEQUENCER_CLOCKING_MODE: u8 = 0x01;
+const SCREEN_OFF: u8 = 0x20;
+
+const DEFAULT_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+
+// NOTE: 0 disables blanking
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_MS);
+static IDLE_MS: AtomicU64 = AtomicU64::new(0);
+static BLANKED: AtomicBool = AtomicBool::new(false);
+
+fn set_screen_enabled(enabled: bool) {
+    interrupts::without_interrupts(|| {
+        let mode = sequencer_read(SEQUENCER_CLOCKING_MODE);
+        let mode = match enabled {
+            true => mode & !SCREEN_OFF,
+            false => mode | SCREEN_OFF,
+        };
+
+        sequencer_write(SEQUENCER_CLOCKING_MODE, mode);
+    });
+}
+
+pub fn set_timeout(seconds: u64) {
+    TIMEOUT_MS.store(seconds * 1000, Ordering::Relaxed);
+    activity();
+}
+
+pub fn is_blanked() -> bool {
+    BLANKED.load(Ordering::Relaxed)
+}
+
+pub fn blank() {
+    if !BLANKED.swap(true, Ordering::Relaxed) {
+        set_screen_enabled(false);
+    }
+}
+
+pub fn unblank() {
+    if BLANKED.swap(false, Ordering::Relaxed) {
+        set_screen_enabled(true);
+    }
+}
+
+// NOTE: to be called by the keyboard handler on every key press
+pub fn activity() {
+    IDLE_MS.store(0, Ordering::Relaxed);
+    unblank();
+}
+
+// NOTE: to be called by the timer handler with the time passed since its previous call
+pub fn tick(elapsed_ms: u64) {
+    let timeout = TIMEOUT_MS.load(Ordering::Relaxed);
+    let idle = IDLE_MS.fetch_add(elapsed_ms, Ordering::Relaxed) + elapsed_ms;
+
+    if timeout != 0 && idle >= timeout {
+        blank();
+    }
+}
+
+#[test_case]
+fn test_blank_after_timeout() {
+    set_timeout(1);
+
+    tick(600);
+    assert!(!is_blanked());
+
+    tick(600);
+    assert!(is_blanked());
+    assert!(sequencer_read(SEQUENCER_CLOCKING_MODE) & SCREEN_OFF != 0);
+
+    activity();
+    assert!(!is_blanked());
+    assert!(sequencer_read(SEQUENCER_CLOCKING_MODE) & SCREEN_OFF == 0);
+
+    set_timeout(DEFAULT_TIMEOUT_MS / 1000);
+}
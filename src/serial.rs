@@ -1,26 +1,336 @@
+use crate::console::Console;
+use crate::queue::ByteQueue;
+use crate::sync::IrqMutex;
+use crate::vga_buffer::Color;
+use core::fmt;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
 use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+// NOTE: register offsets from the port base, DLAB in the line control register switches the
+// first two to the baud rate divisor
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const INTERRUPT_RECEIVED_DATA: u8 = 0x01;
+const LINE_CONTROL_DLAB: u8 = 0x80;
+// NOTE: 8 data bits, no parity, one stop bit
+const LINE_CONTROL_8N1: u8 = 0x03;
+// NOTE: enable, clear both FIFOs, interrupt at 14 bytes
+const FIFO_ENABLE: u8 = 0xc7;
+// NOTE: DTR, RTS and OUT2 (needed for interrupts to reach the PIC)
+const MODEM_READY: u8 = 0x0b;
+const MODEM_LOOPBACK: u8 = 0x1e;
+const LINE_STATUS_DATA_READY: u8 = 0x01;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 0x20;
+
+// NOTE: the UART clock divided by the divisor gives the baud rate
+const UART_CLOCK: u32 = 115_200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ComPort {
+    Com1 = 0x3f8,
+    Com2 = 0x2f8,
+}
+
+impl ComPort {
+    pub fn from_base(base: u16) -> Option<ComPort> {
+        match base {
+            0x3f8 => Some(ComPort::Com1),
+            0x2f8 => Some(ComPort::Com2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    // NOTE: the baud rate does not divide the UART clock or is too slow for a 16 bit divisor
+    InvalidBaudRate,
+    // NOTE: the loopback test failed, there is no working UART at this port
+    NotPresent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub fifo: bool,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 38_400,
+            fifo: true,
+        }
+    }
+}
+
+impl SerialConfig {
+    fn divisor(&self) -> Result<u16, SerialError> {
+        if !UART_CLOCK.is_multiple_of(self.baud_rate) {
+            return Err(SerialError::InvalidBaudRate);
+        }
+
+        u16::try_from(UART_CLOCK / self.baud_rate).map_err(|_| SerialError::InvalidBaudRate)
+    }
+}
+
+// NOTE: a 16550 compatible UART, output to a port that failed to initialize is dropped
+pub struct SerialPort {
+    base: u16,
+    present: bool,
+    // NOTE: what the last escape set, None for the terminal's defaults
+    color: Option<(Color, Color)>,
+}
+
+impl SerialPort {
+    /// # Safety
+    ///
+    /// `base` must be the I/O base of a UART that nothing else drives.
+    pub const unsafe fn new(base: u16) -> SerialPort {
+        SerialPort {
+            base,
+            present: false,
+            color: None,
+        }
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.base + register).read() }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.base + register).write(value) };
+    }
+
+    pub fn init(&mut self, config: SerialConfig) -> Result<(), SerialError> {
+        let divisor = config.divisor()?;
+
+        self.present = false;
+        self.write_register(INTERRUPT_ENABLE, 0x00);
+        self.write_register(LINE_CONTROL, LINE_CONTROL_DLAB);
+        self.write_register(DIVISOR_LOW, (divisor & 0xff) as u8);
+        self.write_register(DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write_register(LINE_CONTROL, LINE_CONTROL_8N1);
+        self.write_register(FIFO_CONTROL, if config.fifo { FIFO_ENABLE } else { 0x00 });
+
+        // NOTE: whatever is sent in loopback mode must come back
+        self.write_register(MODEM_CONTROL, MODEM_LOOPBACK);
+        self.write_register(DATA, 0xae);
+
+        if self.read_register(DATA) != 0xae {
+            return Err(SerialError::NotPresent);
+        }
+
+        self.write_register(MODEM_CONTROL, MODEM_READY);
+        self.present = true;
+
+        Ok(())
+    }
+
+    // NOTE: raises IRQ 4 (COM1) / IRQ 3 (COM2) whenever a byte arrives
+    pub fn enable_receive_interrupt(&mut self) {
+        if self.present {
+            self.write_register(INTERRUPT_ENABLE, INTERRUPT_RECEIVED_DATA);
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+
+        while self.read_register(LINE_STATUS) & LINE_STATUS_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+
+        self.write_register(DATA, byte);
+    }
+
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if !self.present || self.read_register(LINE_STATUS) & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(self.read_register(DATA))
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+
+        Ok(())
+    }
+}
+
+fn open(port: ComPort) -> IrqMutex<SerialPort> {
+    let mut serial_port = unsafe { SerialPort::new(port as u16) };
+
+    // NOTE: a missing port just swallows its output, see SerialPort::send
+    let _ = serial_port.init(SerialConfig::default());
+
+    IrqMutex::new(serial_port)
+}
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        // NOTE: 0x3f8 first serial interface default location
-        let mut serial_port = unsafe { SerialPort::new(0x3f8) };
+    pub static ref SERIAL1: IrqMutex<SerialPort> = open(ComPort::Com1);
+    pub static ref SERIAL2: IrqMutex<SerialPort> = open(ComPort::Com2);
+}
 
-        serial_port.init();
+pub fn port(port: ComPort) -> &'static IrqMutex<SerialPort> {
+    match port {
+        ComPort::Com1 => &SERIAL1,
+        ComPort::Com2 => &SERIAL2,
+    }
+}
 
-        Mutex::new(serial_port)
-    };
+// NOTE: reprograms an already opened port, e.g. for a different baud rate
+pub fn init(port: ComPort, config: SerialConfig) -> Result<(), SerialError> {
+    self::port(port).lock().init(config)
+}
+
+const RECEIVE_BUFFER_SIZE: usize = 256;
+
+// NOTE: bytes arriving while a queue is full are dropped
+static COM1_RECEIVED: ByteQueue<RECEIVE_BUFFER_SIZE> = ByteQueue::new();
+static COM2_RECEIVED: ByteQueue<RECEIVE_BUFFER_SIZE> = ByteQueue::new();
+
+fn received(port: ComPort) -> &'static ByteQueue<RECEIVE_BUFFER_SIZE> {
+    match port {
+        ComPort::Com1 => &COM1_RECEIVED,
+        ComPort::Com2 => &COM2_RECEIVED,
+    }
+}
+
+pub fn enable_receive_interrupt(serial_port: ComPort) {
+    port(serial_port).lock().enable_receive_interrupt();
+}
+
+// NOTE: to be called by the IRQ handler of the port, moves everything the UART holds into the
+// receive buffer
+pub fn handle_interrupt(serial_port: ComPort) {
+    let buffer = received(serial_port);
+    let mut uart = port(serial_port).lock();
+
+    while let Some(byte) = uart.try_receive() {
+        buffer.push(byte);
+    }
+
+    buffer.wake();
+}
+
+// NOTE: also polls the UART, so it works before the receive interrupt is wired up
+pub fn try_read_byte(serial_port: ComPort) -> Option<u8> {
+    received(serial_port)
+        .pop()
+        .or_else(|| port(serial_port).lock().try_receive())
+}
+
+pub fn read_byte(serial_port: ComPort) -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte(serial_port) {
+            return byte;
+        }
+
+        core::hint::spin_loop();
+    }
+}
+
+// NOTE: received bytes as an async stream, there should be only one per port
+pub struct SerialStream {
+    port: ComPort,
+}
+
+impl SerialStream {
+    pub fn new(port: ComPort) -> SerialStream {
+        SerialStream { port }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        received(self.port).poll_pop(cx).map(Some)
+    }
+}
+
+// NOTE: VGA color index -> ANSI color index (black, red, green, yellow, blue, magenta, cyan, white)
+const VGA_TO_ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+// NOTE: colors and clearing are forwarded to the remote terminal as ANSI escapes
+impl Console for SerialPort {
+    fn write_str(&mut self, s: &str) {
+        use core::fmt::Write;
+
+        let _ = Write::write_str(self, s);
+    }
+
+    fn clear(&mut self) {
+        Console::write_str(self, "\x1b[2J\x1b[H");
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        use core::fmt::Write;
+
+        self.color = Some((foreground, background));
+
+        let ansi = |color: Color, base: u8| {
+            let index = color as u8;
+            let bright = if index & 0x08 != 0 { 60 } else { 0 };
+
+            base + bright + VGA_TO_ANSI[(index & 0x07) as usize]
+        };
+
+        let _ = write!(
+            self,
+            "\x1b[{};{}m",
+            ansi(foreground, 30),
+            ansi(background, 40)
+        );
+    }
+
+    fn color(&self) -> Option<(Color, Color)> {
+        self.color
+    }
+
+    fn reset_color(&mut self) {
+        self.color = None;
+        Console::write_str(self, "\x1b[0m");
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        (24, 80)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    _print_to(ComPort::Com1, args);
 }
 
 #[doc(hidden)]
-pub fn _print(args: core::fmt::Arguments) {
+pub fn _print_to(serial_port: ComPort, args: fmt::Arguments) {
     use core::fmt::Write;
 
-    SERIAL1
-        .lock()
-        .write_fmt(args)
-        .expect("Printing to UART_16550 serial failed");
+    let _ = port(serial_port).lock().write_fmt(args);
 }
 
 #[macro_export]
@@ -36,3 +346,36 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+#[macro_export]
+macro_rules! serial_print_to {
+    ($port:expr, $($arg:tt)*) => {
+        $crate::serial::_print_to($port, format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial_println_to {
+    ($port:expr) => ($crate::serial_print_to!($port, "\n"));
+    ($port:expr, $($arg:tt)*) => (
+        $crate::serial_print_to!($port, "{}\n", format_args!($($arg)*))
+    );
+}
+
+#[test_case]
+fn test_baud_rate_divisor() {
+    let config = |baud_rate| SerialConfig {
+        baud_rate,
+        ..SerialConfig::default()
+    };
+
+    assert_eq!(config(115_200).divisor(), Ok(1));
+    assert_eq!(config(9_600).divisor(), Ok(12));
+    assert_eq!(config(0).divisor(), Err(SerialError::InvalidBaudRate));
+    assert_eq!(config(100_000).divisor(), Err(SerialError::InvalidBaudRate));
+}
+
+#[test_case]
+fn test_com1_present() {
+    assert!(SERIAL1.lock().is_present());
+}
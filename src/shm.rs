@@ -0,0 +1,210 @@
+use crate::memory::frame_allocator;
+use crate::memory::phys_to_virt;
+use crate::sync::IrqMutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PhysFrame;
+
+pub const MAX_NAME: usize = 64;
+pub const MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmError {
+    // NOTE: empty or longer than MAX_NAME
+    BadName,
+    // NOTE: 0 or more than MAX_SIZE
+    BadSize,
+    NotFound,
+    // NOTE: creating exclusively a name that is taken
+    Exists,
+    NoMemory,
+}
+
+// NOTE: zeroed frames under a name, mapped whole into the address spaces that open it. Each
+// mapping holds a share of the frames, so they outlive the object until the last one goes
+#[derive(Debug)]
+pub struct SharedMemory {
+    name: Vec<u8>,
+    frames: Vec<PhysFrame>,
+}
+
+// NOTE: by name; unlinking only takes an object out of here, handles and mappings keep it
+static OBJECTS: IrqMutex<BTreeMap<Vec<u8>, Arc<SharedMemory>>> = IrqMutex::new(BTreeMap::new());
+
+impl SharedMemory {
+    fn new(name: &[u8], size: u64) -> Result<SharedMemory, ShmError> {
+        let mut object = SharedMemory {
+            name: name.to_vec(),
+            frames: Vec::new(),
+        };
+
+        // NOTE: dropping a half built object releases what it got so far
+        for _ in 0..size.div_ceil(4096) {
+            let frame = frame_allocator::allocate_frame().ok_or(ShmError::NoMemory)?;
+
+            unsafe {
+                core::ptr::write_bytes(
+                    phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                    0,
+                    4096,
+                )
+            };
+
+            object.frames.push(frame);
+        }
+
+        Ok(object)
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    // NOTE: whole pages
+    pub fn size(&self) -> u64 {
+        self.frames.len() as u64 * 4096
+    }
+
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        frame_allocator::with_frame_allocator(|frames| {
+            for frame in &self.frames {
+                unsafe { frames.release(*frame) };
+            }
+        });
+    }
+}
+
+// NOTE: the same object, not just the same name
+impl PartialEq for SharedMemory {
+    fn eq(&self, other: &SharedMemory) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl Eq for SharedMemory {}
+
+fn check_name(name: &[u8]) -> Result<(), ShmError> {
+    match name.len() {
+        1..=MAX_NAME => Ok(()),
+        _ => Err(ShmError::BadName),
+    }
+}
+
+// NOTE: an existing object keeps its size, `size` only matters for a new one
+pub fn create(name: &[u8], size: u64, exclusive: bool) -> Result<Arc<SharedMemory>, ShmError> {
+    check_name(name)?;
+
+    let mut objects = OBJECTS.lock();
+
+    if let Some(object) = objects.get(name) {
+        return match exclusive {
+            true => Err(ShmError::Exists),
+            false => Ok(object.clone()),
+        };
+    }
+
+    if size == 0 || size > MAX_SIZE {
+        return Err(ShmError::BadSize);
+    }
+
+    let object = Arc::new(SharedMemory::new(name, size)?);
+
+    objects.insert(object.name.clone(), object.clone());
+
+    Ok(object)
+}
+
+pub fn open(name: &[u8]) -> Result<Arc<SharedMemory>, ShmError> {
+    check_name(name)?;
+
+    OBJECTS.lock().get(name).cloned().ok_or(ShmError::NotFound)
+}
+
+pub fn unlink(name: &[u8]) -> Result<(), ShmError> {
+    check_name(name)?;
+
+    let object = OBJECTS.lock().remove(name).ok_or(ShmError::NotFound)?;
+
+    drop(object);
+
+    Ok(())
+}
+
+#[test_case]
+fn test_create_open_unlink() {
+    let object = create(b"test-shm", 5000, true).unwrap();
+
+    assert_eq!(object.size(), 8192);
+    assert_eq!(
+        create(b"test-shm", 4096, true).err(),
+        Some(ShmError::Exists)
+    );
+    assert_eq!(create(b"test-shm", 0, false).unwrap(), object);
+    assert_eq!(open(b"test-shm").unwrap(), object);
+    assert_eq!(create(b"", 4096, false).err(), Some(ShmError::BadName));
+    assert_eq!(
+        create(b"huge", MAX_SIZE + 1, false).err(),
+        Some(ShmError::BadSize)
+    );
+    assert_eq!(unlink(b"test-shm"), Ok(()));
+    assert_eq!(open(b"test-shm").err(), Some(ShmError::NotFound));
+    assert_eq!(object.frames().len(), 2);
+}
+
+#[test_case]
+fn test_mapped_into_processes() {
+    use crate::memory::address_space::{AddressSpace, SHARED};
+    use crate::process::Process;
+    use crate::thread;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use x86_64::structures::paging::{Page, PageTableFlags};
+
+    static FORKED_CHECKED: AtomicBool = AtomicBool::new(false);
+
+    let object = create(b"test-map", 4096, false).unwrap();
+    let first = Process::new("first", AddressSpace::new().unwrap());
+    let second = Process::new("second", AddressSpace::new().unwrap());
+    let writable = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let a = first.map_shared(&object, writable).unwrap();
+    let b = second
+        .map_shared(&object, PageTableFlags::NO_EXECUTE)
+        .unwrap();
+    let child = first
+        .fork(|| {
+            while !FORKED_CHECKED.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+        })
+        .unwrap();
+    let frame = object.frames()[0];
+
+    for process in [&first, &child] {
+        let (mapped, flags) = process
+            .address_space()
+            .translate(Page::containing_address(a))
+            .unwrap();
+
+        assert_eq!(mapped, frame);
+        assert!(flags.contains(PageTableFlags::WRITABLE | SHARED));
+    }
+
+    FORKED_CHECKED.store(true, Ordering::Release);
+
+    let (mapped, flags) = second
+        .address_space()
+        .translate(Page::containing_address(b))
+        .unwrap();
+
+    assert_eq!(mapped, frame);
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    assert_eq!(unlink(b"test-map"), Ok(()));
+    assert_eq!(second.unmap(b, 4096), Ok(()));
+    assert_eq!(child.wait(), 0);
+}
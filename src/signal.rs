@@ -0,0 +1,390 @@
+use crate::memory::address_space::AddressSpace;
+use crate::memory::frame_allocator;
+use crate::memory::paging::MapError;
+use crate::memory::phys_to_virt;
+use crate::percpu::{PerCpu, SignalState, SyscallState};
+use crate::process::{self, exit_code, Delivery};
+use crate::syscall::abi::{SIGSEGV, SYS_SIGRETURN};
+use crate::uaccess::{self, AccessError, USER_END};
+use crate::{gdt, percpu, thread, usermode};
+use core::arch::{asm, global_asm};
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::Ordering;
+use spin::Once;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+// NOTE: below the user rsp a handler's frame leaves alone, the System V red zone
+const RED_ZONE: u64 = 128;
+
+// NOTE: what user code may set in rflags through a signal frame, the arithmetic flags and DF
+const USER_SETTABLE_RFLAGS: u64 = 0xcd5;
+const USER_RFLAGS: u64 = 0x202;
+// NOTE: IF clear, the entry stub runs with interrupts off
+const KERNEL_RFLAGS: u64 = 0x2;
+const TRAP_AND_DIRECTION_FLAGS: u64 = 0x500;
+
+// NOTE: every register of an interrupted ring 3 thread, lowest address first, ending in what
+// iretq pops
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// NOTE: pushed on the user stack for a handler, whose return pops `return_address` into the
+// trampoline; SYS_SIGRETURN finds the rest just below the stack pointer it is made with
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct SignalFrame {
+    return_address: u64,
+    signal: u64,
+    blocked: u64,
+    context: UserContext,
+}
+
+// NOTE: an interrupt or fault that has to deliver a signal on its way back to ring 3 returns
+// here instead, in ring 0 with the user registers still live and the ring 3 iret frame left
+// in the per CPU data. The stub saves both as a UserContext on the thread's kernel stack and
+// delivers; rustos_context_return goes back to ring 3 from a context on top of the stack, with
+// the user GS base swapped back in like every way out of the kernel
+global_asm!(
+    ".global rustos_signal_entry",
+    "rustos_signal_entry:",
+    "mov rsp, gs:[{kernel_rsp}]",
+    "push qword ptr gs:[{ss}]",
+    "push qword ptr gs:[{rsp}]",
+    "push qword ptr gs:[{rflags}]",
+    "push qword ptr gs:[{cs}]",
+    "push qword ptr gs:[{rip}]",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call {deliver}",
+    ".global rustos_context_return",
+    "rustos_context_return:",
+    "cli",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "swapgs",
+    "iretq",
+    kernel_rsp = const offset_of!(PerCpu, syscall) + offset_of!(SyscallState, kernel_rsp),
+    rip = const offset_of!(PerCpu, signal) + offset_of!(SignalState, rip),
+    cs = const offset_of!(PerCpu, signal) + offset_of!(SignalState, cs),
+    rflags = const offset_of!(PerCpu, signal) + offset_of!(SignalState, rflags),
+    rsp = const offset_of!(PerCpu, signal) + offset_of!(SignalState, rsp),
+    ss = const offset_of!(PerCpu, signal) + offset_of!(SignalState, ss),
+    deliver = sym deliver_from_entry,
+);
+
+extern "C" {
+    fn rustos_signal_entry();
+    fn rustos_context_return();
+}
+
+// NOTE: one read-only page at usermode::USER_TRAMPOLINE in every process, shared by all of
+// them: mov eax, SYS_SIGRETURN; syscall; ud2
+static TRAMPOLINE: Once<Option<PhysFrame>> = Once::new();
+
+fn trampoline_frame() -> Option<PhysFrame> {
+    *TRAMPOLINE.call_once(|| {
+        let frame = frame_allocator::allocate_frame()?;
+        let page = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+        let number = (SYS_SIGRETURN as u32).to_le_bytes();
+        let code = [
+            0xb8, number[0], number[1], number[2], number[3], 0x0f, 0x05, 0x0f, 0x0b,
+        ];
+
+        unsafe {
+            core::ptr::write_bytes(page, 0xcc, 4096);
+            core::ptr::copy_nonoverlapping(code.as_ptr(), page, code.len());
+        }
+
+        Some(frame)
+    })
+}
+
+// NOTE: for new address spaces of processes, fork copies the mapping along
+pub(crate) fn map_trampoline(space: &mut AddressSpace) -> Result<(), MapError> {
+    let frame = trampoline_frame().ok_or(MapError::FrameAllocationFailed)?;
+    let page = Page::containing_address(VirtAddr::new(usermode::USER_TRAMPOLINE));
+
+    unsafe {
+        space.map_to(
+            page,
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+        )
+    }
+}
+
+pub(crate) fn has_deliverable() -> bool {
+    process::current().is_some_and(|process| process.signals().has_deliverable())
+}
+
+// NOTE: sends the interrupted ring 3 frame through rustos_signal_entry
+fn redirect(stack_frame: &mut InterruptStackFrame) {
+    let saved = percpu!(signal);
+    let kernel_rsp = percpu!(syscall.kernel_rsp).load(Ordering::Relaxed);
+
+    saved
+        .rip
+        .store(stack_frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+    saved.cs.store(stack_frame.code_segment, Ordering::Relaxed);
+    saved.rflags.store(stack_frame.cpu_flags, Ordering::Relaxed);
+    saved
+        .rsp
+        .store(stack_frame.stack_pointer.as_u64(), Ordering::Relaxed);
+    saved.ss.store(stack_frame.stack_segment, Ordering::Relaxed);
+
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(rustos_signal_entry as *const () as u64);
+            frame.code_segment = gdt::KERNEL_CODE_SELECTOR.0 as u64;
+            frame.cpu_flags = KERNEL_RFLAGS;
+            frame.stack_pointer = VirtAddr::new(kernel_rsp);
+            frame.stack_segment = gdt::KERNEL_DATA_SELECTOR.0 as u64;
+        })
+    };
+}
+
+// NOTE: for interrupt handlers, at the end; a signal that came in while the thread ran in
+// ring 3 is delivered before it goes on
+pub(crate) fn on_interrupt_return(stack_frame: &mut InterruptStackFrame) {
+    if stack_frame.code_segment & 3 == 3 && has_deliverable() {
+        redirect(stack_frame);
+    }
+}
+
+// NOTE: for exception handlers, a fault in ring 3 raises SIGSEGV in the process. True when
+// the handler should return so it gets delivered, false for a fault in the kernel
+pub(crate) fn user_fault(stack_frame: &mut InterruptStackFrame) -> bool {
+    if stack_frame.code_segment & 3 != 3 {
+        return false;
+    }
+
+    let Some(process) = process::current() else {
+        thread::exit(usermode::FAULT_EXIT_CODE);
+    };
+    let _ = process.signals().force(SIGSEGV);
+
+    drop(process);
+    redirect(stack_frame);
+
+    true
+}
+
+extern "C" fn deliver_from_entry(context: &mut UserContext) {
+    deliver(context);
+}
+
+// NOTE: a fatal signal ends the thread here, a handled one rewrites `context` so the thread
+// enters the handler with a SignalFrame below its stack pointer. One signal per return, the
+// next pending one waits for the next
+pub(crate) fn deliver(context: &mut UserContext) {
+    let Some(process) = process::current() else {
+        return;
+    };
+    let delivery = process.signals().take();
+    let signal = match delivery {
+        None => return,
+        Some(Delivery::Exit(signal)) => signal,
+        Some(Delivery::Handle {
+            signal,
+            handler,
+            blocked,
+        }) => match push_frame(context, signal, handler, blocked) {
+            Ok(()) => return,
+            Err(_) => {
+                process.signals().kill(SIGSEGV);
+
+                SIGSEGV
+            }
+        },
+    };
+
+    drop(process);
+    thread::exit(exit_code(signal));
+}
+
+fn push_frame(
+    context: &mut UserContext,
+    signal: u64,
+    handler: VirtAddr,
+    blocked: u64,
+) -> Result<(), AccessError> {
+    // NOTE: 16 byte aligned once the handler's return address is popped, like after a call
+    let address = (context
+        .rsp
+        .wrapping_sub(RED_ZONE + size_of::<SignalFrame>() as u64)
+        & !0xf)
+        .wrapping_sub(8);
+    let frame = SignalFrame {
+        return_address: usermode::USER_TRAMPOLINE,
+        signal,
+        blocked,
+        context: *context,
+    };
+
+    uaccess::write_value(address, &frame)?;
+
+    context.rip = handler.as_u64();
+    context.rsp = address;
+    context.rdi = signal;
+    context.rflags &= !TRAP_AND_DIRECTION_FLAGS;
+
+    Ok(())
+}
+
+// NOTE: SYS_SIGRETURN, back from a handler through the trampoline with the frame's return
+// address already popped. A frame that doesn't check out kills the process with SIGSEGV
+pub(crate) fn sigreturn(user_rsp: u64) -> ! {
+    let frame = uaccess::read_value::<SignalFrame>(user_rsp.wrapping_sub(8));
+    let process = process::current().expect("sigreturn without a process");
+    let mut context = match frame {
+        Ok(frame) if frame.context.rip < USER_END && frame.context.rsp < USER_END => {
+            process.signals().set_blocked(frame.blocked);
+
+            frame.context
+        }
+        _ => {
+            process.signals().kill(SIGSEGV);
+
+            UserContext::default()
+        }
+    };
+
+    drop(process);
+
+    context.cs = gdt::USER_CODE_SELECTOR.0 as u64;
+    context.ss = gdt::USER_DATA_SELECTOR.0 as u64;
+    context.rflags = context.rflags & USER_SETTABLE_RFLAGS | USER_RFLAGS;
+
+    deliver(&mut context);
+
+    unsafe { resume(&context) };
+}
+
+/// # Safety
+///
+/// `context` must lie on the current thread's kernel stack, which is abandoned from it up,
+/// and hold user selectors and an rip and rsp below USER_END.
+pub(crate) unsafe fn resume(context: &UserContext) -> ! {
+    asm!(
+        "mov rsp, {context}",
+        "jmp {exit}",
+        context = in(reg) context,
+        exit = sym rustos_context_return,
+        options(noreturn)
+    );
+}
+
+#[test_case]
+fn test_handler_runs_and_returns() {
+    use crate::process::Action;
+    use crate::syscall::abi::SIGINT;
+
+    // NOTE: maps a flag page into r12, installs a SIGINT handler and spins until the flag is
+    // set; the handler, running on r12 as interrupted, stores the signal plus 40 there and
+    // returns, after which the program exits with the flag
+    let program = [
+        0xbf, 0x00, 0x10, 0x00, 0x00, // mov edi, 4096
+        0xbe, 0x03, 0x00, 0x00, 0x00, // mov esi, PROT_READ | PROT_WRITE
+        0xb8, 0x07, 0x00, 0x00, 0x00, // mov eax, SYS_MMAP
+        0x0f, 0x05, // syscall
+        0x49, 0x89, 0xc4, // mov r12, rax
+        0xbf, 0x02, 0x00, 0x00, 0x00, // mov edi, SIGINT
+        0x48, 0x8d, 0x35, 0x1a, 0x00, 0x00, 0x00, // lea rsi, [rip + 26]
+        0xb8, 0x0e, 0x00, 0x00, 0x00, // mov eax, SYS_SIGACTION
+        0x0f, 0x05, // syscall
+        0x41, 0x80, 0x3c, 0x24, 0x00, // spin: cmp byte [r12], 0
+        0x74, 0xf9, // je spin
+        0x41, 0x0f, 0xb6, 0x3c, 0x24, // movzx edi, byte [r12]
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+        0x8d, 0x47, 0x28, // handler: lea eax, [rdi + 40]
+        0x41, 0x88, 0x04, 0x24, // mov [r12], al
+        0xc3, // ret
+    ];
+    let process = usermode::spawn("sigint", &program).unwrap();
+
+    while process.signals().action(SIGINT) == Ok(Action::Default) {
+        thread::yield_now();
+    }
+
+    assert_eq!(process.signal(SIGINT), Ok(()));
+    assert_eq!(process.wait(), 42);
+}
+
+#[test_case]
+fn test_fatal_signals() {
+    use crate::syscall::abi::SIGKILL;
+
+    // NOTE: jmp $
+    let spinning = usermode::spawn("spin", &[0xeb, 0xfe]).unwrap();
+
+    assert_eq!(spinning.signal(SIGKILL), Ok(()));
+    assert_eq!(spinning.wait(), exit_code(SIGKILL));
+
+    // NOTE: installs a SIGSEGV handler that exits with the signal plus 66, then writes to an
+    // unmapped page
+    let program = [
+        0xbf, 0x0b, 0x00, 0x00, 0x00, // mov edi, SIGSEGV
+        0x48, 0x8d, 0x35, 0x11, 0x00, 0x00, 0x00, // lea rsi, [rip + 17]
+        0xb8, 0x0e, 0x00, 0x00, 0x00, // mov eax, SYS_SIGACTION
+        0x0f, 0x05, // syscall
+        0xc6, 0x04, 0x25, 0x10, 0x00, 0x00, 0x00, 0x01, // mov byte [0x10], 1
+        0x0f, 0x0b, // ud2
+        0x8d, 0x7f, 0x42, // handler: lea edi, [rdi + 66]
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+
+    assert_eq!(usermode::spawn("segv", &program).unwrap().wait(), 77);
+}
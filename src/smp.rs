@@ -0,0 +1,331 @@
+use crate::acpi::{Madt, MadtEntry};
+use crate::memory::paging::{self, MapError};
+use crate::memory::stack::KernelStack;
+use crate::memory::{frame_allocator, phys_to_virt, tlb};
+use crate::percpu::{self, MAX_CPUS};
+use crate::sync::IrqMutex;
+use crate::{apic, gdt, interrupts, syscall, thread, time};
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+const AP_STACK_PAGES: usize = 16;
+
+// NOTE: how long a CPU gets to reach ap_main after its startup IPIs
+const STARTUP_TIMEOUT_US: u64 = 100_000;
+
+// NOTE: the 16-bit startup code an application processor begins in, copied to a page below
+// 1 MiB since the startup IPI only carries the page number. It goes straight to long mode on
+// the kernel's page tables, the page itself is identity mapped for the switch. Fields after
+// the code are patched in before each startup
+global_asm!(
+    ".section .rodata.ap_trampoline, \"a\"",
+    ".global rustos_ap_trampoline",
+    ".global rustos_ap_long_mode",
+    ".global rustos_ap_gdt",
+    ".global rustos_ap_gdt_pointer",
+    ".global rustos_ap_far_pointer",
+    ".global rustos_ap_cr3",
+    ".global rustos_ap_stack",
+    ".global rustos_ap_cpu_index",
+    ".global rustos_ap_entry",
+    ".global rustos_ap_trampoline_end",
+    ".code16",
+    "rustos_ap_trampoline:",
+    "cli",
+    "cld",
+    "mov %cs, %ax",
+    "mov %ax, %ds",
+    "lgdtl (rustos_ap_gdt_pointer - rustos_ap_trampoline)",
+    // NOTE: PAE, then the kernel's level 4 table, then long mode and no-execute in EFER
+    "mov %cr4, %eax",
+    "or $0x20, %eax",
+    "mov %eax, %cr4",
+    "movl (rustos_ap_cr3 - rustos_ap_trampoline), %eax",
+    "mov %eax, %cr3",
+    "mov $0xc0000080, %ecx",
+    "rdmsr",
+    "or $0x900, %eax",
+    "wrmsr",
+    // NOTE: protection, write protect and paging at once
+    "mov $0x80010001, %eax",
+    "mov %eax, %cr0",
+    "ljmpl *(rustos_ap_far_pointer - rustos_ap_trampoline)",
+    ".code64",
+    "rustos_ap_long_mode:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "movq rustos_ap_stack(%rip), %rsp",
+    "movq rustos_ap_cpu_index(%rip), %rdi",
+    "xor %rbp, %rbp",
+    "callq *rustos_ap_entry(%rip)",
+    "ud2",
+    ".align 16",
+    "rustos_ap_gdt:",
+    ".quad 0",
+    ".quad 0x00af9a000000ffff",
+    ".quad 0x00cf92000000ffff",
+    "rustos_ap_gdt_pointer:",
+    ".word 23",
+    ".long 0",
+    "rustos_ap_far_pointer:",
+    ".long 0",
+    ".word 0x08",
+    ".align 8",
+    "rustos_ap_cr3: .quad 0",
+    "rustos_ap_stack: .quad 0",
+    "rustos_ap_cpu_index: .quad 0",
+    "rustos_ap_entry: .quad 0",
+    "rustos_ap_trampoline_end:",
+    ".code64",
+    ".section .text",
+    options(att_syntax)
+);
+
+extern "C" {
+    static rustos_ap_trampoline: u8;
+    static rustos_ap_long_mode: u8;
+    static rustos_ap_gdt: u8;
+    static rustos_ap_gdt_pointer: u8;
+    static rustos_ap_far_pointer: u8;
+    static rustos_ap_cr3: u8;
+    static rustos_ap_stack: u8;
+    static rustos_ap_cpu_index: u8;
+    static rustos_ap_entry: u8;
+    static rustos_ap_trampoline_end: u8;
+}
+
+// NOTE: AP_STACKS[i] is the boot stack of CPU i until it becomes that CPU's idle thread
+static AP_STACKS: IrqMutex<[Option<KernelStack>; MAX_CPUS]> =
+    IrqMutex::new([const { None }; MAX_CPUS]);
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+// NOTE: the copy of the startup code, removed again once every CPU is up
+struct Trampoline {
+    frame: PhysFrame,
+    // NOTE: false when the page was identity mapped already, it is left mapped then
+    mapped: bool,
+}
+
+impl Trampoline {
+    fn install() -> Option<Trampoline> {
+        let (level_4, _) = Cr3::read();
+
+        // NOTE: the startup code loads CR3 while still in 32 bits
+        if level_4.start_address().as_u64() > u32::MAX as u64 {
+            return None;
+        }
+
+        let frame = frame_allocator::allocate_frames_below(0, PhysAddr::new(0x10_0000))?;
+        let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        let mapped = match unsafe { paging::map_to(page, frame, flags) } {
+            Ok(()) => true,
+            Err(MapError::AlreadyMapped(mapped)) if mapped == frame => false,
+            Err(_) => {
+                unsafe { frame_allocator::free_frame(frame) };
+
+                return None;
+            }
+        };
+        let trampoline = Trampoline { frame, mapped };
+        let start = addr_of!(rustos_ap_trampoline);
+        let length = addr_of!(rustos_ap_trampoline_end) as usize - start as usize;
+        let base = trampoline.base();
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(start, trampoline.field(start).cast(), length);
+            trampoline
+                .field(addr_of!(rustos_ap_gdt_pointer))
+                .byte_add(2)
+                .cast::<u32>()
+                .write_unaligned(base + offset(addr_of!(rustos_ap_gdt)) as u32);
+            trampoline
+                .field(addr_of!(rustos_ap_far_pointer))
+                .cast::<u32>()
+                .write_unaligned(base + offset(addr_of!(rustos_ap_long_mode)) as u32);
+            trampoline
+                .field(addr_of!(rustos_ap_cr3))
+                .write(level_4.start_address().as_u64());
+        }
+
+        Some(trampoline)
+    }
+
+    fn base(&self) -> u32 {
+        self.frame.start_address().as_u64() as u32
+    }
+
+    // NOTE: where a symbol of the startup code ended up in the copy
+    fn field(&self, symbol: *const u8) -> *mut u64 {
+        let address = phys_to_virt(self.frame.start_address()) + offset(symbol) as u64;
+
+        address.as_mut_ptr()
+    }
+
+    // NOTE: the startup IPI vector, the page number the CPU starts executing at
+    fn vector(&self) -> u8 {
+        (self.base() >> 12) as u8
+    }
+
+    fn prepare(&self, index: usize, stack_top: VirtAddr) {
+        unsafe {
+            self.field(addr_of!(rustos_ap_stack))
+                .write(stack_top.as_u64());
+            self.field(addr_of!(rustos_ap_cpu_index))
+                .write(index as u64);
+            self.field(addr_of!(rustos_ap_entry))
+                .write(ap_main as *const () as u64);
+        }
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        let page = Page::containing_address(VirtAddr::new(self.frame.start_address().as_u64()));
+
+        if !self.mapped || paging::unmap(page).is_ok() {
+            unsafe { frame_allocator::free_frame(self.frame) };
+        }
+    }
+}
+
+fn offset(symbol: *const u8) -> usize {
+    symbol as usize - addr_of!(rustos_ap_trampoline) as usize
+}
+
+// NOTE: entered from the startup code on the stack prepared for CPU `index`, sets up the
+// CPU's own data, tables and timer, then turns into its idle thread
+extern "C" fn ap_main(index: u64) -> ! {
+    let index = index as usize;
+
+    percpu::init(index, 0);
+    gdt::init_ap();
+    syscall::init();
+    interrupts::init_ap();
+
+    let stack = AP_STACKS.lock()[index]
+        .take()
+        .expect("application processor has no stack");
+
+    thread::init_cpu(stack);
+    tlb::init_cpu();
+    STARTED.store(true, Ordering::Release);
+    x86_64::instructions::interrupts::enable();
+
+    thread::run_idle();
+}
+
+fn wait_started(us: u64) -> bool {
+    let deadline = time::monotonic_ns().saturating_add(us * 1000);
+
+    while time::monotonic_ns() < deadline {
+        if STARTED.load(Ordering::Acquire) {
+            return true;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    STARTED.load(Ordering::Acquire)
+}
+
+// NOTE: INIT, then up to two startup IPIs as the MP specification asks, false if the CPU did
+// not show up within STARTUP_TIMEOUT_US
+fn start(trampoline: &Trampoline, index: usize, apic_id: u8) -> bool {
+    let Ok(stack) = KernelStack::allocate("ap", AP_STACK_PAGES) else {
+        return false;
+    };
+
+    trampoline.prepare(index, stack.top());
+    AP_STACKS.lock()[index] = Some(stack);
+    STARTED.store(false, Ordering::Release);
+
+    apic::send_init(apic_id);
+    time::delay_us(10_000);
+    apic::send_startup(apic_id, trampoline.vector());
+
+    if !wait_started(200) {
+        apic::send_startup(apic_id, trampoline.vector());
+    }
+
+    if wait_started(STARTUP_TIMEOUT_US) {
+        return true;
+    }
+
+    // NOTE: the CPU may still come up late on this stack, it can't be freed
+    core::mem::forget(AP_STACKS.lock()[index].take());
+
+    false
+}
+
+// NOTE: enabled processors in the MADT besides the one running this
+fn application_processors() -> Vec<u8> {
+    let Some(madt) = Madt::find() else {
+        return Vec::new();
+    };
+    let boot = apic::id();
+
+    madt.entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::LocalApic { apic_id, flags, .. } if flags & 1 != 0 && apic_id != boot => {
+                Some(apic_id)
+            }
+            _ => None,
+        })
+        .take(MAX_CPUS - 1)
+        .collect()
+}
+
+// NOTE: starts every other CPU the firmware lists, one after another, once the boot CPU is
+// fully up. Needs the local APIC, which also carries the TLB shootdowns; a CPU that doesn't
+// respond is skipped and its index reused
+pub fn init() {
+    if !interrupts::has_apic_timer() {
+        return;
+    }
+
+    tlb::init_cpu();
+
+    let processors = application_processors();
+
+    if processors.is_empty() {
+        return;
+    }
+
+    let Some(trampoline) = Trampoline::install() else {
+        return log::warn!("smp: no memory below 1 MiB for the startup code");
+    };
+    let mut index = 1;
+
+    for apic_id in processors {
+        match start(&trampoline, index, apic_id) {
+            true => index += 1,
+            false => log::warn!("smp: CPU with APIC id {} did not start", apic_id),
+        }
+    }
+
+    log::info!("smp: {} CPUs online", percpu::online());
+}
+
+// NOTE: CPUs that have been started, the boot CPU included
+pub fn cpu_count() -> usize {
+    percpu::online()
+}
+
+#[test_case]
+fn test_trampoline_layout() {
+    let start = addr_of!(rustos_ap_trampoline);
+    let end = addr_of!(rustos_ap_trampoline_end);
+
+    assert!((end as usize - start as usize) < 4096);
+    assert_eq!(offset(addr_of!(rustos_ap_cr3)) % 8, 0);
+    assert_eq!(unsafe { *start }, 0xfa);
+}
@@ -0,0 +1,15 @@
+mod condvar;
+mod event_flags;
+mod irq_mutex;
+mod mutex;
+pub mod rcu;
+mod semaphore;
+mod wait_queue;
+
+pub use condvar::Condvar;
+pub use event_flags::{EventFlags, EventWait};
+pub use irq_mutex::{IrqMutex, IrqMutexGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use rcu::{Rcu, RcuReadGuard};
+pub use semaphore::Semaphore;
+pub use wait_queue::{WaitQueue, WaitUntil};
@@ -0,0 +1,95 @@
+use super::mutex::MutexGuard;
+use super::WaitQueue;
+use crate::thread;
+
+// NOTE: waits are queued before the mutex is released, so a notify between the release and
+// the sleep still wakes the waiter; wakeups can be spurious, check the condition in a loop or
+// use wait_while
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+
+        let Some(id) = thread::current_id() else {
+            drop(guard);
+            core::hint::spin_loop();
+
+            return mutex.lock();
+        };
+
+        self.waiters.enqueue(id);
+        drop(guard);
+        thread::block();
+        self.waiters.remove(id);
+
+        mutex.lock()
+    }
+
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Condvar {
+        Condvar::new()
+    }
+}
+
+#[test_case]
+fn test_producer_consumer() {
+    use super::Mutex;
+    use alloc::collections::VecDeque;
+
+    static QUEUE: Mutex<VecDeque<u32>> = Mutex::new(VecDeque::new());
+    static NOT_EMPTY: Condvar = Condvar::new();
+
+    let consumer = thread::spawn("consumer", || {
+        let mut sum = 0;
+
+        for _ in 0..10 {
+            let mut queue = NOT_EMPTY.wait_while(QUEUE.lock(), |queue| queue.is_empty());
+
+            sum += queue.pop_front().unwrap();
+        }
+
+        thread::exit(sum as i32);
+    })
+    .unwrap();
+
+    for value in 1..=10 {
+        QUEUE.lock().push_back(value);
+        NOT_EMPTY.notify_one();
+
+        if value % 3 == 0 {
+            thread::yield_now();
+        }
+    }
+
+    assert_eq!(consumer.join(), 55);
+}
@@ -0,0 +1,141 @@
+use super::WaitQueue;
+use core::future::Future;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventWait {
+    // NOTE: at least one of the bits of the mask is set
+    Any,
+    All,
+}
+
+// NOTE: 32 independent flags, e.g. one per completion a driver reports. Setting them never
+// blocks and works from interrupt handlers; waiters either leave the flags as they are (wait)
+// or clear the ones they matched (take), so each event is consumed once
+pub struct EventFlags {
+    bits: AtomicU32,
+    waiters: WaitQueue,
+}
+
+impl EventFlags {
+    pub const fn new() -> EventFlags {
+        EventFlags {
+            bits: AtomicU32::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.bits.load(Ordering::Acquire)
+    }
+
+    pub fn set(&self, mask: u32) {
+        self.bits.fetch_or(mask, Ordering::Release);
+        self.waiters.notify_all();
+    }
+
+    pub fn clear(&self, mask: u32) {
+        self.bits.fetch_and(!mask, Ordering::Release);
+    }
+
+    // NOTE: the matched bits of `mask`, cleared with `take`
+    fn try_match(&self, mask: u32, wait: EventWait, take: bool) -> Option<u32> {
+        let matches = |bits: u32| match wait {
+            EventWait::Any => bits & mask != 0,
+            EventWait::All => bits & mask == mask,
+        };
+        let bits = match take {
+            true => self
+                .bits
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                    matches(bits).then_some(bits & !mask)
+                })
+                .ok()?,
+            false => Some(self.get()).filter(|bits| matches(*bits))?,
+        };
+
+        Some(bits & mask)
+    }
+
+    pub fn try_wait(&self, mask: u32, wait: EventWait) -> Option<u32> {
+        self.try_match(mask, wait, false)
+    }
+
+    pub fn try_take(&self, mask: u32, wait: EventWait) -> Option<u32> {
+        self.try_match(mask, wait, true)
+    }
+
+    fn block(&self, mask: u32, wait: EventWait, take: bool) -> u32 {
+        let mut matched = 0;
+
+        self.waiters.wait_until(|| {
+            self.try_match(mask, wait, take)
+                .map(|bits| matched = bits)
+                .is_some()
+        });
+
+        matched
+    }
+
+    async fn wait_async_inner(&self, mask: u32, wait: EventWait, take: bool) -> u32 {
+        let mut matched = 0;
+
+        self.waiters
+            .wait_until_async(|| {
+                self.try_match(mask, wait, take)
+                    .map(|bits| matched = bits)
+                    .is_some()
+            })
+            .await;
+
+        matched
+    }
+
+    pub fn wait(&self, mask: u32, wait: EventWait) -> u32 {
+        self.block(mask, wait, false)
+    }
+
+    pub fn take(&self, mask: u32, wait: EventWait) -> u32 {
+        self.block(mask, wait, true)
+    }
+
+    pub fn wait_async(&self, mask: u32, wait: EventWait) -> impl Future<Output = u32> + '_ {
+        self.wait_async_inner(mask, wait, false)
+    }
+
+    pub fn take_async(&self, mask: u32, wait: EventWait) -> impl Future<Output = u32> + '_ {
+        self.wait_async_inner(mask, wait, true)
+    }
+}
+
+impl Default for EventFlags {
+    fn default() -> EventFlags {
+        EventFlags::new()
+    }
+}
+
+#[test_case]
+fn test_task_takes_flags() {
+    use crate::task::executor;
+    use core::sync::atomic::AtomicU32;
+
+    static FLAGS: EventFlags = EventFlags::new();
+    static TAKEN: AtomicU32 = AtomicU32::new(0);
+
+    executor::spawn(async {
+        let bits = FLAGS.take_async(0b110, EventWait::All).await;
+
+        TAKEN.store(bits, Ordering::Relaxed);
+    });
+
+    FLAGS.set(0b010);
+    executor::run_ready();
+    assert_eq!(TAKEN.load(Ordering::Relaxed), 0);
+
+    FLAGS.set(0b101);
+    executor::run_ready();
+    assert_eq!(TAKEN.load(Ordering::Relaxed), 0b110);
+    assert_eq!(FLAGS.get(), 0b001);
+    assert_eq!(FLAGS.try_wait(0b011, EventWait::Any), Some(0b001));
+    assert_eq!(FLAGS.try_take(0b010, EventWait::Any), None);
+}
@@ -0,0 +1,180 @@
+use crate::memory::tlb;
+use crate::percpu;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+// NOTE: spinlock for data shared with interrupt handlers, interrupts stay disabled while it is
+// held so a handler can never spin on a lock the code it interrupted holds
+pub struct IrqMutex<T: ?Sized> {
+    // NOTE: index of the CPU holding the lock, NO_OWNER while it is free
+    owner: AtomicUsize,
+    inner: Mutex<T>,
+}
+
+const NO_OWNER: usize = usize::MAX;
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> IrqMutex<T> {
+        IrqMutex {
+            owner: AtomicUsize::new(NO_OWNER),
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqMutex<T> {
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let enabled = interrupts::are_enabled();
+
+        interrupts::disable();
+
+        // NOTE: the holder may be waiting for this CPU in a TLB shootdown, which the IPI can't
+        // deliver with interrupts off
+        let guard = loop {
+            if let Some(guard) = self.inner.try_lock() {
+                break guard;
+            }
+
+            tlb::service();
+            core::hint::spin_loop();
+        };
+
+        self.owner
+            .store(percpu::current().index(), Ordering::Relaxed);
+
+        IrqMutexGuard {
+            guard: ManuallyDrop::new(guard),
+            owner: &self.owner,
+            enabled,
+        }
+    }
+
+    // NOTE: for exception handlers, which may have interrupted a holder of the lock
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let enabled = interrupts::are_enabled();
+
+        interrupts::disable();
+
+        match self.inner.try_lock() {
+            Some(guard) => {
+                self.owner
+                    .store(percpu::current().index(), Ordering::Relaxed);
+
+                Some(IrqMutexGuard {
+                    guard: ManuallyDrop::new(guard),
+                    owner: &self.owner,
+                    enabled,
+                })
+            }
+            None => {
+                if enabled {
+                    interrupts::enable();
+                }
+
+                None
+            }
+        }
+    }
+
+    // NOTE: for exception handlers as well, waits for a holder on another CPU and is None
+    // only when the code the exception interrupted on this CPU holds the lock
+    pub fn lock_unless_held(&self) -> Option<IrqMutexGuard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+
+            if self.owner.load(Ordering::Relaxed) == percpu::current().index() {
+                return None;
+            }
+
+            tlb::service();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Whoever holds the lock must never touch the data again, e.g. because it panicked.
+    pub unsafe fn force_unlock(&self) {
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.inner.force_unlock();
+    }
+}
+
+impl<T: Default> Default for IrqMutex<T> {
+    fn default() -> IrqMutex<T> {
+        IrqMutex::new(T::default())
+    }
+}
+
+// NOTE: restores the interrupt state from before the lock was taken, so nested guards leave
+// interrupts disabled until the outermost one goes
+pub struct IrqMutexGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    owner: &'a AtomicUsize,
+    enabled: bool,
+}
+
+impl<T: ?Sized> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // NOTE: unlocked before interrupts come back on
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_interrupt_state_restored() {
+    let mutex = IrqMutex::new(0);
+
+    interrupts::enable();
+
+    {
+        let mut outer = mutex.lock();
+
+        *outer += 1;
+        assert!(!interrupts::are_enabled());
+
+        let nested = IrqMutex::new(());
+
+        drop(nested.lock());
+        assert!(!interrupts::are_enabled());
+        assert!(mutex.try_lock().is_none());
+        assert!(mutex.lock_unless_held().is_none());
+    }
+
+    assert!(interrupts::are_enabled());
+    assert_eq!(*mutex.lock(), 1);
+
+    interrupts::disable();
+    drop(mutex.lock());
+    assert!(!interrupts::are_enabled());
+
+    interrupts::enable();
+}
@@ -0,0 +1,197 @@
+use super::Mutex;
+use crate::percpu;
+use crate::percpu::MAX_CPUS;
+use crate::thread;
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use x86_64::instructions::interrupts;
+
+// NOTE: read-copy-update for read-mostly data. Readers only load a pointer, with interrupts
+// disabled so they can't be preempted; interrupt handlers already are. A writer publishes a
+// new copy and frees the old one after a grace period, once every other CPU has gone through a
+// quiescent state: a timer tick, a context switch or an idle halt, none of which can happen
+// inside a read section
+pub struct Rcu<T> {
+    current: AtomicPtr<T>,
+    // NOTE: serializes writers, readers never take it
+    writer: Mutex<()>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+unsafe impl<T: Send> Send for Rcu<T> {}
+
+impl<T> Rcu<T> {
+    // NOTE: starts out empty so it can be a static, `set` fills it in
+    pub const fn new() -> Rcu<T> {
+        Rcu {
+            current: AtomicPtr::new(ptr::null_mut()),
+            writer: Mutex::new(()),
+        }
+    }
+
+    // NOTE: None while empty. Usable from interrupt context; the guard must not be held across
+    // anything that blocks or yields
+    pub fn read(&self) -> Option<RcuReadGuard<'_, T>> {
+        let enabled = interrupts::are_enabled();
+
+        interrupts::disable();
+
+        match unsafe { self.current.load(Ordering::Acquire).as_ref() } {
+            Some(value) => Some(RcuReadGuard {
+                value,
+                enabled,
+                _not_send: PhantomData,
+            }),
+            None => {
+                if enabled {
+                    interrupts::enable();
+                }
+
+                None
+            }
+        }
+    }
+
+    // NOTE: publishes what `f` makes of the current value, then waits out the readers of the
+    // old one and frees it. Blocks, so never from interrupt context or inside a read section
+    pub fn update(&self, f: impl FnOnce(Option<&T>) -> Option<T>) {
+        let writer = self.writer.lock();
+        let old = self.current.load(Ordering::Acquire);
+        let new = f(unsafe { old.as_ref() })
+            .map_or(ptr::null_mut(), |value| Box::into_raw(Box::new(value)));
+
+        self.current.store(new, Ordering::SeqCst);
+        drop(writer);
+
+        if !old.is_null() {
+            synchronize();
+            drop(unsafe { Box::from_raw(old) });
+        }
+    }
+
+    pub fn set(&self, value: T) {
+        self.update(|_| Some(value));
+    }
+
+    pub fn clear(&self) {
+        self.update(|_| None);
+    }
+}
+
+impl<T> Default for Rcu<T> {
+    fn default() -> Rcu<T> {
+        Rcu::new()
+    }
+}
+
+// NOTE: nothing can be reading through a shared reference while it is dropped
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        let current = *self.current.get_mut();
+
+        if !current.is_null() {
+            drop(unsafe { Box::from_raw(current) });
+        }
+    }
+}
+
+// NOTE: a read section, restores the interrupt state from before it on drop
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    enabled: bool,
+    // NOTE: the section belongs to the CPU it started on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+// NOTE: the calling CPU is outside any read section
+pub fn quiescent_state() {
+    percpu!(rcu.quiescent).fetch_add(1, Ordering::SeqCst);
+}
+
+// NOTE: around a halt, an idle CPU counts as quiescent without having to wake up for it. The
+// interrupt that ends the halt calls exit_idle before running any handler
+pub fn enter_idle() {
+    quiescent_state();
+    percpu!(rcu.idle).store(true, Ordering::SeqCst);
+}
+
+pub fn exit_idle() {
+    percpu!(rcu.idle).store(false, Ordering::SeqCst);
+}
+
+// NOTE: waits until every read section that started before the call has ended. The calling
+// CPU is outside one by definition, every other CPU has to report a quiescent state or be idle
+pub fn synchronize() {
+    let this = percpu::current().index();
+    let snapshot: [u64; MAX_CPUS] = core::array::from_fn(|index| {
+        percpu::cpu(index).map_or(0, |cpu| cpu.rcu.quiescent.load(Ordering::SeqCst))
+    });
+
+    for (index, quiescent) in snapshot.iter().enumerate().take(percpu::online()) {
+        let Some(cpu) = percpu::cpu(index).filter(|_| index != this) else {
+            continue;
+        };
+
+        while !cpu.rcu.idle.load(Ordering::SeqCst)
+            && cpu.rcu.quiescent.load(Ordering::SeqCst) == *quiescent
+        {
+            thread::yield_now();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[test_case]
+fn test_update_frees_old_copy() {
+    use core::sync::atomic::AtomicUsize;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Table(u32);
+
+    impl Drop for Table {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let rcu = Rcu::new();
+
+    assert!(rcu.read().is_none());
+    rcu.set(Table(1));
+
+    {
+        let table = rcu.read().unwrap();
+
+        assert_eq!(table.0, 1);
+        assert!(!interrupts::are_enabled());
+    }
+
+    assert!(interrupts::are_enabled());
+    rcu.update(|table| table.map(|table| Table(table.0 + 1)));
+    assert_eq!(rcu.read().unwrap().0, 2);
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+
+    rcu.clear();
+    assert!(rcu.read().is_none());
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,85 @@
+use super::WaitQueue;
+use core::future::Future;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// NOTE: counting semaphore, threads block in acquire and async tasks await acquire_async.
+// release never blocks, so interrupt handlers can use it to signal completions
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    pub fn acquire_async(&self) -> impl Future<Output = ()> + '_ {
+        self.waiters.wait_until_async(|| self.try_acquire())
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Semaphore {
+    fn default() -> Semaphore {
+        Semaphore::new(0)
+    }
+}
+
+#[test_case]
+fn test_threads_wait_for_permits() {
+    use crate::thread;
+
+    static PERMITS: Semaphore = Semaphore::new(0);
+    static DONE: Semaphore = Semaphore::new(0);
+
+    let workers: alloc::vec::Vec<_> = (0..3)
+        .map(|_| {
+            thread::spawn("semaphore", || {
+                PERMITS.acquire();
+                DONE.release();
+            })
+            .unwrap()
+        })
+        .collect();
+
+    thread::yield_now();
+    assert!(!DONE.try_acquire());
+
+    for _ in 0..3 {
+        PERMITS.release();
+    }
+
+    for _ in 0..3 {
+        DONE.acquire();
+    }
+
+    for worker in workers {
+        worker.join();
+    }
+
+    assert_eq!(PERMITS.available(), 0);
+}
@@ -0,0 +1,184 @@
+use super::IrqMutex;
+use crate::thread::{self, ThreadId};
+use alloc::collections::VecDeque;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+
+// NOTE: a sleeping thread, or an async task identified by the key it registered with
+enum Waiter {
+    Thread(ThreadId),
+    Task { key: u64, waker: Waker },
+}
+
+impl Waiter {
+    fn wake(self) -> bool {
+        match self {
+            Waiter::Thread(id) => thread::wake(id),
+            Waiter::Task { waker, .. } => {
+                waker.wake();
+
+                true
+            }
+        }
+    }
+}
+
+// NOTE: threads sleeping until another one reports a change; a thread is queued before it
+// checks its condition the last time, and thread::block keeps a wake that arrives before it
+// goes to sleep, so no notification is lost in between. Async tasks wait in the same queue
+// through wait_until_async. Notifying is fine from interrupt handlers
+pub struct WaitQueue {
+    waiters: IrqMutex<VecDeque<Waiter>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: IrqMutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(super) fn enqueue(&self, id: ThreadId) {
+        self.waiters.lock().push_back(Waiter::Thread(id));
+    }
+
+    pub(super) fn remove(&self, id: ThreadId) {
+        self.waiters
+            .lock()
+            .retain(|waiter| !matches!(waiter, Waiter::Thread(thread) if *thread == id));
+    }
+
+    fn register(&self, waker: &Waker) -> u64 {
+        static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+        let key = NEXT_KEY.fetch_add(1, Ordering::Relaxed);
+
+        self.waiters.lock().push_back(Waiter::Task {
+            key,
+            waker: waker.clone(),
+        });
+
+        key
+    }
+
+    // NOTE: false if the task was notified in the meantime
+    fn unregister(&self, key: u64) -> bool {
+        let mut waiters = self.waiters.lock();
+        let position = waiters
+            .iter()
+            .position(|waiter| matches!(waiter, Waiter::Task { key: task, .. } if *task == key));
+
+        position
+            .and_then(|position| waiters.remove(position))
+            .is_some()
+    }
+
+    // NOTE: sleeps until `condition` holds, checking it again after every notification.
+    // Before threads are set up this spins instead
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        while !condition() {
+            let Some(id) = thread::current_id() else {
+                core::hint::spin_loop();
+
+                continue;
+            };
+
+            self.enqueue(id);
+
+            if !condition() {
+                thread::block();
+            }
+
+            self.remove(id);
+        }
+    }
+
+    // NOTE: the future form of wait_until, for async tasks
+    pub fn wait_until_async<F: FnMut() -> bool>(&self, condition: F) -> WaitUntil<'_, F> {
+        WaitUntil {
+            queue: self,
+            condition,
+            key: None,
+        }
+    }
+
+    // NOTE: wakes the longest waiting thread or task, false if there was none
+    pub fn notify_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+
+        waiter.is_some_and(Waiter::wake)
+    }
+
+    pub fn notify_all(&self) -> usize {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+
+        waiters
+            .into_iter()
+            .map(Waiter::wake)
+            .filter(|woken| *woken)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> WaitQueue {
+        WaitQueue::new()
+    }
+}
+
+pub struct WaitUntil<'a, F> {
+    queue: &'a WaitQueue,
+    condition: F,
+    // NOTE: set while registered with the queue
+    key: Option<u64>,
+}
+
+impl<F: FnMut() -> bool + Unpin> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+
+        if let Some(key) = this.key.take() {
+            this.queue.unregister(key);
+        }
+
+        if (this.condition)() {
+            return Poll::Ready(());
+        }
+
+        let key = this.queue.register(context.waker());
+
+        // NOTE: checked again once registered, for a change that came in between
+        if (this.condition)() {
+            this.queue.unregister(key);
+
+            return Poll::Ready(());
+        }
+
+        this.key = Some(key);
+
+        Poll::Pending
+    }
+}
+
+// NOTE: a notification this task got but won't act on goes to the next waiter
+impl<F> Drop for WaitUntil<'_, F> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            if !self.queue.unregister(key) {
+                self.queue.notify_one();
+            }
+        }
+    }
+}
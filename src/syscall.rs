@@ -0,0 +1,1020 @@
+pub mod abi;
+pub mod user;
+
+use crate::fs::{self, FsError, OpenFlags, SeekFrom};
+use crate::futex::FutexError;
+use crate::percpu::{PerCpu, SyscallState};
+use crate::pipe::PipeError;
+use crate::process::{
+    self, Action, ArgumentError, Arguments, File, ProcessError, ProcessId, SignalError, VmaError,
+};
+use crate::shm::{self, ShmError};
+use crate::signal::{self, UserContext};
+use crate::tty::{self, Mode};
+use crate::uaccess::{self, AccessError};
+use crate::{futex, gdt, pipe, thread, usermode};
+use abi::Error;
+use core::arch::{asm, global_asm};
+use core::mem::offset_of;
+use core::time::Duration;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+// NOTE: syscall leaves the user rsp and GS base in place, the stub swaps in the per CPU GS base
+// and the thread's kernel stack from the per CPU data and saves all the user registers but rax
+// in a SyscallFrame, the callee saved ones so fork can start a child from it. Interrupts are
+// masked on entry and enabled once on the kernel stack, so a syscall can block or be
+// preempted. rustos_syscall_return goes back to ring 3 from a frame on top of the stack with
+// the result in its first slot, swapping the user GS base back in
+global_asm!(
+    ".global rustos_syscall_entry",
+    "rustos_syscall_entry:",
+    "swapgs",
+    "mov gs:[{user_rsp}], rsp",
+    "mov rsp, gs:[{kernel_rsp}]",
+    "push qword ptr gs:[{user_rsp}]",
+    "push rcx",
+    "push r11",
+    "push r15",
+    "push r14",
+    "push r13",
+    "push r12",
+    "push rbp",
+    "push rbx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "sti",
+    "mov rdi, rsp",
+    "call {dispatch}",
+    "mov [rsp], rax",
+    ".global rustos_syscall_return",
+    "rustos_syscall_return:",
+    "cli",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rbx",
+    "pop rbp",
+    "pop r12",
+    "pop r13",
+    "pop r14",
+    "pop r15",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "swapgs",
+    "sysretq",
+    user_rsp = const offset_of!(PerCpu, syscall) + offset_of!(SyscallState, user_rsp),
+    kernel_rsp = const offset_of!(PerCpu, syscall) + offset_of!(SyscallState, kernel_rsp),
+    dispatch = sym syscall_dispatch,
+);
+
+// NOTE: user buffers are copied into the kernel, these bound how much a single call takes
+const MAX_WRITE: usize = 64 * 1024;
+const MAX_EXEC_SIZE: usize = 16 * 1024 * 1024;
+
+extern "C" {
+    fn rustos_syscall_entry();
+    fn rustos_syscall_return();
+}
+
+// NOTE: as the entry stub pushed it, lowest address first
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SyscallFrame {
+    number: u64,
+    arguments: [u64; 6],
+    // NOTE: rbx, rbp and r12 to r15
+    callee_saved: [u64; 6],
+    rflags: u64,
+    rip: u64,
+    rsp: u64,
+}
+
+impl SyscallFrame {
+    // NOTE: the registers as sysret would leave them, rcx and r11 holding rip and rflags
+    fn context(&self, result: i64) -> UserContext {
+        let [rdi, rsi, rdx, r10, r8, r9] = self.arguments;
+        let [rbx, rbp, r12, r13, r14, r15] = self.callee_saved;
+
+        UserContext {
+            r15,
+            r14,
+            r13,
+            r12,
+            r11: self.rflags,
+            r10,
+            r9,
+            r8,
+            rbp,
+            rdi,
+            rsi,
+            rdx,
+            rcx: self.rip,
+            rbx,
+            rax: result as u64,
+            rip: self.rip,
+            cs: gdt::USER_CODE_SELECTOR.0 as u64,
+            rflags: self.rflags,
+            rsp: self.rsp,
+            ss: gdt::USER_DATA_SELECTOR.0 as u64,
+        }
+    }
+}
+
+// NOTE: a signal to deliver turns the return into an iretq from a full context
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> i64 {
+    let result = match frame.number {
+        abi::SYS_FORK => fork(frame).map_or_else(Error::to_result, |id| id as i64),
+        abi::SYS_SIGRETURN => signal::sigreturn(frame.rsp),
+        _ => dispatch(frame.number, frame.arguments),
+    };
+
+    if signal::has_deliverable() {
+        let mut context = frame.context(result);
+
+        signal::deliver(&mut context);
+
+        unsafe { signal::resume(&context) };
+    }
+
+    result
+}
+
+fn dispatch(number: u64, arguments: [u64; 6]) -> i64 {
+    let result = match number {
+        abi::SYS_EXIT => thread::exit(arguments[0] as i32),
+        abi::SYS_WRITE => write(arguments[0], arguments[1], arguments[2] as usize),
+        abi::SYS_SLEEP => match thread::sleep_interruptible(Duration::from_millis(arguments[0])) {
+            true => Ok(0),
+            false => Err(Error::Interrupted),
+        },
+        abi::SYS_EXEC => exec(
+            arguments[0],
+            arguments[1] as usize,
+            arguments[2],
+            arguments[3],
+        ),
+        abi::SYS_WAIT => wait(arguments[0]),
+        abi::SYS_WAITPID => waitpid(arguments[0], arguments[1], arguments[2]),
+        abi::SYS_GETPID => process::current()
+            .map(|process| process.id().as_u64())
+            .ok_or(Error::NoSuchCall),
+        abi::SYS_GETPPID => process::current()
+            .map(|process| process.parent().map_or(0, |parent| parent.id().as_u64()))
+            .ok_or(Error::NoSuchCall),
+        abi::SYS_BRK => brk(arguments[0]),
+        abi::SYS_MMAP => mmap(arguments[0], arguments[1]),
+        abi::SYS_MUNMAP => munmap(arguments[0], arguments[1]),
+        abi::SYS_READ => read(arguments[0], arguments[1], arguments[2] as usize),
+        abi::SYS_PIPE => pipe(arguments[0]),
+        abi::SYS_CLOSE => close(arguments[0]),
+        abi::SYS_DUP2 => dup2(arguments[0], arguments[1]),
+        abi::SYS_KILL => kill(arguments[0], arguments[1]),
+        abi::SYS_SIGACTION => sigaction(arguments[0], arguments[1]),
+        abi::SYS_SIGMASK => sigmask(arguments[0]),
+        abi::SYS_FUTEX_WAIT => futex_wait(arguments[0], arguments[1]),
+        abi::SYS_FUTEX_WAKE => futex_wake(arguments[0], arguments[1]),
+        abi::SYS_SHM_OPEN => shm_open(arguments[0], arguments[1], arguments[2], arguments[3]),
+        abi::SYS_SHM_MAP => shm_map(arguments[0], arguments[1]),
+        abi::SYS_SHM_UNLINK => shm_unlink(arguments[0], arguments[1]),
+        abi::SYS_OPEN => open(arguments[0], arguments[1], arguments[2]),
+        abi::SYS_READDIR => readdir(arguments[0], arguments[1], arguments[2] as usize),
+        abi::SYS_SEEK => seek(arguments[0], arguments[1] as i64, arguments[2]),
+        abi::SYS_MKDIR => mkdir(arguments[0], arguments[1]),
+        abi::SYS_UNLINK => unlink(arguments[0], arguments[1]),
+        abi::SYS_RENAME => rename(arguments[0], arguments[1], arguments[2], arguments[3]),
+        abi::SYS_TTYMODE => Ok(tty::console()
+            .set_mode(Mode::from_bits(arguments[0]))
+            .bits()),
+        _ => Err(Error::NoSuchCall),
+    };
+
+    result.map_or_else(Error::to_result, |value| value as i64)
+}
+
+impl From<ProcessError> for Error {
+    fn from(error: ProcessError) -> Error {
+        match error {
+            ProcessError::Exited | ProcessError::Busy => Error::Busy,
+            ProcessError::NoChild => Error::NoChild,
+            ProcessError::Interrupted => Error::Interrupted,
+            ProcessError::Map(_) | ProcessError::Stack(_) => Error::NoMemory,
+            ProcessError::Elf(_) => Error::BadExecutable,
+            ProcessError::Vma(error) => error.into(),
+        }
+    }
+}
+
+impl From<AccessError> for Error {
+    fn from(error: AccessError) -> Error {
+        match error {
+            AccessError::TooLong => Error::InvalidArgument,
+            _ => Error::BadAddress,
+        }
+    }
+}
+
+impl From<FutexError> for Error {
+    fn from(error: FutexError) -> Error {
+        match error {
+            FutexError::Unaligned => Error::InvalidArgument,
+            FutexError::WouldBlock => Error::WouldBlock,
+            FutexError::Interrupted => Error::Interrupted,
+            FutexError::Access(error) => error.into(),
+        }
+    }
+}
+
+impl From<PipeError> for Error {
+    fn from(error: PipeError) -> Error {
+        match error {
+            PipeError::Closed => Error::BrokenPipe,
+            PipeError::Interrupted => Error::Interrupted,
+        }
+    }
+}
+
+impl From<ShmError> for Error {
+    fn from(error: ShmError) -> Error {
+        match error {
+            ShmError::BadName | ShmError::BadSize => Error::InvalidArgument,
+            ShmError::NotFound => Error::NotFound,
+            ShmError::Exists => Error::Exists,
+            ShmError::NoMemory => Error::NoMemory,
+        }
+    }
+}
+
+impl From<FsError> for Error {
+    fn from(error: FsError) -> Error {
+        match error {
+            FsError::NotFound => Error::NotFound,
+            FsError::NotDirectory => Error::NotDirectory,
+            FsError::IsDirectory => Error::IsDirectory,
+            FsError::Exists => Error::Exists,
+            FsError::NotEmpty => Error::NotEmpty,
+            FsError::ReadOnly | FsError::AccessDenied => Error::PermissionDenied,
+            FsError::InvalidPath | FsError::BadOffset | FsError::Unsupported => {
+                Error::InvalidArgument
+            }
+            FsError::NameTooLong => Error::NameTooLong,
+            FsError::CrossDevice => Error::CrossDevice,
+            FsError::Busy => Error::Busy,
+            FsError::NoSpace => Error::NoSpace,
+            FsError::Io | FsError::Corrupt => Error::Io,
+            FsError::Interrupted => Error::Interrupted,
+        }
+    }
+}
+
+impl From<ArgumentError> for Error {
+    fn from(_: ArgumentError) -> Error {
+        Error::InvalidArgument
+    }
+}
+
+impl From<VmaError> for Error {
+    fn from(error: VmaError) -> Error {
+        match error {
+            VmaError::BadRange => Error::InvalidArgument,
+            VmaError::OutOfAddressSpace => Error::NoMemory,
+        }
+    }
+}
+
+impl From<SignalError> for Error {
+    fn from(_: SignalError) -> Error {
+        Error::InvalidArgument
+    }
+}
+
+fn user_address(address: u64) -> Result<VirtAddr, Error> {
+    VirtAddr::try_new(address).map_err(|_| Error::InvalidArgument)
+}
+
+// NOTE: unlike Linux a break that can't be set is an error rather than the old break
+fn brk(address: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let brk = match address {
+        0 => None,
+        address => Some(user_address(address)?),
+    };
+
+    Ok(process.brk(brk)?.as_u64())
+}
+
+fn prot_flags(prot: u64) -> Result<PageTableFlags, Error> {
+    if prot & !(abi::PROT_READ | abi::PROT_WRITE | abi::PROT_EXEC) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut flags = PageTableFlags::empty();
+
+    if prot & abi::PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    if prot & abi::PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    Ok(flags)
+}
+
+fn mmap(len: u64, prot: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+
+    Ok(process.map_anonymous(len, prot_flags(prot)?)?.as_u64())
+}
+
+fn munmap(address: u64, len: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+
+    process.unmap(user_address(address)?, len)?;
+
+    Ok(0)
+}
+
+// NOTE: the child gets its first thread started from a copy of the parent's frame, with 0 as
+// the result where the parent gets the child's id
+fn fork(frame: &SyscallFrame) -> Result<u64, Error> {
+    let parent = process::current().ok_or(Error::NoSuchCall)?;
+    let mut child_frame = *frame;
+
+    child_frame.number = 0;
+
+    let child = parent.fork(move || unsafe { resume(child_frame) })?;
+
+    Ok(child.id().as_u64())
+}
+
+// NOTE: back to ring 3 through the stub's exit path, popping `frame` where it lies on this
+// thread's kernel stack; whatever is above it is left behind for good
+unsafe fn resume(frame: SyscallFrame) -> ! {
+    let top = thread::kernel_stack_top().expect("ring 3 needs a thread's kernel stack");
+
+    gdt::set_kernel_stack(top);
+
+    asm!(
+        "mov rsp, {frame}",
+        "jmp {exit}",
+        frame = in(reg) &frame,
+        exit = sym rustos_syscall_return,
+        options(noreturn)
+    );
+}
+
+// NOTE: the executable is an image in the caller's memory, not a path. Callers read it from
+// wherever it is stored, /initrd for one. Only returns on failure, with the old program still
+// in place
+fn exec(address: u64, len: usize, argv: u64, envp: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+
+    if len > MAX_EXEC_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut arguments = Arguments::new();
+
+    read_strings(argv, |arg| arguments.push_arg(arg))?;
+    read_strings(envp, |var| arguments.push_env(var))?;
+
+    let data = uaccess::read_user(address, len)?;
+    let image = process.exec(&data, &arguments)?;
+
+    drop(arguments);
+    drop(data);
+    drop(process);
+
+    unsafe { usermode::enter(image.entry, image.stack_top) };
+}
+
+// NOTE: a null terminated array of pointers to strings, 0 for none. Every string takes room
+// from MAX_ARGUMENTS_SIZE, so `push` failing ends a runaway array
+fn read_strings(
+    array: u64,
+    mut push: impl FnMut(&[u8]) -> Result<(), ArgumentError>,
+) -> Result<(), Error> {
+    if array == 0 {
+        return Ok(());
+    }
+
+    for index in 0.. {
+        let address = array.checked_add(index * 8).ok_or(Error::BadAddress)?;
+        let string = match uaccess::read_value::<u64>(address)? {
+            0 => return Ok(()),
+            string => uaccess::read_string(string, process::MAX_ARGUMENTS_SIZE)?,
+        };
+
+        push(&string)?;
+    }
+
+    Ok(())
+}
+
+fn wait(id: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoChild)?;
+    let code = process.wait_child(ProcessId::from_u64(id))?;
+
+    Ok(code as u32 as u64)
+}
+
+fn waitpid(id: u64, status: u64, flags: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoChild)?;
+
+    if flags & !abi::WNOHANG != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let id = match id {
+        abi::WAIT_ANY => None,
+        id => Some(ProcessId::from_u64(id)),
+    };
+    let Some((child, code)) = process.reap(id, flags & abi::WNOHANG == 0)? else {
+        return Ok(0);
+    };
+
+    // NOTE: the child is reaped either way, like on Linux its status is lost with a bad pointer
+    if status != 0 {
+        uaccess::write_value(status, &code)?;
+    }
+
+    Ok(child.as_u64())
+}
+
+fn file(descriptor: u64) -> Result<File, Error> {
+    process::current()
+        .and_then(|process| process.files().get(descriptor as usize))
+        .ok_or(Error::BadDescriptor)
+}
+
+// NOTE: through the calling process's descriptors. At most MAX_WRITE bytes go out per call, the
+// result says how many
+fn write(descriptor: u64, address: u64, len: usize) -> Result<u64, Error> {
+    let file = file(descriptor)?;
+    let bytes = uaccess::read_user(address, len.min(MAX_WRITE))?;
+
+    let written = match file {
+        File::Console => tty::console().write(&bytes),
+        File::PipeWrite(writer) => writer.write(&bytes)?,
+        File::Vfs(file) => file.write(&bytes)?,
+        File::PipeRead(_) | File::SharedMemory { .. } => return Err(Error::BadDescriptor),
+    };
+
+    Ok(written as u64)
+}
+
+// NOTE: at most MAX_WRITE bytes per call as well
+fn read(descriptor: u64, address: u64, len: usize) -> Result<u64, Error> {
+    let file = file(descriptor)?;
+    let mut buffer = alloc::vec![0; len.min(MAX_WRITE)];
+
+    let read = match file {
+        File::Console => tty::console()
+            .read(&mut buffer)
+            .map_err(|_| Error::Interrupted)?,
+        // NOTE: the bytes stay in the pipe if the copy faults
+        File::PipeRead(reader) => {
+            return reader
+                .read_with(&mut buffer, |bytes| {
+                    uaccess::copy_to_user(address, bytes).map_err(Error::from)
+                })
+                .map(|read| read as u64);
+        }
+        File::Vfs(file) => file.read(&mut buffer)?,
+        File::PipeWrite(_) | File::SharedMemory { .. } => return Err(Error::BadDescriptor),
+    };
+
+    uaccess::copy_to_user(address, &buffer[..read])?;
+
+    Ok(read as u64)
+}
+
+fn pipe(address: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadDescriptor)?;
+    let (reader, writer) = pipe::pipe();
+    let mut files = process.files();
+    let read = files
+        .insert(File::PipeRead(reader))
+        .ok_or(Error::TooManyFiles)?;
+    let Some(write) = files.insert(File::PipeWrite(writer)) else {
+        files.close(read);
+
+        return Err(Error::TooManyFiles);
+    };
+
+    drop(files);
+
+    if let Err(error) = uaccess::write_value(address, &[read as u32, write as u32]) {
+        let mut files = process.files();
+
+        files.close(read);
+        files.close(write);
+
+        return Err(error.into());
+    }
+
+    Ok(0)
+}
+
+fn close(descriptor: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadDescriptor)?;
+    let file = process.files().close(descriptor as usize);
+
+    file.map(|_| 0).ok_or(Error::BadDescriptor)
+}
+
+fn dup2(source: u64, target: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadDescriptor)?;
+    let replaced = process
+        .files()
+        .duplicate(source as usize, target as usize)
+        .map_err(|_| Error::BadDescriptor)?;
+
+    drop(replaced);
+
+    Ok(target)
+}
+
+fn shm_name(address: u64, len: u64) -> Result<alloc::vec::Vec<u8>, Error> {
+    if len == 0 || len > shm::MAX_NAME as u64 {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(uaccess::read_user(address, len as usize)?)
+}
+
+fn shm_open(address: u64, len: u64, size: u64, flags: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadDescriptor)?;
+
+    if flags & !(abi::SHM_CREATE | abi::SHM_EXCLUSIVE | abi::SHM_WRITE) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let name = shm_name(address, len)?;
+    let object = match flags & abi::SHM_CREATE {
+        0 => shm::open(&name)?,
+        _ => shm::create(&name, size, flags & abi::SHM_EXCLUSIVE != 0)?,
+    };
+    let file = File::SharedMemory {
+        object,
+        writable: flags & abi::SHM_WRITE != 0,
+    };
+    let descriptor = process.files().insert(file).ok_or(Error::TooManyFiles)?;
+
+    Ok(descriptor as u64)
+}
+
+fn shm_map(descriptor: u64, prot: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let File::SharedMemory { object, writable } = file(descriptor)? else {
+        return Err(Error::BadDescriptor);
+    };
+
+    if prot & abi::PROT_WRITE != 0 && !writable {
+        return Err(Error::PermissionDenied);
+    }
+
+    Ok(process.map_shared(&object, prot_flags(prot)?)?.as_u64())
+}
+
+fn shm_unlink(address: u64, len: u64) -> Result<u64, Error> {
+    shm::unlink(&shm_name(address, len)?)?;
+
+    Ok(0)
+}
+
+fn path(address: u64, len: u64) -> Result<alloc::string::String, Error> {
+    match len {
+        0 => return Err(Error::InvalidArgument),
+        len if len > fs::MAX_PATH as u64 => return Err(Error::NameTooLong),
+        _ => {}
+    }
+
+    alloc::string::String::from_utf8(uaccess::read_user(address, len as usize)?)
+        .map_err(|_| Error::InvalidArgument)
+}
+
+fn open(address: u64, len: u64, flags: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadDescriptor)?;
+    let open_flags = OpenFlags::from_bits(flags);
+
+    if open_flags.bits() != flags {
+        return Err(Error::InvalidArgument);
+    }
+
+    let file = fs::vfs().open(&path(address, len)?, open_flags)?;
+    let descriptor = process
+        .files()
+        .insert(File::Vfs(file))
+        .ok_or(Error::TooManyFiles)?;
+
+    Ok(descriptor as u64)
+}
+
+fn readdir(descriptor: u64, address: u64, len: usize) -> Result<u64, Error> {
+    let File::Vfs(file) = file(descriptor)? else {
+        return Err(Error::NotDirectory);
+    };
+    let mut buffer = alloc::vec::Vec::new();
+
+    // NOTE: an entry that doesn't fit is put back for the next call
+    while let Some(entry) = file.read_dir()? {
+        let kind = match entry.kind {
+            fs::InodeKind::File => abi::DIRENT_FILE,
+            fs::InodeKind::Directory => abi::DIRENT_DIRECTORY,
+            fs::InodeKind::Device => abi::DIRENT_DEVICE,
+        };
+
+        if buffer.len() + 2 + entry.name.len() > len.min(MAX_WRITE) {
+            file.seek(SeekFrom::Current(-1))?;
+
+            if buffer.is_empty() {
+                return Err(Error::InvalidArgument);
+            }
+
+            break;
+        }
+
+        buffer.extend_from_slice(&[kind, entry.name.len() as u8]);
+        buffer.extend_from_slice(entry.name.as_bytes());
+    }
+
+    uaccess::copy_to_user(address, &buffer)?;
+
+    Ok(buffer.len() as u64)
+}
+
+fn seek(descriptor: u64, offset: i64, whence: u64) -> Result<u64, Error> {
+    let File::Vfs(file) = file(descriptor)? else {
+        return Err(Error::BadDescriptor);
+    };
+    let position = match whence {
+        abi::SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+        abi::SEEK_CUR => SeekFrom::Current(offset),
+        abi::SEEK_END => SeekFrom::End(offset),
+        _ => return Err(Error::InvalidArgument),
+    };
+
+    Ok(file.seek(position)?)
+}
+
+fn mkdir(address: u64, len: u64) -> Result<u64, Error> {
+    fs::vfs().mkdir(&path(address, len)?)?;
+
+    Ok(0)
+}
+
+fn unlink(address: u64, len: u64) -> Result<u64, Error> {
+    fs::vfs().unlink(&path(address, len)?)?;
+
+    Ok(0)
+}
+
+fn rename(from: u64, from_len: u64, to: u64, to_len: u64) -> Result<u64, Error> {
+    fs::vfs().rename(&path(from, from_len)?, &path(to, to_len)?)?;
+
+    Ok(0)
+}
+
+fn kill(id: u64, number: u64) -> Result<u64, Error> {
+    let target = process::find(ProcessId::from_u64(id)).ok_or(Error::NoSuchProcess)?;
+
+    if target.has_exited() {
+        return Err(Error::NoSuchProcess);
+    }
+
+    target.signal(number)?;
+
+    Ok(0)
+}
+
+fn sigaction(number: u64, handler: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let action = match handler {
+        abi::SIG_DFL => Action::Default,
+        abi::SIG_IGN => Action::Ignore,
+        handler if handler < uaccess::USER_END => Action::Handler(VirtAddr::new(handler)),
+        _ => return Err(Error::InvalidArgument),
+    };
+    let old = process.signals().set_action(number, action)?;
+
+    Ok(match old {
+        Action::Default => abi::SIG_DFL,
+        Action::Ignore => abi::SIG_IGN,
+        Action::Handler(handler) => handler.as_u64(),
+    })
+}
+
+fn sigmask(blocked: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let old = process.signals().set_blocked(blocked);
+
+    Ok(old)
+}
+
+fn futex_wait(address: u64, expected: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let expected = u32::try_from(expected).map_err(|_| Error::InvalidArgument)?;
+    let id = process.id();
+
+    drop(process);
+    futex::wait(id, address, expected)?;
+
+    Ok(0)
+}
+
+fn futex_wake(address: u64, count: u64) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::NoSuchCall)?;
+    let woken = futex::wake(process.id(), address, count as usize)?;
+
+    Ok(woken as u64)
+}
+
+// NOTE: per CPU, after the GDT since STAR refers to its selectors
+pub fn init() {
+    let mask = RFlags::INTERRUPT_FLAG
+        | RFlags::DIRECTION_FLAG
+        | RFlags::TRAP_FLAG
+        | RFlags::ALIGNMENT_CHECK;
+
+    Star::write(
+        gdt::USER_CODE_SELECTOR,
+        gdt::USER_DATA_SELECTOR,
+        gdt::KERNEL_CODE_SELECTOR,
+        gdt::KERNEL_DATA_SELECTOR,
+    )
+    .expect("GDT layout doesn't fit sysret");
+    LStar::write(VirtAddr::new(rustos_syscall_entry as *const () as u64));
+    SFMask::write(mask);
+
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+#[test_case]
+fn test_write_and_exit() {
+    // NOTE: write(STDOUT, "hi", 2), then exit with what it returned
+    let program = [
+        0x48, 0x8d, 0x35, 0x1a, 0x00, 0x00, 0x00, // lea rsi, [rip + 26]
+        0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+        0xba, 0x02, 0x00, 0x00, 0x00, // mov edx, 2
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+        0x0f, 0x05, // syscall
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, 0
+        0x0f, 0x05, // syscall
+        b'h', b'i',
+    ];
+
+    assert_eq!(usermode::spawn("write", &program).unwrap().wait(), 2);
+}
+
+#[test_case]
+fn test_unknown_syscall() {
+    let program = [
+        0xb8, 0x63, 0x00, 0x00, 0x00, // mov eax, 99
+        0x0f, 0x05, // syscall
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, 0
+        0x0f, 0x05, // syscall
+    ];
+
+    assert_eq!(
+        usermode::spawn("unknown", &program).unwrap().wait(),
+        Error::NoSuchCall.to_result() as i32
+    );
+    assert_eq!(dispatch(abi::SYS_WRITE, [7, 0, 0, 0, 0, 0]), -2);
+}
+
+#[test_case]
+fn test_fork_and_wait() {
+    // NOTE: the child overwrites a value on the shared stack and exits with it, the parent
+    // still sees its own copy and exits with the sum of both
+    let program = [
+        0x6a, 0x05, // push 5
+        0xb8, 0x03, 0x00, 0x00, 0x00, // mov eax, SYS_FORK
+        0x0f, 0x05, // syscall
+        0x48, 0x85, 0xc0, // test rax, rax
+        0x75, 0x10, // jnz parent
+        0x48, 0xc7, 0x04, 0x24, 0x07, 0x00, 0x00, 0x00, // mov qword [rsp], 7
+        0x5f, // pop rdi
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+        0x48, 0x89, 0xc7, // parent: mov rdi, rax
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, SYS_WAIT
+        0x0f, 0x05, // syscall
+        0x5f, // pop rdi
+        0x01, 0xc7, // add edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let parent = usermode::spawn("fork", &program).unwrap();
+
+    assert_eq!(parent.wait(), 12);
+    assert!(parent.children().is_empty());
+}
+
+#[test_case]
+fn test_exec() {
+    // NOTE: exec(image, len, 0, 0), exit with the error if it comes back
+    let mut program = alloc::vec![
+        0x48, 0x8d, 0x35, 0x1d, 0x00, 0x00, 0x00, // lea rsi, [rip + 29]
+        0x48, 0x89, 0xf7, // mov rdi, rsi
+        0xbe, 0x00, 0x00, 0x00, 0x00, // mov esi, len
+        0x31, 0xd2, // xor edx, edx
+        0x45, 0x31, 0xd2, // xor r10d, r10d
+        0xb8, 0x04, 0x00, 0x00, 0x00, // mov eax, SYS_EXEC
+        0x0f, 0x05, // syscall
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let exit_42 = [
+        0xbf, 0x2a, 0x00, 0x00, 0x00, // mov edi, 42
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let image = crate::elf::build_executable(&exit_42, crate::elf::PF_R | crate::elf::PF_X, 0);
+
+    program[11..15].copy_from_slice(&(image.len() as u32).to_le_bytes());
+
+    let mut truncated = program.clone();
+
+    truncated[11..15].copy_from_slice(&32u32.to_le_bytes());
+    truncated.extend_from_slice(&image[..32]);
+    program.extend_from_slice(&image);
+
+    assert_eq!(usermode::spawn("exec", &program).unwrap().wait(), 42);
+    assert_eq!(
+        usermode::spawn("exec", &truncated).unwrap().wait(),
+        Error::BadExecutable.to_result() as i32
+    );
+}
+
+#[test_case]
+fn test_exec_passes_arguments() {
+    // NOTE: exec(image, len, argv, 0) with argv = ["(", "("] on the stack; the new program
+    // exits with argc plus the first byte of argv[1]
+    let mut program = alloc::vec![
+        0x48, 0x8d, 0x3d, 0x25, 0x00, 0x00, 0x00, // lea rdi, [rip + 37]
+        0x48, 0x8d, 0x05, 0x1c, 0x00, 0x00, 0x00, // lea rax, [rip + 28]
+        0x6a, 0x00, // push 0
+        0x50, // push rax
+        0x50, // push rax
+        0x48, 0x89, 0xe2, // mov rdx, rsp
+        0x45, 0x31, 0xd2, // xor r10d, r10d
+        0xbe, 0x00, 0x00, 0x00, 0x00, // mov esi, len
+        0xb8, 0x04, 0x00, 0x00, 0x00, // mov eax, SYS_EXEC
+        0x0f, 0x05, // syscall
+        0x89, 0xc7, // mov edi, eax
+        0x31, 0xc0, // xor eax, eax
+        0x0f, 0x05, // syscall
+        b'(', 0x00,
+    ];
+    let argc_plus_arg = [
+        0x48, 0x8b, 0x3c, 0x24, // mov rdi, [rsp]
+        0x48, 0x8b, 0x44, 0x24, 0x10, // mov rax, [rsp + 16]
+        0x0f, 0xb6, 0x00, // movzx eax, byte [rax]
+        0x01, 0xc7, // add edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let image =
+        crate::elf::build_executable(&argc_plus_arg, crate::elf::PF_R | crate::elf::PF_X, 0);
+
+    program[25..29].copy_from_slice(&(image.len() as u32).to_le_bytes());
+    program.extend_from_slice(&image);
+
+    assert_eq!(usermode::spawn("argv", &program).unwrap().wait(), 42);
+}
+
+#[test_case]
+fn test_brk_and_mmap() {
+    // NOTE: grows the heap by two pages and writes its last word, maps a page and writes
+    // that, then exits with the sum of both
+    let program = [
+        0x31, 0xff, // xor edi, edi
+        0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_BRK
+        0x0f, 0x05, // syscall
+        0x48, 0x89, 0xc3, // mov rbx, rax
+        0x48, 0x8d, 0xb8, 0x00, 0x20, 0x00, 0x00, // lea rdi, [rax + 0x2000]
+        0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_BRK
+        0x0f, 0x05, // syscall
+        0x48, 0xc7, 0x83, 0xf8, 0x1f, 0x00, 0x00, 0x05, 0x00, 0x00,
+        0x00, // mov [rbx+0x1ff8], 5
+        0xbf, 0x00, 0x10, 0x00, 0x00, // mov edi, 4096
+        0xbe, 0x03, 0x00, 0x00, 0x00, // mov esi, PROT_READ | PROT_WRITE
+        0xb8, 0x07, 0x00, 0x00, 0x00, // mov eax, SYS_MMAP
+        0x0f, 0x05, // syscall
+        0x48, 0xc7, 0x00, 0x25, 0x00, 0x00, 0x00, // mov qword [rax], 37
+        0x48, 0x8b, 0x38, // mov rdi, [rax]
+        0x48, 0x03, 0xbb, 0xf8, 0x1f, 0x00, 0x00, // add rdi, [rbx + 0x1ff8]
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+    let process = usermode::spawn("heap", &program).unwrap();
+
+    assert_eq!(process.wait(), 42);
+    assert_eq!(process.vmas().iter().count(), 2);
+}
+
+#[test_case]
+fn test_wild_pointer_faults() {
+    // NOTE: writes just past the break, which no area covers
+    let program = [
+        0x31, 0xff, // xor edi, edi
+        0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_BRK
+        0x0f, 0x05, // syscall
+        0xc6, 0x40, 0x10, 0x01, // mov byte [rax + 16], 1
+        0x31, 0xff, // xor edi, edi
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+
+    assert_eq!(
+        usermode::spawn("wild", &program).unwrap().wait(),
+        usermode::FAULT_EXIT_CODE
+    );
+}
+
+#[test_case]
+fn test_write_rejects_unmapped_buffer() {
+    // NOTE: write(STDOUT, break + 16, 4) with nothing mapped there, exit with the result
+    let program = [
+        0x31, 0xff, // xor edi, edi
+        0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, SYS_BRK
+        0x0f, 0x05, // syscall
+        0x48, 0x8d, 0x70, 0x10, // lea rsi, [rax + 16]
+        0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+        0xba, 0x04, 0x00, 0x00, 0x00, // mov edx, 4
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE
+        0x0f, 0x05, // syscall
+        0x89, 0xc7, // mov edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+
+    assert_eq!(
+        usermode::spawn("unmapped", &program).unwrap().wait(),
+        Error::BadAddress.to_result() as i32
+    );
+}
+
+#[test_case]
+fn test_pipe_between_processes() {
+    // NOTE: pipe(fds) on the stack, then fork; the child writes "hi" into the pipe and exits,
+    // the parent closes its write end, reads twice and exits with the first byte plus both
+    // counts, the second read being end of file once the child is gone
+    let program = [
+        0x48, 0x83, 0xec, 0x10, // sub rsp, 16
+        0x48, 0x89, 0xe7, // mov rdi, rsp
+        0xb8, 0x0a, 0x00, 0x00, 0x00, // mov eax, SYS_PIPE
+        0x0f, 0x05, // syscall
+        0xb8, 0x03, 0x00, 0x00, 0x00, // mov eax, SYS_FORK
+        0x0f, 0x05, // syscall
+        0x48, 0x85, 0xc0, // test rax, rax
+        0x75, 0x25, // jnz parent
+        0x66, 0xc7, 0x44, 0x24, 0x08, 0x68, 0x69, // mov word [rsp + 8], "hi"
+        0x8b, 0x7c, 0x24, 0x04, // mov edi, [rsp + 4]
+        0x48, 0x8d, 0x74, 0x24, 0x08, // lea rsi, [rsp + 8]
+        0xba, 0x02, 0x00, 0x00, 0x00, // mov edx, 2
+        0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE
+        0x0f, 0x05, // syscall
+        0x31, 0xff, // xor edi, edi
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+        0x8b, 0x7c, 0x24, 0x04, // parent: mov edi, [rsp + 4]
+        0xb8, 0x0b, 0x00, 0x00, 0x00, // mov eax, SYS_CLOSE
+        0x0f, 0x05, // syscall
+        0x8b, 0x3c, 0x24, // mov edi, [rsp]
+        0x48, 0x8d, 0x74, 0x24, 0x08, // lea rsi, [rsp + 8]
+        0xba, 0x08, 0x00, 0x00, 0x00, // mov edx, 8
+        0xb8, 0x09, 0x00, 0x00, 0x00, // mov eax, SYS_READ
+        0x0f, 0x05, // syscall
+        0x89, 0xc3, // mov ebx, eax
+        0x8b, 0x3c, 0x24, // mov edi, [rsp]
+        0x48, 0x8d, 0x74, 0x24, 0x08, // lea rsi, [rsp + 8]
+        0xba, 0x08, 0x00, 0x00, 0x00, // mov edx, 8
+        0xb8, 0x09, 0x00, 0x00, 0x00, // mov eax, SYS_READ
+        0x0f, 0x05, // syscall
+        0x0f, 0xb6, 0x7c, 0x24, 0x08, // movzx edi, byte [rsp + 8]
+        0x01, 0xdf, // add edi, ebx
+        0x01, 0xc7, // add edi, eax
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+        0x0f, 0x05, // syscall
+    ];
+
+    assert_eq!(usermode::spawn("pipe", &program).unwrap().wait(), 106);
+}
@@ -0,0 +1,183 @@
+// NOTE: shared by the kernel and user programs and free of dependencies, so a program can use
+// it through #[path]. The number goes in rax and the arguments in rdi, rsi, rdx, r10, r8 and r9
+// like on Linux; the result comes back in rax, rcx and r11 are clobbered
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_SLEEP: u64 = 2;
+// NOTE: returns the child's id, 0 in the child
+pub const SYS_FORK: u64 = 3;
+// NOTE: image, length, argv and envp; the last two are null terminated arrays of pointers to
+// NUL terminated strings or 0 for none, the new program finds them on its stack as on Linux
+pub const SYS_EXEC: u64 = 4;
+// NOTE: the exit code of a child, truncated to 32 bits
+pub const SYS_WAIT: u64 = 5;
+// NOTE: sets the end of the heap unless given 0 and returns it
+pub const SYS_BRK: u64 = 6;
+// NOTE: length and PROT_* bits, always anonymous and private; returns the address
+pub const SYS_MMAP: u64 = 7;
+pub const SYS_MUNMAP: u64 = 8;
+// NOTE: blocks until there is something to read, 0 is end of file
+pub const SYS_READ: u64 = 9;
+// NOTE: writes the read and the write descriptor as two u32 where the argument points
+pub const SYS_PIPE: u64 = 10;
+pub const SYS_CLOSE: u64 = 11;
+// NOTE: makes the second descriptor a copy of the first, returns it
+pub const SYS_DUP2: u64 = 12;
+// NOTE: process id and signal number
+pub const SYS_KILL: u64 = 13;
+// NOTE: signal number and SIG_DFL, SIG_IGN or the handler's address, returns the old one.
+// A handler is called like `extern "C" fn(signal: u64)` and returns into a trampoline that
+// makes SYS_SIGRETURN
+pub const SYS_SIGACTION: u64 = 14;
+// NOTE: replaces the blocked mask, bit n for signal n, and returns the old one
+pub const SYS_SIGMASK: u64 = 15;
+pub const SYS_SIGRETURN: u64 = 16;
+// NOTE: replaces the console TTY's TTY_* mode bits and returns the old ones
+pub const SYS_TTYMODE: u64 = 17;
+// NOTE: child id or WAIT_ANY, where to store the exit code as an i32 or 0, and WNOHANG; reaps an
+// exited child and returns its id, 0 under WNOHANG while none has exited
+pub const SYS_WAITPID: u64 = 18;
+pub const SYS_GETPID: u64 = 19;
+// NOTE: 0 for a process without a parent
+pub const SYS_GETPPID: u64 = 20;
+// NOTE: address of a u32 and the value expected there; sleeps until a wake for the address,
+// or fails with WouldBlock right away if the value is different
+pub const SYS_FUTEX_WAIT: u64 = 21;
+// NOTE: address and how many waiters to wake at most, returns how many it woke
+pub const SYS_FUTEX_WAKE: u64 = 22;
+// NOTE: name, name length, size and SHM_* flags; opens a shared memory object, creating it with
+// `size` bytes rounded to pages under SHM_CREATE, and returns a descriptor for it
+pub const SYS_SHM_OPEN: u64 = 23;
+// NOTE: descriptor and PROT_* bits, maps the whole object and returns the address. PROT_WRITE
+// needs a descriptor opened with SHM_WRITE
+pub const SYS_SHM_MAP: u64 = 24;
+// NOTE: name and name length; mappings and descriptors keep the object until they are gone
+pub const SYS_SHM_UNLINK: u64 = 25;
+// NOTE: absolute path, path length and OPEN_* flags, returns a descriptor. SYS_READ and
+// SYS_WRITE go through it at its offset
+pub const SYS_OPEN: u64 = 26;
+// NOTE: descriptor of a directory, buffer and length; fills the buffer with as many entries as
+// fit, each a DIRENT_* kind byte, a name length byte and the name, and returns how many bytes
+// that took, 0 after the last entry
+pub const SYS_READDIR: u64 = 27;
+// NOTE: descriptor, offset as an i64 and SEEK_*, returns the new offset
+pub const SYS_SEEK: u64 = 28;
+// NOTE: path and path length
+pub const SYS_MKDIR: u64 = 29;
+// NOTE: path and path length, removes a file or an empty directory
+pub const SYS_UNLINK: u64 = 30;
+// NOTE: old path, its length, new path and its length, within one filesystem
+pub const SYS_RENAME: u64 = 31;
+
+pub const SHM_CREATE: u64 = 1;
+// NOTE: with SHM_CREATE, fails with Exists if the name is taken
+pub const SHM_EXCLUSIVE: u64 = 2;
+pub const SHM_WRITE: u64 = 4;
+
+pub const OPEN_READ: u64 = 1;
+pub const OPEN_WRITE: u64 = 2;
+pub const OPEN_CREATE: u64 = 4;
+pub const OPEN_TRUNCATE: u64 = 8;
+pub const OPEN_APPEND: u64 = 16;
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+pub const DIRENT_FILE: u8 = 1;
+pub const DIRENT_DIRECTORY: u8 = 2;
+pub const DIRENT_DEVICE: u8 = 3;
+
+pub const WAIT_ANY: u64 = u64::MAX;
+pub const WNOHANG: u64 = 1;
+
+pub const STDIN: u64 = 0;
+
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+pub const SIGINT: u64 = 2;
+pub const SIGKILL: u64 = 9;
+pub const SIGSEGV: u64 = 11;
+// NOTE: signal numbers are below this
+pub const NSIG: u64 = 32;
+
+pub const TTY_CANONICAL: u64 = 1;
+pub const TTY_ECHO: u64 = 2;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+// NOTE: returned negated, anything else is a successful result
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NoSuchCall = 1,
+    BadDescriptor = 2,
+    BadAddress = 3,
+    BadExecutable = 4,
+    NoMemory = 5,
+    NoChild = 6,
+    Busy = 7,
+    InvalidArgument = 8,
+    // NOTE: writing to a pipe nobody reads from anymore
+    BrokenPipe = 9,
+    TooManyFiles = 10,
+    NoSuchProcess = 11,
+    // NOTE: a blocking call gave up for a signal, which is delivered on the way out
+    Interrupted = 12,
+    // NOTE: a futex didn't hold the expected value
+    WouldBlock = 13,
+    NotFound = 14,
+    Exists = 15,
+    PermissionDenied = 16,
+    NotDirectory = 17,
+    IsDirectory = 18,
+    // NOTE: removing a directory with entries
+    NotEmpty = 19,
+    NoSpace = 20,
+    // NOTE: the device behind a filesystem failed
+    Io = 21,
+    NameTooLong = 22,
+    // NOTE: a rename between two filesystems
+    CrossDevice = 23,
+}
+
+impl Error {
+    pub const fn to_result(self) -> i64 {
+        -(self as i64)
+    }
+
+    pub fn from_result(value: i64) -> Result<u64, Error> {
+        match value {
+            -1 => Err(Error::NoSuchCall),
+            -2 => Err(Error::BadDescriptor),
+            -3 => Err(Error::BadAddress),
+            -4 => Err(Error::BadExecutable),
+            -5 => Err(Error::NoMemory),
+            -6 => Err(Error::NoChild),
+            -7 => Err(Error::Busy),
+            -8 => Err(Error::InvalidArgument),
+            -9 => Err(Error::BrokenPipe),
+            -10 => Err(Error::TooManyFiles),
+            -11 => Err(Error::NoSuchProcess),
+            -12 => Err(Error::Interrupted),
+            -13 => Err(Error::WouldBlock),
+            -14 => Err(Error::NotFound),
+            -15 => Err(Error::Exists),
+            -16 => Err(Error::PermissionDenied),
+            -17 => Err(Error::NotDirectory),
+            -18 => Err(Error::IsDirectory),
+            -19 => Err(Error::NotEmpty),
+            -20 => Err(Error::NoSpace),
+            -21 => Err(Error::Io),
+            -22 => Err(Error::NameTooLong),
+            -23 => Err(Error::CrossDevice),
+            value => Ok(value as u64),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// NOTE: the user side of the ABI, for programs built against it; like abi.rs it only needs
+// core, so both can be pulled into a program with #[path]
+use super::abi::{self, Error};
+use core::arch::asm;
+
+/// # Safety
+///
+/// `number` must be a call that takes no arguments.
+#[inline(always)]
+pub unsafe fn syscall0(number: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result
+}
+
+/// # Safety
+///
+/// The arguments must be what the kernel expects for `number`.
+#[inline(always)]
+pub unsafe fn syscall1(number: u64, a0: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        in("rdi") a0,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result
+}
+
+/// # Safety
+///
+/// Same as `syscall1`.
+#[inline(always)]
+pub unsafe fn syscall2(number: u64, a0: u64, a1: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        in("rdi") a0,
+        in("rsi") a1,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result
+}
+
+/// # Safety
+///
+/// Same as `syscall1`.
+#[inline(always)]
+pub unsafe fn syscall3(number: u64, a0: u64, a1: u64, a2: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result
+}
+
+/// # Safety
+///
+/// Same as `syscall1`.
+#[inline(always)]
+pub unsafe fn syscall4(number: u64, a0: u64, a1: u64, a2: u64, a3: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        in("r10") a3,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall1(abi::SYS_EXIT, code as u64) };
+
+    // NOTE: exit never returns, nothing else to do in ring 3
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn write(descriptor: u64, bytes: &[u8]) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall3(
+            abi::SYS_WRITE,
+            descriptor,
+            bytes.as_ptr() as u64,
+            bytes.len() as u64,
+        )
+    };
+
+    Error::from_result(result).map(|written| written as usize)
+}
+
+pub fn sleep_ms(ms: u64) {
+    unsafe { syscall1(abi::SYS_SLEEP, ms) };
+}
+
+// NOTE: the child's id in the parent, 0 in the child
+pub fn fork() -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall0(abi::SYS_FORK) })
+}
+
+// NOTE: an argv or envp array for exec, which has to end with a null pointer unless it is
+// empty
+fn terminated(array: &[*const u8]) -> Result<u64, Error> {
+    match array.last() {
+        None => Ok(0),
+        Some(last) if last.is_null() => Ok(array.as_ptr() as u64),
+        Some(_) => Err(Error::InvalidArgument),
+    }
+}
+
+// NOTE: replaces the program with the executable in `image`, started with the NUL terminated
+// strings in `argv` and `envp`. Only returns if that failed
+pub fn exec(image: &[u8], argv: &[*const u8], envp: &[*const u8]) -> Error {
+    let (argv, envp) = match (terminated(argv), terminated(envp)) {
+        (Ok(argv), Ok(envp)) => (argv, envp),
+        (Err(error), _) | (_, Err(error)) => return error,
+    };
+    let result = unsafe {
+        syscall4(
+            abi::SYS_EXEC,
+            image.as_ptr() as u64,
+            image.len() as u64,
+            argv,
+            envp,
+        )
+    };
+
+    Error::from_result(result)
+        .err()
+        .unwrap_or(Error::BadExecutable)
+}
+
+pub fn wait(child: u64) -> Result<i32, Error> {
+    Error::from_result(unsafe { syscall1(abi::SYS_WAIT, child) }).map(|code| code as u32 as i32)
+}
+
+// NOTE: `child` or WAIT_ANY, the reaped child's id and code. With WNOHANG in `flags` None while
+// no child has exited yet
+pub fn waitpid(child: u64, flags: u64) -> Result<Option<(u64, i32)>, Error> {
+    let mut code = 0i32;
+    let result = unsafe { syscall3(abi::SYS_WAITPID, child, &mut code as *mut i32 as u64, flags) };
+
+    Error::from_result(result).map(|child| match child {
+        0 => None,
+        child => Some((child, code)),
+    })
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall0(abi::SYS_GETPID) as u64 }
+}
+
+// NOTE: 0 without a parent
+pub fn getppid() -> u64 {
+    unsafe { syscall0(abi::SYS_GETPPID) as u64 }
+}
+
+// NOTE: 0 leaves the break where it is
+pub fn brk(address: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall1(abi::SYS_BRK, address) })
+}
+
+pub fn mmap(len: u64, prot: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_MMAP, len, prot) })
+}
+
+pub fn munmap(address: u64, len: u64) -> Result<(), Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_MUNMAP, address, len) }).map(|_| ())
+}
+
+pub fn read(descriptor: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall3(
+            abi::SYS_READ,
+            descriptor,
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64,
+        )
+    };
+
+    Error::from_result(result).map(|read| read as usize)
+}
+
+// NOTE: the read and the write end
+pub fn pipe() -> Result<(u64, u64), Error> {
+    let mut descriptors = [0u32; 2];
+    let result = unsafe { syscall1(abi::SYS_PIPE, descriptors.as_mut_ptr() as u64) };
+
+    Error::from_result(result).map(|_| (descriptors[0] as u64, descriptors[1] as u64))
+}
+
+pub fn close(descriptor: u64) -> Result<(), Error> {
+    Error::from_result(unsafe { syscall1(abi::SYS_CLOSE, descriptor) }).map(|_| ())
+}
+
+pub fn dup2(source: u64, target: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_DUP2, source, target) })
+}
+
+pub fn kill(process: u64, signal: u64) -> Result<(), Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_KILL, process, signal) }).map(|_| ())
+}
+
+// NOTE: SIG_DFL, SIG_IGN or the address of an `extern "C" fn(u64)`, returns the old one
+pub fn sigaction(signal: u64, handler: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_SIGACTION, signal, handler) })
+}
+
+// NOTE: sleeps while `*word` is `expected` until a wake for it, Err(WouldBlock) if it wasn't.
+// Wakeups can be spurious, callers check their condition again
+pub fn futex_wait(word: &core::sync::atomic::AtomicU32, expected: u32) -> Result<(), Error> {
+    let result = unsafe { syscall2(abi::SYS_FUTEX_WAIT, word.as_ptr() as u64, expected as u64) };
+
+    Error::from_result(result).map(|_| ())
+}
+
+// NOTE: wakes up to `count` threads waiting on `word`, returns how many it woke
+pub fn futex_wake(word: &core::sync::atomic::AtomicU32, count: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_FUTEX_WAKE, word.as_ptr() as u64, count) })
+}
+
+// NOTE: a descriptor for the shared memory object `name`, see SYS_SHM_OPEN for `flags`
+pub fn shm_open(name: &[u8], size: u64, flags: u64) -> Result<u64, Error> {
+    let result = unsafe {
+        syscall4(
+            abi::SYS_SHM_OPEN,
+            name.as_ptr() as u64,
+            name.len() as u64,
+            size,
+            flags,
+        )
+    };
+
+    Error::from_result(result)
+}
+
+// NOTE: maps the whole object with PROT_* bits, returns the address
+pub fn shm_map(descriptor: u64, prot: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall2(abi::SYS_SHM_MAP, descriptor, prot) })
+}
+
+pub fn shm_unlink(name: &[u8]) -> Result<(), Error> {
+    let result = unsafe { syscall2(abi::SYS_SHM_UNLINK, name.as_ptr() as u64, name.len() as u64) };
+
+    Error::from_result(result).map(|_| ())
+}
+
+// NOTE: an absolute path and OPEN_* flags, returns a descriptor
+pub fn open(path: &str, flags: u64) -> Result<u64, Error> {
+    let result = unsafe {
+        syscall3(
+            abi::SYS_OPEN,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            flags,
+        )
+    };
+
+    Error::from_result(result)
+}
+
+// NOTE: fills `buffer` with packed entries as SYS_READDIR describes, 0 after the last
+pub fn readdir(descriptor: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall3(
+            abi::SYS_READDIR,
+            descriptor,
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64,
+        )
+    };
+
+    Error::from_result(result).map(|read| read as usize)
+}
+
+// NOTE: SEEK_* says what `offset` is relative to, returns the new offset
+pub fn seek(descriptor: u64, offset: i64, whence: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall3(abi::SYS_SEEK, descriptor, offset as u64, whence) })
+}
+
+pub fn mkdir(path: &str) -> Result<(), Error> {
+    let result = unsafe { syscall2(abi::SYS_MKDIR, path.as_ptr() as u64, path.len() as u64) };
+
+    Error::from_result(result).map(|_| ())
+}
+
+pub fn unlink(path: &str) -> Result<(), Error> {
+    let result = unsafe { syscall2(abi::SYS_UNLINK, path.as_ptr() as u64, path.len() as u64) };
+
+    Error::from_result(result).map(|_| ())
+}
+
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    let result = unsafe {
+        syscall4(
+            abi::SYS_RENAME,
+            from.as_ptr() as u64,
+            from.len() as u64,
+            to.as_ptr() as u64,
+            to.len() as u64,
+        )
+    };
+
+    Error::from_result(result).map(|_| ())
+}
+
+// NOTE: TTY_* bits, returns the old ones
+pub fn tty_mode(mode: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall1(abi::SYS_TTYMODE, mode) })
+}
+
+pub fn sigmask(blocked: u64) -> Result<u64, Error> {
+    Error::from_result(unsafe { syscall1(abi::SYS_SIGMASK, blocked) })
+}
@@ -0,0 +1,62 @@
+pub mod executor;
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub use executor::{run_ready, spawn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> TaskId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// NOTE: a pinned top level future, it runs until it completes and its output is dropped
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    // NOTE: TSC cycles spent in its polls
+    cycles: u64,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+            cycles: 0,
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+// NOTE: returns Pending once after waking itself, lets other ready tasks run in between
+pub async fn yield_now() {
+    let mut yielded = false;
+
+    core::future::poll_fn(|context| match yielded {
+        true => Poll::Ready(()),
+        false => {
+            yielded = true;
+            context.waker().wake_by_ref();
+
+            Poll::Pending
+        }
+    })
+    .await
+}
@@ -0,0 +1,134 @@
+use super::{Task, TaskId};
+use crate::sync::IrqMutex;
+use crate::tsc;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+// NOTE: tasks are taken out while they're polled so they can spawn and wake freely
+static TASKS: IrqMutex<BTreeMap<TaskId, (Task, Arc<TaskWaker>)>> = IrqMutex::new(BTreeMap::new());
+// NOTE: woken tasks, each at most once thanks to TaskWaker::queued; spawn reserves a slot per
+// task so wakers called from interrupt handlers never allocate
+static READY: IrqMutex<VecDeque<TaskId>> = IrqMutex::new(VecDeque::new());
+
+struct TaskWaker {
+    id: TaskId,
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn schedule(&self) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            READY.lock().push_back(self.id);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+// NOTE: queues `future` to run from the idle loop, usable any time after the heap is up
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> TaskId {
+    let task = Task::new(future);
+    let id = task.id;
+    let waker = Arc::new(TaskWaker {
+        id,
+        queued: AtomicBool::new(false),
+    });
+
+    {
+        let mut tasks = TASKS.lock();
+        let mut ready = READY.lock();
+        let additional = (tasks.len() + 1).saturating_sub(ready.len());
+
+        ready.reserve(additional);
+        tasks.insert(id, (task, waker.clone()));
+    }
+
+    waker.schedule();
+
+    id
+}
+
+fn pop_ready() -> Option<TaskId> {
+    READY.lock().pop_front()
+}
+
+// NOTE: polls every task that was woken, including ones woken while this runs; returns how
+// many polls were made
+pub fn run_ready() -> usize {
+    let mut polls = 0;
+
+    while let Some(id) = pop_ready() {
+        let Some((mut task, waker)) = TASKS.lock().remove(&id) else {
+            continue;
+        };
+
+        // NOTE: cleared before the poll so a wake during it queues the task again
+        waker.queued.store(false, Ordering::Release);
+
+        let task_waker = Waker::from(waker.clone());
+        let mut context = Context::from_waker(&task_waker);
+
+        polls += 1;
+
+        let start = tsc::read();
+        let poll = task.poll(&mut context);
+
+        task.cycles += tsc::read().saturating_sub(start);
+
+        if poll == Poll::Pending {
+            TASKS.lock().insert(id, (task, waker));
+        }
+    }
+
+    polls
+}
+
+pub fn has_ready() -> bool {
+    !READY.lock().is_empty()
+}
+
+pub fn task_count() -> usize {
+    TASKS.lock().len()
+}
+
+// NOTE: TSC cycles each task not being polled right now has used so far
+pub fn task_cycles() -> Vec<(TaskId, u64)> {
+    TASKS
+        .lock()
+        .iter()
+        .map(|(id, (task, _))| (*id, task.cycles))
+        .collect()
+}
+
+#[test_case]
+fn test_spawn_and_wake() {
+    use core::sync::atomic::AtomicUsize;
+
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+    let before = task_count();
+
+    spawn(async {
+        STEPS.fetch_add(1, Ordering::Relaxed);
+        super::yield_now().await;
+        STEPS.fetch_add(1, Ordering::Relaxed);
+    });
+
+    assert_eq!(task_count(), before + 1);
+    assert!(run_ready() >= 2);
+    assert_eq!(STEPS.load(Ordering::Relaxed), 2);
+    assert_eq!(task_count(), before);
+}
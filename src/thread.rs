@@ -0,0 +1,730 @@
+mod context;
+mod join;
+pub mod scheduler;
+
+use crate::gdt;
+use crate::memory::stack::{KernelStack, StackError};
+use crate::memory::{paging, tlb};
+use crate::process::{Process, ProcessError};
+use crate::sync::{rcu, IrqMutex, IrqMutexGuard};
+use crate::{percpu, tsc};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use join::ExitStatus;
+use scheduler::Scheduler;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::VirtAddr;
+
+pub use join::JoinHandle;
+
+pub const STACK_PAGES: usize = 16;
+// NOTE: ticks a thread runs before the timer interrupt switches to the next one of the same
+// or a higher priority
+pub const TIME_SLICE_TICKS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> ThreadId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+const PRIORITIES: usize = Priority::High as usize + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Ready,
+    Blocked,
+    Dead,
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+pub struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    priority: Priority,
+    state: State,
+    // NOTE: a CPU's idle thread, which never waits in a run queue
+    idle: bool,
+    // NOTE: timer ticks spent running
+    ticks: u64,
+    // NOTE: TSC cycles spent running, up to the last switch away from it
+    cycles: u64,
+    // NOTE: set by a wake that found the thread not blocked, the next block returns at once
+    wake_pending: bool,
+    // NOTE: where exit leaves the code for the JoinHandle
+    status: Arc<ExitStatus>,
+    // NOTE: saved stack pointer while switched out
+    rsp: u64,
+    // NOTE: None for the boot thread which runs on the bootloader's stack
+    stack: Option<KernelStack>,
+    // NOTE: the process whose page tables the thread runs on, None for kernel threads
+    process: Option<Arc<Process>>,
+}
+
+static SCHEDULER: IrqMutex<Scheduler> = IrqMutex::new(Scheduler::new());
+
+// NOTE: turns the code running so far into the boot thread and creates the idle thread,
+// after the heap is up
+pub fn init() {
+    let (mut idle, _) = create(ThreadId::new(), "idle", Priority::Low, || run_idle())
+        .expect("idle thread has no stack");
+
+    idle.idle = true;
+
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+
+        if scheduler.current().is_none() {
+            let boot = Thread::running("boot", Priority::Normal, None);
+
+            percpu!(scheduler.current_thread).store(boot.id.0, Ordering::Relaxed);
+            percpu!(scheduler.switched_at).store(tsc::read(), Ordering::Relaxed);
+            scheduler.set_current(boot);
+        }
+
+        scheduler.set_idle(idle);
+    });
+}
+
+// NOTE: for an application processor, whose boot code on `stack` becomes its idle thread
+pub(crate) fn init_cpu(stack: KernelStack) {
+    let mut idle = Thread::running("idle", Priority::Low, Some(stack));
+
+    idle.idle = true;
+
+    interrupts::without_interrupts(|| {
+        percpu!(scheduler.current_thread).store(idle.id.0, Ordering::Relaxed);
+        percpu!(scheduler.switched_at).store(tsc::read(), Ordering::Relaxed);
+        SCHEDULER.lock().set_current(idle);
+    });
+}
+
+impl Thread {
+    // NOTE: for code that is already running on its own stack
+    fn running(name: &'static str, priority: Priority, stack: Option<KernelStack>) -> Box<Thread> {
+        Box::new(Thread {
+            id: ThreadId::new(),
+            name,
+            priority,
+            state: State::Running,
+            idle: false,
+            ticks: 0,
+            cycles: 0,
+            wake_pending: false,
+            status: ExitStatus::new(),
+            rsp: 0,
+            stack,
+            process: None,
+        })
+    }
+}
+
+// NOTE: body of the idle threads, halts (with the tick stopped where possible) until an
+// interrupt makes a thread ready
+pub(crate) fn run_idle() -> ! {
+    loop {
+        interrupts::disable();
+
+        let ready = SCHEDULER.lock().has_ready();
+
+        match ready {
+            true => interrupts::enable(),
+            false => {
+                percpu!(stats.idle_halts).fetch_add(1, Ordering::Relaxed);
+                crate::interrupts::tickless::idle_halt();
+            }
+        }
+
+        yield_now();
+    }
+}
+
+pub fn spawn(
+    name: &'static str,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle, StackError> {
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+// NOTE: the new thread is queued behind the ready ones of its priority and starts with
+// interrupts enabled
+pub fn spawn_with_priority(
+    name: &'static str,
+    priority: Priority,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle, StackError> {
+    let (thread, handle) = create(ThreadId::new(), name, priority, f)?;
+
+    start(thread);
+
+    Ok(handle)
+}
+
+// NOTE: a thread of `process`, registered there before it can run
+pub(crate) fn spawn_in(
+    process: &Arc<Process>,
+    name: &'static str,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle, ProcessError> {
+    let id = ThreadId::new();
+
+    if !process.add_thread(id) {
+        return Err(ProcessError::Exited);
+    }
+
+    let (mut thread, handle) =
+        create(id, name, Priority::Normal, f).inspect_err(|_| process.remove_thread(id))?;
+
+    thread.process = Some(process.clone());
+    start(thread);
+
+    Ok(handle)
+}
+
+fn start(thread: Box<Thread>) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+
+        request_preemption(&scheduler, thread.priority);
+        scheduler.push(thread);
+    });
+}
+
+fn create(
+    id: ThreadId,
+    name: &'static str,
+    priority: Priority,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<(Box<Thread>, JoinHandle), StackError> {
+    let stack = KernelStack::allocate(name, STACK_PAGES)?;
+    let entry: *mut Entry = Box::into_raw(Box::new(Box::new(f)));
+    let rsp = unsafe { context::initial_stack(stack.top().as_u64(), entry as u64) };
+    let status = ExitStatus::new();
+    let thread = Box::new(Thread {
+        id,
+        name,
+        priority,
+        state: State::Ready,
+        idle: false,
+        ticks: 0,
+        cycles: 0,
+        wake_pending: false,
+        status: status.clone(),
+        rsp,
+        stack: Some(stack),
+        process: None,
+    });
+    let handle = JoinHandle::new(thread.id, status);
+
+    Ok((thread, handle))
+}
+
+extern "C" fn thread_main(entry: *mut Entry) -> ! {
+    SCHEDULER.lock().finish_switch();
+    reap();
+    interrupts::enable();
+
+    let entry = unsafe { Box::from_raw(entry) };
+
+    entry();
+    exit(0);
+}
+
+// NOTE: frees the stacks of exited threads, never called on one of them
+fn reap() {
+    let dead = interrupts::without_interrupts(|| core::mem::take(&mut SCHEDULER.lock().dead));
+
+    drop(dead);
+}
+
+// NOTE: a ready thread outranking the running one takes over at the next tick, anything
+// takes over from the idle thread
+fn request_preemption(scheduler: &Scheduler, priority: Priority) {
+    let current = scheduler.current().map(|thread| thread.priority);
+
+    if scheduler.is_idle() || current.is_some_and(|current| priority > current) {
+        percpu!(scheduler.need_resched).store(true, Ordering::Relaxed);
+    }
+}
+
+// NOTE: interrupts must be off; the current thread goes wherever `state` says and `next` runs
+// until something switches back
+fn switch_to(mut scheduler: IrqMutexGuard<Scheduler>, mut next: Box<Thread>, state: State) {
+    let new_rsp = next.rsp;
+    let kernel_stack = next.stack.as_ref().map(KernelStack::top);
+    let level_4 = next
+        .process
+        .as_ref()
+        .map_or_else(paging::kernel_level_4, |process| process.level_4());
+
+    next.state = State::Running;
+    percpu!(scheduler.current_thread).store(next.id.0, Ordering::Relaxed);
+    percpu!(stats.context_switches).fetch_add(1, Ordering::Relaxed);
+
+    let mut current = scheduler.set_current(next).expect("no thread is running");
+    // NOTE: the Box keeps `old_rsp` in place until the switch is finished
+    let old_rsp: *mut u64 = &mut current.rsp;
+    let now = tsc::read();
+    let switched_at = percpu!(scheduler.switched_at).swap(now, Ordering::Relaxed);
+
+    current.cycles += now.saturating_sub(switched_at);
+    rcu::quiescent_state();
+
+    current.state = state;
+    scheduler.switch_out(current);
+    drop(scheduler);
+
+    if let Some(top) = kernel_stack {
+        gdt::set_kernel_stack(top);
+    }
+
+    // NOTE: kernel stacks are in level 4 entries every address space shares
+    if Cr3::read().0 != level_4 {
+        unsafe { tlb::load_level_4(level_4) };
+    }
+
+    unsafe { context::switch(old_rsp, new_rsp) };
+
+    // NOTE: back on this thread, possibly on another CPU
+    SCHEDULER.lock().finish_switch();
+}
+
+// NOTE: a ready current thread only gives way to one of at least its own priority
+fn schedule(state: State) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+
+        percpu!(scheduler.slice_left).store(TIME_SLICE_TICKS, Ordering::Relaxed);
+        percpu!(scheduler.need_resched).store(false, Ordering::Relaxed);
+
+        let Some(priority) = scheduler.current().map(|thread| thread.priority) else {
+            return;
+        };
+        let next = match state {
+            State::Ready => scheduler.pop(priority),
+            _ => scheduler.pop_or_idle(),
+        };
+
+        if let Some(next) = next {
+            switch_to(scheduler, next, state);
+        }
+    });
+
+    reap();
+}
+
+// NOTE: gives the rest of the time slice to the next ready thread
+pub fn yield_now() {
+    schedule(State::Ready);
+}
+
+// NOTE: the stack and the thread itself are freed by the next thread to run, `code` goes to
+// whoever joins it and, for the last thread of a process, to whoever waits for that
+pub fn exit(code: i32) -> ! {
+    let current = interrupts::without_interrupts(|| {
+        let scheduler = SCHEDULER.lock();
+
+        scheduler
+            .current()
+            .map(|thread| (thread.id, thread.status.clone(), thread.process.clone()))
+    });
+
+    if let Some((id, status, process)) = current {
+        status.finish(code);
+
+        if let Some(process) = process {
+            process.thread_exited(id, code);
+        }
+    }
+
+    schedule(State::Dead);
+
+    unreachable!("exited thread was switched back to");
+}
+
+// NOTE: sleeps until `wake` is called for the current thread, at once if that already
+// happened since the last block
+pub fn block() {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let Some(current) = scheduler.current_mut() else {
+            return;
+        };
+
+        if core::mem::take(&mut current.wake_pending) {
+            return;
+        }
+
+        if let Some(next) = scheduler.pop_or_idle() {
+            percpu!(scheduler.slice_left).store(TIME_SLICE_TICKS, Ordering::Relaxed);
+            switch_to(scheduler, next, State::Blocked);
+        }
+    });
+
+    reap();
+}
+
+// NOTE: safe to call from interrupt handlers, false if no such thread exists
+pub fn wake(id: ThreadId) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+
+        if let Some(priority) = scheduler.unblock(id) {
+            request_preemption(&scheduler, priority);
+
+            return true;
+        }
+
+        match scheduler.find_mut(id) {
+            Some(thread) => {
+                thread.wake_pending = true;
+
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+struct ThreadWaker(ThreadId);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        wake(self.0);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        wake(self.0);
+    }
+}
+
+// NOTE: runs `future` to completion on the current thread, which sleeps between polls; before
+// threads are set up this spins instead
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let id = current_id();
+    let waker = match id {
+        Some(id) => Waker::from(Arc::new(ThreadWaker(id))),
+        None => futures_util::task::noop_waker(),
+    };
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+
+        match id {
+            Some(_) => block(),
+            None => core::hint::spin_loop(),
+        }
+    }
+}
+
+// NOTE: other threads run meanwhile, unlike with time::sleep_ms which halts in place
+pub fn sleep(duration: Duration) {
+    block_on(crate::time::sleep(duration));
+}
+
+// NOTE: sleep that gives up early with false once there is a signal the current thread has to
+// act on, Process::signal wakes it to check
+pub fn sleep_interruptible(duration: Duration) -> bool {
+    let mut sleep = crate::time::sleep(duration);
+
+    block_on(core::future::poll_fn(|context| {
+        if crate::signal::has_deliverable() {
+            return Poll::Ready(false);
+        }
+
+        core::pin::Pin::new(&mut sleep).poll(context).map(|()| true)
+    }))
+}
+
+// NOTE: same as current_id without taking the scheduler lock, from the per CPU data
+pub fn current_id_fast() -> Option<ThreadId> {
+    let id = percpu!(scheduler.current_thread).load(Ordering::Relaxed);
+
+    (id != u64::MAX).then_some(ThreadId(id))
+}
+
+pub fn current_id() -> Option<ThreadId> {
+    SCHEDULER.lock().current().map(|thread| thread.id)
+}
+
+pub fn current_name() -> Option<&'static str> {
+    SCHEDULER.lock().current().map(|thread| thread.name)
+}
+
+pub fn current_process() -> Option<Arc<Process>> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .current()
+            .and_then(|thread| thread.process.clone())
+    })
+}
+
+// NOTE: top of the current thread's stack, None for the boot thread
+pub fn kernel_stack_top() -> Option<VirtAddr> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .current()
+            .and_then(|thread| thread.stack.as_ref())
+            .map(KernelStack::top)
+    })
+}
+
+// NOTE: timer ticks the current thread has spent running
+pub fn runtime_ticks() -> u64 {
+    SCHEDULER.lock().current().map_or(0, |thread| thread.ticks)
+}
+
+// NOTE: whether a thread other than the current one could run
+pub fn has_ready() -> bool {
+    SCHEDULER.lock().has_ready()
+}
+
+// NOTE: threads that have not exited yet
+pub fn count() -> usize {
+    SCHEDULER.lock().len()
+}
+
+// NOTE: to be called by the timer interrupt handler, accounts the tick to the current thread
+// and counts down its time slice
+pub(crate) fn tick() {
+    percpu!(stats.ticks).fetch_add(1, Ordering::Relaxed);
+    rcu::quiescent_state();
+
+    if let Some(mut scheduler) = SCHEDULER.try_lock() {
+        if scheduler.is_idle() {
+            percpu!(stats.idle_ticks).fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(current) = scheduler.current_mut() {
+            current.ticks += 1;
+        }
+    }
+
+    if percpu!(scheduler.slice_left).fetch_sub(1, Ordering::Relaxed) <= 1 {
+        percpu!(scheduler.need_resched).store(true, Ordering::Relaxed);
+    }
+}
+
+// NOTE: called by the timer interrupt handler once the interrupt is acknowledged, the
+// preempted thread resumes inside the handler and returns from it later
+pub(crate) fn preempt_if_needed() {
+    if percpu!(scheduler.need_resched).load(Ordering::Relaxed) {
+        yield_now();
+    }
+}
+
+#[test_case]
+fn test_preemption() {
+    use core::sync::atomic::AtomicBool;
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    let before = count();
+
+    spawn("counter", || {
+        while !STOP.load(Ordering::Relaxed) {
+            COUNTER.fetch_add(1, Ordering::Relaxed);
+        }
+
+        DONE.store(true, Ordering::Relaxed);
+    })
+    .unwrap();
+
+    // NOTE: spins without yielding, only preemption lets the counter move
+    while COUNTER.load(Ordering::Relaxed) == 0 {
+        core::hint::spin_loop();
+    }
+
+    STOP.store(true, Ordering::Relaxed);
+
+    while !DONE.load(Ordering::Relaxed) {
+        yield_now();
+    }
+
+    yield_now();
+
+    assert_eq!(count(), before);
+}
+
+#[test_case]
+fn test_fairness() {
+    use core::sync::atomic::AtomicBool;
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static RUNTIMES: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+    static ITERATIONS: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+
+    let before = count();
+
+    for index in 0..2 {
+        spawn("spinner", move || {
+            while !STOP.load(Ordering::Relaxed) {
+                ITERATIONS[index].fetch_add(1, Ordering::Relaxed);
+            }
+
+            RUNTIMES[index].store(runtime_ticks(), Ordering::Relaxed);
+        })
+        .unwrap();
+    }
+
+    let deadline = crate::time::ticks() + 20 * TIME_SLICE_TICKS as u64;
+
+    while crate::time::ticks() < deadline {
+        yield_now();
+    }
+
+    STOP.store(true, Ordering::Relaxed);
+
+    while count() > before {
+        yield_now();
+    }
+
+    let [first, second] = RUNTIMES
+        .each_ref()
+        .map(|ticks| ticks.load(Ordering::Relaxed));
+
+    assert!(ITERATIONS
+        .iter()
+        .all(|count| count.load(Ordering::Relaxed) > 0));
+    assert!(first.abs_diff(second) <= 2 * TIME_SLICE_TICKS as u64);
+}
+
+#[test_case]
+fn test_priority_and_wake() {
+    use core::sync::atomic::AtomicBool;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    let boot = current_id().unwrap();
+
+    spawn_with_priority("low", Priority::Low, move || {
+        RAN.store(true, Ordering::Relaxed);
+        wake(boot);
+    })
+    .unwrap();
+
+    let deadline = crate::time::ticks() + 3 * TIME_SLICE_TICKS as u64;
+
+    // NOTE: yielding never hands the CPU to a lower priority
+    while crate::time::ticks() < deadline {
+        yield_now();
+    }
+
+    assert!(!RAN.load(Ordering::Relaxed));
+
+    block();
+
+    assert!(RAN.load(Ordering::Relaxed));
+
+    // NOTE: a wake for a thread that is not blocked is kept for its next block
+    assert!(wake(boot));
+    block();
+}
+
+#[test_case]
+fn test_join() {
+    let before = count();
+    let handle = spawn("exit", || exit(7)).unwrap();
+
+    assert_eq!(handle.join(), 7);
+
+    // NOTE: the thread is still counted between finishing and switching away for good
+    let deadline = crate::time::ticks() + 100;
+
+    while count() != before && crate::time::ticks() < deadline {
+        yield_now();
+    }
+
+    assert_eq!(count(), before);
+
+    let handle = spawn("return", || {}).unwrap();
+    let id = handle.id();
+
+    assert_eq!(handle.join(), 0);
+    assert!(!wake(id));
+}
+
+#[test_case]
+fn test_join_from_task() {
+    static CODE: AtomicU64 = AtomicU64::new(0);
+
+    let handle = spawn("exit", || exit(3)).unwrap();
+
+    crate::task::spawn(async move {
+        CODE.store(handle.await as u64, Ordering::Relaxed);
+    });
+
+    while CODE.load(Ordering::Relaxed) == 0 {
+        crate::task::run_ready();
+        yield_now();
+    }
+
+    assert_eq!(CODE.load(Ordering::Relaxed), 3);
+}
+
+#[test_case]
+fn test_idle_thread() {
+    let halts = || {
+        (0..crate::percpu::online())
+            .filter_map(crate::percpu::cpu)
+            .map(|cpu| cpu.stats.idle_halts.load(Ordering::Relaxed))
+            .sum::<u64>()
+    };
+    let boot = current_id().unwrap();
+    let before = halts();
+    let waker = spawn("waker", move || {
+        sleep(Duration::from_millis(5));
+        wake(boot);
+    })
+    .unwrap();
+
+    // NOTE: both threads are blocked for a while, only the idle thread can run
+    block();
+
+    assert_eq!(waker.join(), 0);
+    assert_eq!(current_id_fast(), Some(boot));
+    assert!(halts() > before);
+}
+
+#[test_case]
+fn test_sleep_blocks() {
+    let start = crate::time::ticks();
+    let sleeper = spawn("sleeper", || sleep(Duration::from_millis(5))).unwrap();
+
+    assert!(!sleeper.is_finished());
+    assert_eq!(sleeper.join(), 0);
+    assert!(crate::time::ticks() >= start + 5);
+}
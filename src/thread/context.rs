@@ -0,0 +1,67 @@
+use core::arch::global_asm;
+
+// NOTE: callee saved registers are pushed on the old stack and popped from the new one, the
+// rest is saved by the caller, interrupt handlers included
+global_asm!(
+    ".global rustos_switch_context",
+    "rustos_switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    ".global rustos_thread_start",
+    "rustos_thread_start:",
+    "mov rdi, r12",
+    "call {thread_main}",
+    "ud2",
+    thread_main = sym super::thread_main,
+);
+
+extern "C" {
+    fn rustos_switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn rustos_thread_start();
+}
+
+/// # Safety
+///
+/// Interrupts must be off, `new_rsp` must come from a previous switch or `initial_stack` and
+/// `old_rsp` has to stay valid until the old thread is switched back to.
+pub(super) unsafe fn switch(old_rsp: *mut u64, new_rsp: u64) {
+    rustos_switch_context(old_rsp, new_rsp);
+}
+
+// NOTE: lays out a stack the way switch leaves it, the first switch to it `ret`s into
+// rustos_thread_start with `argument` in r12; returns the stack pointer to switch to
+//
+// top - 8   rustos_thread_start
+// top - 16  rbp, rbx, r12, r13, r14, r15 below it
+pub(super) unsafe fn initial_stack(top: u64, argument: u64) -> u64 {
+    let slots = top as *mut u64;
+    let frame = [
+        0,
+        0,
+        0,
+        argument,
+        0,
+        0,
+        rustos_thread_start as *const () as u64,
+    ];
+
+    for (index, value) in frame.iter().rev().enumerate() {
+        slots.sub(index + 1).write(*value);
+    }
+
+    top - frame.len() as u64 * 8
+}
@@ -0,0 +1,101 @@
+use super::ThreadId;
+use crate::sync::IrqMutex;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Once;
+
+// NOTE: shared by a thread and its JoinHandle, outlives whichever goes first
+pub(super) struct ExitStatus {
+    code: Once<i32>,
+    // NOTE: thread blocked in JoinHandle::join
+    joiner: IrqMutex<Option<ThreadId>>,
+    // NOTE: task awaiting the JoinHandle
+    waker: AtomicWaker,
+}
+
+impl ExitStatus {
+    pub fn new() -> Arc<ExitStatus> {
+        Arc::new(ExitStatus {
+            code: Once::new(),
+            joiner: IrqMutex::new(None),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    pub fn finish(&self, code: i32) {
+        self.code.call_once(|| code);
+        self.waker.wake();
+
+        let joiner = *self.joiner.lock();
+
+        if let Some(joiner) = joiner {
+            super::wake(joiner);
+        }
+    }
+
+    fn code(&self) -> Option<i32> {
+        self.code.r#try().copied()
+    }
+}
+
+// NOTE: dropping the handle detaches the thread, which is cleaned up on exit either way
+pub struct JoinHandle {
+    id: ThreadId,
+    status: Arc<ExitStatus>,
+}
+
+impl JoinHandle {
+    pub(super) fn new(id: ThreadId, status: Arc<ExitStatus>) -> JoinHandle {
+        JoinHandle { id, status }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status.code().is_some()
+    }
+
+    pub fn try_join(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    // NOTE: blocks the calling thread until the joined one exits, async tasks await the
+    // handle instead
+    pub fn join(self) -> i32 {
+        let current = super::current_id();
+
+        assert_ne!(current, Some(self.id), "thread joined itself");
+
+        *self.status.joiner.lock() = current;
+
+        loop {
+            if let Some(code) = self.status.code() {
+                return code;
+            }
+
+            super::block();
+        }
+    }
+}
+
+impl Future for JoinHandle {
+    type Output = i32;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<i32> {
+        if let Some(code) = self.status.code() {
+            return Poll::Ready(code);
+        }
+
+        self.status.waker.register(context.waker());
+
+        match self.status.code() {
+            Some(code) => Poll::Ready(code),
+            None => Poll::Pending,
+        }
+    }
+}
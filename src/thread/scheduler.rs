@@ -0,0 +1,239 @@
+use super::{Priority, State, Thread, ThreadId, PRIORITIES};
+use crate::percpu::{self, MAX_CPUS};
+use crate::task::{executor, TaskId};
+use crate::tsc;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::sync::atomic::Ordering;
+use x86_64::instructions::interrupts;
+
+// NOTE: one round-robin queue per priority shared by every CPU, the highest non-empty one
+// always runs first. Threads are boxed so the stack pointer saved while switching away from
+// one stays in place
+pub(super) struct Scheduler {
+    running: [Option<Box<Thread>>; MAX_CPUS],
+    // NOTE: the thread each CPU is switching away from; it is only queued by finish_switch
+    // once its stack pointer is saved, so no other CPU can pick it up halfway
+    switched_out: [Option<Box<Thread>>; MAX_CPUS],
+    queues: [VecDeque<Box<Thread>>; PRIORITIES],
+    // NOTE: threads waiting in block() until something wakes them
+    blocked: BTreeMap<ThreadId, Box<Thread>>,
+    // NOTE: exited threads whose stacks are freed once something else runs
+    #[allow(clippy::vec_box)]
+    pub dead: Vec<Box<Thread>>,
+    // NOTE: per CPU, runs when nothing else is ready and is never queued
+    idle: [Option<Box<Thread>>; MAX_CPUS],
+}
+
+fn cpu() -> usize {
+    percpu::current().index()
+}
+
+impl Scheduler {
+    pub const fn new() -> Scheduler {
+        Scheduler {
+            running: [const { None }; MAX_CPUS],
+            switched_out: [const { None }; MAX_CPUS],
+            queues: [const { VecDeque::new() }; PRIORITIES],
+            blocked: BTreeMap::new(),
+            dead: Vec::new(),
+            idle: [const { None }; MAX_CPUS],
+        }
+    }
+
+    // NOTE: the thread running on the calling CPU
+    pub fn current(&self) -> Option<&Thread> {
+        self.running[cpu()].as_deref()
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Thread> {
+        self.running[cpu()].as_deref_mut()
+    }
+
+    pub fn set_current(&mut self, thread: Box<Thread>) -> Option<Box<Thread>> {
+        self.running[cpu()].replace(thread)
+    }
+
+    pub fn set_idle(&mut self, thread: Box<Thread>) {
+        self.idle[cpu()] = Some(thread);
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.current().is_some_and(|thread| thread.idle)
+    }
+
+    // NOTE: an idle thread goes back to its CPU's slot instead of a queue
+    pub fn push(&mut self, thread: Box<Thread>) {
+        match thread.idle {
+            true => self.idle[cpu()] = Some(thread),
+            false => self.queues[thread.priority as usize].push_back(thread),
+        }
+    }
+
+    pub fn switch_out(&mut self, thread: Box<Thread>) {
+        self.switched_out[cpu()] = Some(thread);
+    }
+
+    // NOTE: run by the thread a CPU switched to, puts the one it switched away from where its
+    // state says; a wake that came in the meantime makes a blocking thread ready again
+    pub fn finish_switch(&mut self) {
+        let Some(mut thread) = self.switched_out[cpu()].take() else {
+            return;
+        };
+
+        match thread.state {
+            State::Dead => self.dead.push(thread),
+            State::Blocked if !core::mem::take(&mut thread.wake_pending) => {
+                self.blocked.insert(thread.id, thread);
+            }
+            _ => {
+                thread.state = State::Ready;
+                self.push(thread);
+            }
+        }
+    }
+
+    pub fn unblock(&mut self, id: ThreadId) -> Option<Priority> {
+        let mut thread = self.blocked.remove(&id)?;
+        let priority = thread.priority;
+
+        thread.state = State::Ready;
+        self.push(thread);
+
+        Some(priority)
+    }
+
+    pub fn has_ready(&self) -> bool {
+        self.queues.iter().any(|queue| !queue.is_empty())
+    }
+
+    // NOTE: next thread to run whose priority is at least `minimum`
+    pub fn pop(&mut self, minimum: Priority) -> Option<Box<Thread>> {
+        self.queues[minimum as usize..]
+            .iter_mut()
+            .rev()
+            .find_map(VecDeque::pop_front)
+    }
+
+    // NOTE: for a current thread that can't go on, the idle thread if nothing else is ready
+    pub fn pop_or_idle(&mut self) -> Option<Box<Thread>> {
+        self.pop(Priority::Low).or_else(|| self.idle[cpu()].take())
+    }
+
+    pub fn find_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        let running = self.running.iter_mut().flatten();
+        let switched_out = self.switched_out.iter_mut().flatten();
+        let ready = self.queues.iter_mut().flatten();
+        let blocked = self.blocked.values_mut();
+
+        running
+            .chain(switched_out)
+            .chain(ready)
+            .chain(blocked)
+            .find(|thread| thread.id == id)
+            .map(|thread| &mut **thread)
+    }
+
+    // NOTE: every thread, with the CPU it is running on if any
+    fn threads(&self) -> impl Iterator<Item = (&Thread, Option<usize>)> {
+        let running = self
+            .running
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, thread)| Some((thread.as_deref()?, Some(cpu))));
+        let others = self
+            .switched_out
+            .iter()
+            .chain(&self.idle)
+            .flatten()
+            .chain(self.queues.iter().flatten())
+            .chain(self.blocked.values())
+            .map(|thread| (&**thread, None));
+
+        running.chain(others)
+    }
+
+    // NOTE: threads that have not exited, idle threads aside
+    pub fn len(&self) -> usize {
+        let ready: usize = self.queues.iter().map(VecDeque::len).sum();
+        let on_cpus = self
+            .running
+            .iter()
+            .chain(&self.switched_out)
+            .flatten()
+            .filter(|thread| !thread.idle && thread.state != State::Dead)
+            .count();
+
+        ready + self.blocked.len() + on_cpus
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    Thread { id: ThreadId, name: &'static str },
+    Async(TaskId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskStats {
+    pub kind: TaskKind,
+    pub cpu_ns: u64,
+}
+
+// NOTE: CPU time of every live thread and async task, busiest first. Running threads include
+// their current slice. Async tasks are timed per poll, so that time also counts for the thread
+// polling them, and a task in the middle of a poll is left out
+pub fn task_stats() -> Vec<TaskStats> {
+    let now = tsc::read();
+    let mut stats: Vec<TaskStats> = interrupts::without_interrupts(|| {
+        super::SCHEDULER
+            .lock()
+            .threads()
+            .map(|(thread, cpu)| {
+                let slice = cpu.and_then(percpu::cpu).map_or(0, |cpu| {
+                    now.saturating_sub(cpu.scheduler.switched_at.load(Ordering::Relaxed))
+                });
+
+                TaskStats {
+                    kind: TaskKind::Thread {
+                        id: thread.id,
+                        name: thread.name,
+                    },
+                    cpu_ns: tsc::cycles_to_ns(thread.cycles + slice),
+                }
+            })
+            .collect()
+    });
+
+    stats.extend(
+        executor::task_cycles()
+            .into_iter()
+            .map(|(id, cycles)| TaskStats {
+                kind: TaskKind::Async(id),
+                cpu_ns: tsc::cycles_to_ns(cycles),
+            }),
+    );
+    stats.sort_by_key(|task| Reverse(task.cpu_ns));
+
+    stats
+}
+
+#[test_case]
+fn test_task_stats() {
+    let id = super::current_id().unwrap();
+
+    crate::time::delay_us(2000);
+
+    let stats = task_stats();
+    let current = stats
+        .iter()
+        .find(|task| matches!(task.kind, TaskKind::Thread { id: thread, .. } if thread == id))
+        .unwrap();
+
+    assert!(current.cpu_ns >= 2_000_000);
+    assert!(stats
+        .windows(2)
+        .all(|pair| pair[0].cpu_ns >= pair[1].cpu_ns));
+}
@@ -0,0 +1,150 @@
+mod timer;
+
+use crate::{hpet, pit, tsc};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+pub use timer::{sleep, sleep_until, timeout, Elapsed, Sleep, Timeout};
+
+// NOTE: rate of the timer interrupt driving TICKS
+pub const TIMER_HZ: u64 = 1000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: to be called by the timer interrupt handler only
+pub fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    timer::expire(now);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// NOTE: tick the next sleep or timeout expires at, if any
+pub fn next_deadline() -> Option<u64> {
+    timer::next_deadline()
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TIMER_HZ
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    Ticks,
+    Hpet,
+    Tsc,
+}
+
+static CLOCK_SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Ticks as u8);
+// NOTE: TSC value monotonic_ns counts from
+static TSC_START: AtomicU64 = AtomicU64::new(0);
+
+// NOTE: picks the cheapest clock that is known to be steady, after the HPET is set up; the
+// TSC is calibrated either way for measuring short intervals
+pub fn init() {
+    tsc::calibrate();
+
+    let source = match (tsc::is_invariant(), hpet::is_present()) {
+        (true, _) => {
+            TSC_START.store(tsc::read(), Ordering::Relaxed);
+
+            ClockSource::Tsc
+        }
+        (false, true) => ClockSource::Hpet,
+        (false, false) => ClockSource::Ticks,
+    };
+
+    CLOCK_SOURCE.store(source as u8, Ordering::Relaxed);
+}
+
+pub fn clock_source() -> ClockSource {
+    match CLOCK_SOURCE.load(Ordering::Relaxed) {
+        2 => ClockSource::Tsc,
+        1 => ClockSource::Hpet,
+        _ => ClockSource::Ticks,
+    }
+}
+
+// NOTE: nanoseconds since boot, never goes backwards, safe from interrupt handlers
+pub fn monotonic_ns() -> u64 {
+    match clock_source() {
+        ClockSource::Tsc => {
+            tsc::cycles_to_ns(tsc::read().saturating_sub(TSC_START.load(Ordering::Relaxed)))
+        }
+        ClockSource::Hpet => hpet::nanoseconds(),
+        ClockSource::Ticks => ticks() * (1_000_000_000 / TIMER_HZ),
+    }
+}
+
+// NOTE: busy waits, usable with interrupts disabled and before the timer runs
+pub fn delay_us(us: u64) {
+    if clock_source() == ClockSource::Ticks {
+        let mut remaining = us;
+
+        while remaining > 0 {
+            let chunk = remaining.min(pit::MAX_POLLED_WAIT_US as u64);
+
+            pit::wait_polled_us(chunk as u32);
+            remaining -= chunk;
+        }
+
+        return;
+    }
+
+    let deadline = monotonic_ns().saturating_add(us.saturating_mul(1000));
+
+    while monotonic_ns() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+// NOTE: halts until enough ticks have passed, async code should await `sleep` instead; falls
+// back to delay_us when interrupts are disabled since no tick would arrive
+pub fn sleep_ms(ms: u64) {
+    if !x86_64::instructions::interrupts::are_enabled() {
+        return delay_us(ms.saturating_mul(1000));
+    }
+
+    let deadline = ticks().saturating_add(ms.saturating_mul(TIMER_HZ).div_ceil(1000));
+
+    while ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_timer_ticks() {
+    let start = ticks();
+
+    while ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_monotonic_ns() {
+    let start = monotonic_ns();
+    let start_ticks = ticks();
+
+    while ticks() < start_ticks + 2 {
+        x86_64::instructions::hlt();
+    }
+
+    assert!(monotonic_ns() > start);
+}
+
+#[test_case]
+fn test_sleep_and_delay() {
+    let start = monotonic_ns();
+
+    sleep_ms(5);
+    assert!(monotonic_ns() - start >= 4_000_000);
+
+    let start = monotonic_ns();
+
+    delay_us(2000);
+    assert!(monotonic_ns() - start >= 1_000_000);
+}
@@ -0,0 +1,203 @@
+use super::{ticks, TIMER_HZ};
+use crate::sync::IrqMutex;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+// NOTE: one slot per tick, timers further out than a lap wait in their slot for later laps
+const SLOTS: usize = 256;
+
+struct Entry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+// NOTE: hashed timer wheel, the timer interrupt only ever looks at the slot of the current
+// tick
+static WHEEL: IrqMutex<[Vec<Entry>; SLOTS]> = IrqMutex::new([const { Vec::new() }; SLOTS]);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// NOTE: called from time::tick, wakes everything due by `now`
+pub(super) fn expire(now: u64) {
+    let mut wheel = WHEEL.lock();
+
+    wheel[now as usize % SLOTS].retain(|entry| {
+        if entry.deadline > now {
+            return true;
+        }
+
+        entry.waker.wake_by_ref();
+
+        false
+    });
+}
+
+// NOTE: earliest tick a sleeping future waits for, scans the whole wheel
+pub(super) fn next_deadline() -> Option<u64> {
+    let wheel = WHEEL.lock();
+
+    wheel.iter().flatten().map(|entry| entry.deadline).min()
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos().saturating_mul(TIMER_HZ as u128);
+
+    ticks.div_ceil(1_000_000_000).min(u64::MAX as u128) as u64
+}
+
+// NOTE: completes at the first tick at or after the deadline; registering and cancelling
+// happen on poll and drop, so an unpolled Sleep costs nothing
+pub struct Sleep {
+    deadline: u64,
+    id: Option<u64>,
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(ticks().saturating_add(duration_to_ticks(duration)))
+}
+
+// NOTE: `deadline` is in ticks since boot
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep { deadline, id: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            WHEEL.lock()[self.deadline as usize % SLOTS].retain(|entry| entry.id != id);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let deadline = self.deadline;
+        let id = self.id;
+
+        // NOTE: the deadline check and the insert can't be split by the tick that expires it
+        let registered = interrupts::without_interrupts(|| {
+            let mut wheel = WHEEL.lock();
+            let slot = &mut wheel[deadline as usize % SLOTS];
+
+            if ticks() >= deadline {
+                if let Some(id) = id {
+                    slot.retain(|entry| entry.id != id);
+                }
+
+                return None;
+            }
+
+            match id.and_then(|id| slot.iter_mut().find(|entry| entry.id == id)) {
+                Some(entry) => {
+                    if !entry.waker.will_wake(cx.waker()) {
+                        entry.waker = cx.waker().clone();
+                    }
+
+                    Some(entry.id)
+                }
+                None => {
+                    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+                    slot.push(Entry {
+                        id,
+                        deadline,
+                        waker: cx.waker().clone(),
+                    });
+
+                    Some(id)
+                }
+            }
+        });
+
+        match registered {
+            Some(id) => {
+                self.id = Some(id);
+
+                Poll::Pending
+            }
+            None => {
+                self.id = None;
+
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+// NOTE: `future` raced against a sleep, the future is dropped when the time runs out
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // NOTE: neither field is moved out, the future's pin carries over
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        Pin::new(&mut this.sleep).poll(cx).map(|_| Err(Elapsed))
+    }
+}
+
+#[test_case]
+fn test_sleep_and_timeout() {
+    use crate::task;
+    use core::sync::atomic::AtomicBool;
+
+    static SLEPT: AtomicBool = AtomicBool::new(false);
+    static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+    let start = ticks();
+
+    task::spawn(async {
+        sleep(Duration::from_millis(5)).await;
+        SLEPT.store(true, Ordering::Relaxed);
+    });
+    task::spawn(async {
+        let result = timeout(Duration::from_millis(2), core::future::pending::<()>()).await;
+
+        TIMED_OUT.store(result == Err(Elapsed), Ordering::Relaxed);
+    });
+
+    while !(SLEPT.load(Ordering::Relaxed) && TIMED_OUT.load(Ordering::Relaxed)) {
+        task::run_ready();
+        x86_64::instructions::hlt();
+
+        assert!(ticks() - start < 1000, "timers never fired");
+    }
+
+    assert!(ticks() - start >= 5);
+}
@@ -0,0 +1,53 @@
+use crate::{hpet, pit};
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const CPUID_EXTENDED_MAX: u32 = 0x8000_0000;
+const CPUID_ADVANCED_POWER: u32 = 0x8000_0007;
+// NOTE: CPUID 0x80000007, EDX bit 8
+const INVARIANT_TSC: u32 = 1 << 8;
+
+const CALIBRATION_MS: u32 = 20;
+
+// NOTE: 0 until calibrated
+static FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// NOTE: an invariant TSC ticks at a constant rate across P-, C- and T-states
+pub fn is_invariant() -> bool {
+    __cpuid(CPUID_EXTENDED_MAX).eax >= CPUID_ADVANCED_POWER
+        && __cpuid(CPUID_ADVANCED_POWER).edx & INVARIANT_TSC != 0
+}
+
+// NOTE: measures the TSC against the HPET when there is one, else against the PIT's wait
+pub fn calibrate() -> u64 {
+    let hpet_start = hpet::counter();
+    let start = read();
+
+    pit::wait_polled_ms(CALIBRATION_MS);
+
+    let elapsed = read() - start;
+    let elapsed_ns = match hpet::is_present() {
+        true => hpet::ticks_to_ns(hpet::counter().wrapping_sub(hpet_start)),
+        false => CALIBRATION_MS as u64 * 1_000_000,
+    };
+    let hz = (elapsed as u128 * 1_000_000_000 / elapsed_ns.max(1) as u128) as u64;
+
+    FREQUENCY_HZ.store(hz, Ordering::Relaxed);
+
+    hz
+}
+
+pub fn frequency_hz() -> u64 {
+    FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    match frequency_hz() {
+        0 => 0,
+        hz => (cycles as u128 * 1_000_000_000 / hz as u128) as u64,
+    }
+}
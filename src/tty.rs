@@ -0,0 +1,400 @@
+use crate::keyboard;
+use crate::print;
+use crate::process::{self, ProcessId};
+use crate::signal;
+use crate::sync::{IrqMutex, WaitQueue};
+use crate::syscall::abi::{SIGINT, TTY_CANONICAL, TTY_ECHO};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use futures_util::stream::StreamExt;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+// NOTE: a canonical line longer than this takes no more characters until Enter
+pub const MAX_LINE: usize = 1024;
+// NOTE: typed ahead and not read yet, anything past it is dropped
+pub const MAX_INPUT: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtyError {
+    // NOTE: a signal became deliverable to the reader's process while it waited
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    // NOTE: input is handed out a line at a time and can be edited until Enter
+    pub canonical: bool,
+    pub echo: bool,
+}
+
+impl Mode {
+    pub fn from_bits(bits: u64) -> Mode {
+        Mode {
+            canonical: bits & TTY_CANONICAL != 0,
+            echo: bits & TTY_ECHO != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        let mut bits = 0;
+
+        if self.canonical {
+            bits |= TTY_CANONICAL;
+        }
+
+        if self.echo {
+            bits |= TTY_ECHO;
+        }
+
+        bits
+    }
+}
+
+impl Default for Mode {
+    fn default() -> Mode {
+        Mode {
+            canonical: true,
+            echo: true,
+        }
+    }
+}
+
+// NOTE: what a typed byte did besides what it echoed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Buffered,
+    // NOTE: there is something for readers now
+    Readable,
+    // NOTE: Ctrl+C, which throws away the line being edited in either mode
+    Interrupt,
+}
+
+// NOTE: turns typed bytes into what readers get. In canonical mode a line is edited in `line`
+// and moves to `input` with Enter, or without a newline through Ctrl+D, which on an empty line
+// makes the next read return end of file
+#[derive(Debug)]
+pub struct LineDiscipline {
+    mode: Mode,
+    line: Vec<u8>,
+    input: VecDeque<u8>,
+    end_of_file: bool,
+}
+
+impl LineDiscipline {
+    pub const fn new() -> LineDiscipline {
+        LineDiscipline {
+            mode: Mode {
+                canonical: true,
+                echo: true,
+            },
+            line: Vec::new(),
+            input: VecDeque::new(),
+            end_of_file: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // NOTE: a half edited line is handed out as it is when leaving canonical mode
+    pub fn set_mode(&mut self, mode: Mode) -> Mode {
+        if !mode.canonical {
+            self.flush_line();
+        }
+
+        core::mem::replace(&mut self.mode, mode)
+    }
+
+    fn flush_line(&mut self) {
+        let room = MAX_INPUT.saturating_sub(self.input.len());
+        let line = core::mem::take(&mut self.line);
+
+        self.input.extend(line.into_iter().take(room));
+    }
+
+    // NOTE: what to show for `byte` goes to `echo`
+    pub fn receive(&mut self, byte: u8, echo: &mut Vec<u8>) -> Input {
+        if byte == CTRL_C {
+            self.line.clear();
+
+            if self.mode.echo {
+                echo.extend_from_slice(b"^C\n");
+            }
+
+            return Input::Interrupt;
+        }
+
+        if !self.mode.canonical {
+            if self.input.len() < MAX_INPUT {
+                self.input.push_back(byte);
+            }
+
+            if self.mode.echo {
+                echo.push(byte);
+            }
+
+            return Input::Readable;
+        }
+
+        match byte {
+            BACKSPACE | DELETE => {
+                // NOTE: a whole UTF-8 character goes, continuation bytes first
+                while let Some(removed) = self.line.pop() {
+                    if removed & 0xc0 != 0x80 {
+                        if self.mode.echo {
+                            echo.push(BACKSPACE);
+                        }
+
+                        break;
+                    }
+                }
+
+                Input::Buffered
+            }
+            CTRL_D => {
+                if self.line.is_empty() {
+                    self.end_of_file = true;
+                }
+
+                self.flush_line();
+
+                Input::Readable
+            }
+            b'\n' => {
+                self.line.push(b'\n');
+                self.flush_line();
+
+                if self.mode.echo {
+                    echo.push(b'\n');
+                }
+
+                Input::Readable
+            }
+            byte if self.line.len() < MAX_LINE - 1 => {
+                self.line.push(byte);
+
+                if self.mode.echo {
+                    echo.push(byte);
+                }
+
+                Input::Buffered
+            }
+            _ => Input::Buffered,
+        }
+    }
+
+    // NOTE: None when there is nothing to read yet; a canonical read stops after a newline
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if self.input.is_empty() {
+            return match core::mem::take(&mut self.end_of_file) || buffer.is_empty() {
+                true => Some(0),
+                false => None,
+            };
+        }
+
+        let mut read = 0;
+
+        while read < buffer.len() {
+            let Some(byte) = self.input.pop_front() else {
+                break;
+            };
+
+            buffer[read] = byte;
+            read += 1;
+
+            if self.mode.canonical && byte == b'\n' {
+                break;
+            }
+        }
+
+        Some(read)
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> LineDiscipline {
+        LineDiscipline::new()
+    }
+}
+
+// NOTE: a terminal, the keyboard feeding its line discipline and its output going to the
+// console. Processes reach it through File::Console
+pub struct Tty {
+    discipline: IrqMutex<LineDiscipline>,
+    readable: WaitQueue,
+    // NOTE: the process Ctrl+C interrupts, 0 for none
+    foreground: AtomicU64,
+}
+
+static CONSOLE: Tty = Tty::new();
+
+pub fn console() -> &'static Tty {
+    &CONSOLE
+}
+
+impl Tty {
+    pub const fn new() -> Tty {
+        Tty {
+            discipline: IrqMutex::new(LineDiscipline::new()),
+            readable: WaitQueue::new(),
+            foreground: AtomicU64::new(0),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.discipline.lock().mode()
+    }
+
+    pub fn set_mode(&self, mode: Mode) -> Mode {
+        let old = self.discipline.lock().set_mode(mode);
+
+        self.readable.notify_all();
+
+        old
+    }
+
+    pub fn set_foreground(&self, id: Option<ProcessId>) {
+        self.foreground
+            .store(id.map_or(0, |id| id.as_u64()), Ordering::Relaxed);
+    }
+
+    pub fn foreground(&self) -> Option<ProcessId> {
+        match self.foreground.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(ProcessId::from_u64(id)),
+        }
+    }
+
+    // NOTE: typed input, Ctrl+C sends SIGINT to the foreground process and wakes readers so
+    // one blocked in the foreground gets to act on it
+    pub fn receive(&self, character: char) {
+        let mut bytes = [0; 4];
+        let mut echo = Vec::new();
+        let mut wake = false;
+
+        for byte in character.encode_utf8(&mut bytes).bytes() {
+            match self.discipline.lock().receive(byte, &mut echo) {
+                Input::Buffered => {}
+                Input::Readable => wake = true,
+                Input::Interrupt => {
+                    let _ = self
+                        .foreground()
+                        .and_then(process::find)
+                        .map(|process| process.signal(SIGINT));
+
+                    wake = true;
+                }
+            }
+        }
+
+        if !echo.is_empty() {
+            self.write(&echo);
+        }
+
+        if wake {
+            self.readable.notify_all();
+        }
+    }
+
+    pub fn try_read(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.discipline.lock().read(buffer)
+    }
+
+    // NOTE: blocks until there is input, a line in canonical mode
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, TtyError> {
+        let mut result = None;
+
+        self.readable.wait_until(|| {
+            result = match self.try_read(buffer) {
+                Some(read) => Some(Ok(read)),
+                None if signal::has_deliverable() => Some(Err(TtyError::Interrupted)),
+                None => None,
+            };
+
+            result.is_some()
+        });
+
+        result.unwrap_or(Ok(0))
+    }
+
+    // NOTE: text that isn't UTF-8 is printed lossily
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        print!("{}", String::from_utf8_lossy(bytes));
+
+        bytes.len()
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Tty {
+        Tty::new()
+    }
+}
+
+// NOTE: hands typed characters to the console TTY, the default consumer of the keyboard
+pub async fn run_console() {
+    let Some(mut keys) = keyboard::keys() else {
+        return;
+    };
+
+    while let Some(key) = keys.next().await {
+        if let Some(character) = key.character() {
+            console().receive(character);
+        }
+    }
+}
+
+#[test_case]
+fn test_canonical_editing() {
+    let mut discipline = LineDiscipline::new();
+    let mut echo = Vec::new();
+    let mut buffer = [0u8; 16];
+
+    for byte in *b"lx" {
+        assert_eq!(discipline.receive(byte, &mut echo), Input::Buffered);
+    }
+
+    discipline.receive(BACKSPACE, &mut echo);
+
+    for byte in *b"s\n" {
+        discipline.receive(byte, &mut echo);
+    }
+
+    assert_eq!(echo, b"lx\x08s\n");
+    assert_eq!(discipline.read(&mut buffer), Some(3));
+    assert_eq!(&buffer[..3], b"ls\n");
+    assert_eq!(discipline.read(&mut buffer), None);
+
+    discipline.receive(b'q', &mut echo);
+
+    assert_eq!(discipline.receive(CTRL_C, &mut echo), Input::Interrupt);
+    assert_eq!(discipline.receive(CTRL_D, &mut echo), Input::Readable);
+    assert_eq!(discipline.read(&mut buffer), Some(0));
+    assert_eq!(discipline.read(&mut buffer), None);
+}
+
+#[test_case]
+fn test_raw_mode() {
+    let mut discipline = LineDiscipline::new();
+    let mut echo = Vec::new();
+    let mut buffer = [0u8; 4];
+
+    discipline.receive(b'a', &mut echo);
+    discipline.set_mode(Mode {
+        canonical: false,
+        echo: false,
+    });
+
+    assert_eq!(discipline.receive(BACKSPACE, &mut echo), Input::Readable);
+    assert_eq!(echo, b"a");
+    assert_eq!(discipline.read(&mut buffer), Some(2));
+    assert_eq!(&buffer[..2], b"a\x08");
+    assert_eq!(Mode::from_bits(TTY_ECHO).bits(), TTY_ECHO);
+}
@@ -0,0 +1,222 @@
+use crate::memory::cow::COPY_ON_WRITE;
+use crate::memory::{phys_to_virt, physical_memory_offset};
+use crate::process;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::ptr::addr_of;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{
+    OffsetPageTable, Page, PageTable, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+// NOTE: end of the lower half, user pointers have to stay below it
+pub(crate) const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    // NOTE: wraps around or reaches into the upper half
+    OutOfRange,
+    // NOTE: neither mapped for ring 3 nor in one of the process's areas
+    NotMapped,
+    ReadOnly,
+    // NOTE: the memory went away between the check and the copy, e.g. another thread unmapped
+    // it, or the area's page couldn't be backed
+    Fault,
+    // NOTE: no NUL where a string had to end
+    TooLong,
+}
+
+// NOTE: rep movsb with the faulting instruction known to the page fault handler, which
+// resumes a fault in it at the end with rcx still counting what was left
+global_asm!(
+    ".global rustos_copy_user",
+    ".global rustos_copy_user_copy",
+    ".global rustos_copy_user_done",
+    "rustos_copy_user:",
+    "mov rcx, rdx",
+    "rustos_copy_user_copy:",
+    "rep movsb",
+    "rustos_copy_user_done:",
+    "mov rax, rcx",
+    "ret",
+);
+
+extern "C" {
+    // NOTE: returns how many bytes weren't copied
+    fn rustos_copy_user(destination: *mut u8, source: *const u8, len: usize) -> usize;
+    static rustos_copy_user_copy: u8;
+    static rustos_copy_user_done: u8;
+}
+
+// NOTE: for the page fault handler once nothing could resolve the fault, true when it hit a
+// user copy, which then returns early instead
+pub(crate) fn fixup_fault(stack_frame: &mut InterruptStackFrame) -> bool {
+    let copy = addr_of!(rustos_copy_user_copy) as u64;
+    let done = VirtAddr::new(addr_of!(rustos_copy_user_done) as u64);
+
+    if stack_frame.instruction_pointer.as_u64() != copy {
+        return false;
+    }
+
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = done)
+    };
+
+    true
+}
+
+// NOTE: every page of the range has to be mapped for ring 3 in the active address space or
+// lie in one of the current process's areas, which the copy then faults in. Copy-on-write
+// pages count as writable, the copy resolves them the same way
+fn check(address: u64, len: usize, write: bool) -> Result<(), AccessError> {
+    let end = address
+        .checked_add(len as u64)
+        .filter(|end| *end <= USER_END)
+        .ok_or(AccessError::OutOfRange)?;
+
+    if len == 0 {
+        return Ok(());
+    }
+
+    let (level_4, _) = Cr3::read();
+    let table: &mut PageTable = unsafe { &mut *phys_to_virt(level_4.start_address()).as_mut_ptr() };
+    let mapper = unsafe { OffsetPageTable::new(table, physical_memory_offset()) };
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(address));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    let process = process::current();
+
+    for page in Page::range_inclusive(first, last) {
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. }
+                if flags.contains(PageTableFlags::USER_ACCESSIBLE) =>
+            {
+                flags
+            }
+            TranslateResult::NotMapped => process
+                .as_ref()
+                .and_then(|process| {
+                    let vmas = process.vmas();
+
+                    vmas.find(page.start_address()).map(|area| area.flags)
+                })
+                .ok_or(AccessError::NotMapped)?,
+            _ => return Err(AccessError::NotMapped),
+        };
+
+        if write && !flags.intersects(PageTableFlags::WRITABLE | COPY_ON_WRITE) {
+            return Err(AccessError::ReadOnly);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn copy_from_user(destination: &mut [u8], source: u64) -> Result<(), AccessError> {
+    check(source, destination.len(), false)?;
+
+    let left = unsafe {
+        rustos_copy_user(
+            destination.as_mut_ptr(),
+            source as *const u8,
+            destination.len(),
+        )
+    };
+
+    match left {
+        0 => Ok(()),
+        _ => Err(AccessError::Fault),
+    }
+}
+
+pub fn copy_to_user(destination: u64, source: &[u8]) -> Result<(), AccessError> {
+    check(destination, source.len(), true)?;
+
+    let left = unsafe { rustos_copy_user(destination as *mut u8, source.as_ptr(), source.len()) };
+
+    match left {
+        0 => Ok(()),
+        _ => Err(AccessError::Fault),
+    }
+}
+
+// NOTE: the `len` bytes at `address` copied into the kernel
+pub fn read_user(address: u64, len: usize) -> Result<Vec<u8>, AccessError> {
+    let mut buffer = alloc::vec![0; len];
+
+    copy_from_user(&mut buffer, address)?;
+
+    Ok(buffer)
+}
+
+// NOTE: a NUL terminated string without its NUL, at most `max` bytes. Read up to a page end at
+// a time, so it may end right before memory that isn't mapped
+pub fn read_string(address: u64, max: usize) -> Result<Vec<u8>, AccessError> {
+    let mut string = Vec::new();
+    let mut address = address;
+
+    loop {
+        let len = (4096 - address % 4096).min((max + 1 - string.len()) as u64);
+        let chunk = read_user(address, len as usize)?;
+
+        if let Some(end) = chunk.iter().position(|&byte| byte == 0) {
+            string.extend_from_slice(&chunk[..end]);
+
+            return Ok(string);
+        }
+
+        string.extend_from_slice(&chunk);
+
+        if string.len() > max {
+            return Err(AccessError::TooLong);
+        }
+
+        address += len;
+    }
+}
+
+// NOTE: plain data only, anything with invalid bit patterns isn't safe to read from ring 3
+pub fn read_value<T: Copy + Default>(address: u64) -> Result<T, AccessError> {
+    let mut value = T::default();
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, size_of::<T>()) };
+
+    copy_from_user(bytes, address)?;
+
+    Ok(value)
+}
+
+pub fn write_value<T: Copy>(address: u64, value: &T) -> Result<(), AccessError> {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+
+    copy_to_user(address, bytes)
+}
+
+#[test_case]
+fn test_kernel_pointers_rejected() {
+    let mut buffer = [0u8; 8];
+    let kernel = buffer.as_ptr() as u64;
+
+    assert_eq!(
+        copy_from_user(&mut buffer, 0xffff_8000_0000_0000),
+        Err(AccessError::OutOfRange)
+    );
+    assert_eq!(
+        copy_from_user(&mut buffer, u64::MAX - 2),
+        Err(AccessError::OutOfRange)
+    );
+    assert_eq!(
+        copy_to_user(0x7000_0000_0000, &buffer),
+        Err(AccessError::NotMapped)
+    );
+    assert_eq!(
+        read_value::<u64>(kernel).err(),
+        Some(AccessError::NotMapped)
+    );
+    assert_eq!(copy_to_user(0x7000_0000_0000, &[]), Ok(()));
+}
@@ -0,0 +1,171 @@
+use crate::gdt;
+use crate::memory::address_space::AddressSpace;
+use crate::memory::paging::MapError;
+use crate::memory::phys_to_virt;
+use crate::process::{self, Process, ProcessError};
+use crate::syscall::abi;
+use crate::thread;
+use alloc::sync::Arc;
+use core::arch::asm;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+// NOTE: user programs live in level 4 entry 32, which the kernel leaves unused so every
+// address space has it private; code at the start, the stack growing down 1 GiB above
+pub const USER_CODE_BASE: u64 = 0x1000_0000_0000;
+pub const USER_STACK_TOP: u64 = 0x1000_4000_0000;
+// NOTE: code, data and the heap after them end here, 1 MiB short of the stack
+pub const USER_DATA_END: u64 = USER_STACK_TOP - 1024 * 1024;
+// NOTE: anonymous mappings go in the rest of the level 4 entry, from 1 GiB above the stack
+pub const USER_MMAP_BASE: u64 = 0x1000_8000_0000;
+pub const USER_REGION_END: u64 = 0x1080_0000_0000;
+// NOTE: the signal trampoline page, a page above the stack top
+pub const USER_TRAMPOLINE: u64 = USER_STACK_TOP + 4096;
+
+pub const MAX_CODE_SIZE: usize = 1024 * 1024;
+const USER_STACK_PAGES: u64 = 16;
+
+// NOTE: what a program that faulted in ring 3 exits with, killed by the SIGSEGV the fault
+// raises unless it handles that
+pub const FAULT_EXIT_CODE: i32 = process::exit_code(abi::SIGSEGV);
+
+// NOTE: IF set, nothing else
+const USER_RFLAGS: u64 = 0x202;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserError {
+    TooLarge(usize),
+    Map(MapError),
+    Process(ProcessError),
+}
+
+impl From<MapError> for UserError {
+    fn from(error: MapError) -> UserError {
+        UserError::Map(error)
+    }
+}
+
+impl From<ProcessError> for UserError {
+    fn from(error: ProcessError) -> UserError {
+        UserError::Process(error)
+    }
+}
+
+// NOTE: where a loaded program starts and its heap begins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserImage {
+    pub entry: VirtAddr,
+    // NOTE: where rsp starts, below the arguments of an ELF executable
+    pub stack_top: VirtAddr,
+    pub heap_start: VirtAddr,
+}
+
+// NOTE: maps `code` read-only and executable at USER_CODE_BASE and a writable, non-executable
+// stack below USER_STACK_TOP, all of it reachable from ring 3
+pub fn load(space: &mut AddressSpace, code: &[u8]) -> Result<UserImage, UserError> {
+    if code.len() > MAX_CODE_SIZE {
+        return Err(UserError::TooLarge(code.len()));
+    }
+
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let base = VirtAddr::new(USER_CODE_BASE);
+
+    for (index, chunk) in code.chunks(4096).enumerate() {
+        let page = Page::containing_address(base + index as u64 * 4096);
+        let frame = space.map(page, user)?;
+        let destination = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), destination, chunk.len()) };
+    }
+
+    Ok(UserImage {
+        entry: base,
+        stack_top: map_stack(space)?,
+        heap_start: (base + code.len() as u64).align_up(4096u64),
+    })
+}
+
+// NOTE: the user stack below USER_STACK_TOP, returns its top
+pub fn map_stack(space: &mut AddressSpace) -> Result<VirtAddr, MapError> {
+    let stack_top = VirtAddr::new(USER_STACK_TOP);
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE;
+
+    for index in 1..=USER_STACK_PAGES {
+        space.map(Page::containing_address(stack_top - index * 4096), flags)?;
+    }
+
+    Ok(stack_top)
+}
+
+/// # Safety
+///
+/// `entry` and `stack_top` must be mapped user accessible in the active address space, and the
+/// current thread must own a kernel stack for the interrupts that arrive in ring 3.
+pub unsafe fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let kernel_stack = thread::kernel_stack_top().expect("ring 3 needs a thread's kernel stack");
+    let code = gdt::USER_CODE_SELECTOR.0 as u64;
+    let data = gdt::USER_DATA_SELECTOR.0 as u64;
+
+    gdt::set_kernel_stack(kernel_stack);
+
+    // NOTE: iretq pops rip, cs, rflags, rsp and ss; the kernel stack is left behind for good.
+    // Ring 3 gets the user GS base, see percpu::KernelGs
+    asm!(
+        "cli",
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        "swapgs",
+        "iretq",
+        data = in(reg) data,
+        stack = in(reg) stack_top.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
+
+// NOTE: runs `code` in ring 3 as a process of its own with a fresh address space
+pub fn spawn(name: &'static str, code: &[u8]) -> Result<Arc<Process>, UserError> {
+    let mut space = AddressSpace::new()?;
+    let image = load(&mut space, code)?;
+
+    Ok(process::start(name, space, image)?)
+}
+
+#[test_case]
+fn test_fault_in_ring_3_ends_thread() {
+    // NOTE: ud2
+    let program = spawn("ud2", &[0x0f, 0x0b]).unwrap();
+
+    assert_eq!(program.wait(), FAULT_EXIT_CODE);
+}
+
+#[test_case]
+fn test_user_cannot_touch_kernel_memory() {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static TARGET: AtomicU64 = AtomicU64::new(0);
+
+    let address = TARGET.as_ptr() as u64;
+    let mut code = [0u8; 16];
+
+    // NOTE: movabs rax, address; mov byte [rax], 1; nop; ud2
+    code[..2].copy_from_slice(&[0x48, 0xb8]);
+    code[2..10].copy_from_slice(&address.to_le_bytes());
+    code[10..14].copy_from_slice(&[0xc6, 0x00, 0x01, 0x90]);
+    code[14..16].copy_from_slice(&[0x0f, 0x0b]);
+
+    let program = spawn("poke", &code).unwrap();
+
+    assert_eq!(program.wait(), FAULT_EXIT_CODE);
+    assert_eq!(TARGET.load(Ordering::Relaxed), 0);
+}
@@ -1,7 +1,23 @@
+mod ansi;
+mod text_mode;
+
+pub use text_mode::{set_text_mode, text_mode, TextMode};
+
+use crate::console::Console;
+use crate::cp437;
+use crate::sync::IrqMutex;
+use crate::vga_registers::{attribute_read, attribute_write, crtc_read, crtc_write};
+use ansi::{Action, Params, Parser};
 use core::fmt;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
+use x86_64::instructions::interrupts;
+
+#[cfg(test)]
+use crate::{println, println_colored};
 
 #[repr(u8)]
 #[allow(dead_code)]
@@ -25,12 +41,39 @@ pub enum Color {
     WHITE,
 }
 
+// NOTE: in VGA index order
+const COLORS: [Color; 16] = [
+    Color::BLACK,
+    Color::BLUE,
+    Color::GREEN,
+    Color::CYAN,
+    Color::RED,
+    Color::MAGENTA,
+    Color::BROWN,
+    Color::LIGHTGRAY,
+    Color::DARKGRAY,
+    Color::LIGHTBLUE,
+    Color::LIGHTGREEN,
+    Color::LIGHTCYAN,
+    Color::LIGHTRED,
+    Color::PINK,
+    Color::YELLOW,
+    Color::WHITE,
+];
+
+impl Color {
+    // NOTE: the colors of the VGA palette, in index order
+    pub fn all() -> impl Iterator<Item = Color> {
+        COLORS.into_iter()
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ColorMode(u8);
 
 impl ColorMode {
-    fn new(foreground: Color, background: Color) -> ColorMode {
+    const fn new(foreground: Color, background: Color) -> ColorMode {
         // NOTE:
         //  - background * (2 ** 4)
         //  - bitwise OR
@@ -40,6 +83,74 @@ impl ColorMode {
         //      0111
         ColorMode((background as u8) << 4 | (foreground as u8))
     }
+
+    fn set_foreground(&mut self, value: u8) {
+        self.0 = (self.0 & 0xf0) | (value & 0x0f);
+    }
+
+    fn set_background(&mut self, value: u8) {
+        self.0 = (self.0 & 0x0f) | (value & 0x0f) << 4;
+    }
+
+    fn colors(self) -> (Color, Color) {
+        (
+            COLORS[(self.0 & 0x0f) as usize],
+            COLORS[(self.0 >> 4) as usize],
+        )
+    }
+}
+
+const DEFAULT_COLOR_MODE: ColorMode = ColorMode::new(Color::YELLOW, Color::BLACK);
+
+// NOTE: ANSI color indexes (black, red, green, yellow, blue, magenta, cyan, white) in VGA order
+const ANSI_COLORS: [Color; 8] = [
+    Color::BLACK,
+    Color::RED,
+    Color::GREEN,
+    Color::BROWN,
+    Color::BLUE,
+    Color::MAGENTA,
+    Color::CYAN,
+    Color::LIGHTGRAY,
+];
+
+// NOTE: bit 3 of a VGA color selects its bright variant
+const BRIGHT: u8 = 0x08;
+// NOTE: attribute bit 7, blinks the character unless bright backgrounds are enabled (in which
+// case it is bit 3 of the background)
+const BLINK: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharStyle {
+    pub foreground: Color,
+    pub background: Color,
+    pub blink: bool,
+}
+
+impl CharStyle {
+    pub const fn new(foreground: Color, background: Color) -> CharStyle {
+        CharStyle {
+            foreground,
+            background,
+            blink: false,
+        }
+    }
+
+    pub const fn blinking(mut self) -> CharStyle {
+        self.blink = true;
+        self
+    }
+}
+
+impl From<CharStyle> for ColorMode {
+    fn from(style: CharStyle) -> ColorMode {
+        let color_mode = ColorMode::new(style.foreground, style.background);
+
+        match style.blink {
+            true => ColorMode(color_mode.0 | BLINK),
+            false => color_mode,
+        }
+    }
 }
 
 // NOTE: repr(C) ensures struct field order
@@ -51,19 +162,132 @@ struct ScreenChar {
 }
 
 // NOTE: default matrix size of the I/O VGA buffer
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+const DEFAULT_BUFFER_HEIGHT: usize = 25;
+const DEFAULT_BUFFER_WIDTH: usize = 80;
+
+// NOTE: screens are stored at the size of the largest text mode, see TextMode
+const MAX_BUFFER_HEIGHT: usize = 50;
+const MAX_BUFFER_WIDTH: usize = 80;
+
+// NOTE: size of the text mode the hardware is currently in, see set_text_mode
+static ROWS: AtomicUsize = AtomicUsize::new(DEFAULT_BUFFER_HEIGHT);
+static COLUMNS: AtomicUsize = AtomicUsize::new(DEFAULT_BUFFER_WIDTH);
 
 #[repr(transparent)]
 struct Buffer {
     // NOTE: volatile is necessary to prevent compiler optimizations
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [Volatile<ScreenChar>; MAX_BUFFER_WIDTH * MAX_BUFFER_HEIGHT],
+}
+
+impl Buffer {
+    // NOTE: rows are packed back to back, as wide as the current text mode
+    fn at(&mut self, row: usize, col: usize) -> &mut Volatile<ScreenChar> {
+        &mut self.chars[row * COLUMNS.load(Ordering::Relaxed) + col]
+    }
+}
+
+const VGA_BUFFER_ADDRESS: usize = 0xb8000;
+
+// NOTE: only the visible writer may touch the VGA memory, see Writer::flush
+fn hardware() -> &'static mut Buffer {
+    unsafe { &mut *(VGA_BUFFER_ADDRESS as *mut Buffer) }
+}
+
+// NOTE: RAM shadow of a screen, writers draw here and flush to the VGA memory
+pub(crate) struct ScreenStorage([[ScreenChar; MAX_BUFFER_WIDTH]; MAX_BUFFER_HEIGHT]);
+
+pub(crate) const BLANK_SCREEN: ScreenStorage =
+    ScreenStorage([[BLANK_CHAR; MAX_BUFFER_WIDTH]; MAX_BUFFER_HEIGHT]);
+
+// NOTE: number of lines kept after they are pushed off the top of the screen
+const SCROLLBACK_LINES: usize = 500;
+
+const BLANK_CHAR: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_mode: DEFAULT_COLOR_MODE,
+};
+
+struct Scrollback {
+    lines: [[ScreenChar; MAX_BUFFER_WIDTH]; SCROLLBACK_LINES],
+    // NOTE: index of the oldest line inside the ring
+    start: usize,
+    len: usize,
 }
 
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback {
+            lines: [[BLANK_CHAR; MAX_BUFFER_WIDTH]; SCROLLBACK_LINES],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, line: [ScreenChar; MAX_BUFFER_WIDTH]) {
+        let end = (self.start + self.len) % SCROLLBACK_LINES;
+
+        self.lines[end] = line;
+
+        // NOTE: once the ring is full the oldest line is overwritten
+        if self.len < SCROLLBACK_LINES {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % SCROLLBACK_LINES;
+        }
+    }
+
+    fn line(&self, index: usize) -> &[ScreenChar; MAX_BUFFER_WIDTH] {
+        &self.lines[(self.start + index) % SCROLLBACK_LINES]
+    }
+}
+
+// NOTE: kept outside of WRITER as a plain static so the ring never lives on the stack
+static SCROLLBACK: Mutex<Scrollback> = Mutex::new(Scrollback::new());
+
+static mut PRIMARY_SCREEN: ScreenStorage = BLANK_SCREEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    // NOTE: after every write_str call, so print! without a newline still shows up
+    Write,
+    Line,
+    // NOTE: only on an explicit Writer::flush
+    Manual,
+}
+
+const ALL_ROWS_DIRTY: u64 = u64::MAX;
+
+const DEFAULT_TAB_WIDTH: usize = 8;
+const BACKSPACE: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusBar {
+    Top,
+    Bottom,
+}
+
+const STATUS_COLOR_MODE: ColorMode = ColorMode::new(Color::BLACK, Color::LIGHTGRAY);
+
 pub struct Writer {
+    row_position: usize,
     col_position: usize,
     color_mode: ColorMode,
-    buffer: &'static mut Buffer,
+    // NOTE: how many lines the view is scrolled back, 0 means the live screen
+    scroll_offset: usize,
+    scrollback: Option<&'static Mutex<Scrollback>>,
+    ansi: Parser,
+    tab_width: usize,
+    // NOTE: a status row is never scrolled nor cleared by regular output
+    status_bar: Option<StatusBar>,
+    flush_mode: FlushMode,
+    // NOTE: one bit per row changed since the last flush
+    dirty_rows: u64,
+    // NOTE: only the visible writer flushes to the VGA memory and drives the hardware cursor
+    visible: bool,
+    // NOTE: size of the text mode, at most MAX_BUFFER_HEIGHT x MAX_BUFFER_WIDTH
+    height: usize,
+    width: usize,
+    shadow: &'static mut ScreenStorage,
 }
 
 impl fmt::Write for Writer {
@@ -75,87 +299,720 @@ impl fmt::Write for Writer {
 }
 
 impl Writer {
-    fn clear_row(&mut self, row: usize) {
-        let blank_char = ScreenChar {
+    fn new(shadow: &'static mut ScreenStorage, visible: bool) -> Writer {
+        Writer {
+            row_position: 0,
+            col_position: 0,
+            color_mode: DEFAULT_COLOR_MODE,
+            scroll_offset: 0,
+            scrollback: None,
+            ansi: Parser::new(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            status_bar: None,
+            flush_mode: FlushMode::Write,
+            dirty_rows: 0,
+            visible,
+            height: ROWS.load(Ordering::Relaxed),
+            width: COLUMNS.load(Ordering::Relaxed),
+            shadow,
+        }
+    }
+
+    pub(crate) fn offscreen(shadow: &'static mut ScreenStorage) -> Writer {
+        Writer::new(shadow, false)
+    }
+
+    // NOTE: hands the VGA memory over to `other`, both screens keep their own contents
+    pub(crate) fn swap_screen(&mut self, other: &mut Writer) {
+        self.scroll_to_bottom();
+        other.scroll_to_bottom();
+
+        core::mem::swap(&mut self.visible, &mut other.visible);
+
+        let shown = if self.visible { self } else { other };
+
+        shown.dirty_rows = ALL_ROWS_DIRTY;
+        shown.flush();
+        shown.update_cursor();
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // NOTE: (rows, columns) of the whole screen, including a status bar
+    pub fn size(&self) -> (usize, usize) {
+        (self.height, self.width)
+    }
+
+    // NOTE: lines that no longer fit above the cursor are pushed into the scrollback, a status
+    // bar keeps its contents
+    pub(crate) fn resize(&mut self, height: usize, width: usize) {
+        let height = height.clamp(2, MAX_BUFFER_HEIGHT);
+        let width = width.clamp(1, MAX_BUFFER_WIDTH);
+
+        self.scroll_to_bottom();
+
+        let status = self.status_row().map(|row| {
+            let line = self.shadow.0[row];
+
+            self.clear_row(row);
+            line
+        });
+        let end = match self.status_bar {
+            Some(StatusBar::Bottom) => height - 1,
+            _ => height,
+        };
+
+        while self.row_position >= end {
+            self.scroll();
+            self.row_position -= 1;
+        }
+
+        let blank_char = self.blank_char();
+
+        for (row, line) in self.shadow.0.iter_mut().enumerate() {
+            let from = if row < self.height.min(height) {
+                self.width
+            } else {
+                0
+            };
+
+            line[from.min(width)..].fill(blank_char);
+        }
+
+        self.height = height;
+        self.width = width;
+
+        if let (Some(row), Some(line)) = (self.status_row(), status) {
+            self.shadow.0[row] = line;
+        }
+
+        self.move_to(self.row_position, self.col_position);
+        self.dirty_rows = ALL_ROWS_DIRTY;
+        self.flush();
+        self.update_cursor();
+    }
+
+    pub fn char_at(&self, row: usize, col: usize) -> u8 {
+        self.shadow.0[row][col].ascii_character
+    }
+
+    pub fn set_flush_mode(&mut self, mode: FlushMode) {
+        self.flush_mode = mode;
+        self.flush();
+    }
+
+    // NOTE: copies the rows changed since the last flush to the VGA memory
+    pub fn flush(&mut self) {
+        if !self.visible || self.scroll_offset != 0 || self.dirty_rows == 0 {
+            return;
+        }
+
+        let buffer = hardware();
+
+        for row in 0..self.height {
+            if self.dirty_rows & (1 << row) == 0 {
+                continue;
+            }
+
+            for col in 0..self.width {
+                buffer.at(row, col).write(self.shadow.0[row][col]);
+            }
+        }
+
+        self.dirty_rows = 0;
+    }
+
+    pub fn clear_screen(&mut self) {
+        self.scroll_to_bottom();
+
+        let (first, end) = self.text_rows();
+
+        for row in first..end {
+            self.clear_row(row);
+        }
+
+        self.move_to(first, 0);
+        self.update_cursor();
+        self.auto_flush();
+    }
+
+    pub fn enable_status_bar(&mut self, position: StatusBar) {
+        self.scroll_to_bottom();
+        self.status_bar = Some(position);
+        self.set_status("");
+
+        // NOTE: keep the cursor out of the status row
+        self.move_to(self.row_position, self.col_position);
+        self.update_cursor();
+    }
+
+    pub fn disable_status_bar(&mut self) {
+        if let Some(row) = self.status_row() {
+            self.status_bar = None;
+            self.clear_row(row);
+            self.auto_flush();
+        }
+    }
+
+    // NOTE: text longer than a row is cut, shorter text is padded with blanks
+    pub fn set_status(&mut self, status: &str) {
+        let Some(row) = self.status_row() else {
+            return;
+        };
+        let mut bytes = status.bytes();
+
+        for col in 0..self.width {
+            let ascii_character = match bytes.next() {
+                Some(byte @ 0x20..=0x7e) => byte,
+                Some(_) => 0x3f,
+                None => b' ',
+            };
+
+            self.put_char(
+                row,
+                col,
+                ScreenChar {
+                    ascii_character,
+                    color_mode: STATUS_COLOR_MODE,
+                },
+            );
+        }
+
+        // NOTE: the status row stays up to date even while scrolled back
+        if self.scroll_offset == 0 {
+            self.auto_flush();
+        } else if self.visible {
+            for col in 0..self.width {
+                hardware().at(row, col).write(self.shadow.0[row][col]);
+            }
+        }
+    }
+
+    fn status_row(&self) -> Option<usize> {
+        match self.status_bar {
+            Some(StatusBar::Top) => Some(0),
+            Some(StatusBar::Bottom) => Some(self.height - 1),
+            None => None,
+        }
+    }
+
+    // NOTE: (first, end) rows available to regular output, end is exclusive
+    fn text_rows(&self) -> (usize, usize) {
+        match self.status_bar {
+            Some(StatusBar::Top) => (1, self.height),
+            Some(StatusBar::Bottom) => (0, self.height - 1),
+            None => (0, self.height),
+        }
+    }
+
+    // NOTE: tab stops are placed every `width` columns
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.max(1);
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.col_position)
+    }
+
+    // NOTE: out of range coordinates are clamped to the screen
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.move_to(row, col);
+        self.update_cursor();
+    }
+
+    // NOTE: draws at fixed coordinates and puts the cursor back where it was
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        let (saved_row, saved_col) = self.position();
+
+        self.move_to(row, col);
+        self.write_string(s);
+        self.row_position = saved_row;
+        self.col_position = saved_col;
+        self.update_cursor();
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_mode = ColorMode::new(foreground, background);
+    }
+
+    pub fn set_style(&mut self, style: CharStyle) {
+        self.color_mode = style.into();
+    }
+
+    // NOTE: the previous style is restored once the closure returns
+    pub fn with_style<F>(&mut self, style: CharStyle, f: F)
+    where
+        F: FnOnce(&mut Writer),
+    {
+        let previous = self.color_mode;
+
+        self.set_style(style);
+        f(self);
+        self.color_mode = previous;
+    }
+
+    // NOTE: the previous color is restored once the closure returns
+    pub fn with_color<F>(&mut self, foreground: Color, background: Color, f: F)
+    where
+        F: FnOnce(&mut Writer),
+    {
+        let previous = self.color_mode;
+
+        self.set_color(foreground, background);
+        f(self);
+        self.color_mode = previous;
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let Some(history) = self.scrollback else {
+            return;
+        };
+        let scrollback = history.lock();
+
+        self.scroll_offset = (self.scroll_offset + lines).min(scrollback.len);
+        self.render_scrollback(&scrollback);
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let Some(history) = self.scrollback else {
+            return;
+        };
+
+        if self.scroll_offset == 0 {
+            return;
+        }
+
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+
+        if self.scroll_offset == 0 {
+            self.dirty_rows = ALL_ROWS_DIRTY;
+            self.flush();
+        } else {
+            self.render_scrollback(&history.lock());
+        }
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        let offset = self.scroll_offset;
+
+        self.scroll_down(offset);
+    }
+
+    // NOTE: intended for Shift+PageUp / Shift+PageDown handlers
+    pub fn page_up(&mut self) {
+        let (first, end) = self.text_rows();
+
+        self.scroll_up(end - first - 1);
+    }
+
+    pub fn page_down(&mut self) {
+        let (first, end) = self.text_rows();
+
+        self.scroll_down(end - first - 1);
+    }
+
+    // NOTE: the visible rows are a window over history lines followed by the shadow screen,
+    // drawn straight to the VGA memory so the shadow keeps the live contents
+    fn render_scrollback(&self, scrollback: &Scrollback) {
+        if !self.visible {
+            return;
+        }
+
+        let buffer = hardware();
+        let (first_row, end_row) = self.text_rows();
+        let first = scrollback.len - self.scroll_offset;
+
+        for row in first_row..end_row {
+            let index = first + row - first_row;
+            let line = if index < scrollback.len {
+                scrollback.line(index)
+            } else {
+                &self.shadow.0[index - scrollback.len + first_row]
+            };
+
+            for (col, character) in line[..self.width].iter().enumerate() {
+                buffer.at(row, col).write(*character);
+            }
+        }
+    }
+
+    fn put_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.shadow.0[row][col] = character;
+        self.dirty_rows |= 1 << row;
+    }
+
+    fn blank_char(&self) -> ScreenChar {
+        ScreenChar {
             ascii_character: b' ',
             color_mode: self.color_mode,
-        };
+        }
+    }
 
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank_char);
+    fn clear_row(&mut self, row: usize) {
+        self.clear_cols(row, 0, self.width);
+    }
+
+    fn clear_cols(&mut self, row: usize, from: usize, to: usize) {
+        let blank_char = self.blank_char();
+
+        for col in from..to {
+            self.put_char(row, col, blank_char);
+        }
+    }
+
+    fn scroll(&mut self) {
+        let (first, end) = self.text_rows();
+
+        if let Some(history) = self.scrollback {
+            history.lock().push(self.shadow.0[first]);
         }
+
+        self.shadow.0.copy_within(first + 1..end, first);
+        self.dirty_rows = ALL_ROWS_DIRTY;
+        self.clear_row(end - 1);
     }
 
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
+        let (_, end) = self.text_rows();
 
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        if self.row_position < end - 1 {
+            self.row_position += 1;
+        } else {
+            self.scroll();
         }
 
-        self.clear_row(BUFFER_HEIGHT - 1);
         self.col_position = 0;
+
+        if self.flush_mode == FlushMode::Line {
+            self.flush();
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        let (first, end) = self.text_rows();
+
+        self.row_position = row.clamp(first, end - 1);
+        self.col_position = col.min(self.width - 1);
     }
 
     fn write_byte(&mut self, byte: u8) {
+        // NOTE: new output always snaps the view back to the live screen
+        self.scroll_to_bottom();
+
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.col_position;
-                let color_mode = self.color_mode;
+            b'\r' => self.col_position = 0,
+            b'\t' => {
+                let next_stop = (self.col_position / self.tab_width + 1) * self.tab_width;
 
-                if self.col_position >= BUFFER_WIDTH {
-                    self.new_line();
+                self.clear_cols(
+                    self.row_position,
+                    self.col_position,
+                    next_stop.min(self.width),
+                );
+                self.col_position = next_stop.min(self.width);
+            }
+            BACKSPACE => {
+                if self.col_position > 0 {
+                    self.col_position = self.col_position.min(self.width) - 1;
+                    self.clear_cols(self.row_position, self.col_position, self.col_position + 1);
                 }
+            }
+            byte => self.put_glyph(byte),
+        }
 
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_mode,
-                });
+        self.update_cursor();
+    }
 
-                self.col_position += 1;
-            }
+    // NOTE: writes a CP437 glyph as is, without interpreting control characters
+    fn write_glyph(&mut self, glyph: u8) {
+        self.scroll_to_bottom();
+        self.put_glyph(glyph);
+        self.update_cursor();
+    }
+
+    fn put_glyph(&mut self, glyph: u8) {
+        if self.col_position >= self.width {
+            self.new_line();
+        }
+
+        let row = self.row_position;
+        let col = self.col_position;
+        let color_mode = self.color_mode;
+
+        self.put_char(
+            row,
+            col,
+            ScreenChar {
+                ascii_character: glyph,
+                color_mode,
+            },
+        );
+
+        self.col_position += 1;
+    }
+
+    // NOTE: the cursor stays on the last column while a line is full
+    fn update_cursor(&self) {
+        if !self.visible {
+            return;
         }
+
+        move_cursor(self.row_position, self.col_position.min(self.width - 1));
     }
 
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0x3f), // NOTE: 0x3f == question mark
+        for c in s.chars() {
+            // NOTE: non ASCII characters never take part in escape sequences
+            if !c.is_ascii() {
+                cp437::encode(c, |glyph| self.write_glyph(glyph));
+
+                continue;
+            }
+
+            match self.ansi.advance(c as u8) {
+                Some(Action::Print(byte)) => match byte {
+                    0x20..=0x7e | b'\n' | b'\r' | b'\t' | BACKSPACE => self.write_byte(byte),
+                    _ => self.write_byte(0x3f), // NOTE: 0x3f == question mark
+                },
+                Some(Action::Csi { params, final_byte }) => self.apply_csi(&params, final_byte),
+                None => {}
             }
         }
+
+        self.auto_flush();
+    }
+
+    fn auto_flush(&mut self) {
+        if self.flush_mode == FlushMode::Write {
+            self.flush();
+        }
+    }
+
+    fn apply_csi(&mut self, params: &Params, final_byte: u8) {
+        self.scroll_to_bottom();
+
+        // NOTE: movement counts and positions of 0 behave as 1
+        let count = params.get(0).max(1) as usize;
+
+        match final_byte {
+            b'm' => self.apply_sgr(params),
+            b'H' | b'f' => {
+                let row = params.get(0).max(1) as usize - 1;
+                let col = params.get(1).max(1) as usize - 1;
+
+                self.move_to(row, col);
+            }
+            b'A' => self.move_to(self.row_position.saturating_sub(count), self.col_position),
+            b'B' => self.move_to(self.row_position + count, self.col_position),
+            b'C' => self.move_to(self.row_position, self.col_position + count),
+            b'D' => self.move_to(self.row_position, self.col_position.saturating_sub(count)),
+            b'J' => self.erase_display(params.get(0)),
+            b'K' => self.erase_line(params.get(0)),
+            _ => {}
+        }
+
+        self.update_cursor();
+    }
+
+    fn apply_sgr(&mut self, params: &Params) {
+        if params.as_slice().is_empty() {
+            self.color_mode = DEFAULT_COLOR_MODE;
+        }
+
+        for value in params.as_slice() {
+            match *value {
+                0 => self.color_mode = DEFAULT_COLOR_MODE,
+                1 => self.color_mode.0 |= BRIGHT,
+                5 => self.color_mode.0 |= BLINK,
+                22 => self.color_mode.0 &= !BRIGHT,
+                25 => self.color_mode.0 &= !BLINK,
+                30..=37 => self
+                    .color_mode
+                    .set_foreground(ANSI_COLORS[(*value - 30) as usize] as u8),
+                39 => self.color_mode.set_foreground(DEFAULT_COLOR_MODE.0 & 0x0f),
+                40..=47 => self
+                    .color_mode
+                    .set_background(ANSI_COLORS[(*value - 40) as usize] as u8),
+                49 => self.color_mode.set_background(DEFAULT_COLOR_MODE.0 >> 4),
+                90..=97 => self
+                    .color_mode
+                    .set_foreground(ANSI_COLORS[(*value - 90) as usize] as u8 | BRIGHT),
+                100..=107 => self
+                    .color_mode
+                    .set_background(ANSI_COLORS[(*value - 100) as usize] as u8 | BRIGHT),
+                _ => {}
+            }
+        }
+    }
+
+    // NOTE: 0 = cursor to end, 1 = start to cursor, 2 = everything
+    fn erase_display(&mut self, mode: u16) {
+        let (row, col) = (self.row_position, self.col_position.min(self.width));
+        let (first, end) = self.text_rows();
+
+        match mode {
+            0 => {
+                self.clear_cols(row, col, self.width);
+
+                for row in row + 1..end {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                for row in first..row {
+                    self.clear_row(row);
+                }
+
+                self.clear_cols(row, 0, (col + 1).min(self.width));
+            }
+            2 | 3 => {
+                for row in first..end {
+                    self.clear_row(row);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (self.row_position, self.col_position.min(self.width));
+
+        match mode {
+            0 => self.clear_cols(row, col, self.width),
+            1 => self.clear_cols(row, 0, (col + 1).min(self.width)),
+            2 => self.clear_row(row),
+            _ => {}
+        }
     }
 }
 
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+// NOTE: bit 5 of the cursor start register disables the cursor
+const CURSOR_DISABLE: u8 = 0x20;
+
+// NOTE: start/end are the scanlines (0..=15) the cursor block covers
+pub fn enable_cursor(start: u8, end: u8) {
+    crtc_write(
+        CRTC_CURSOR_START,
+        (crtc_read(CRTC_CURSOR_START) & 0xc0) | (start & 0x1f),
+    );
+    crtc_write(
+        CRTC_CURSOR_END,
+        (crtc_read(CRTC_CURSOR_END) & 0xe0) | (end & 0x1f),
+    );
+}
+
+pub fn disable_cursor() {
+    crtc_write(CRTC_CURSOR_START, CURSOR_DISABLE);
+}
+
+pub fn move_cursor(row: usize, col: usize) {
+    let position = (row * COLUMNS.load(Ordering::Relaxed) + col) as u16;
+
+    crtc_write(CRTC_CURSOR_LOCATION_LOW, (position & 0xff) as u8);
+    crtc_write(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+}
+
+pub fn cursor_position() -> (usize, usize) {
+    let position = (crtc_read(CRTC_CURSOR_LOCATION_HIGH) as usize) << 8
+        | crtc_read(CRTC_CURSOR_LOCATION_LOW) as usize;
+
+    let columns = COLUMNS.load(Ordering::Relaxed);
+
+    (position / columns, position % columns)
+}
+
 // NOTE: lazy_static call is to make a non-const function as const on compile time
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        col_position: 0,
-        color_mode: ColorMode::new(Color::YELLOW, Color::BLACK),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+    pub static ref WRITER: IrqMutex<Writer> = {
+        let mut writer = Writer::new(unsafe { &mut *addr_of_mut!(PRIMARY_SCREEN) }, true);
+
+        // NOTE: keep whatever the bootloader left on screen
+        for row in 0..writer.height {
+            for col in 0..writer.width {
+                writer.shadow.0[row][col] = hardware().at(row, col).read();
+            }
+        }
+
+        writer.row_position = writer.height - 1;
+        writer.scrollback = Some(&SCROLLBACK);
+
+        IrqMutex::new(writer)
+    };
+}
+
+// NOTE: bit 3 of the attribute mode control register picks blinking over bright backgrounds
+const ATTRIBUTE_MODE_CONTROL: u8 = 0x10;
+const ATTRIBUTE_BLINK_ENABLE: u8 = 0x08;
+
+pub fn blink_enabled() -> bool {
+    interrupts::without_interrupts(|| {
+        attribute_read(ATTRIBUTE_MODE_CONTROL) & ATTRIBUTE_BLINK_ENABLE != 0
+    })
+}
+
+// NOTE: when disabled, blinking characters show a bright background instead (16 backgrounds)
+pub fn set_blink_enabled(enabled: bool) {
+    interrupts::without_interrupts(|| {
+        let mode = attribute_read(ATTRIBUTE_MODE_CONTROL);
+        let mode = match enabled {
+            true => mode | ATTRIBUTE_BLINK_ENABLE,
+            false => mode & !ATTRIBUTE_BLINK_ENABLE,
+        };
+
+        attribute_write(ATTRIBUTE_MODE_CONTROL, mode);
     });
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+pub fn clear_screen() {
+    WRITER.lock().clear_screen();
 }
 
-#[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+pub fn set_position(row: usize, col: usize) {
+    WRITER.lock().set_position(row, col);
 }
 
-#[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
+pub fn write_at(row: usize, col: usize, s: &str) {
+    WRITER.lock().write_at(row, col, s);
+}
 
-    WRITER.lock().write_fmt(args).unwrap();
+pub fn set_status(status: &str) {
+    WRITER.lock().set_status(status);
+}
+
+impl Console for Writer {
+    fn write_str(&mut self, s: &str) {
+        self.write_string(s);
+    }
+
+    fn clear(&mut self) {
+        self.clear_screen();
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        Writer::set_color(self, foreground, background);
+    }
+
+    fn color(&self) -> Option<(Color, Color)> {
+        Some(self.color_mode.colors())
+    }
+
+    fn reset_color(&mut self) {
+        self.color_mode = DEFAULT_COLOR_MODE;
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        let (first, end) = self.text_rows();
+
+        (end - first, self.width)
+    }
 }
 
 #[test_case]
@@ -176,9 +1033,245 @@ fn test_println_output() {
 
     println!("{}", s);
 
+    interrupts::without_interrupts(|| {
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = WRITER.lock().shadow.0[DEFAULT_BUFFER_HEIGHT - 2][i];
+
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+    });
+}
+
+#[test_case]
+fn test_println_colored_output() {
+    let s = "colored line";
+
+    println_colored!(Color::RED, Color::WHITE, "{}", s);
+
+    let writer = WRITER.lock();
+
+    for (i, c) in s.chars().enumerate() {
+        let screen_char = writer.shadow.0[DEFAULT_BUFFER_HEIGHT - 2][i];
+
+        assert_eq!(char::from(screen_char.ascii_character), c);
+        assert_eq!(
+            screen_char.color_mode,
+            ColorMode::new(Color::RED, Color::WHITE)
+        );
+    }
+
+    assert_eq!(writer.color_mode, DEFAULT_COLOR_MODE);
+}
+
+#[test_case]
+fn test_scrollback_keeps_pushed_lines() {
+    use core::fmt::Write;
+
+    let s = "line pushed into the scrollback";
+    let mut writer = WRITER.lock();
+
+    writeln!(writer, "\n{}", s).unwrap();
+
+    // NOTE: the marker line leaves the screen before the last newline
+    for _ in 0..DEFAULT_BUFFER_HEIGHT {
+        writeln!(writer).unwrap();
+    }
+
+    writer.scroll_up(2);
+
     for (i, c) in s.chars().enumerate() {
-        let screen_char = WRITER.lock().buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        let screen_char = hardware().at(0, i).read();
 
         assert_eq!(char::from(screen_char.ascii_character), c);
     }
+
+    writer.scroll_to_bottom();
+
+    assert_eq!(writer.scroll_offset, 0);
+    assert_eq!(writer.shadow.0[0][0], BLANK_CHAR);
+}
+
+#[test_case]
+fn test_cursor_follows_writer() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    write!(writer, "\ncursor").unwrap();
+
+    assert_eq!(cursor_position(), (DEFAULT_BUFFER_HEIGHT - 1, 6));
+}
+
+#[test_case]
+fn test_ansi_sgr_and_cursor_position() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    write!(writer, "\x1b[1;1H\x1b[31;44mA\x1b[0mB").unwrap();
+
+    let colored = writer.shadow.0[0][0];
+    let plain = writer.shadow.0[0][1];
+
+    assert_eq!(char::from(colored.ascii_character), 'A');
+    assert_eq!(colored.color_mode, ColorMode::new(Color::RED, Color::BLUE));
+    assert_eq!(char::from(plain.ascii_character), 'B');
+    assert_eq!(plain.color_mode, DEFAULT_COLOR_MODE);
+
+    write!(writer, "\x1b[{};1H", DEFAULT_BUFFER_HEIGHT).unwrap();
+
+    assert_eq!(writer.row_position, DEFAULT_BUFFER_HEIGHT - 1);
+}
+
+#[test_case]
+fn test_manual_flush() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    writeln!(writer).unwrap();
+    writer.set_flush_mode(FlushMode::Manual);
+    write!(writer, "buffered").unwrap();
+
+    assert_eq!(
+        hardware().at(DEFAULT_BUFFER_HEIGHT - 1, 0).read(),
+        BLANK_CHAR
+    );
+
+    writer.flush();
+
+    assert_eq!(
+        hardware()
+            .at(DEFAULT_BUFFER_HEIGHT - 1, 0)
+            .read()
+            .ascii_character,
+        b'b'
+    );
+
+    writer.set_flush_mode(FlushMode::Write);
+}
+
+#[test_case]
+fn test_write_at_and_clear_screen() {
+    let mut writer = WRITER.lock();
+    let position = writer.position();
+
+    writer.write_at(3, 10, "xy");
+
+    assert_eq!(writer.char_at(3, 10), b'x');
+    assert_eq!(writer.char_at(3, 11), b'y');
+    assert_eq!(writer.position(), position);
+
+    writer.clear_screen();
+
+    assert_eq!(writer.position(), (0, 0));
+    assert_eq!(writer.char_at(3, 10), b' ');
+    assert_eq!(hardware().at(3, 10).read(), BLANK_CHAR);
+
+    writer.set_position(DEFAULT_BUFFER_HEIGHT - 1, 0);
+}
+
+#[test_case]
+fn test_status_bar_survives_scrolling() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    writer.enable_status_bar(StatusBar::Top);
+    writer.set_status("ticks: 42");
+
+    for _ in 0..DEFAULT_BUFFER_HEIGHT {
+        writeln!(writer, "scrolling below the status bar").unwrap();
+    }
+
+    assert_eq!(writer.char_at(0, 0), b't');
+    assert_eq!(writer.shadow.0[0][0].color_mode, STATUS_COLOR_MODE);
+    assert_eq!(writer.char_at(1, 0), b's');
+
+    writer.disable_status_bar();
+
+    assert_eq!(writer.char_at(0, 0), b' ');
+}
+
+#[test_case]
+fn test_control_characters() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    write!(writer, "\nab\x08c\tx\rZ").unwrap();
+
+    let row = writer.row_position;
+
+    assert_eq!(writer.char_at(row, 0), b'Z');
+    assert_eq!(writer.char_at(row, 1), b'c');
+    assert_eq!(writer.char_at(row, 2), b' ');
+    assert_eq!(writer.char_at(row, 8), b'x');
+    assert_eq!(writer.position(), (row, 1));
+
+    writer.set_tab_width(4);
+    write!(writer, "\n\tt").unwrap();
+    writer.set_tab_width(DEFAULT_TAB_WIDTH);
+
+    assert_eq!(writer.char_at(writer.row_position, 4), b't');
+}
+
+#[test_case]
+fn test_utf8_to_cp437() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+
+    write!(writer, "\né…█").unwrap();
+
+    let row = writer.row_position;
+    let expected = [0x82, b'.', b'.', b'.', 0xdb];
+
+    for (col, glyph) in expected.iter().enumerate() {
+        assert_eq!(writer.char_at(row, col), *glyph);
+    }
+}
+
+#[test_case]
+fn test_blinking_style() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    let style = CharStyle::new(Color::WHITE, Color::RED).blinking();
+
+    writer.with_style(style, |writer| write!(writer, "\x1b[1;1HA").unwrap());
+    write!(writer, "\x1b[5mB\x1b[25mC\x1b[0m").unwrap();
+
+    assert_eq!(
+        writer.shadow.0[0][0].color_mode.0,
+        0xc0 | Color::WHITE as u8
+    );
+    assert_eq!(
+        writer.shadow.0[0][1].color_mode.0,
+        DEFAULT_COLOR_MODE.0 | BLINK
+    );
+    assert_eq!(writer.shadow.0[0][2].color_mode, DEFAULT_COLOR_MODE);
+    assert_eq!(writer.color_mode, DEFAULT_COLOR_MODE);
+}
+
+#[test_case]
+fn test_resize_keeps_cursor_line() {
+    static mut TEST_SCREEN: ScreenStorage = BLANK_SCREEN;
+
+    let mut writer = Writer::offscreen(unsafe { &mut *addr_of_mut!(TEST_SCREEN) });
+
+    writer.resize(MAX_BUFFER_HEIGHT, MAX_BUFFER_WIDTH);
+
+    for row in 0..40 {
+        writer.write_string(if row == 39 { "last" } else { "line\n" });
+    }
+
+    assert_eq!(writer.position(), (39, 4));
+
+    writer.resize(DEFAULT_BUFFER_HEIGHT, DEFAULT_BUFFER_WIDTH);
+
+    assert_eq!(writer.size(), (DEFAULT_BUFFER_HEIGHT, DEFAULT_BUFFER_WIDTH));
+    assert_eq!(writer.position(), (DEFAULT_BUFFER_HEIGHT - 1, 4));
+    assert_eq!(writer.char_at(DEFAULT_BUFFER_HEIGHT - 1, 0), b'l');
+    assert_eq!(writer.char_at(DEFAULT_BUFFER_HEIGHT - 2, 0), b'l');
 }
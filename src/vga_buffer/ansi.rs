@@ -0,0 +1,151 @@
+// NOTE: subset of ANSI/VT100 escape sequences (ESC [ params final) understood by the writer
+const ESCAPE: u8 = 0x1b;
+const MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Params {
+    const fn new() -> Params {
+        Params {
+            values: [0; MAX_PARAMS],
+            len: 0,
+        }
+    }
+
+    // NOTE: missing parameters read as 0, like VT100 does
+    pub fn get(&self, index: usize) -> u16 {
+        if index < self.len {
+            self.values[index]
+        } else {
+            0
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u16] {
+        &self.values[..self.len]
+    }
+
+    fn push_digit(&mut self, digit: u8) {
+        if self.len == 0 {
+            self.len = 1;
+        }
+
+        let value = &mut self.values[self.len - 1];
+
+        *value = value
+            .saturating_mul(10)
+            .saturating_add((digit - b'0') as u16);
+    }
+
+    fn next(&mut self) {
+        if self.len == 0 {
+            self.len = 1;
+        }
+
+        if self.len < MAX_PARAMS {
+            self.len += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Print(u8),
+    Csi { params: Params, final_byte: u8 },
+}
+
+pub struct Parser {
+    state: State,
+    params: Params,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: Params::new(),
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            State::Ground => match byte {
+                ESCAPE => {
+                    self.state = State::Escape;
+
+                    None
+                }
+                byte => Some(Action::Print(byte)),
+            },
+            State::Escape => {
+                if byte == b'[' {
+                    self.state = State::Csi;
+                    self.params = Params::new();
+                } else {
+                    // NOTE: non CSI escapes are swallowed
+                    self.state = State::Ground;
+                }
+
+                None
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    self.params.push_digit(byte);
+
+                    None
+                }
+                b';' => {
+                    self.params.next();
+
+                    None
+                }
+                // NOTE: private markers such as "?" are accepted and ignored
+                0x3c..=0x3f => None,
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+
+                    Some(Action::Csi {
+                        params: self.params,
+                        final_byte: byte,
+                    })
+                }
+                _ => {
+                    self.state = State::Ground;
+
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[test_case]
+fn test_parser_csi_params() {
+    let mut parser = Parser::new();
+    let mut action = None;
+
+    for byte in b"\x1b[12;;5H" {
+        action = parser.advance(*byte);
+    }
+
+    match action {
+        Some(Action::Csi { params, final_byte }) => {
+            assert_eq!(final_byte, b'H');
+            assert_eq!(params.as_slice(), &[12, 0, 5]);
+        }
+        _ => panic!("expected a CSI action"),
+    }
+
+    assert_eq!(parser.advance(b'a'), Some(Action::Print(b'a')));
+}
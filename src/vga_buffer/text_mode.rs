@@ -0,0 +1,118 @@
+use super::{COLUMNS, CRTC_CURSOR_END, CRTC_CURSOR_START, ROWS};
+use crate::console::{console, CONSOLE_COUNT};
+use crate::framebuffer::{default_font, font_8x8, Font};
+use crate::vga_registers::{crtc_read, crtc_write, graphics_write, sequencer_write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::interrupts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TextMode {
+    // NOTE: 8x16 font, what the BIOS leaves us in
+    Text80x25,
+    // NOTE: 8x8 font on the same 400 scanlines
+    Text80x50,
+}
+
+impl TextMode {
+    // NOTE: (rows, columns)
+    pub const fn dimensions(self) -> (usize, usize) {
+        match self {
+            TextMode::Text80x25 => (25, 80),
+            TextMode::Text80x50 => (50, 80),
+        }
+    }
+
+    fn font(self) -> Font {
+        match self {
+            TextMode::Text80x25 => default_font(),
+            TextMode::Text80x50 => font_8x8(),
+        }
+    }
+}
+
+static CURRENT_MODE: AtomicU8 = AtomicU8::new(TextMode::Text80x25 as u8);
+
+pub fn text_mode() -> TextMode {
+    match CURRENT_MODE.load(Ordering::Relaxed) {
+        mode if mode == TextMode::Text80x50 as u8 => TextMode::Text80x50,
+        _ => TextMode::Text80x25,
+    }
+}
+
+const FONT_ADDRESS: usize = 0xa0000;
+// NOTE: plane 2 holds one 32 byte slot per character, whatever the font height
+const FONT_SLOT_SIZE: usize = 32;
+
+const SEQUENCER_MAP_MASK: u8 = 0x02;
+const SEQUENCER_MEMORY_MODE: u8 = 0x04;
+const GRAPHICS_READ_MAP: u8 = 0x04;
+const GRAPHICS_MODE: u8 = 0x05;
+const GRAPHICS_MISC: u8 = 0x06;
+const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+
+// NOTE: maps plane 2 linearly at 0xa0000, writes the glyphs and restores the text mode setup
+fn load_font(font: &Font) {
+    sequencer_write(SEQUENCER_MAP_MASK, 0x04);
+    sequencer_write(SEQUENCER_MEMORY_MODE, 0x07);
+    graphics_write(GRAPHICS_READ_MAP, 0x02);
+    graphics_write(GRAPHICS_MODE, 0x00);
+    graphics_write(GRAPHICS_MISC, 0x04);
+
+    for index in 0..=255u8 {
+        let glyph = font.glyph(index);
+        let slot = (FONT_ADDRESS + index as usize * FONT_SLOT_SIZE) as *mut u8;
+
+        for line in 0..FONT_SLOT_SIZE {
+            let bits = if line < font.height {
+                glyph[line * font.bytes_per_row()]
+            } else {
+                0
+            };
+
+            unsafe { core::ptr::write_volatile(slot.add(line), bits) };
+        }
+    }
+
+    sequencer_write(SEQUENCER_MAP_MASK, 0x03);
+    sequencer_write(SEQUENCER_MEMORY_MODE, 0x03);
+    graphics_write(GRAPHICS_READ_MAP, 0x00);
+    graphics_write(GRAPHICS_MODE, 0x10);
+    graphics_write(GRAPHICS_MISC, 0x0e);
+}
+
+// NOTE: the cursor keeps its enabled/disabled state and covers the last two scanlines
+fn set_char_height(height: u8) {
+    crtc_write(
+        CRTC_MAX_SCAN_LINE,
+        (crtc_read(CRTC_MAX_SCAN_LINE) & 0xe0) | (height - 1),
+    );
+    crtc_write(
+        CRTC_CURSOR_START,
+        (crtc_read(CRTC_CURSOR_START) & 0xe0) | (height - 2),
+    );
+    crtc_write(
+        CRTC_CURSOR_END,
+        (crtc_read(CRTC_CURSOR_END) & 0xe0) | (height - 1),
+    );
+}
+
+// NOTE: every virtual console is resized, output that no longer fits goes to the scrollback
+pub fn set_text_mode(mode: TextMode) {
+    interrupts::without_interrupts(|| {
+        let font = mode.font();
+        let (rows, columns) = mode.dimensions();
+
+        load_font(&font);
+        set_char_height(font.height as u8);
+
+        ROWS.store(rows, Ordering::Relaxed);
+        COLUMNS.store(columns, Ordering::Relaxed);
+        CURRENT_MODE.store(mode as u8, Ordering::Relaxed);
+
+        // NOTE: same lock order as console::switch_console
+        for index in 0..CONSOLE_COUNT {
+            console(index).lock().resize(rows, columns);
+        }
+    });
+}
@@ -0,0 +1,152 @@
+use x86_64::instructions::port::Port;
+
+// NOTE: VGA I/O ports, the CRT controller sits at 0x3d4/0x3d5 in color mode
+const MISC_WRITE_PORT: u16 = 0x3c2;
+const SEQUENCER_INDEX_PORT: u16 = 0x3c4;
+const SEQUENCER_DATA_PORT: u16 = 0x3c5;
+const GRAPHICS_INDEX_PORT: u16 = 0x3ce;
+const GRAPHICS_DATA_PORT: u16 = 0x3cf;
+const ATTRIBUTE_PORT: u16 = 0x3c0;
+const ATTRIBUTE_READ_PORT: u16 = 0x3c1;
+const INPUT_STATUS_PORT: u16 = 0x3da;
+const DAC_WRITE_INDEX_PORT: u16 = 0x3c8;
+const DAC_DATA_PORT: u16 = 0x3c9;
+const CRTC_INDEX_PORT: u16 = 0x3d4;
+const CRTC_DATA_PORT: u16 = 0x3d5;
+
+// NOTE: bit 5 of the attribute index keeps the display enabled
+const ATTRIBUTE_PALETTE_ENABLE: u8 = 0x20;
+
+fn indexed_read(index_port: u16, data_port: u16, index: u8) -> u8 {
+    let mut index_port = Port::<u8>::new(index_port);
+    let mut data_port = Port::<u8>::new(data_port);
+
+    unsafe {
+        index_port.write(index);
+
+        data_port.read()
+    }
+}
+
+fn indexed_write(index_port: u16, data_port: u16, index: u8, value: u8) {
+    let mut index_port = Port::<u8>::new(index_port);
+    let mut data_port = Port::<u8>::new(data_port);
+
+    unsafe {
+        index_port.write(index);
+        data_port.write(value);
+    }
+}
+
+pub fn crtc_read(index: u8) -> u8 {
+    indexed_read(CRTC_INDEX_PORT, CRTC_DATA_PORT, index)
+}
+
+pub fn crtc_write(index: u8, value: u8) {
+    indexed_write(CRTC_INDEX_PORT, CRTC_DATA_PORT, index, value);
+}
+
+pub fn sequencer_read(index: u8) -> u8 {
+    indexed_read(SEQUENCER_INDEX_PORT, SEQUENCER_DATA_PORT, index)
+}
+
+pub fn sequencer_write(index: u8, value: u8) {
+    indexed_write(SEQUENCER_INDEX_PORT, SEQUENCER_DATA_PORT, index, value);
+}
+
+pub fn graphics_write(index: u8, value: u8) {
+    indexed_write(GRAPHICS_INDEX_PORT, GRAPHICS_DATA_PORT, index, value);
+}
+
+pub fn misc_write(value: u8) {
+    unsafe { Port::<u8>::new(MISC_WRITE_PORT).write(value) };
+}
+
+// NOTE: reading the input status register resets the attribute flip-flop to "index"
+fn reset_attribute_flip_flop() {
+    unsafe { Port::<u8>::new(INPUT_STATUS_PORT).read() };
+}
+
+pub fn attribute_read(index: u8) -> u8 {
+    let mut attribute_port = Port::<u8>::new(ATTRIBUTE_PORT);
+    let mut read_port = Port::<u8>::new(ATTRIBUTE_READ_PORT);
+
+    reset_attribute_flip_flop();
+
+    unsafe {
+        attribute_port.write(index | ATTRIBUTE_PALETTE_ENABLE);
+
+        read_port.read()
+    }
+}
+
+pub fn attribute_write(index: u8, value: u8) {
+    let mut attribute_port = Port::<u8>::new(ATTRIBUTE_PORT);
+
+    reset_attribute_flip_flop();
+
+    unsafe {
+        attribute_port.write(index | ATTRIBUTE_PALETTE_ENABLE);
+        attribute_port.write(value);
+    }
+}
+
+// NOTE: DAC components are 6 bit wide (0..=63)
+pub fn dac_write(index: u8, red: u8, green: u8, blue: u8) {
+    let mut index_port = Port::<u8>::new(DAC_WRITE_INDEX_PORT);
+    let mut data_port = Port::<u8>::new(DAC_DATA_PORT);
+
+    unsafe {
+        index_port.write(index);
+        data_port.write(red);
+        data_port.write(green);
+        data_port.write(blue);
+    }
+}
+
+pub struct ModeRegisters {
+    pub misc: u8,
+    pub sequencer: [u8; 5],
+    pub crtc: [u8; 25],
+    pub graphics: [u8; 9],
+    pub attribute: [u8; 21],
+}
+
+// NOTE: CRTC registers 0..=7 are write protected by bit 7 of register 0x11
+const CRTC_HORIZONTAL_BLANK_END: u8 = 0x03;
+const CRTC_VERTICAL_RETRACE_END: u8 = 0x11;
+
+pub fn write_mode(registers: &ModeRegisters) {
+    misc_write(registers.misc);
+
+    for (index, value) in registers.sequencer.iter().enumerate() {
+        sequencer_write(index as u8, *value);
+    }
+
+    crtc_write(
+        CRTC_HORIZONTAL_BLANK_END,
+        crtc_read(CRTC_HORIZONTAL_BLANK_END) | 0x80,
+    );
+    crtc_write(
+        CRTC_VERTICAL_RETRACE_END,
+        crtc_read(CRTC_VERTICAL_RETRACE_END) & !0x80,
+    );
+
+    for (index, value) in registers.crtc.iter().enumerate() {
+        let value = match index as u8 {
+            CRTC_HORIZONTAL_BLANK_END => value | 0x80,
+            CRTC_VERTICAL_RETRACE_END => value & !0x80,
+            _ => *value,
+        };
+
+        crtc_write(index as u8, value);
+    }
+
+    for (index, value) in registers.graphics.iter().enumerate() {
+        graphics_write(index as u8, *value);
+    }
+
+    for (index, value) in registers.attribute.iter().enumerate() {
+        attribute_write(index as u8, *value);
+    }
+}
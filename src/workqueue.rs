@@ -0,0 +1,241 @@
+use crate::allocator;
+use crate::sync::{IrqMutex, WaitQueue};
+use crate::thread::{self, Priority};
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const SYSTEM_QUEUE_SIZE: usize = 64;
+
+type Work = Box<dyn FnOnce() + Send>;
+
+struct Pending<const SIZE: usize> {
+    work: [Option<Work>; SIZE],
+    head: usize,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorkqueueStats {
+    pub queued: usize,
+    pub completed: usize,
+    pub dropped: usize,
+    pub pending: usize,
+    // NOTE: most items that were ever waiting at once
+    pub peak: usize,
+}
+
+// NOTE: work queued from any context, interrupt handlers included, and run later by a worker
+// thread with interrupts enabled, in the order it was scheduled. At most SIZE items wait at a
+// time
+pub struct Workqueue<const SIZE: usize> {
+    pending: IrqMutex<Pending<SIZE>>,
+    ready: WaitQueue,
+    queued: AtomicUsize,
+    completed: AtomicUsize,
+    dropped: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl<const SIZE: usize> Workqueue<SIZE> {
+    pub const fn new() -> Workqueue<SIZE> {
+        Workqueue {
+            pending: IrqMutex::new(Pending {
+                work: [const { None }; SIZE],
+                head: 0,
+                len: 0,
+            }),
+            ready: WaitQueue::new(),
+            queued: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    // NOTE: safe from interrupt handlers, returns false and drops the work when the queue is
+    // full or the closure can't be boxed; closures capturing nothing never allocate
+    pub fn schedule(&self, work: impl FnOnce() + Send + 'static) -> bool {
+        let Ok(work) = allocator::try_box(work) else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+
+            return false;
+        };
+
+        let queued = {
+            let mut pending = self.pending.lock();
+            let full = pending.len == SIZE;
+
+            if !full {
+                let slot = (pending.head + pending.len) % SIZE;
+
+                pending.work[slot] = Some(work);
+                pending.len += 1;
+                self.peak.fetch_max(pending.len, Ordering::Relaxed);
+            }
+
+            !full
+        };
+
+        match queued {
+            true => {
+                self.queued.fetch_add(1, Ordering::Relaxed);
+                self.ready.notify_one();
+            }
+            false => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        queued
+    }
+
+    fn pop(&self) -> Option<Work> {
+        let mut pending = self.pending.lock();
+
+        if pending.len == 0 {
+            return None;
+        }
+
+        let head = pending.head;
+        let work = pending.work[head].take();
+
+        pending.head = (head + 1) % SIZE;
+        pending.len -= 1;
+
+        work
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().len
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> WorkqueueStats {
+        WorkqueueStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            dropped: self.dropped(),
+            pending: self.len(),
+            peak: self.peak.load(Ordering::Relaxed),
+        }
+    }
+
+    // NOTE: runs until the queue is empty, including work scheduled while running; returns
+    // how many items ran. The lock is never held while work runs
+    pub fn run(&self) -> usize {
+        let mut count = 0;
+
+        while let Some(work) = self.pop() {
+            work();
+            count += 1;
+            self.completed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        count
+    }
+
+    // NOTE: body of a worker thread, sleeps whenever the queue is empty
+    pub fn run_worker(&self) -> ! {
+        loop {
+            self.run();
+            self.ready.wait_until(|| !self.is_empty());
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for Workqueue<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static SYSTEM: Workqueue<SYSTEM_QUEUE_SIZE> = Workqueue::new();
+
+// NOTE: starts the worker of the system queue, after thread::init; it runs at high priority
+// so work deferred by interrupt handlers doesn't wait behind busy threads
+pub fn init() {
+    thread::spawn_with_priority("kworker", Priority::High, || SYSTEM.run_worker())
+        .expect("workqueue worker has no stack");
+}
+
+// NOTE: queues `work` on the system queue
+pub fn defer(work: impl FnOnce() + Send + 'static) -> bool {
+    SYSTEM.schedule(work)
+}
+
+// NOTE: runs the system queue on the calling thread instead of waiting for the worker
+pub fn run_pending() -> usize {
+    SYSTEM.run()
+}
+
+pub fn has_pending() -> bool {
+    !SYSTEM.is_empty()
+}
+
+pub fn dropped() -> usize {
+    SYSTEM.dropped()
+}
+
+pub fn stats() -> WorkqueueStats {
+    SYSTEM.stats()
+}
+
+#[cfg(test)]
+static TEST_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+#[test_case]
+fn test_workqueue_order() {
+    static QUEUE: Workqueue<2> = Workqueue::new();
+
+    fn first() {
+        assert_eq!(TEST_RUNS.fetch_add(1, Ordering::Relaxed), 0);
+    }
+
+    fn second() {
+        assert_eq!(TEST_RUNS.fetch_add(1, Ordering::Relaxed), 1);
+    }
+
+    TEST_RUNS.store(0, Ordering::Relaxed);
+
+    assert!(QUEUE.schedule(first));
+    assert!(QUEUE.schedule(second));
+    assert!(!QUEUE.schedule(second));
+    assert_eq!(QUEUE.dropped(), 1);
+    assert_eq!(QUEUE.run(), 2);
+    assert!(QUEUE.is_empty());
+    assert_eq!(TEST_RUNS.load(Ordering::Relaxed), 2);
+}
+
+#[test_case]
+fn test_deferred_closure_runs_on_worker() {
+    use core::sync::atomic::AtomicU64;
+
+    static WORKER: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    let before = stats();
+    let current = thread::current_id().unwrap();
+
+    assert!(defer(move || {
+        let id = thread::current_id().unwrap();
+
+        assert_ne!(id, current);
+        WORKER.store(id.as_u64(), Ordering::Relaxed);
+    }));
+
+    while WORKER.load(Ordering::Relaxed) == u64::MAX {
+        thread::yield_now();
+    }
+
+    let after = stats();
+
+    assert_eq!(after.queued, before.queued + 1);
+    assert_eq!(after.completed, before.completed + 1);
+    assert!(after.peak >= 1);
+}
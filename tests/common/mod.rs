@@ -0,0 +1,245 @@
+// NOTE: helpers and checks the filesystem tests share, each file with `mod common;` uses some
+#![allow(dead_code)]
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use rustos::block::{self, BlockDevice, BlockError};
+use rustos::fs::{FileSystem, FsError, InodeKind, OpenFlags, SeekFrom, Vfs};
+use rustos::sync::Mutex;
+
+pub const CREATE: OpenFlags = OpenFlags {
+    create: true,
+    truncate: true,
+    ..OpenFlags::READ_WRITE
+};
+
+// NOTE: a block device keeping only the blocks that aren't all zeroes, for volumes far larger
+// than the heap
+pub struct SparseDisk {
+    block_size: usize,
+    block_count: u64,
+    blocks: Mutex<BTreeMap<u64, Vec<u8>>>,
+}
+
+impl SparseDisk {
+    // NOTE: zeroed
+    pub fn new(block_size: usize, block_count: u64) -> SparseDisk {
+        SparseDisk {
+            block_size,
+            block_count,
+            blocks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // NOTE: from what build.rs makes of an image: its number of 512 byte sectors, then each
+    // sector that isn't all zeroes after its index, all u64s little endian
+    pub fn from_sectors(block_size: usize, sectors: &[u8]) -> SparseDisk {
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(sectors[offset..offset + 8].try_into().unwrap());
+        let disk = SparseDisk::new(block_size, u64_at(0) * 512 / block_size as u64);
+
+        for offset in (8..sectors.len()).step_by(8 + 512) {
+            let sector = &sectors[offset + 8..offset + 8 + 512];
+
+            block::write_bytes(&disk, u64_at(offset) * 512, sector).unwrap();
+        }
+
+        disk
+    }
+}
+
+impl BlockDevice for SparseDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self.block_size, self.block_count, start, buffer.len())?;
+
+        let blocks = self.blocks.lock();
+
+        for (block, chunk) in (start..).zip(buffer.chunks_exact_mut(self.block_size)) {
+            match blocks.get(&block) {
+                Some(data) => chunk.copy_from_slice(data),
+                None => chunk.fill(0),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self.block_size, self.block_count, start, data.len())?;
+
+        let mut blocks = self.blocks.lock();
+
+        for (block, chunk) in (start..).zip(data.chunks_exact(self.block_size)) {
+            match chunk.iter().all(|&byte| byte == 0) {
+                true => blocks.remove(&block),
+                false => blocks.insert(block, chunk.to_vec()),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+pub fn mount(fs: Arc<dyn FileSystem>) -> Vfs {
+    let vfs = Vfs::new();
+
+    vfs.mount("/", fs).unwrap();
+
+    vfs
+}
+
+// NOTE: in the order the directory lists them
+pub fn names(vfs: &Vfs, path: &str) -> Vec<String> {
+    vfs.read_dir(path)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect()
+}
+
+pub fn sorted_names(vfs: &Vfs, path: &str) -> Vec<String> {
+    let mut names = names(vfs, path);
+
+    names.sort();
+
+    names
+}
+
+pub fn contents(vfs: &Vfs, path: &str) -> Vec<u8> {
+    let file = vfs.open(path, OpenFlags::READ_ONLY).unwrap();
+    let mut contents = vec![0; file.metadata().size as usize];
+
+    assert_eq!(file.read(&mut contents), Ok(contents.len()));
+
+    contents
+}
+
+// NOTE: the checks below are for an empty writable filesystem mounted on `vfs`'s root and leave
+// what they made behind
+
+pub fn check_files(vfs: &Vfs) {
+    let file = vfs.open("/notes", CREATE).unwrap();
+
+    assert_eq!(file.write(b"hello "), Ok(6));
+    assert_eq!(file.write(b"world"), Ok(5));
+    assert_eq!(file.metadata().size, 11);
+    assert_eq!(contents(vfs, "/notes"), b"hello world");
+    assert_eq!(file.seek(SeekFrom::Start(6)), Ok(6));
+    assert_eq!(file.write(b"there"), Ok(5));
+    assert_eq!(contents(vfs, "/notes"), b"hello there");
+    assert_eq!(file.seek(SeekFrom::End(-5)), Ok(6));
+
+    let mut buffer = [0; 16];
+
+    assert_eq!(file.read(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"there");
+    assert_eq!(file.read(&mut buffer), Ok(0));
+    assert_eq!(file.seek(SeekFrom::Current(-20)), Err(FsError::BadOffset));
+
+    let other = vfs.open("/other", CREATE).unwrap();
+
+    assert_eq!(other.write(b"kept"), Ok(4));
+    assert_eq!(vfs.open("/other", CREATE).unwrap().metadata().size, 0);
+    assert_eq!(
+        vfs.open("/missing", OpenFlags::READ_ONLY).err(),
+        Some(FsError::NotFound)
+    );
+    assert_eq!(
+        vfs.create("/notes", InodeKind::File).err(),
+        Some(FsError::Exists)
+    );
+}
+
+pub fn check_directories(vfs: &Vfs) {
+    vfs.mkdir("/usr").unwrap();
+    vfs.mkdir("/usr/bin").unwrap();
+    vfs.mkdir("/usr/lib").unwrap();
+    vfs.create("/usr/bin/sh", InodeKind::File).unwrap();
+
+    assert_eq!(sorted_names(vfs, "/usr"), ["bin", "lib"]);
+    assert_eq!(
+        vfs.lookup("/usr/lib/../bin/./sh").unwrap().path(),
+        "/usr/bin/sh"
+    );
+    assert_eq!(
+        vfs.mkdir("/usr/bin/sh/x").err(),
+        Some(FsError::NotDirectory)
+    );
+    assert_eq!(vfs.mkdir("/missing/x").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.unlink("/usr/bin"), Err(FsError::NotEmpty));
+    assert_eq!(
+        vfs.open("/usr", OpenFlags::READ_WRITE).err(),
+        Some(FsError::IsDirectory)
+    );
+
+    let directory = vfs.open("/usr", OpenFlags::READ_ONLY).unwrap();
+    let mut listed = Vec::new();
+
+    while let Some(entry) = directory.read_dir().unwrap() {
+        assert_eq!(entry.kind, InodeKind::Directory);
+        listed.push(entry.name);
+    }
+
+    listed.sort();
+    assert_eq!(listed, ["bin", "lib"]);
+
+    assert_eq!(vfs.unlink("/usr/bin/sh"), Ok(()));
+    assert_eq!(vfs.unlink("/usr/bin"), Ok(()));
+    assert_eq!(vfs.unlink("/usr/bin"), Err(FsError::NotFound));
+    assert_eq!(names(vfs, "/usr"), ["lib"]);
+}
+
+pub fn check_rename(vfs: &Vfs) {
+    vfs.mkdir("/a").unwrap();
+    vfs.mkdir("/a/b").unwrap();
+    vfs.mkdir("/c").unwrap();
+    vfs.open("/a/b/inner", CREATE).unwrap().write(b"0").unwrap();
+    vfs.open("/a/file", CREATE).unwrap().write(b"1").unwrap();
+    vfs.open("/c/other", CREATE).unwrap().write(b"2").unwrap();
+
+    assert_eq!(vfs.rename("/a/file", "/a/renamed"), Ok(()));
+    assert_eq!(sorted_names(vfs, "/a"), ["b", "renamed"]);
+    assert_eq!(vfs.rename("/a/renamed", "/c/other"), Ok(()));
+    assert_eq!(contents(vfs, "/c/other"), b"1");
+    assert_eq!(vfs.rename("/c/other", "/c/other"), Ok(()));
+    assert_eq!(vfs.rename("/c/other", "/a/b"), Err(FsError::IsDirectory));
+    assert_eq!(vfs.rename("/a/b", "/c/other"), Err(FsError::NotDirectory));
+    assert_eq!(vfs.rename("/a", "/a/b/a"), Err(FsError::InvalidPath));
+    assert_eq!(vfs.rename("/a/missing", "/c/x"), Err(FsError::NotFound));
+
+    // NOTE: both ways round, the directories get locked in the same order either way. What is
+    // inside moves along
+    assert_eq!(vfs.rename("/a/b", "/c/b"), Ok(()));
+    assert_eq!(contents(vfs, "/c/b/inner"), b"0");
+    assert_eq!(vfs.rename("/c/b", "/a/b"), Ok(()));
+    assert_eq!(names(vfs, "/a"), ["b"]);
+    assert_eq!(names(vfs, "/c"), ["other"]);
+    assert_eq!(contents(vfs, "/a/b/../b/inner"), b"0");
+}
+
+// NOTE: the file is closed when this returns
+pub fn check_unlinked_file_stays_open(vfs: &Vfs) {
+    let file = vfs.open("/gone", CREATE).unwrap();
+
+    assert_eq!(file.write(&[1; 1000]), Ok(1000));
+    assert_eq!(vfs.unlink("/gone"), Ok(()));
+    assert_eq!(vfs.lookup("/gone").err(), Some(FsError::NotFound));
+    assert_eq!(file.write(b"still here"), Ok(10));
+    assert_eq!(file.seek(SeekFrom::Start(1000)), Ok(1000));
+
+    let mut buffer = [0; 10];
+
+    assert_eq!(file.read(&mut buffer), Ok(10));
+    assert_eq!(&buffer, b"still here");
+}
@@ -0,0 +1,193 @@
+#!/usr/bin/env python3
+# Writes fat32.img, a stand-in for the volume fat32.sh makes with mkfs.fat and mtools, for when
+# those aren't at hand. It shares no code with the kernel's driver. The layout is the one
+# `mkfs.fat -F 32 -s 1 -S 512 -n RUSTOS` picks for 34 MiB: 32 reserved sectors with the boot
+# sector backup at 6 and FSInfo at 1 and 7, two FATs of 536 sectors and 68528 one sector
+# clusters, above the 65525 FAT32 needs. The files are the ones fat32.sh copies in, with
+# big.bin's clusters interleaved with docs/'s and a deleted entry in the root directory.
+#
+#   python3 tests/data/fat32.py tests/data/fat32.img
+
+import struct
+import sys
+
+SECTOR = 512
+SECTORS = 34 * 2048
+RESERVED = 32
+FAT_SIZE = 536
+DATA = RESERVED + 2 * FAT_SIZE
+CLUSTERS = SECTORS - DATA
+END = 0x0FFFFFFF
+
+# 2024-05-01 12:00:00
+DATE = (2024 - 1980) << 9 | 5 << 5 | 1
+TIME = 12 << 11
+
+ATTR_VOLUME_ID = 0x08
+ATTR_DIRECTORY = 0x10
+ATTR_ARCHIVE = 0x20
+ATTR_LONG_NAME = 0x0F
+LOWER_BASE = 0x08
+LOWER_EXTENSION = 0x10
+
+image = bytearray(SECTORS * SECTOR)
+fat = [0x0FFFFFF8, END] + [0] * CLUSTERS
+
+
+def boot_sector():
+    boot = bytearray(SECTOR)
+    # NOTE: mkfs.fat's code that prints the message after it when booted
+    code = bytes.fromhex("0e1fbe777cac22c0740b56b40ebb0700cd105eebf032e4cd16cd19ebfe")
+    message = (b"This is not a bootable disk.  Please insert a bootable floppy and\r\n"
+               b"press any key to try again ... \r\n")
+
+    struct.pack_into("<3s8sHBHBHHBHHHII", boot, 0, b"\xeb\x58\x90", b"mkfs.fat", SECTOR, 1,
+                     RESERVED, 2, 0, 0, 0xF8, 0, 32, 64, 0, SECTORS)
+    struct.pack_into("<IHHIHH12sBBBI11s8s", boot, 36, FAT_SIZE, 0, 0, 2, 1, 6, b"", 0x80, 0,
+                     0x29, 0x12345678, b"RUSTOS     ", b"FAT32   ")
+    boot[90:90 + len(code)] = code
+    boot[90 + len(code):90 + len(code) + len(message)] = message
+    boot[510:512] = b"\x55\xaa"
+
+    return boot
+
+
+def fsinfo(free, next):
+    sector = bytearray(SECTOR)
+
+    struct.pack_into("<I", sector, 0, 0x41615252)
+    struct.pack_into("<IIII", sector, 484, 0x61417272, free, next, 0)
+    struct.pack_into("<I", sector, 508, 0xAA550000)
+
+    return sector
+
+
+def cluster_offset(cluster):
+    return (DATA + cluster - 2) * SECTOR
+
+
+def chain(clusters):
+    for cluster, next in zip(clusters, clusters[1:] + [END]):
+        fat[cluster] = next
+
+
+def write(clusters, data):
+    chain(clusters)
+
+    for index, cluster in enumerate(clusters):
+        piece = data[index * SECTOR:(index + 1) * SECTOR]
+        offset = cluster_offset(cluster)
+
+        image[offset:offset + len(piece)] = piece
+
+
+def short_entry(short, attributes, cluster=0, size=0, case=0):
+    assert len(short) == 11
+
+    return struct.pack("<11sBBBHHHHHHHI", short, attributes, case, 0, TIME, DATE, DATE,
+                       cluster >> 16, TIME, DATE, cluster & 0xFFFF, size)
+
+
+def checksum(short):
+    sum = 0
+
+    for byte in short:
+        sum = ((sum >> 1 | sum << 7) + byte) & 0xFF
+
+    return sum
+
+
+# NOTE: the long name entries for `name`, last piece first, then the short entry
+def long_entries(name, short, attributes, cluster=0, size=0):
+    units = list(name.encode("utf-16-le"))
+    units = [units[index] | units[index + 1] << 8 for index in range(0, len(units), 2)]
+    count = (len(units) + 12) // 13
+
+    if len(units) % 13:
+        units.append(0)
+
+    units += [0xFFFF] * (count * 13 - len(units))
+
+    entries = []
+
+    for number in range(count, 0, -1):
+        piece = units[(number - 1) * 13:number * 13]
+        order = number | (0x40 if number == count else 0)
+
+        entries.append(struct.pack("<B5HBBB6HH2H", order, *piece[:5], ATTR_LONG_NAME, 0,
+                                   checksum(short), *piece[5:11], 0, *piece[11:]))
+
+    return entries + [short_entry(short, attributes, cluster, size)]
+
+
+def deleted(entries):
+    return [b"\xe5" + entry[1:] for entry in entries]
+
+
+def directory(clusters, entries, cluster=None, parent=None):
+    if cluster is not None:
+        entries = [short_entry(b".          ", ATTR_DIRECTORY, cluster),
+                   short_entry(b"..         ", ATTR_DIRECTORY, parent)] + entries
+
+    assert len(entries) * 32 <= len(clusters) * SECTOR
+    write(clusters, b"".join(entries))
+
+
+HELLO = b"Hello, FAT32\n"
+BIG = bytes(index * 7 % 251 for index in range(3000))
+MIXED = b"Mixed case\n"
+UNICODE = "utf-8 name\n".encode()
+
+# NOTE: big.bin and docs/ are split across each other, the way files written side by side end up
+write([3], HELLO)
+write([4, 5, 6, 10, 11, 12], BIG)
+docs = [7, 8, 9, 13, 14, 15]
+deeper = 16
+notes = list(range(17, 37))
+unicode = 37
+mixed = 38
+
+root = [
+    short_entry(b"RUSTOS     ", ATTR_VOLUME_ID),
+    short_entry(b"HELLO   TXT", ATTR_ARCHIVE, 3, len(HELLO), LOWER_BASE | LOWER_EXTENSION),
+    *deleted(long_entries("old notes.txt", b"OLDNOT~1TXT", ATTR_ARCHIVE, 39, 5)),
+    short_entry(b"BIG     BIN", ATTR_ARCHIVE, 4, len(BIG), LOWER_BASE | LOWER_EXTENSION),
+    short_entry(b"DOCS       ", ATTR_DIRECTORY, docs[0], 0, LOWER_BASE),
+    *long_entries("Mixed Case.TXT", b"MIXEDC~1TXT", ATTR_ARCHIVE, mixed, len(MIXED)),
+    short_entry(b"EMPTY      ", ATTR_ARCHIVE, 0, 0, LOWER_BASE),
+]
+docs_entries = []
+
+for number, cluster in enumerate(notes):
+    note = b"note %02d\n" % number
+    tail = b"~%d" % (number + 1)
+    short = b"AFAIRL"[:8 - len(tail)] + tail
+
+    write([cluster], note)
+    docs_entries += long_entries("a fairly long file name number %02d" % number,
+                                 short.ljust(8) + b"   ", ATTR_ARCHIVE, cluster, len(note))
+
+docs_entries.append(short_entry(b"DEEPER     ", ATTR_DIRECTORY, deeper, 0, LOWER_BASE))
+
+write([unicode], UNICODE)
+write([mixed], MIXED)
+directory([2], root)
+directory(docs, docs_entries, docs[0], 0)
+directory([deeper], long_entries("Ünïcödé.txt", b"_N_C_D~1TXT", ATTR_ARCHIVE, unicode,
+                                 len(UNICODE)), deeper, docs[0])
+
+free = fat[2:].count(0)
+tables = b"".join(struct.pack("<I", entry) for entry in fat)
+tables += bytes(FAT_SIZE * SECTOR - len(tables))
+
+for backup in [0, 6]:
+    image[backup * SECTOR:(backup + 1) * SECTOR] = boot_sector()
+    image[(backup + 1) * SECTOR:(backup + 2) * SECTOR] = fsinfo(free, mixed)
+
+for number in range(2):
+    offset = (RESERVED + number * FAT_SIZE) * SECTOR
+
+    image[offset:offset + len(tables)] = tables
+
+with open(sys.argv[1] if len(sys.argv) > 1 else "fat32.img", "wb") as output:
+    output.write(image)
@@ -0,0 +1,41 @@
+#!/bin/sh
+# Makes fat32.img with mkfs.fat and mtools, then has fsck.fat check it and fat32-driver.img.
+# Needs dosfstools and mtools. Run from the top of the tree:
+#
+#   sh tests/data/fat32.sh
+#
+# Until this has been run the fat32.img in the tree is the stand-in fat32.py writes
+set -eu
+
+image=tests/data/fat32.img
+tree=$(mktemp -d)
+trap 'rm -rf "$tree"' EXIT
+
+export LC_ALL=C.UTF-8 MTOOLS_SKIP_CHECK=1
+
+rm -f "$image"
+mkfs.fat -C -F 32 -s 1 -S 512 -n RUSTOS -i 12345678 "$image" 34816
+
+printf 'Hello, FAT32\n' >"$tree/hello.txt"
+printf 'gone\n' >"$tree/old notes.txt"
+python3 -c 'import sys; sys.stdout.buffer.write(bytes(i * 7 % 251 for i in range(3000)))' \
+    >"$tree/big.bin"
+printf 'utf-8 name\n' >"$tree/Ünïcödé.txt"
+printf 'Mixed case\n' >"$tree/Mixed Case.TXT"
+: >"$tree/empty"
+
+mcopy -i "$image" "$tree/hello.txt" "$tree/old notes.txt" "$tree/big.bin" ::/
+mmd -i "$image" ::/docs
+
+for number in $(seq -w 0 19); do
+    printf 'note %s\n' "$number" >"$tree/note"
+    mcopy -i "$image" "$tree/note" "::/docs/a fairly long file name number $number"
+done
+
+mmd -i "$image" ::/docs/deeper
+mcopy -i "$image" "$tree/Ünïcödé.txt" ::/docs/deeper/
+mcopy -i "$image" "$tree/Mixed Case.TXT" "$tree/empty" ::/
+mdel -i "$image" "::/old notes.txt"
+
+fsck.fat -n -V "$image"
+fsck.fat -n -V tests/data/fat32-driver.img
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rustos::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+
+        idt
+    };
+}
+
+// NOTE: the fault has to be an instruction fetch from a present page, i.e. NX did its job
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if error_code
+        .contains(PageFaultErrorCode::INSTRUCTION_FETCH | PageFaultErrorCode::PROTECTION_VIOLATION)
+    {
+        serial_println!("[OK]");
+        exit_qemu(QemuExitCode::SUCCESS);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("unexpected page fault: {:?}", error_code);
+        exit_qemu(QemuExitCode::FAILED);
+    }
+
+    rustos::hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("execute_heap::execute_heap...\t");
+
+    rustos::init(boot_info);
+
+    // NOTE: the test IDT has no IRQ handlers
+    x86_64::instructions::interrupts::disable();
+    TEST_IDT.load();
+
+    // NOTE: a single `ret`
+    let code = Box::new(0xc3u8);
+    let function: extern "C" fn() = unsafe { core::mem::transmute(&*code as *const u8) };
+
+    function();
+
+    serial_println!("[failed]");
+    serial_println!("executing from the heap did not fault");
+    exit_qemu(QemuExitCode::FAILED);
+
+    rustos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
@@ -0,0 +1,153 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use common::{contents, sorted_names};
+use core::panic::PanicInfo;
+use rustos::block::RamDisk;
+use rustos::fs::{Ext2, FileSystem, FsError, InodeKind, OpenFlags, SeekFrom, Vfs};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustos::init(boot_info);
+    test_main();
+
+    rustos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// NOTE: made on Linux with e2fsprogs 1.47 from a tree with hello.txt, docs/ with 60 files
+// "a fairly long file name number NN" and docs/deeper/Ünïcödé.txt, big, sparse with a hole
+// before its tail at 300 KiB, the symlinks link and slowlink, and the fifo pipe:
+//
+//   mkfs.ext2 -b 1024 -N 96 -I 128 -m 0 -L rustos -O ^resize_inode,^dir_index,^ext_attr \
+//       -d tree ext2.img 256
+const IMAGE: &[u8] = include_bytes!("data/ext2.img");
+
+fn mount(block_size: usize) -> Vfs {
+    let disk = Arc::new(RamDisk::from_image(block_size, IMAGE.to_vec()));
+
+    common::mount(Arc::new(Ext2::new(disk).unwrap()))
+}
+
+#[test_case]
+fn test_files() {
+    // NOTE: the volume's 1 KiB blocks over smaller and larger device blocks
+    for block_size in [512, 4096] {
+        let vfs = mount(block_size);
+
+        assert_eq!(
+            sorted_names(&vfs, "/"),
+            ["big", "docs", "hello.txt", "lost+found", "sparse"]
+        );
+        assert_eq!(contents(&vfs, "/hello.txt"), b"Hello from Linux\n");
+
+        // NOTE: past the direct blocks into the single indirect one
+        let big = contents(&vfs, "/big");
+
+        assert_eq!(big.len(), 41083);
+        assert!(big
+            .iter()
+            .enumerate()
+            .all(|(index, &byte)| byte == (index * 7 % 251) as u8));
+    }
+}
+
+#[test_case]
+fn test_sparse_file() {
+    let vfs = mount(512);
+    let sparse = contents(&vfs, "/sparse");
+    let tail = b"tail of a sparse file\n";
+
+    // NOTE: the tail is in a double indirect block, the hole has no blocks at all
+    assert_eq!(sparse.len(), 300 * 1024 + tail.len());
+    assert!(sparse[..300 * 1024].iter().all(|&byte| byte == 0));
+    assert_eq!(&sparse[300 * 1024..], tail);
+
+    let file = vfs.open("/sparse", OpenFlags::READ_ONLY).unwrap();
+    let mut piece = [1; 8];
+
+    assert_eq!(
+        file.seek(SeekFrom::Start(300 * 1024 - 4)),
+        Ok(300 * 1024 - 4)
+    );
+    assert_eq!(file.read(&mut piece), Ok(8));
+    assert_eq!(&piece, b"\0\0\0\0tail");
+    assert_eq!(file.seek(SeekFrom::Start(400 * 1024)), Ok(400 * 1024));
+    assert_eq!(file.read(&mut piece), Ok(0));
+}
+
+#[test_case]
+fn test_directories() {
+    let vfs = mount(512);
+    let docs = sorted_names(&vfs, "/docs");
+    let mut expected: Vec<String> = (0..60)
+        .map(|number| format!("a fairly long file name number {:02}", number))
+        .collect();
+
+    expected.push(String::from("deeper"));
+    expected.sort();
+
+    // NOTE: more entries than fit in one block
+    assert_eq!(docs, expected);
+    assert_eq!(
+        contents(&vfs, "/docs/a fairly long file name number 42"),
+        b"note 42\n"
+    );
+    assert_eq!(
+        contents(&vfs, "/docs/deeper/../deeper/Ünïcödé.txt"),
+        b"utf-8 name\n"
+    );
+    assert_eq!(
+        vfs.lookup("/docs/deeper").unwrap().metadata().kind,
+        InodeKind::Directory
+    );
+    assert!(sorted_names(&vfs, "/lost+found").is_empty());
+    assert_eq!(
+        vfs.lookup("/hello.txt/x").err(),
+        Some(FsError::NotDirectory)
+    );
+    assert_eq!(vfs.lookup("/HELLO.TXT").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_skipped_and_read_only() {
+    let vfs = mount(512);
+
+    // NOTE: symlinks and fifos aren't served
+    for path in ["/link", "/slowlink", "/pipe"] {
+        assert_eq!(vfs.lookup(path).err(), Some(FsError::NotFound));
+    }
+
+    let file = vfs.open("/hello.txt", OpenFlags::READ_WRITE).unwrap();
+
+    assert_eq!(file.write(b"x"), Err(FsError::ReadOnly));
+    assert_eq!(
+        vfs.create("/new", InodeKind::File).err(),
+        Some(FsError::ReadOnly)
+    );
+    assert_eq!(vfs.mkdir("/docs/new").err(), Some(FsError::ReadOnly));
+    assert_eq!(vfs.unlink("/hello.txt"), Err(FsError::ReadOnly));
+    assert_eq!(vfs.rename("/hello.txt", "/bye.txt"), Err(FsError::ReadOnly));
+
+    let fs = Ext2::new(Arc::new(RamDisk::from_image(1024, IMAGE.to_vec()))).unwrap();
+
+    assert_eq!(fs.name(), "ext2");
+    assert_eq!(fs.root().metadata().inode, 2);
+}
@@ -0,0 +1,487 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use common::{contents, names, SparseDisk, CREATE};
+use core::panic::PanicInfo;
+use rustos::block::{self, BlockDevice, RamDisk};
+use rustos::fs::{Fat32, FsError, InodeKind, OpenFlags, SeekFrom, Vfs};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustos::init(boot_info);
+    test_main();
+
+    rustos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+// NOTE: 34 MiB in 512 byte sectors, which makes 512 byte clusters and more of them than the
+// 65525 FAT32 needs. SparseDisk keeps only the blocks in use
+const SECTORS: u64 = 34 * 2048;
+
+// NOTE: a volume the driver had no part in making, from data/fat32.sh or its stand-in
+// data/fat32.py, see there. It has hello.txt, big.bin, docs/ with 20 files "a fairly long file
+// name number NN" and deeper/Ünïcödé.txt, "Mixed Case.TXT", the empty file empty, a deleted
+// entry and the label. build.rs drops its zero sectors, see SparseDisk::from_sectors
+const REFERENCE_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fat32.img.sectors"));
+
+// NOTE: what driver_volume wrote, for fsck.fat to look at in data/fat32.sh
+const DRIVER_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fat32-driver.img.sectors"));
+
+fn disk() -> Arc<SparseDisk> {
+    let disk = Arc::new(SparseDisk::new(512, SECTORS));
+
+    Fat32::format(disk.as_ref()).unwrap();
+
+    disk
+}
+
+fn mount(disk: &Arc<SparseDisk>) -> Vfs {
+    common::mount(Arc::new(Fat32::new(disk.clone()).unwrap()))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+// NOTE: what fsck.fat checks, written separately from the driver: the FATs agree, every chain
+// is as long as its file, no cluster is in two chains or in none, "." and ".." point where they
+// should and the FSInfo free count is right. Returns the number of free clusters
+fn check(disk: &dyn BlockDevice) -> usize {
+    let mut boot = [0; 512];
+
+    block::read_bytes(disk, 0, &mut boot).unwrap();
+
+    let sector = u16_at(&boot, 11);
+    let cluster_size = boot[13] as usize * sector;
+    let reserved = u16_at(&boot, 14);
+    let fat_size = u32_at(&boot, 36) * sector;
+    let data = reserved * sector + boot[16] as usize * fat_size;
+    let total = match u16_at(&boot, 19) {
+        0 => u32_at(&boot, 32),
+        total => total,
+    };
+    let clusters = (total * sector - data) / cluster_size;
+    let mut first = vec![0; fat_size];
+    let mut other = vec![0; sector];
+
+    block::read_bytes(disk, (reserved * sector) as u64, &mut first).unwrap();
+
+    // NOTE: the other FATs a sector at a time, together they would take much of the heap
+    for index in 1..boot[16] as usize {
+        for (number, expected) in first.chunks_exact(sector).enumerate() {
+            let offset = reserved * sector + index * fat_size + number * sector;
+
+            block::read_bytes(disk, offset as u64, &mut other).unwrap();
+            assert!(other == expected, "the FATs differ");
+        }
+    }
+
+    let fat = |cluster: usize| u32_at(&first, cluster * 4) & 0x0fff_ffff;
+    let mut used = vec![false; clusters + 2];
+    // NOTE: marks the chain from `first` used and calls `visit` with each of its clusters, a
+    // file filling the volume has too many to collect
+    let walk = |first: usize, used: &mut Vec<bool>, visit: &mut dyn FnMut(usize)| {
+        let mut cluster = first;
+
+        while first != 0 && cluster < 0x0fff_fff8 {
+            assert!(
+                (2..clusters + 2).contains(&cluster),
+                "bad cluster {}",
+                cluster
+            );
+            assert!(!used[cluster], "cluster {} is in two chains", cluster);
+            used[cluster] = true;
+            visit(cluster);
+            cluster = fat(cluster);
+        }
+    };
+    let read = |chain: &[usize]| {
+        let mut bytes = vec![0; chain.len() * cluster_size];
+
+        for (index, cluster) in chain.iter().enumerate() {
+            let offset = data + (cluster - 2) * cluster_size;
+
+            block::read_bytes(
+                disk,
+                offset as u64,
+                &mut bytes[index * cluster_size..(index + 1) * cluster_size],
+            )
+            .unwrap();
+        }
+
+        bytes
+    };
+    let mut directories = vec![(u32_at(&boot, 44), 0)];
+
+    while let Some((first, parent)) = directories.pop() {
+        let mut chain = Vec::new();
+
+        walk(first, &mut used, &mut |cluster| chain.push(cluster));
+
+        let entries = read(&chain);
+
+        for (slot, entry) in entries.chunks_exact(32).enumerate() {
+            let cluster = u16_at(entry, 20) << 16 | u16_at(entry, 26);
+
+            match (entry[0], entry[11]) {
+                (0, _) => break,
+                (0xe5, _) | (_, 0x0f) => continue,
+                (_, attributes) if attributes & 0x08 != 0 => continue,
+                (b'.', _) if entry[1] == b' ' => assert_eq!((slot, cluster), (0, first)),
+                (b'.', _) if entry[1] == b'.' => assert_eq!((slot, cluster), (1, parent)),
+                (_, attributes) if attributes & 0x10 != 0 => {
+                    let parent = match first == u32_at(&boot, 44) {
+                        true => 0,
+                        false => first,
+                    };
+
+                    directories.push((cluster, parent));
+                }
+                _ => {
+                    let size = u32_at(entry, 28);
+                    let mut length = 0;
+
+                    walk(cluster, &mut used, &mut |_| length += 1);
+                    assert_eq!(
+                        length,
+                        size.div_ceil(cluster_size),
+                        "the chain of a {} byte file",
+                        size
+                    );
+                }
+            }
+        }
+    }
+
+    let lost = (2..clusters + 2).find(|&cluster| fat(cluster) != 0 && !used[cluster]);
+
+    assert_eq!(lost, None, "lost clusters");
+
+    let free = (2..clusters + 2)
+        .filter(|&cluster| fat(cluster) == 0)
+        .count();
+    let mut fsinfo = [0; 512];
+
+    block::read_bytes(disk, (u16_at(&boot, 48) * sector) as u64, &mut fsinfo).unwrap();
+
+    match u32_at(&fsinfo, 488) {
+        0xffff_ffff => {}
+        counted => assert_eq!(counted, free, "the FSInfo free count"),
+    }
+
+    free
+}
+
+// NOTE: the driver's own volume after a bit of everything, what DRIVER_IMAGE was made from
+fn driver_volume() -> Arc<SparseDisk> {
+    let disk = disk();
+    let vfs = mount(&disk);
+
+    vfs.mkdir("/usr").unwrap();
+    vfs.mkdir("/usr/bin").unwrap();
+
+    for number in 0..20 {
+        let file = vfs
+            .open(&format!("/usr/bin/program number {:02}", number), CREATE)
+            .unwrap();
+
+        file.write(format!("program {}\n", number).repeat(number).as_bytes())
+            .unwrap();
+    }
+
+    vfs.open("/notes.txt", CREATE)
+        .unwrap()
+        .write(b"hello there")
+        .unwrap();
+    vfs.rename("/usr/bin/program number 03", "/usr/Moved Program")
+        .unwrap();
+    vfs.unlink("/usr/bin/program number 05").unwrap();
+    vfs.sync().unwrap();
+
+    disk
+}
+
+#[test_case]
+fn test_reference_volume() {
+    // NOTE: the volume's 512 byte sectors over larger device blocks too
+    for block_size in [512, 4096] {
+        let disk = Arc::new(SparseDisk::from_sectors(block_size, REFERENCE_IMAGE));
+        let vfs = mount(&disk);
+
+        assert_eq!(
+            names(&vfs, "/"),
+            ["hello.txt", "big.bin", "docs", "Mixed Case.TXT", "empty"]
+        );
+        assert_eq!(contents(&vfs, "/hello.txt"), b"Hello, FAT32\n");
+        assert_eq!(contents(&vfs, "/MIXED case.txt"), b"Mixed case\n");
+        assert!(contents(&vfs, "/empty").is_empty());
+        assert!(contents(&vfs, "/big.bin")
+            .iter()
+            .enumerate()
+            .all(|(index, &byte)| byte == (index * 7 % 251) as u8));
+        assert_eq!(contents(&vfs, "/big.bin").len(), 3000);
+
+        let mut expected: Vec<String> = (0..20)
+            .map(|number| format!("a fairly long file name number {:02}", number))
+            .collect();
+
+        expected.push(String::from("deeper"));
+        assert_eq!(names(&vfs, "/docs"), expected);
+        assert_eq!(
+            contents(&vfs, "/docs/a fairly long file name number 13"),
+            b"note 13\n"
+        );
+        assert_eq!(
+            contents(&vfs, "/docs/deeper/../deeper/Ünïcödé.txt"),
+            b"utf-8 name\n"
+        );
+    }
+
+    // NOTE: and the driver's changes to it still pass
+    let disk = Arc::new(SparseDisk::from_sectors(512, REFERENCE_IMAGE));
+    let free = check(disk.as_ref());
+    let vfs = mount(&disk);
+
+    vfs.open("/docs/deeper/new file", CREATE)
+        .unwrap()
+        .write(&[3; 1500])
+        .unwrap();
+    vfs.unlink("/big.bin").unwrap();
+    vfs.sync().unwrap();
+    assert_eq!(check(disk.as_ref()), free + 6 - 3);
+}
+
+#[test_case]
+fn test_driver_volume() {
+    // NOTE: what the driver writes now and what it wrote when DRIVER_IMAGE was made, both read
+    // back the same
+    let disks = [
+        driver_volume(),
+        Arc::new(SparseDisk::from_sectors(512, DRIVER_IMAGE)),
+    ];
+
+    for disk in &disks {
+        let vfs = mount(disk);
+
+        check(disk.as_ref());
+        assert_eq!(names(&vfs, "/"), ["usr", "notes.txt"]);
+        assert_eq!(
+            contents(&vfs, "/usr/Moved Program"),
+            b"program 3\n".repeat(3)
+        );
+        assert_eq!(names(&vfs, "/usr/bin").len(), 18);
+        assert_eq!(
+            contents(&vfs, "/usr/bin/program number 19"),
+            b"program 19\n".repeat(19)
+        );
+        assert_eq!(
+            vfs.lookup("/usr/bin/program number 05").err(),
+            Some(FsError::NotFound)
+        );
+    }
+}
+
+#[test_case]
+fn test_fresh_volume() {
+    let disk = disk();
+    let free = check(disk.as_ref());
+    let vfs = mount(&disk);
+
+    assert!(names(&vfs, "/").is_empty());
+    assert_eq!(vfs.sync(), Ok(()));
+    assert_eq!(check(disk.as_ref()), free);
+    assert_eq!(Fat32::format(&RamDisk::new(512, 32)), Err(FsError::NoSpace));
+}
+
+#[test_case]
+fn test_files() {
+    let disk = disk();
+    let vfs = mount(&disk);
+
+    common::check_files(&vfs);
+
+    let append = OpenFlags {
+        append: true,
+        ..OpenFlags::READ_WRITE
+    };
+    let log = vfs.open("/A Longer Log Name.log", CREATE).unwrap();
+    let bytes: Vec<u8> = (0..=255).collect();
+
+    for _ in 0..10 {
+        assert_eq!(
+            vfs.open("/A Longer Log Name.log", append)
+                .unwrap()
+                .write(&bytes),
+            Ok(256)
+        );
+    }
+
+    assert_eq!(log.metadata().size, 2560);
+    assert_eq!(vfs.sync(), Ok(()));
+    check(disk.as_ref());
+
+    // NOTE: everything is on the device, a second mount sees it under any case
+    let vfs = mount(&disk);
+
+    assert_eq!(contents(&vfs, "/NOTES"), b"hello there");
+    assert_eq!(contents(&vfs, "/a longer log name.log"), bytes.repeat(10));
+    assert_eq!(
+        vfs.create("/bad?name", InodeKind::File).err(),
+        Some(FsError::InvalidPath)
+    );
+    check(disk.as_ref());
+}
+
+#[test_case]
+fn test_truncate() {
+    let disk = disk();
+    let vfs = mount(&disk);
+    let free = check(disk.as_ref());
+    let file = vfs.open("/data", CREATE).unwrap();
+    let inode = file.dentry().inode();
+
+    assert_eq!(file.write(&[0xaa; 2000]), Ok(2000));
+    assert_eq!(inode.truncate(100), Ok(()));
+    assert_eq!(check(disk.as_ref()), free - 1);
+
+    // NOTE: what was written past the new end reads as zeroes once the file grows again
+    assert_eq!(inode.truncate(1500), Ok(()));
+    assert_eq!(file.seek(SeekFrom::Start(3000)), Ok(3000));
+    assert_eq!(file.write(b"end"), Ok(3));
+
+    let data = contents(&vfs, "/data");
+
+    assert_eq!(data.len(), 3003);
+    assert!(data[..100].iter().all(|&byte| byte == 0xaa));
+    assert!(data[100..3000].iter().all(|&byte| byte == 0));
+    assert_eq!(&data[3000..], b"end");
+    assert_eq!(check(disk.as_ref()), free - 6);
+    assert_eq!(inode.truncate(0), Ok(()));
+    assert_eq!(check(disk.as_ref()), free);
+}
+
+#[test_case]
+fn test_directories() {
+    let disk = disk();
+    let vfs = mount(&disk);
+
+    common::check_directories(&vfs);
+    check(disk.as_ref());
+    vfs.mkdir("/usr/bin").unwrap();
+
+    // NOTE: 16 entries to a cluster, these take a short and two long name entries each
+    let expected: Vec<String> = (0..30)
+        .map(|index| format!("program number {:02}", index))
+        .collect();
+
+    for name in &expected {
+        vfs.create(&format!("/usr/bin/{}", name), InodeKind::File)
+            .unwrap();
+    }
+
+    assert_eq!(names(&vfs, "/usr/bin"), expected);
+    check(disk.as_ref());
+
+    assert_eq!(vfs.unlink("/usr/bin"), Err(FsError::NotEmpty));
+
+    for name in &expected[..29] {
+        vfs.unlink(&format!("/usr/bin/{}", name)).unwrap();
+    }
+
+    // NOTE: freed entries are reused, the directory doesn't grow
+    vfs.create("/usr/bin/sh", InodeKind::File).unwrap();
+    assert_eq!(names(&vfs, "/usr/bin"), ["sh", "program number 29"]);
+
+    let free = check(disk.as_ref());
+
+    vfs.unlink("/usr/bin/sh").unwrap();
+    vfs.unlink("/usr/bin/program number 29").unwrap();
+    vfs.unlink("/usr/bin").unwrap();
+    assert!(check(disk.as_ref()) > free);
+    assert_eq!(names(&vfs, "/"), ["usr"]);
+    assert_eq!(vfs.mkdir("/usr/bin/x").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_rename() {
+    let disk = disk();
+    let vfs = mount(&disk);
+
+    common::check_rename(&vfs);
+
+    // NOTE: only the case changes, the entry stays where it is
+    assert_eq!(vfs.rename("/c/other", "/c/Other File"), Ok(()));
+    assert_eq!(vfs.rename("/c/Other File", "/c/other file"), Ok(()));
+    assert_eq!(names(&vfs, "/c"), ["other file"]);
+
+    // NOTE: ".." of a directory moved elsewhere follows it
+    assert_eq!(vfs.rename("/a/b", "/b"), Ok(()));
+    check(disk.as_ref());
+
+    let vfs = mount(&disk);
+
+    assert_eq!(names(&vfs, "/"), ["a", "c", "b"]);
+    assert!(names(&vfs, "/a").is_empty());
+    assert_eq!(names(&vfs, "/c"), ["other file"]);
+    assert_eq!(contents(&vfs, "/b/inner"), b"0");
+}
+
+#[test_case]
+fn test_unlinked_file_stays_open() {
+    let disk = disk();
+    let vfs = mount(&disk);
+    let free = check(disk.as_ref());
+
+    // NOTE: the file's clusters are freed once it is closed
+    common::check_unlinked_file_stays_open(&vfs);
+    assert_eq!(vfs.sync(), Ok(()));
+    assert_eq!(check(disk.as_ref()), free);
+}
+
+#[test_case]
+fn test_full_volume() {
+    let disk = disk();
+    let vfs = mount(&disk);
+    let free = check(disk.as_ref());
+    let file = vfs.open("/big", CREATE).unwrap();
+
+    // NOTE: a write that doesn't fit isn't made at all, smaller ones fill what is left. Zeroes,
+    // so SparseDisk doesn't keep them
+    for chunk in [&[0; 4096][..], &[0; 512]] {
+        while file.write(chunk).is_ok() {}
+
+        assert_eq!(file.write(chunk), Err(FsError::NoSpace));
+    }
+
+    assert_eq!(vfs.create("/more", InodeKind::File).map(|_| ()), Ok(()));
+    assert_eq!(vfs.mkdir("/dir").err(), Some(FsError::NoSpace));
+    assert_eq!(check(disk.as_ref()), 0);
+
+    vfs.unlink("/big").unwrap();
+    drop(file);
+    vfs.unlink("/more").unwrap();
+    assert_eq!(check(disk.as_ref()), free);
+}
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use rustos::allocator::{self, OutOfMemory, HEAP_SIZE};
+use rustos::memory::{self, oom};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustos::init(boot_info);
+    test_main();
+
+    rustos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn test_large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+
+    for i in 0..n {
+        vec.push(i);
+    }
+
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+// NOTE: only works if freed memory is reused
+#[test_case]
+fn test_many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn test_usage_statistics() {
+    let before = memory::stats();
+    let values: Vec<u64> = (0..100).collect();
+    let during = memory::stats();
+
+    assert_eq!(during.heap.used, before.heap.used + values.capacity() * 8);
+    assert!(during.heap.peak >= during.heap.used);
+    assert!(during.heap.allocations > before.heap.allocations);
+
+    drop(values);
+
+    let after = memory::stats();
+
+    assert_eq!(after.heap.used, before.heap.used);
+    assert_eq!(
+        after.heap.allocations - before.heap.allocations,
+        after.heap.deallocations - before.heap.deallocations
+    );
+    assert_eq!(after.frames, before.frames);
+}
+
+// NOTE: fills the heap with 64 KiB blocks until the allocator gives up, then frees them again
+#[test_case]
+fn test_exhaustion() {
+    static REPORTS: AtomicU64 = AtomicU64::new(0);
+
+    fn handler(request: oom::OomRequest) -> bool {
+        assert!(matches!(request, oom::OomRequest::Heap(_)));
+        REPORTS.fetch_add(1, Ordering::Relaxed);
+
+        false
+    }
+
+    let before = memory::stats();
+    let layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+    let huge = Layout::from_size_align(HEAP_SIZE * 2, 8).unwrap();
+    let mut blocks = Vec::new();
+
+    oom::set_handler(Some(handler));
+
+    assert_eq!(allocator::try_alloc(huge), Err(OutOfMemory(huge)));
+    assert_eq!(REPORTS.load(Ordering::Relaxed), 1);
+
+    blocks.reserve(HEAP_SIZE / layout.size());
+
+    while let Ok(block) = allocator::try_alloc(layout) {
+        blocks.push(block);
+    }
+
+    assert!(!blocks.is_empty());
+    assert_eq!(REPORTS.load(Ordering::Relaxed), 2);
+    assert_eq!(allocator::try_alloc(layout), Err(OutOfMemory(layout)));
+    assert_eq!(REPORTS.load(Ordering::Relaxed), 3);
+
+    oom::set_handler(None);
+
+    for block in blocks.drain(..) {
+        unsafe { allocator::dealloc(block, layout) };
+    }
+
+    drop(blocks);
+
+    assert_eq!(memory::stats().heap.used, before.heap.used);
+    assert!(allocator::try_box(1u64).is_ok());
+}
+
+#[test_case]
+fn test_collections() {
+    let mut map = BTreeMap::new();
+    let mut text = String::from("rust");
+
+    text.push_str("os");
+    map.insert(text.clone(), 1);
+
+    assert_eq!(map.get("rustos"), Some(&1));
+}
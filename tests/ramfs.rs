@@ -0,0 +1,174 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use common::{contents, names, CREATE};
+use core::panic::PanicInfo;
+use rustos::fs::{self, DevFs, FsError, InodeKind, OpenFlags, RamFs, SeekFrom, Vfs};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustos::init(boot_info);
+    test_main();
+
+    rustos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustos::test_panic_handler(info)
+}
+
+fn ramfs() -> Vfs {
+    common::mount(Arc::new(RamFs::new()))
+}
+
+#[test_case]
+fn test_boot_namespace() {
+    let vfs = fs::vfs();
+
+    assert_eq!(
+        vfs.lookup("/").unwrap().metadata().kind,
+        InodeKind::Directory
+    );
+    assert_eq!(names(vfs, "/dev"), ["console", "null", "zero"]);
+    assert_eq!(
+        vfs.lookup("/tmp").unwrap().metadata().kind,
+        InodeKind::Directory
+    );
+
+    let file = vfs.open("/tmp/boot", CREATE).unwrap();
+
+    assert_eq!(file.write(b"scratch"), Ok(7));
+    assert_eq!(contents(vfs, "/tmp/boot"), b"scratch");
+    assert_eq!(vfs.unlink("/tmp/boot"), Ok(()));
+    assert_eq!(vfs.unlink("/dev"), Err(FsError::Busy));
+}
+
+#[test_case]
+fn test_files() {
+    common::check_files(&ramfs());
+}
+
+#[test_case]
+fn test_open_modes() {
+    let vfs = ramfs();
+
+    vfs.open("/log", CREATE).unwrap().write(b"one\n").unwrap();
+
+    let append = OpenFlags {
+        append: true,
+        ..OpenFlags::READ_WRITE
+    };
+    let first = vfs.open("/log", append).unwrap();
+    let second = vfs.open("/log", append).unwrap();
+
+    assert_eq!(first.write(b"two\n"), Ok(4));
+    assert_eq!(second.write(b"three\n"), Ok(6));
+    assert_eq!(contents(&vfs, "/log"), b"one\ntwo\nthree\n");
+
+    let read_only = vfs.open("/log", OpenFlags::READ_ONLY).unwrap();
+
+    assert_eq!(read_only.write(b"no"), Err(FsError::AccessDenied));
+
+    let write_only = vfs
+        .open(
+            "/log",
+            OpenFlags {
+                read: false,
+                ..OpenFlags::READ_WRITE
+            },
+        )
+        .unwrap();
+
+    assert_eq!(write_only.read(&mut [0; 4]), Err(FsError::AccessDenied));
+}
+
+#[test_case]
+fn test_directories() {
+    let vfs = ramfs();
+
+    common::check_directories(&vfs);
+
+    // NOTE: kept in name order
+    vfs.create("/usr/a", InodeKind::File).unwrap();
+    vfs.mkdir("/usr/z").unwrap();
+    assert_eq!(names(&vfs, "/usr"), ["a", "lib", "z"]);
+}
+
+#[test_case]
+fn test_unlinked_file_stays_open() {
+    common::check_unlinked_file_stays_open(&ramfs());
+}
+
+#[test_case]
+fn test_rename() {
+    common::check_rename(&ramfs());
+}
+
+#[test_case]
+fn test_mounts() {
+    let vfs = ramfs();
+
+    vfs.mkdir("/mnt").unwrap();
+    vfs.create("/mnt/hidden", InodeKind::File).unwrap();
+    vfs.mount("/mnt", Arc::new(RamFs::new())).unwrap();
+
+    assert!(names(&vfs, "/mnt").is_empty());
+
+    vfs.mkdir("/mnt/inner").unwrap();
+    vfs.open("/mnt/inner/file", CREATE).unwrap();
+
+    assert_eq!(vfs.lookup("/mnt/inner/../..").unwrap().path(), "/");
+    assert_eq!(names(&vfs, "/mnt/inner/../../mnt"), ["inner"]);
+    assert_eq!(
+        vfs.rename("/mnt/inner/file", "/file"),
+        Err(FsError::CrossDevice)
+    );
+    assert_eq!(vfs.rename("/mnt", "/elsewhere"), Err(FsError::Busy));
+    assert_eq!(vfs.unlink("/mnt"), Err(FsError::Busy));
+
+    vfs.mount("/mnt/inner", Arc::new(DevFs::new())).unwrap();
+
+    assert_eq!(
+        vfs.lookup("/mnt/inner/zero").unwrap().metadata().kind,
+        InodeKind::Device
+    );
+    assert_eq!(vfs.unmount("/mnt").err(), Some(FsError::Busy));
+    assert!(vfs.unmount("/mnt/inner").is_ok());
+    assert_eq!(names(&vfs, "/mnt/inner"), ["file"]);
+    assert!(vfs.unmount("/mnt").is_ok());
+    assert_eq!(names(&vfs, "/mnt"), ["hidden"]);
+    assert_eq!(vfs.unmount("/mnt").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_large_file() {
+    let vfs = ramfs();
+    let file = vfs.open("/large", CREATE).unwrap();
+    let block: Vec<u8> = (0..=255).collect();
+
+    for _ in 0..256 {
+        assert_eq!(file.write(&block), Ok(256));
+    }
+
+    assert_eq!(file.metadata().size, 64 * 1024);
+    assert_eq!(file.seek(SeekFrom::Start(1000)), Ok(1000));
+
+    let mut buffer = [0; 4];
+
+    assert_eq!(file.read(&mut buffer), Ok(4));
+    assert_eq!(buffer, [232, 233, 234, 235]);
+    assert_eq!(file.dentry().inode().truncate(10), Ok(()));
+    assert_eq!(contents(&vfs, "/large"), &block[..10]);
+}
@@ -0,0 +1,9 @@
+use std::env;
+
+// NOTE: the examples are linked with link.ld; a program of its own needs the same link argument
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+
+    println!("cargo:rerun-if-changed=link.ld");
+    println!("cargo:rustc-link-arg-examples=-T{}/link.ld", manifest_dir);
+}
@@ -0,0 +1,35 @@
+// NOTE: cargo build --example hello in user/, then load target/x86_64-unknown-none/debug/
+// examples/hello with process::spawn
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use rustos_user::{entry, env, io, println};
+
+entry!(main);
+
+fn main() -> i32 {
+    println!("hello from ring 3");
+
+    for (index, arg) in env::args().enumerate() {
+        println!("argv[{}] = {:?}", index, arg);
+    }
+
+    let squares: Vec<u64> = (1..=10).map(|n| n * n).collect();
+
+    println!("squares: {:?}", squares);
+    println!("what's your name?");
+
+    let mut buffer = [0u8; 64];
+    let name = match io::read_line(&mut buffer) {
+        Ok(read) => String::from_utf8_lossy(&buffer[..read]).trim().into(),
+        Err(_) => String::from("stranger"),
+    };
+
+    println!("hi {}", name);
+
+    0
+}
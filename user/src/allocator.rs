@@ -0,0 +1,116 @@
+use crate::abi::{PROT_READ, PROT_WRITE};
+use crate::syscall;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+
+const PAGE_SIZE: usize = 4096;
+// NOTE: each size is also the block's alignment, so they have to be powers of two
+const BLOCK_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+// NOTE: small blocks are carved from mappings of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct BlockNode {
+    next: *mut BlockNode,
+}
+
+struct State {
+    heads: [*mut BlockNode; BLOCK_SIZES.len()],
+    // NOTE: the unused rest of the last chunk
+    chunk_next: usize,
+    chunk_end: usize,
+}
+
+// NOTE: freed small blocks go on a per size list, new ones come from 64 KiB anonymous mappings
+// that are never given back. Anything bigger gets pages of its own and unmaps them again.
+// Programs have a single thread, but the state must not be touched from a signal handler
+// that interrupted an allocation
+pub struct MmapAllocator {
+    state: UnsafeCell<State>,
+}
+
+unsafe impl Sync for MmapAllocator {}
+
+#[global_allocator]
+static ALLOCATOR: MmapAllocator = MmapAllocator {
+    state: UnsafeCell::new(State {
+        heads: [ptr::null_mut(); BLOCK_SIZES.len()],
+        chunk_next: 0,
+        chunk_end: 0,
+    }),
+};
+
+fn block_index(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+
+    BLOCK_SIZES.iter().position(|&size| size >= required)
+}
+
+fn map(len: usize) -> *mut u8 {
+    syscall::mmap(len as u64, PROT_READ | PROT_WRITE)
+        .map_or(ptr::null_mut(), |address| address as *mut u8)
+}
+
+impl State {
+    // NOTE: chunks are page aligned, so the bump pointer starts aligned for every size
+    fn carve(&mut self, size: usize) -> *mut u8 {
+        if self.chunk_end - self.chunk_next < size {
+            let chunk = map(CHUNK_SIZE);
+
+            if chunk.is_null() {
+                return chunk;
+            }
+
+            self.chunk_next = chunk as usize;
+            self.chunk_end = chunk as usize + CHUNK_SIZE;
+        }
+
+        let block = self.chunk_next;
+
+        self.chunk_next += size;
+
+        block as *mut u8
+    }
+}
+
+unsafe impl GlobalAlloc for MmapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let state = &mut *self.state.get();
+
+        let Some(index) = block_index(&layout) else {
+            // NOTE: mappings are only page aligned
+            if layout.align() > PAGE_SIZE {
+                return ptr::null_mut();
+            }
+
+            return map(layout.size());
+        };
+
+        match state.heads[index] {
+            head if head.is_null() => state.carve(BLOCK_SIZES[index]),
+            head => {
+                state.heads[index] = (*head).next;
+
+                head as *mut u8
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let state = &mut *self.state.get();
+
+        match block_index(&layout) {
+            Some(index) => {
+                let node = ptr as *mut BlockNode;
+
+                node.write(BlockNode {
+                    next: state.heads[index],
+                });
+                state.heads[index] = node;
+            }
+            None => {
+                let _ = syscall::munmap(ptr as u64, layout.size() as u64);
+            }
+        }
+    }
+}
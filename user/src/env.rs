@@ -0,0 +1,67 @@
+use core::ffi::{c_char, CStr};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+// NOTE: the null terminated argv and envp arrays the kernel put on the initial stack
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(ptr::null_mut());
+static ENVP: AtomicPtr<*const c_char> = AtomicPtr::new(ptr::null_mut());
+
+// NOTE: `stack` is rsp as the kernel entered _start with it, pointing at argc
+pub(crate) unsafe fn init(stack: *const u64) {
+    let argc = *stack as usize;
+    let argv = stack.add(1) as *mut *const c_char;
+
+    ARGV.store(argv, Ordering::Relaxed);
+    ENVP.store(argv.add(argc + 1), Ordering::Relaxed);
+}
+
+// NOTE: walks one of the arrays up to its null pointer
+pub struct Strings {
+    next: *const *const c_char,
+}
+
+impl Iterator for Strings {
+    type Item = &'static CStr;
+
+    fn next(&mut self) -> Option<&'static CStr> {
+        if self.next.is_null() {
+            return None;
+        }
+
+        let string = unsafe { *self.next };
+
+        if string.is_null() {
+            return None;
+        }
+
+        self.next = unsafe { self.next.add(1) };
+
+        Some(unsafe { CStr::from_ptr(string) })
+    }
+}
+
+// NOTE: the program's arguments, its own name first by convention
+pub fn args() -> Strings {
+    Strings {
+        next: ARGV.load(Ordering::Relaxed),
+    }
+}
+
+// NOTE: the environment as NAME=value strings
+pub fn vars() -> Strings {
+    Strings {
+        next: ENVP.load(Ordering::Relaxed),
+    }
+}
+
+// NOTE: the value of the first NAME=value for `name`
+pub fn var(name: &str) -> Option<&'static CStr> {
+    vars().find_map(|var| {
+        let bytes = var.to_bytes_with_nul();
+
+        match bytes.strip_prefix(name.as_bytes())?.split_first()? {
+            (b'=', value) => CStr::from_bytes_with_nul(value).ok(),
+            _ => None,
+        }
+    })
+}
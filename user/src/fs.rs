@@ -0,0 +1,153 @@
+use crate::abi::{
+    Error, DIRENT_DEVICE, DIRENT_DIRECTORY, DIRENT_FILE, OPEN_CREATE, OPEN_READ, OPEN_TRUNCATE,
+    OPEN_WRITE, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+use crate::{io, syscall};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub use crate::syscall::{mkdir, rename, unlink};
+
+// NOTE: a descriptor from SYS_OPEN, closed when dropped
+#[derive(Debug)]
+pub struct File {
+    descriptor: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+impl File {
+    // NOTE: with OPEN_* flags
+    pub fn open_with(path: &str, flags: u64) -> Result<File, Error> {
+        syscall::open(path, flags).map(|descriptor| File { descriptor })
+    }
+
+    pub fn open(path: &str) -> Result<File, Error> {
+        File::open_with(path, OPEN_READ)
+    }
+
+    // NOTE: for writing, empty whether it existed or not
+    pub fn create(path: &str) -> Result<File, Error> {
+        File::open_with(path, OPEN_READ | OPEN_WRITE | OPEN_CREATE | OPEN_TRUNCATE)
+    }
+
+    pub fn descriptor(&self) -> u64 {
+        self.descriptor
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        syscall::read(self.descriptor, buffer)
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, Error> {
+        syscall::write(self.descriptor, bytes)
+    }
+
+    pub fn write_all(&self, bytes: &[u8]) -> Result<(), Error> {
+        io::write_all(self.descriptor, bytes)
+    }
+
+    // NOTE: from the offset to the end
+    pub fn read_to_end(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0; 4096];
+
+        loop {
+            match self.read(&mut buffer)? {
+                0 => return Ok(bytes),
+                read => bytes.extend_from_slice(&buffer[..read]),
+            }
+        }
+    }
+
+    pub fn seek(&self, position: SeekFrom) -> Result<u64, Error> {
+        let (offset, whence) = match position {
+            SeekFrom::Start(offset) => (offset as i64, SEEK_SET),
+            SeekFrom::Current(offset) => (offset, SEEK_CUR),
+            SeekFrom::End(offset) => (offset, SEEK_END),
+        };
+
+        syscall::seek(self.descriptor, offset, whence)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.descriptor);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Device,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+}
+
+// NOTE: the entries of a directory, fetched a bufferful at a time
+pub struct ReadDir {
+    file: File,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+pub fn read_dir(path: &str) -> Result<ReadDir, Error> {
+    Ok(ReadDir {
+        file: File::open(path)?,
+        buffer: Vec::new(),
+        position: 0,
+    })
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Result<DirEntry, Error>> {
+        if self.position == self.buffer.len() {
+            self.buffer.resize(1024, 0);
+            self.position = 0;
+
+            match syscall::readdir(self.file.descriptor, &mut self.buffer) {
+                Ok(len) => self.buffer.truncate(len),
+                Err(error) => {
+                    self.buffer.clear();
+
+                    return Some(Err(error));
+                }
+            }
+
+            if self.buffer.is_empty() {
+                return None;
+            }
+        }
+
+        let kind = self.buffer[self.position];
+        let len = self.buffer[self.position + 1] as usize;
+        let name = &self.buffer[self.position + 2..self.position + 2 + len];
+
+        self.position += 2 + len;
+
+        let file_type = match kind {
+            DIRENT_FILE => FileType::File,
+            DIRENT_DIRECTORY => FileType::Directory,
+            DIRENT_DEVICE => FileType::Device,
+            _ => return Some(Err(Error::InvalidArgument)),
+        };
+
+        Some(Ok(DirEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            file_type,
+        }))
+    }
+}
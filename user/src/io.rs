@@ -0,0 +1,63 @@
+use crate::abi::{Error, STDERR, STDIN, STDOUT};
+use crate::syscall;
+use core::fmt;
+
+// NOTE: writes all of `bytes`, a short write is retried with the rest
+pub fn write_all(descriptor: u64, mut bytes: &[u8]) -> Result<(), Error> {
+    while !bytes.is_empty() {
+        match syscall::write(descriptor, bytes)? {
+            0 => return Err(Error::BrokenPipe),
+            written => bytes = &bytes[written..],
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE: a line from the console in its default canonical mode, 0 at end of file
+pub fn read_line(buffer: &mut [u8]) -> Result<usize, Error> {
+    syscall::read(STDIN, buffer)
+}
+
+pub struct Stdout;
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(STDOUT, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+pub struct Stderr;
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(STDERR, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::io::_eprint(format_args!("\n")));
+    ($($arg:tt)*) => ($crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Stdout, args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Stderr, args);
+}
@@ -0,0 +1,73 @@
+// NOTE: runtime support for rustos user programs: the syscall wrappers, a _start that calls the
+// program's main through entry!, its arguments and environment, files and directories, futex based
+// locks, a panic handler printing to stderr and a global allocator on top of SYS_MMAP. A program is
+// a #![no_std] #![no_main] binary built for this crate's target and linked with link.ld, see
+// examples/hello.rs
+#![no_std]
+
+extern crate alloc;
+
+#[path = "../../src/syscall/abi.rs"]
+pub mod abi;
+#[path = "../../src/syscall/user.rs"]
+pub mod syscall;
+
+mod allocator;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod process;
+pub mod sync;
+
+use core::arch::global_asm;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+// NOTE: the kernel enters with rsp on argc, aligned to 16 bytes; the call leaves it the way a
+// function expects it, on a zeroed frame pointer for backtraces
+global_asm!(
+    ".global _start",
+    "_start:",
+    "xor ebp, ebp",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call {start}",
+    "ud2",
+    start = sym start,
+);
+
+extern "Rust" {
+    // NOTE: defined by entry!
+    fn __rustos_user_main() -> i32;
+}
+
+extern "C" fn start(stack: *const u64) -> ! {
+    unsafe { env::init(stack) };
+
+    let code = unsafe { __rustos_user_main() };
+
+    syscall::exit(code)
+}
+
+// NOTE: names the program's `fn() -> i32`, whose result is the exit code
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __rustos_user_main() -> i32 {
+            let main: fn() -> i32 = $main;
+
+            main()
+        }
+    };
+}
+
+// NOTE: what a program that panicked exits with, like a Rust program on Linux
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let _ = writeln!(io::Stderr, "panic: {}", info);
+
+    syscall::exit(PANIC_EXIT_CODE)
+}
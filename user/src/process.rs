@@ -0,0 +1,29 @@
+use crate::abi::Error;
+use crate::syscall;
+use alloc::vec::Vec;
+use core::ptr;
+
+// NOTE: exec with `args` and `env` as the new program's, copied to add their NULs. A string
+// with a NUL in it ends there
+pub fn exec(image: &[u8], args: &[&str], env: &[&str]) -> Error {
+    let strings: Vec<Vec<u8>> = args
+        .iter()
+        .chain(env)
+        .map(|string| {
+            let mut bytes = Vec::with_capacity(string.len() + 1);
+
+            bytes.extend_from_slice(string.as_bytes());
+            bytes.push(0);
+
+            bytes
+        })
+        .collect();
+    let mut pointers: Vec<*const u8> = strings.iter().map(|string| string.as_ptr()).collect();
+
+    pointers.insert(args.len(), ptr::null());
+    pointers.push(ptr::null());
+
+    let (argv, envp) = pointers.split_at(args.len() + 1);
+
+    syscall::exec(image, argv, envp)
+}